@@ -0,0 +1,74 @@
+//! Measures parsing of RCS files, which happens once for every file in the
+//! CVSROOT, and of just their admin sections, as used when listing symbols.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+/// A small file in the shape CVS usually leaves behind: a vendor import, a
+/// handful of trunk revisions, two release branches, and commit IDs.
+const MAIN_C: &[u8] = include_bytes!("fixtures/main.c,v");
+
+/// Builds a file that has been around for a long time: `revisions` trunk
+/// revisions of a file with `lines` lines, each changing a single line, and a
+/// tag on every revision, as left by nightly builds.
+fn long_lived(revisions: usize, lines: usize) -> Vec<u8> {
+    let mut admin = format!("head\t1.{};\naccess;\nsymbols", revisions);
+    for revision in (1..=revisions).rev() {
+        admin.push_str(&format!("\n\tnightly-{}:1.{}", revision, revision));
+    }
+    admin.push_str(";\nlocks; strict;\ncomment\t@# @;\n\n");
+
+    let mut deltas = String::new();
+    let mut texts = String::from("\ndesc\n@@\n");
+    for revision in (1..=revisions).rev() {
+        let next = match revision {
+            1 => String::new(),
+            _ => format!("1.{}", revision - 1),
+        };
+        deltas.push_str(&format!(
+            "\n1.{}\ndate\t2001.01.01.00.00.{:02};\tauthor nightly;\tstate Exp;\nbranches;\nnext\t{};\n",
+            revision,
+            revision % 60,
+            next
+        ));
+
+        let text = if revision == revisions {
+            (1..=lines)
+                .map(|line| format!("line {} of a long lived file\n", line))
+                .collect()
+        } else {
+            let line = revision % lines + 1;
+            format!(
+                "d{} 1\na{} 1\nline {} before build {}\n",
+                line, line, line, revision
+            )
+        };
+        texts.push_str(&format!(
+            "\n\n1.{}\nlog\n@nightly build {}\n@\ntext\n@{}@\n",
+            revision, revision, text
+        ));
+    }
+
+    format!("{}{}{}", admin, deltas, texts).into_bytes()
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let inputs = [
+        ("main.c", MAIN_C.to_vec()),
+        ("long lived", long_lived(1_000, 2_000)),
+    ];
+
+    let mut group = c.benchmark_group("parse");
+    for (name, input) in inputs.iter() {
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        group.bench_with_input(BenchmarkId::new("file", name), input, |b, input| {
+            b.iter(|| comma_v::parse(input).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("admin", name), input, |b, input| {
+            b.iter(|| comma_v::parse_admin(input).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse);
+criterion_main!(benches);
@@ -0,0 +1,31 @@
+use std::{fs, path::PathBuf};
+
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    #[structopt(parse(from_os_str), help = "input ,v files")]
+    files: Vec<PathBuf>,
+}
+
+fn main() -> anyhow::Result<()> {
+    let opt = Opt::from_args();
+
+    // Only the admin section is needed, so the deltas are never parsed.
+    for path in opt.files.iter() {
+        let admin = comma_v::parse_admin(&fs::read(path)?)?;
+        println!(
+            "{}: head {}",
+            path.to_string_lossy(),
+            admin
+                .head
+                .map(|head| head.to_string())
+                .unwrap_or_else(|| "none".into())
+        );
+        for (symbol, num) in admin.symbols.iter() {
+            println!("\t{}:{}", String::from_utf8_lossy(symbol), num);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,131 @@
+use std::{fmt::Display, str::FromStr};
+
+use thiserror::Error;
+
+/// Deviations from the RCS date format that are accepted when parsing, since
+/// some tools other than RCS and CVS write dates that RCS itself would reject.
+///
+/// Two digit years, and a single leap second, are always accepted, since RCS
+/// writes both.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DateQuirks {
+    /// Fields may be padded with leading spaces, such as `2021. 8. 1`.
+    pub padding: bool,
+
+    /// Three digit years are counted from 1900, as in a C `struct tm`, such as
+    /// `121` for 2021.
+    pub tm_years: bool,
+
+    /// Months may be given as English abbreviations, such as `Aug`.
+    pub month_names: bool,
+
+    /// Hours, minutes, and seconds past the end of their range carry into the
+    /// next unit, so `23.59.60` is midnight the next day.
+    pub overflow: bool,
+}
+
+impl DateQuirks {
+    /// Every quirk.
+    pub const ALL: Self = Self {
+        padding: true,
+        tm_years: true,
+        month_names: true,
+        overflow: true,
+    };
+
+    const NAMES: [&'static str; 4] = ["padding", "tm-years", "month-names", "overflow"];
+
+    fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "padding" => Some(&mut self.padding),
+            "tm-years" => Some(&mut self.tm_years),
+            "month-names" => Some(&mut self.month_names),
+            "overflow" => Some(&mut self.overflow),
+            _ => None,
+        }
+    }
+}
+
+impl Display for DateQuirks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let enabled = [self.padding, self.tm_years, self.month_names, self.overflow];
+        let names: Vec<&str> = Self::NAMES
+            .iter()
+            .zip(enabled.iter())
+            .filter(|(_name, enabled)| **enabled)
+            .map(|(name, _enabled)| *name)
+            .collect();
+
+        if names.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", names.join(","))
+        }
+    }
+}
+
+impl FromStr for DateQuirks {
+    type Err = DateQuirksError;
+
+    /// Parses a comma separated list of quirk names, `all`, or `none`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => return Ok(Self::ALL),
+            "none" => return Ok(Self::default()),
+            _ => {}
+        }
+
+        let mut quirks = Self::default();
+        for name in s.split(',').map(str::trim) {
+            match quirks.get_mut(name) {
+                Some(enabled) => *enabled = true,
+                None => return Err(DateQuirksError(name.to_string())),
+            }
+        }
+        Ok(quirks)
+    }
+}
+
+/// The reason a date was rejected.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum DateError {
+    #[error("expected six dot separated numbers")]
+    Syntax,
+
+    #[error("invalid date input: {year}-{month}-{day}")]
+    InvalidDate { year: u32, month: u32, day: u32 },
+
+    #[error("invalid time input: {hour}:{minute}:{second}")]
+    InvalidTime { hour: u32, minute: u32, second: u32 },
+}
+
+#[derive(Debug, Error)]
+#[error("unknown date quirk {0:?}: expected all, none, or a comma separated list of padding, tm-years, month-names, and overflow")]
+pub struct DateQuirksError(String);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_date_quirks_parse() {
+        assert_eq!("none".parse::<DateQuirks>().unwrap(), DateQuirks::default());
+        assert_eq!("all".parse::<DateQuirks>().unwrap(), DateQuirks::ALL);
+        assert_eq!(
+            "padding, overflow".parse::<DateQuirks>().unwrap(),
+            DateQuirks {
+                padding: true,
+                overflow: true,
+                ..Default::default()
+            }
+        );
+
+        for quirks in [DateQuirks::default(), DateQuirks::ALL] {
+            assert_eq!(quirks.to_string().parse::<DateQuirks>().unwrap(), quirks);
+        }
+
+        for s in ["", "leap", "padding,"] {
+            assert!(s.parse::<DateQuirks>().is_err(), "{:?}", s);
+        }
+    }
+}
@@ -3,8 +3,23 @@ use std::{num::ParseIntError, str::Utf8Error};
 use nom::error::ErrorKind;
 use thiserror::Error;
 
+use crate::DateError;
+
 #[derive(Debug, Error)]
 pub enum Error {
+    #[error("revision number has no non-zero components: {0}")]
+    EmptyNum(String),
+
+    #[error("invalid checksum in integrity string: {0}")]
+    InvalidChecksum(String),
+
+    #[error("invalid date {:?}: {source}", String::from_utf8_lossy(.raw))]
+    InvalidDate {
+        raw: Vec<u8>,
+        #[source]
+        source: DateError,
+    },
+
     #[error("contains can only be invoked on a branch, with a commit")]
     InvalidTypesForContains,
 
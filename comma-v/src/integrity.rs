@@ -0,0 +1,144 @@
+use std::{collections::HashMap, fmt::Display, str::FromStr};
+
+use sha1::{Digest, Sha1};
+
+use crate::{Error, IntString, Num};
+
+/// A checksum of the content of a revision, as recorded in the integrity
+/// string of an RCS file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Checksum {
+    Sha1([u8; 20]),
+}
+
+impl Checksum {
+    /// Returns true if the given revision content, before any keywords are
+    /// expanded, matches the checksum.
+    pub fn matches(&self, content: &[u8]) -> bool {
+        match self {
+            Self::Sha1(digest) => Sha1::digest(content).as_slice() == digest,
+        }
+    }
+}
+
+impl Display for Checksum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Sha1(digest) => {
+                write!(f, "sha1:")?;
+                for byte in digest.iter() {
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl FromStr for Checksum {
+    type Err = Error;
+
+    /// Parses an algorithm and a hex digest separated by a colon, such as
+    /// `sha1:da39a3ee5e6b4b0d3255bfef95601890afd80709`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidChecksum(s.to_string());
+        match s.split_once(':') {
+            Some(("sha1", hex)) => {
+                let mut digest = [0; 20];
+                if hex.len() != digest.len() * 2 {
+                    return Err(invalid());
+                }
+                for (byte, pair) in digest.iter_mut().zip(hex.as_bytes().chunks(2)) {
+                    *byte = std::str::from_utf8(pair)
+                        .ok()
+                        .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                        .ok_or_else(invalid)?;
+                }
+                Ok(Self::Sha1(digest))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl IntString {
+    /// Returns the revision checksums in the integrity string, keyed by
+    /// revision.
+    ///
+    /// RCS reserves the integrity string without defining its content, so
+    /// only whitespace separated entries of the form `REVISION:CHECKSUM`, such
+    /// as `1.2:sha1:<hex>`, are read as checksums. Anything without a colon is
+    /// ignored, but an entry with a colon that can't be read is an error.
+    pub fn checksums(&self) -> Result<HashMap<Num, Checksum>, Error> {
+        let integrity = std::str::from_utf8(self)?;
+
+        let mut checksums = HashMap::new();
+        for entry in integrity.split_whitespace() {
+            if let Some((revision, checksum)) = entry.split_once(':') {
+                let revision = match Num::from_str(revision) {
+                    Ok(revision @ Num::Commit(_)) => revision,
+                    _ => return Err(Error::InvalidChecksum(entry.to_string())),
+                };
+                checksums.insert(revision, checksum.parse()?);
+            }
+        }
+
+        Ok(checksums)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMPTY: &str = "sha1:da39a3ee5e6b4b0d3255bfef95601890afd80709";
+    const HELLO: &str = "sha1:f572d396fae9206628714fb2ce00f72e94f2258f";
+
+    #[test]
+    fn test_checksum() -> anyhow::Result<()> {
+        let empty = Checksum::from_str(EMPTY)?;
+        assert!(empty.matches(b""));
+        assert!(!empty.matches(b"hello\n"));
+        assert_eq!(empty.to_string(), EMPTY);
+        assert!(Checksum::from_str(HELLO)?.matches(b"hello\n"));
+
+        for s in [
+            "",
+            "sha1",
+            "sha1:",
+            "sha1:da39",
+            "md5:d41d8cd98f00b204e9800998ecf8427e",
+            "sha1:xx39a3ee5e6b4b0d3255bfef95601890afd80709",
+        ] {
+            assert!(Checksum::from_str(s).is_err(), "{:?}", s);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_checksums() -> anyhow::Result<()> {
+        let integrity = IntString(format!("1.1:{} note 1.2:{}", EMPTY, HELLO).into_bytes());
+        let checksums = integrity.checksums()?;
+        assert_eq!(checksums.len(), 2);
+        assert!(checksums[&Num::from_str("1.1")?].matches(b""));
+        assert!(checksums[&Num::from_str("1.2")?].matches(b"hello\n"));
+
+        assert!(IntString(b"free text".to_vec()).checksums()?.is_empty());
+        for integrity in [
+            format!("1.1.2:{}", EMPTY),
+            format!("x:{}", EMPTY),
+            "1.1:sha1".to_string(),
+        ] {
+            assert!(
+                IntString(integrity.clone().into_bytes())
+                    .checksums()
+                    .is_err(),
+                "{:?}",
+                integrity
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -1,20 +1,68 @@
 use nom::Finish;
 
+mod date;
 mod error;
+mod integrity;
 mod num;
 mod parser;
 mod types;
 
+pub use date::{DateError, DateQuirks, DateQuirksError};
 pub use error::Error;
+pub use integrity::Checksum;
 pub use num::Num;
 pub use types::*;
 
 /// Parses a full RCS file.
 pub fn parse(input: &[u8]) -> Result<File, Error> {
-    Ok(Finish::finish(parser::file(input))
+    parse_with_quirks(input, DateQuirks::default())
+}
+
+/// Parses a full RCS file, accepting the given deviations from the RCS date
+/// format.
+pub fn parse_with_quirks(input: &[u8], quirks: DateQuirks) -> Result<File, Error> {
+    finish(parser::file(input, quirks))
+}
+
+/// Parses a full RCS file, leaving its log messages and delta texts in the
+/// input until they're used.
+pub fn parse_lazy(input: &[u8]) -> Result<LazyFile<'_>, Error> {
+    parse_lazy_with_quirks(input, DateQuirks::default())
+}
+
+/// Parses a full RCS file lazily, accepting the given deviations from the RCS
+/// date format.
+pub fn parse_lazy_with_quirks(input: &[u8], quirks: DateQuirks) -> Result<LazyFile<'_>, Error> {
+    finish(parser::lazy_file(input, quirks))
+}
+
+fn finish<T>(result: parser::IResult<&[u8], T>) -> Result<T, Error> {
+    Ok(Finish::finish(result)
+        .map_err(|e| match e.date {
+            Some(source) => Error::InvalidDate {
+                raw: e
+                    .input
+                    .iter()
+                    .take_while(|c| **c != b';' && **c != b'\n')
+                    .copied()
+                    .collect(),
+                source,
+            },
+            None => Error::ParseError {
+                location: Vec::from(e.input),
+                kind: e.kind,
+            },
+        })?
+        .1)
+}
+
+/// Parses only the admin section of an RCS file, which includes the head
+/// revision and symbols, without parsing the deltas that follow it.
+pub fn parse_admin(input: &[u8]) -> Result<Admin, Error> {
+    Ok(Finish::finish(parser::file_admin(input))
         .map_err(|e| Error::ParseError {
             location: Vec::from(e.input),
-            kind: e.code,
+            kind: e.kind,
         })?
         .1)
 }
@@ -79,6 +79,101 @@ impl Num {
             Num::Commit(parts) => Num::Branch(parts[0..parts.len() - 1].to_vec()),
         }
     }
+
+    /// Returns the branch containing this number: for a commit, the branch
+    /// it's on, such as `1.1.2` for `1.1.2.3`, and for a branch, the branch it
+    /// was made from, such as `1` for `1.1.2`.
+    ///
+    /// `None` is returned for the trunk itself.
+    pub fn branch_of(&self) -> Option<Self> {
+        match self {
+            Num::Branch(parts) if parts.len() > 1 => {
+                Some(Num::Branch(parts[0..parts.len() - 2].to_vec()))
+            }
+            Num::Branch(_) => None,
+            Num::Commit(_) => Some(self.to_branch()),
+        }
+    }
+
+    /// Returns the commit that the branch containing this commit was made
+    /// from, or that this branch was made from, such as `1.1` for both
+    /// `1.1.2.3` and `1.1.2`.
+    ///
+    /// `None` is returned on the trunk.
+    pub fn branch_point(&self) -> Option<Self> {
+        match self.to_branch() {
+            Num::Branch(parts) if parts.len() > 1 => {
+                Some(Num::Commit(parts[0..parts.len() - 1].to_vec()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the commit that this commit was derived from, as far as can be
+    /// told from the number alone: the previous commit on the same branch, or
+    /// the branch point for the first commit on a branch. For a branch, this
+    /// is its branch point.
+    ///
+    /// `None` is returned for the first commit of each trunk release, such as
+    /// `1.1` or `2.1`, since the latter follows whichever `1.x` revision was
+    /// newest when it was made, which only the RCS file itself knows.
+    pub fn parent_revision(&self) -> Option<Self> {
+        match self {
+            Num::Commit(parts) => match parts.split_last() {
+                Some((last, prefix)) if *last > 1 => {
+                    let mut parent = prefix.to_vec();
+                    parent.push(last - 1);
+                    Some(Num::Commit(parent))
+                }
+                _ => self.branch_point(),
+            },
+            Num::Branch(_) => self.branch_point(),
+        }
+    }
+
+    /// Returns the number of branches between this number and the trunk:
+    /// 0 for `1.2` or the trunk itself, 1 for `1.2.4.1` or `1.2.4`, and so on.
+    pub fn depth(&self) -> usize {
+        match self {
+            Num::Branch(parts) => parts.len().saturating_sub(1) / 2,
+            Num::Commit(parts) => parts.len().saturating_sub(2) / 2,
+        }
+    }
+
+    /// Returns true if this is a trunk commit, or the trunk itself.
+    pub fn is_on_trunk(&self) -> bool {
+        self.depth() == 0
+    }
+
+    /// Checks if this commit is derived from the given commit, either directly
+    /// or through any number of other commits and branch points.
+    ///
+    /// Trunk commits are derived from every earlier trunk commit, whatever
+    /// their release number. A commit isn't derived from itself, and `false`
+    /// is returned if either number is a branch.
+    pub fn is_descendant_of(&self, other: &Num) -> bool {
+        if !matches!((self, other), (Num::Commit(_), Num::Commit(_))) {
+            return false;
+        }
+
+        // Walk back through the branch points until we're on a branch that's
+        // as shallow as the other commit's.
+        let mut ancestor = self.clone();
+        while ancestor.depth() > other.depth() {
+            match ancestor.branch_point() {
+                Some(branch_point) => ancestor = branch_point,
+                None => return false,
+            }
+        }
+
+        let same_branch = ancestor.depth() == other.depth()
+            && (ancestor.is_on_trunk() || ancestor.to_branch() == other.to_branch());
+        if ancestor == *self {
+            same_branch && other < self
+        } else {
+            same_branch && other <= &ancestor
+        }
+    }
 }
 
 impl TryFrom<&[u8]> for Num {
@@ -105,6 +200,7 @@ impl FromStr for Num {
             })
             .collect::<Result<Vec<u64>, ParseIntError>>()
         {
+            Ok(parts) if parts.is_empty() => Err(Error::EmptyNum(s.to_string())),
             Ok(parts) if parts.len() % 2 == 0 => Ok(Self::Commit(parts)),
             Ok(parts) => Ok(Self::Branch(parts)),
             Err(e) => Err(e.into()),
@@ -162,6 +258,83 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_num_branch_of() {
+        assert_eq!(num("1.1.2.3").branch_of(), Some(num("1.1.2")));
+        assert_eq!(num("1.1.2").branch_of(), Some(num("1")));
+        assert_eq!(num("1.1.2.1.4").branch_of(), Some(num("1.1.2")));
+        assert_eq!(num("1.3").branch_of(), Some(num("1")));
+        assert_eq!(num("1").branch_of(), None);
+    }
+
+    #[test]
+    fn test_num_branch_point() {
+        assert_eq!(num("1.1.2.3").branch_point(), Some(num("1.1")));
+        assert_eq!(num("1.1.2").branch_point(), Some(num("1.1")));
+        assert_eq!(num("1.1.2.1.4.2").branch_point(), Some(num("1.1.2.1")));
+        assert_eq!(num("1.3").branch_point(), None);
+        assert_eq!(num("1").branch_point(), None);
+    }
+
+    #[test]
+    fn test_num_parent_revision() {
+        assert_eq!(num("1.3").parent_revision(), Some(num("1.2")));
+        assert_eq!(num("1.1.2.3").parent_revision(), Some(num("1.1.2.2")));
+
+        // The first commit on a branch, and a branch itself, come from the
+        // branch point.
+        assert_eq!(num("1.1.2.1").parent_revision(), Some(num("1.1")));
+        assert_eq!(num("1.1.2.1.4.1").parent_revision(), Some(num("1.1.2.1")));
+        assert_eq!(num("1.2.0.4").parent_revision(), Some(num("1.2")));
+
+        // The first commit of each trunk release can't be determined.
+        assert_eq!(num("1.1").parent_revision(), None);
+        assert_eq!(num("2.1").parent_revision(), None);
+        assert_eq!(num("1").parent_revision(), None);
+    }
+
+    #[test]
+    fn test_num_depth() {
+        assert_eq!(num("1").depth(), 0);
+        assert_eq!(num("1.4").depth(), 0);
+        assert_eq!(num("1.4.2").depth(), 1);
+        assert_eq!(num("1.4.2.1").depth(), 1);
+        assert_eq!(num("1.4.2.1.6").depth(), 2);
+        assert_eq!(num("1.4.2.1.6.3").depth(), 2);
+
+        assert!(num("1").is_on_trunk());
+        assert!(num("2.7").is_on_trunk());
+        assert!(!num("1.1.1").is_on_trunk());
+        assert!(!num("1.1.1.1").is_on_trunk());
+    }
+
+    #[test]
+    fn test_num_is_descendant_of() {
+        // On the trunk, including across releases.
+        assert!(num("1.3").is_descendant_of(&num("1.1")));
+        assert!(num("2.1").is_descendant_of(&num("1.9")));
+        assert!(!num("1.1").is_descendant_of(&num("1.3")));
+        assert!(!num("1.3").is_descendant_of(&num("1.3")));
+
+        // On a branch, from the branch point and anything before it.
+        assert!(num("1.2.2.1").is_descendant_of(&num("1.2")));
+        assert!(num("1.2.2.1").is_descendant_of(&num("1.1")));
+        assert!(num("1.2.2.3").is_descendant_of(&num("1.2.2.1")));
+        assert!(num("1.2.2.1.4.1").is_descendant_of(&num("1.2")));
+        assert!(num("1.2.2.1.4.1").is_descendant_of(&num("1.2.2.1")));
+        assert!(!num("1.2.2.1").is_descendant_of(&num("1.3")));
+        assert!(!num("1.2.2.2").is_descendant_of(&num("1.2.2.3")));
+        assert!(!num("1.2.2.1.4.1").is_descendant_of(&num("1.2.2.2")));
+
+        // Not across sibling branches, or up from a branch to the trunk.
+        assert!(!num("1.2.4.1").is_descendant_of(&num("1.2.2.1")));
+        assert!(!num("1.2").is_descendant_of(&num("1.1.2.1")));
+
+        // Branches aren't commits.
+        assert!(!num("1.2.2.1").is_descendant_of(&num("1.2.2")));
+        assert!(!num("1.2.2").is_descendant_of(&num("1.1")));
+    }
+
     #[test]
     fn test_num_parse() -> anyhow::Result<()> {
         assert_eq!(num("1.1"), Num::Commit(vec![1, 1]));
@@ -172,7 +345,7 @@ mod test {
         assert_eq!(num("1.2.0.3"), Num::Branch(vec![1, 2, 3]));
 
         // Now the failures.
-        for input in ["", "x", "1.", "1.x"] {
+        for input in ["", "x", "1.", "1.x", "0", "0.0"] {
             assert!(Num::from_str(input).is_err());
         }
 
@@ -182,4 +355,23 @@ mod test {
     fn num(s: &str) -> Num {
         Num::from_str(s).unwrap()
     }
+
+    proptest::proptest! {
+        #[test]
+        fn test_num_arbitrary_input(input in "[0-9.]{0,16}") {
+            if let Ok(num) = Num::from_str(&input) {
+                let branch = num.to_branch();
+                let _ = branch.contains(&num);
+                let _ = num.contains(&branch);
+
+                // Every parent is an ancestor, and is a step closer to the
+                // root of the tree.
+                if let (Num::Commit(_), Some(parent)) = (&num, num.parent_revision()) {
+                    assert!(num.is_descendant_of(&parent));
+                    assert!(!parent.is_descendant_of(&num));
+                    assert!(parent.depth() <= num.depth());
+                }
+            }
+        }
+    }
 }
@@ -5,26 +5,62 @@ use nom::{
     bytes::complete::tag,
     character::complete::{multispace0, multispace1},
     combinator::{map, opt},
+    error::{ErrorKind, FromExternalError},
     multi::{fold_many0, many0},
     sequence::{delimited, preceded, separated_pair, terminated, tuple},
-    IResult,
 };
 
-use crate::{num, types};
+use crate::{num, types, DateError, DateQuirks};
 
 mod char;
 
 mod scalar;
 use self::scalar::*;
 
-pub(crate) fn file(input: &[u8]) -> IResult<&[u8], types::File> {
+pub(crate) type IResult<I, O> = nom::IResult<I, O, ParseError<I>>;
+
+/// The error returned by the parsers: this is the same as nom's own error,
+/// except that the reason a date was rejected is kept.
+#[derive(Debug, PartialEq)]
+pub(crate) struct ParseError<I> {
+    pub input: I,
+    pub kind: ErrorKind,
+    pub date: Option<DateError>,
+}
+
+impl<I> ParseError<I> {
+    fn date(input: I, error: DateError) -> Self {
+        Self {
+            input,
+            kind: ErrorKind::Verify,
+            date: Some(error),
+        }
+    }
+}
+
+impl<I> nom::error::ParseError<I> for ParseError<I> {
+    fn from_error_kind(input: I, kind: ErrorKind) -> Self {
+        Self {
+            input,
+            kind,
+            date: None,
+        }
+    }
+
+    fn append(_input: I, _kind: ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+impl<I, E> FromExternalError<I, E> for ParseError<I> {
+    fn from_external_error(input: I, kind: ErrorKind, _e: E) -> Self {
+        nom::error::ParseError::from_error_kind(input, kind)
+    }
+}
+
+pub(crate) fn file(input: &[u8], quirks: DateQuirks) -> IResult<&[u8], types::File> {
     map(
-        tuple((
-            delimited(multispace0, admin, multispace0),
-            many0(terminated(delta, multispace0)),
-            terminated(desc, multispace0),
-            many0(terminated(delta_text, multispace0)),
-        )),
+        sections(quirks, delta_text),
         |(admin, delta, desc, delta_text)| types::File {
             admin,
             delta: delta.into_iter().collect(),
@@ -34,6 +70,53 @@ pub(crate) fn file(input: &[u8]) -> IResult<&[u8], types::File> {
     )(input)
 }
 
+/// Parses a file without copying its log messages and delta texts out of the
+/// input.
+pub(crate) fn lazy_file(input: &[u8], quirks: DateQuirks) -> IResult<&[u8], types::LazyFile<'_>> {
+    map(
+        sections(quirks, raw_delta_text),
+        |(admin, delta, desc, delta_text)| types::LazyFile {
+            admin,
+            delta: delta.into_iter().collect(),
+            desc,
+            delta_text: delta_text.into_iter().collect(),
+        },
+    )(input)
+}
+
+/// Parses each section of a file, using the given parser for delta texts.
+#[allow(clippy::type_complexity)]
+fn sections<'a, T, F>(
+    quirks: DateQuirks,
+    delta_text: F,
+) -> impl FnMut(
+    &'a [u8],
+) -> IResult<
+    &'a [u8],
+    (
+        types::Admin,
+        Vec<(num::Num, types::Delta)>,
+        types::Desc,
+        Vec<(num::Num, T)>,
+    ),
+>
+where
+    F: FnMut(&'a [u8]) -> IResult<&'a [u8], (num::Num, T)>,
+{
+    tuple((
+        delimited(multispace0, admin, multispace0),
+        many0(terminated(move |input| delta(input, quirks), multispace0)),
+        terminated(desc, multispace0),
+        many0(terminated(delta_text, multispace0)),
+    ))
+}
+
+/// Parses the admin section at the start of a file, leaving the rest of the
+/// file unparsed.
+pub(crate) fn file_admin(input: &[u8]) -> IResult<&[u8], types::Admin> {
+    preceded(multispace0, admin)(input)
+}
+
 fn admin(input: &[u8]) -> IResult<&[u8], types::Admin> {
     map(
         permutation((
@@ -48,7 +131,7 @@ fn admin(input: &[u8]) -> IResult<&[u8], types::Admin> {
                     opt(num),
                     tuple((multispace0, tag(b";"), multispace0)),
                 )),
-                |branch| branch.map(|b| b.unwrap()),
+                Option::flatten,
             ),
             delimited(
                 tag(b"access"),
@@ -57,18 +140,11 @@ fn admin(input: &[u8]) -> IResult<&[u8], types::Admin> {
             ),
             delimited(
                 tag(b"symbols"),
-                fold_many0(
-                    separated_pair(
-                        delimited(multispace0, sym, multispace0),
-                        tag(b":"),
-                        delimited(multispace0, num, multispace0),
-                    ),
-                    HashMap::new,
-                    |mut acc, (k, v)| {
-                        acc.insert(k, v);
-                        acc
-                    },
-                ),
+                many0(separated_pair(
+                    delimited(multispace0, sym, multispace0),
+                    tag(b":"),
+                    delimited(multispace0, num, multispace0),
+                )),
                 tuple((multispace0, tag(b";"), multispace0)),
             ),
             delimited(
@@ -121,14 +197,14 @@ fn admin(input: &[u8]) -> IResult<&[u8], types::Admin> {
     )(input)
 }
 
-fn delta(input: &[u8]) -> IResult<&[u8], (num::Num, types::Delta)> {
+fn delta(input: &[u8], quirks: DateQuirks) -> IResult<&[u8], (num::Num, types::Delta)> {
     map(
         tuple((
             terminated(num, multispace1),
             permutation((
                 delimited(
                     tuple((tag(b"date"), multispace1)),
-                    date,
+                    |input| date(input, quirks),
                     tuple((multispace0, tag(b";"), multispace0)),
                 ),
                 delimited(
@@ -187,6 +263,19 @@ fn delta_text(input: &[u8]) -> IResult<&[u8], (num::Num, types::DeltaText)> {
     )(input)
 }
 
+fn raw_delta_text(input: &[u8]) -> IResult<&[u8], (num::Num, types::RawDeltaText<'_>)> {
+    map(
+        tuple((
+            num,
+            preceded(multispace1, tag(b"log")),
+            delimited(multispace1, raw_string, multispace1),
+            tag(b"text"),
+            preceded(multispace1, raw_string),
+        )),
+        |(num, _, log, _, text)| (num, types::RawDeltaText { log, text }),
+    )(input)
+}
+
 fn desc(input: &[u8]) -> IResult<&[u8], types::Desc> {
     preceded(tuple((tag(b"desc"), multispace1)), string)(input)
 }
@@ -196,8 +285,9 @@ mod tests {
     use std::str::FromStr;
 
     use chrono::DateTime;
+    use proptest::prelude::*;
 
-    use crate::num::Num;
+    use crate::{num::Num, types::Sym};
 
     use super::*;
 
@@ -214,12 +304,74 @@ mod tests {
         assert_eq!(*have.comment.unwrap(), b"# ");
         assert!(have.expand.is_none());
 
+        // Duplicate symbols are preserved in the order they appear.
+        let have = admin(
+            b"head 1.2;\naccess;\nsymbols\n\tTAG:1.2\n\tBR:1.1.0.2\n\tTAG:1.1;\nlocks; strict;\n",
+        )?
+        .1;
+        assert_eq!(
+            have.symbols,
+            vec![
+                (Sym::from(b"TAG".to_vec()), Num::from_str("1.2")?),
+                (Sym::from(b"BR".to_vec()), Num::from_str("1.1.0.2")?),
+                (Sym::from(b"TAG".to_vec()), Num::from_str("1.1")?),
+            ]
+        );
+
+        // An empty branch is the same as no branch at all.
+        let have = admin(b"head 1.1;\nbranch ;\naccess;\nsymbols;\nlocks; strict;\n")?.1;
+        assert!(have.branch.is_none());
+
         Ok(())
     }
 
+    #[test]
+    fn test_file_admin() -> anyhow::Result<()> {
+        let (rest, have) = file_admin(include_bytes!("fixtures/file/input"))?;
+        assert_eq!(have.head.unwrap().to_string(), "1.4");
+        assert!(rest.starts_with(b"1.4"));
+
+        // Nothing after the admin section is parsed, so it may be truncated or
+        // otherwise invalid.
+        let (rest, have) = file_admin(
+            b"\nhead 1.2;\naccess;\nsymbols\n\tREL1:1.1;\nlocks; strict;\n\n1.2\ndate garbage",
+        )?;
+        assert_eq!(have.head.unwrap().to_string(), "1.2");
+        assert_eq!(have.symbols.len(), 1);
+        assert_eq!(rest, b"1.2\ndate garbage");
+        assert!(
+            crate::parse(b"head 1.2;\naccess;\nsymbols;\nlocks; strict;\n\n1.2\ndate garbage")
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_invalid_date() {
+        let input = b"head 1.2;\naccess;\nsymbols;\nlocks; strict;\n\n1.2\ndate\t2021.Aug.01.00.00.00;\tauthor adam;\tstate Exp;\nbranches;\nnext\t;\n\ndesc\n@@\n";
+        match crate::parse(input) {
+            Err(crate::Error::InvalidDate { raw, source }) => {
+                assert_eq!(raw, b"2021.Aug.01.00.00.00");
+                assert_eq!(source, DateError::Syntax);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let quirks = DateQuirks {
+            month_names: true,
+            ..Default::default()
+        };
+        assert!(crate::parse_with_quirks(input, quirks).is_ok());
+    }
+
     #[test]
     fn test_delta() -> anyhow::Result<()> {
-        let (num, have) = delta(include_bytes!("fixtures/delta/input"))?.1;
+        let (num, have) = delta(
+            include_bytes!("fixtures/delta/input"),
+            DateQuirks::default(),
+        )?
+        .1;
         assert_eq!(num.to_string(), "1.2");
         assert_eq!(
             have.date,
@@ -252,6 +404,27 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_raw_delta_text() -> anyhow::Result<()> {
+        let (num, have) = raw_delta_text(include_bytes!("fixtures/delta_text/input"))?.1;
+        assert_eq!(num.to_string(), "1.1");
+        assert_eq!(
+            *have.log.to_vstring(),
+            include_bytes!("fixtures/delta_text/log"),
+        );
+        assert_eq!(
+            *have.text.to_vstring(),
+            include_bytes!("fixtures/delta_text/text"),
+        );
+
+        let (_, have) = raw_delta_text(b"1.2 log @a@@b@ text @@")?.1;
+        assert_eq!(have.log, types::RawString(b"a@@b"));
+        assert_eq!(*have.log.to_vstring(), b"a@b");
+        assert_eq!(*have.text.to_vstring(), b"");
+
+        Ok(())
+    }
+
     #[test]
     fn test_desc() -> anyhow::Result<()> {
         assert_eq!(*desc(b"desc @@")?.1, b"");
@@ -263,7 +436,7 @@ mod tests {
 
     #[test]
     fn test_file() -> anyhow::Result<()> {
-        let have = file(include_bytes!("fixtures/file/input"))?.1;
+        let have = file(include_bytes!("fixtures/file/input"), DateQuirks::default())?.1;
 
         // We'll just spot check.
         assert_eq!(have.admin.head.unwrap().to_string(), "1.4");
@@ -288,4 +461,98 @@ mod tests {
 
         Ok(())
     }
+
+    /// Renders a minimal RCS file with a linear history of the given revisions,
+    /// each of which is a (log, text) pair.
+    fn render(revisions: &[(Vec<u8>, Vec<u8>)]) -> Vec<u8> {
+        let escape = |s: &[u8]| {
+            let mut out = vec![b'@'];
+            for c in s {
+                if *c == b'@' {
+                    out.push(b'@');
+                }
+                out.push(*c);
+            }
+            out.push(b'@');
+            out
+        };
+
+        let n = revisions.len();
+        let mut out = format!("head\t1.{};\n", n).into_bytes();
+        out.extend(b"access;\nsymbols;\nlocks; strict;\n\n");
+        for i in (1..=n).rev() {
+            out.extend(format!("1.{}\ndate\t2021.08.20.17.34.{:02};", i, i % 60).bytes());
+            out.extend(b"\tauthor adam;\tstate Exp;\nbranches;\nnext\t");
+            if i > 1 {
+                out.extend(format!("1.{}", i - 1).bytes());
+            }
+            out.extend(b";\n\n");
+        }
+        out.extend(b"desc\n@@\n\n");
+        for (i, (log, text)) in revisions.iter().enumerate().rev() {
+            out.extend(format!("1.{}\nlog\n", i + 1).bytes());
+            out.extend(escape(log));
+            out.extend(b"\ntext\n");
+            out.extend(escape(text));
+            out.extend(b"\n\n");
+        }
+
+        out
+    }
+
+    proptest! {
+        #[test]
+        fn test_file_arbitrary_input(input in proptest::collection::vec(any::<u8>(), 0..512)) {
+            // We don't care about the result, only that we don't panic.
+            let _ = crate::parse(&input);
+        }
+
+        #[test]
+        fn test_file_mutated_input(
+            revisions in proptest::collection::vec((any::<Vec<u8>>(), any::<Vec<u8>>()), 1..5),
+            index in any::<prop::sample::Index>(),
+            byte in any::<u8>(),
+        ) {
+            let mut input = render(&revisions);
+            let i = index.index(input.len());
+            input[i] = byte;
+            let _ = crate::parse(&input);
+        }
+
+        #[test]
+        fn test_file_roundtrip(
+            revisions in proptest::collection::vec((any::<Vec<u8>>(), any::<Vec<u8>>()), 1..5),
+        ) {
+            let have = crate::parse(&render(&revisions)).unwrap();
+            prop_assert_eq!(have.head().unwrap().to_string(), format!("1.{}", revisions.len()));
+            prop_assert_eq!(have.delta.len(), revisions.len());
+            for (i, (log, text)) in revisions.iter().enumerate() {
+                let num = Num::from_str(&format!("1.{}", i + 1)).unwrap();
+                let (_delta, delta_text) = have.revision(&num).unwrap();
+                prop_assert_eq!(&*delta_text.log, log);
+                prop_assert_eq!(&*delta_text.text, text);
+            }
+        }
+
+        #[test]
+        fn test_lazy_file_roundtrip(
+            revisions in proptest::collection::vec((any::<Vec<u8>>(), any::<Vec<u8>>()), 1..5),
+        ) {
+            let input = render(&revisions);
+            let have = crate::parse_lazy(&input).unwrap();
+            prop_assert_eq!(have.delta.len(), revisions.len());
+            for (i, (log, text)) in revisions.iter().enumerate() {
+                let num = Num::from_str(&format!("1.{}", i + 1)).unwrap();
+                let (_delta, delta_text) = have.revision(&num).unwrap();
+                prop_assert_eq!(&*delta_text.log.to_vstring(), log);
+
+                // Reading a byte at a time crosses every escape.
+                let mut read = Vec::new();
+                for byte in std::io::Read::bytes(delta_text.text.reader()) {
+                    read.push(byte.unwrap());
+                }
+                prop_assert_eq!(&read, text);
+            }
+        }
+    }
 }
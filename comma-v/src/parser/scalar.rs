@@ -1,19 +1,17 @@
 use std::{convert::TryFrom, fmt::Debug, str::FromStr, time::SystemTime};
 
-use chrono::{DateTime, NaiveDate, Utc};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
 use nom::{
     branch::alt,
     bytes::complete::{tag, take_till1, take_while, take_while1},
-    character::complete::digit1,
-    combinator::{map, map_res, value},
-    multi::fold_many0,
-    sequence::{delimited, terminated, tuple},
-    IResult,
+    character::complete::{alpha1, digit1, space0},
+    combinator::{map, map_opt, map_res, recognize, value},
+    multi::{fold_many0, many0_count},
+    sequence::{delimited, preceded, terminated, tuple},
 };
-use thiserror::Error;
 
-use super::char::*;
-use crate::{num, types};
+use super::{char::*, IResult, ParseError};
+use crate::{num, types, DateError, DateQuirks};
 
 pub(super) fn integrity_string(input: &[u8]) -> IResult<&[u8], types::IntString> {
     // TODO: thirdp support
@@ -63,47 +61,120 @@ pub(super) fn string(input: &[u8]) -> IResult<&[u8], types::VString> {
     )(input)
 }
 
+/// Parses a string without unescaping it, returning what's between its
+/// delimiters.
+pub(super) fn raw_string(input: &[u8]) -> IResult<&[u8], types::RawString<'_>> {
+    map(
+        delimited(
+            tag(b"@"),
+            recognize(many0_count(alt((string_literal, tag(b"@@"))))),
+            tag(b"@"),
+        ),
+        types::RawString,
+    )(input)
+}
+
 pub(super) fn sym(input: &[u8]) -> IResult<&[u8], types::Sym> {
     map(take_while(is_idchar), |bytes| types::Sym(Vec::from(bytes)))(input)
 }
 
-pub(super) fn date(input: &[u8]) -> IResult<&[u8], SystemTime> {
-    map_res(
-        tuple((
-            terminated(digits, tag(b".")),
-            terminated(digits, tag(b".")),
-            terminated(digits, tag(b".")),
-            terminated(digits, tag(b".")),
-            terminated(digits, tag(b".")),
-            digits,
-        )),
-        |(year, month, day, hour, minute, second)| -> Result<SystemTime, Error> {
-            if let Some(date) =
-                NaiveDate::from_ymd_opt(if year < 100 { year + 1900 } else { year }, month, day)
-            {
-                if let Some(dt) = date.and_hms_milli_opt(
-                    hour,
-                    minute,
-                    if second >= 60 { 59 } else { second },
-                    if second >= 60 {
-                        (second - 59) * 1000
-                    } else {
-                        0
-                    },
-                ) {
-                    Ok(DateTime::<Utc>::from_utc(dt, Utc).into())
-                } else {
-                    Err(Error::InvalidTime {
-                        hour,
-                        minute,
-                        second,
-                    })
-                }
+pub(super) fn date(input: &[u8], quirks: DateQuirks) -> IResult<&[u8], SystemTime> {
+    // Once we've seen the date keyword, nothing else can be here, so any
+    // error is a failure that includes the reason the date was rejected.
+    let (rest, (year, month, day, hour, minute, second)) = tuple((
+        terminated(date_field(quirks), tag(b".")),
+        terminated(month(quirks), tag(b".")),
+        terminated(date_field(quirks), tag(b".")),
+        terminated(date_field(quirks), tag(b".")),
+        terminated(date_field(quirks), tag(b".")),
+        date_field(quirks),
+    ))(input)
+    .map_err(|_| nom::Err::Failure(ParseError::date(input, DateError::Syntax)))?;
+
+    match to_time(quirks, year, month, day, hour, minute, second) {
+        Ok(time) => Ok((rest, time)),
+        Err(e) => Err(nom::Err::Failure(ParseError::date(input, e))),
+    }
+}
+
+fn date_field(quirks: DateQuirks) -> impl Fn(&[u8]) -> IResult<&[u8], u32> {
+    move |input| {
+        if quirks.padding {
+            preceded(space0, digits)(input)
+        } else {
+            digits(input)
+        }
+    }
+}
+
+fn month(quirks: DateQuirks) -> impl Fn(&[u8]) -> IResult<&[u8], u32> {
+    move |input| {
+        if quirks.month_names {
+            alt((date_field(quirks), month_name))(input)
+        } else {
+            date_field(quirks)(input)
+        }
+    }
+}
+
+const MONTH_NAMES: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+fn month_name(input: &[u8]) -> IResult<&[u8], u32> {
+    map_opt(alpha1, |name: &[u8]| {
+        MONTH_NAMES
+            .iter()
+            .position(|month| month.as_bytes().eq_ignore_ascii_case(name))
+            .map(|i| i as u32 + 1)
+    })(input)
+}
+
+fn to_time(
+    quirks: DateQuirks,
+    year: u32,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+) -> Result<SystemTime, DateError> {
+    let full_year = match year {
+        0..=99 => year + 1900,
+        100..=999 if quirks.tm_years => year + 1900,
+        _ => year,
+    };
+    let date = i32::try_from(full_year)
+        .ok()
+        .and_then(|full_year| NaiveDate::from_ymd_opt(full_year, month, day))
+        .ok_or(DateError::InvalidDate { year, month, day })?;
+
+    let dt = if quirks.overflow {
+        let seconds = i64::from(hour) * 3600 + i64::from(minute) * 60 + i64::from(second);
+        date.and_hms_opt(0, 0, 0)
+            .and_then(|midnight| midnight.checked_add_signed(Duration::seconds(seconds)))
+    } else {
+        date.and_hms_milli_opt(
+            hour,
+            minute,
+            if second >= 60 { 59 } else { second },
+            if second >= 60 {
+                // Leap seconds are represented as milliseconds past the 59th
+                // second; anything beyond a single leap second will be
+                // rejected by chrono.
+                (second - 59).saturating_mul(1000)
             } else {
-                Err(Error::InvalidDate { year, month, day })
-            }
-        },
-    )(input)
+                0
+            },
+        )
+    }
+    .ok_or(DateError::InvalidTime {
+        hour,
+        minute,
+        second,
+    })?;
+
+    Ok(DateTime::<Utc>::from_utc(dt, Utc).into())
 }
 
 fn digits<T>(input: &[u8]) -> IResult<&[u8], T>
@@ -116,15 +187,6 @@ where
     })(input)
 }
 
-#[derive(Debug, Error)]
-enum Error {
-    #[error("invalid date input: {year}-{month}-{day}")]
-    InvalidDate { year: i32, month: u32, day: u32 },
-
-    #[error("invalid time input: {hour}:{minute}:{second}")]
-    InvalidTime { hour: u32, minute: u32, second: u32 },
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -144,32 +206,120 @@ mod tests {
     #[test]
     fn test_date() -> anyhow::Result<()> {
         // Straight up parse errors.
-        assert_parse_error(b"", date);
-        assert_parse_error(b"not.a.digit.oh.my.word", date);
-        assert_parse_error(b".....", date);
+        assert_parse_error(b"", strict);
+        assert_parse_error(b"not.a.digit.oh.my.word", strict);
+        assert_parse_error(b".....", strict);
 
         // Range errors.
-        assert_parse_error(&build_date_input(2021, 0, 1, 0, 0, 0), date);
-        assert_parse_error(&build_date_input(2021, 13, 1, 0, 0, 0), date);
-        assert_parse_error(&build_date_input(2021, 1, 0, 0, 0, 0), date);
-        assert_parse_error(&build_date_input(2021, 1, 32, 0, 0, 0), date);
-        assert_parse_error(&build_date_input(2021, 1, 1, 24, 0, 0), date);
-        assert_parse_error(&build_date_input(2021, 1, 1, 0, 60, 0), date);
-        assert_parse_error(&build_date_input(2021, 1, 1, 0, 0, 61), date);
+        assert_parse_error(&build_date_input(2021, 0, 1, 0, 0, 0), strict);
+        assert_parse_error(&build_date_input(2021, 13, 1, 0, 0, 0), strict);
+        assert_parse_error(&build_date_input(2021, 1, 0, 0, 0, 0), strict);
+        assert_parse_error(&build_date_input(2021, 1, 32, 0, 0, 0), strict);
+        assert_parse_error(&build_date_input(2021, 1, 1, 24, 0, 0), strict);
+        assert_parse_error(&build_date_input(2021, 1, 1, 0, 60, 0), strict);
+        assert_parse_error(&build_date_input(2021, 1, 1, 0, 0, 61), strict);
+        assert_parse_error(&build_date_input(2021, 1, 1, 0, 0, u32::MAX), strict);
 
         // Actually valid inputs.
         assert_eq!(
-            date(b"2021.08.11.19.08.27")?.1,
+            strict(b"2021.08.11.19.08.27")?.1,
             DateTime::parse_from_rfc3339("2021-08-11T19:08:27+00:00")?.into(),
         );
         assert_eq!(
-            date(b"98.08.11.19.08.27")?.1,
+            strict(b"98.08.11.19.08.27")?.1,
             DateTime::parse_from_rfc3339("1998-08-11T19:08:27+00:00")?.into(),
         );
 
+        // The reason a date was rejected is kept.
+        assert_eq!(
+            strict(b"2021.13.01.00.00.00;"),
+            Err(nom::Err::Failure(ParseError::date(
+                &b"2021.13.01.00.00.00;"[..],
+                DateError::InvalidDate {
+                    year: 2021,
+                    month: 13,
+                    day: 1
+                }
+            )))
+        );
+        assert_eq!(
+            strict(b"2021. 8.11.19.08.27;").unwrap_err(),
+            nom::Err::Failure(ParseError::date(
+                &b"2021. 8.11.19.08.27;"[..],
+                DateError::Syntax
+            ))
+        );
+
         Ok(())
     }
 
+    #[test]
+    fn test_date_quirks() -> anyhow::Result<()> {
+        let expected = |rfc3339| -> anyhow::Result<SystemTime> {
+            Ok(DateTime::parse_from_rfc3339(rfc3339)?.into())
+        };
+
+        // Each quirk is only accepted when it's enabled.
+        for (input, quirks, want) in [
+            (
+                &b"2021. 8. 1. 9.08.27"[..],
+                "padding",
+                "2021-08-01T09:08:27+00:00",
+            ),
+            (
+                b"121.08.11.19.08.27",
+                "tm-years",
+                "2021-08-11T19:08:27+00:00",
+            ),
+            (
+                b"2021.Aug.11.19.08.27",
+                "month-names",
+                "2021-08-11T19:08:27+00:00",
+            ),
+            (
+                b"2021.dec.11.19.08.27",
+                "month-names",
+                "2021-12-11T19:08:27+00:00",
+            ),
+            (
+                b"2021.12.31.23.59.61",
+                "overflow",
+                "2022-01-01T00:00:01+00:00",
+            ),
+            (
+                b"2021.08.11.24.00.00",
+                "overflow",
+                "2021-08-12T00:00:00+00:00",
+            ),
+        ] {
+            assert_eq!(quirk(input, quirks)?.1, expected(want)?, "{:?}", input);
+            assert_eq!(quirk(input, "all")?.1, expected(want)?, "{:?}", input);
+            if quirks != "tm-years" {
+                assert!(strict(input).is_err(), "{:?}", input);
+            }
+        }
+
+        // Without the quirk, a three digit year is taken literally.
+        assert_eq!(
+            strict(b"121.08.11.19.08.27")?.1,
+            expected("0121-08-11T19:08:27+00:00")?
+        );
+
+        // Dates are still validated.
+        assert_parse_error(b"2021.Foo.11.19.08.27", |input| quirk(input, "all"));
+        assert_parse_error(b"2021.02.30.19.08.27", |input| quirk(input, "all"));
+
+        Ok(())
+    }
+
+    fn strict(input: &[u8]) -> IResult<&[u8], SystemTime> {
+        date(input, DateQuirks::default())
+    }
+
+    fn quirk<'a>(input: &'a [u8], quirks: &str) -> IResult<&'a [u8], SystemTime> {
+        date(input, quirks.parse().unwrap())
+    }
+
     fn assert_parse_error<F, T>(input: &[u8], f: F)
     where
         F: Fn(&[u8]) -> IResult<&[u8], T>,
@@ -1,6 +1,10 @@
 use derive_more::{Deref, From, Into};
 use eq_macro::EqU8;
-use std::{collections::HashMap, io::Cursor, time::SystemTime};
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Cursor, Read},
+    time::SystemTime,
+};
 
 use crate::Num;
 
@@ -46,12 +50,41 @@ impl File {
     }
 }
 
+/// An RCS file whose log messages and delta texts are left in the input until
+/// they're needed, so that parsing a large file doesn't copy every revision
+/// out of it, and the input can be mapped from disk rather than read.
+#[derive(Debug, Clone)]
+pub struct LazyFile<'a> {
+    pub admin: Admin,
+    pub delta: HashMap<Num, Delta>,
+    pub desc: Desc,
+    pub delta_text: HashMap<Num, RawDeltaText<'a>>,
+}
+
+impl<'a> LazyFile<'a> {
+    pub fn head(&self) -> Option<&Num> {
+        self.admin.head.as_ref()
+    }
+
+    pub fn revision(&self, revision: &Num) -> Option<(&Delta, &RawDeltaText<'a>)> {
+        if let Some(delta) = self.delta.get(revision) {
+            if let Some(delta_text) = self.delta_text.get(revision) {
+                return Some((delta, delta_text));
+            }
+        }
+
+        None
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Admin {
     pub head: Option<Num>,
     pub branch: Option<Num>,
     pub access: Vec<Id>,
-    pub symbols: HashMap<Sym, Num>,
+    /// The symbols in the order they appear in the file. Malformed files may
+    /// define the same symbol more than once, so duplicates are preserved.
+    pub symbols: Vec<(Sym, Num)>,
     pub locks: HashMap<Id, Num>,
     pub strict: bool,
     pub integrity: Option<IntString>,
@@ -77,6 +110,71 @@ pub struct DeltaText {
     pub text: VString,
 }
 
+/// The delta text of a [`LazyFile`], which borrows from the input.
+#[derive(Debug, Clone, Copy)]
+pub struct RawDeltaText<'a> {
+    pub log: RawString<'a>,
+    pub text: RawString<'a>,
+}
+
+/// A string as it appears in an RCS file, without its delimiting `@`s, but
+/// with each `@` within it still doubled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawString<'a>(pub(crate) &'a [u8]);
+
+impl<'a> RawString<'a> {
+    /// Copies the string out of the input.
+    pub fn to_vstring(&self) -> VString {
+        let mut s = Vec::with_capacity(self.0.len());
+        self.reader()
+            .read_to_end(&mut s)
+            .expect("reading from a slice can't fail");
+        VString(s)
+    }
+
+    /// Returns a reader over the string, which doesn't copy it out of the
+    /// input first.
+    pub fn reader(&self) -> Unescape<'a> {
+        Unescape { rest: self.0 }
+    }
+}
+
+/// Reads a [`RawString`], replacing each `@@` with `@` as it goes.
+#[derive(Debug)]
+pub struct Unescape<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Read for Unescape<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<'a> BufRead for Unescape<'a> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // The parser only accepts strings where every @ is doubled, so an @
+        // here is always the first of a pair, and is returned on its own.
+        Ok(match self.rest.iter().position(|c| *c == b'@') {
+            Some(0) => &self.rest[..1],
+            Some(n) => &self.rest[..n],
+            None => self.rest,
+        })
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if amt > 0 && self.rest.starts_with(b"@") {
+            self.rest = &self.rest[2..];
+        } else {
+            self.rest = &self.rest[amt..];
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default, PartialEq, Eq, EqU8, Deref, From, Into, Hash)]
 pub struct Id(pub Vec<u8>);
 
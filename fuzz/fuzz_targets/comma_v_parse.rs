@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(file) = comma_v::parse(data) {
+        // Walk the revisions in the same way as the importer, since the
+        // numbers themselves come from untrusted input.
+        if let Some(head) = file.head() {
+            let branch = head.to_branch();
+            for (num, delta) in file.delta.iter() {
+                let _ = branch.contains(num);
+                let _ = file.revision(num);
+                for branch in delta.branches.iter() {
+                    let _ = branch.to_branch().contains(num);
+                }
+            }
+        }
+    }
+});
@@ -0,0 +1,18 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rcs_ed::{File, Script};
+
+fuzz_target!(|data: &[u8]| {
+    // The input is split into the original file and the ed script at the first
+    // NUL byte.
+    let split = data.iter().position(|c| *c == 0).unwrap_or(data.len());
+    let (file, script) = data.split_at(split);
+
+    if let (Ok(file), Ok(commands)) = (
+        File::new(file),
+        Script::parse(script.get(1..).unwrap_or_default()).into_command_list(),
+    ) {
+        let _ = file.apply(&commands);
+    }
+});
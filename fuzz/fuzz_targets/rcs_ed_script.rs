@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rcs_ed::Script;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Script::parse(data).into_command_list();
+});
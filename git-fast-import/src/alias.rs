@@ -0,0 +1,42 @@
+use crate::{Command, Error, Mark};
+
+/// An `alias` command, which creates a new mark that refers to the same object
+/// as an existing one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Alias {
+    to: Mark,
+}
+
+impl Alias {
+    /// Constructs an alias of the given mark.
+    pub fn new(to: Mark) -> Self {
+        Self { to }
+    }
+
+    /// Returns the mark being aliased.
+    pub fn to(&self) -> Mark {
+        self.to
+    }
+}
+
+impl Command for Alias {
+    fn write(&self, writer: &mut impl std::io::Write, mark: Mark) -> Result<(), Error> {
+        writeln!(writer, "alias")?;
+        writeln!(writer, "mark {}", mark)?;
+        writeln!(writer, "to {}", self.to)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let mut buf = Vec::new();
+        Alias::new(Mark(2)).write(&mut buf, Mark(5)).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "alias\nmark :5\nto :2\n");
+    }
+}
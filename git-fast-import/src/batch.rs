@@ -0,0 +1,195 @@
+use std::{
+    io::{self, IoSlice, Write},
+    iter,
+};
+
+use crate::{Blob, Mark};
+
+/// The most slices that are passed to a single vectored write. Linux won't
+/// accept more than 1024 in a single `writev` call.
+const MAX_SLICES: usize = 1024;
+
+/// The most blobs that are held in a batch. Each blob takes three slices: its
+/// header, its data, and the newline after its data.
+const MAX_BLOBS: usize = MAX_SLICES / 3;
+
+/// Blob commands that haven't yet been written.
+///
+/// Writing each blob command separately takes several system calls, which adds
+/// up when a repository has millions of small file revisions. Instead, blobs
+/// are held until the batch is full, or until another command needs to be
+/// written, and then the whole batch is written with vectored I/O, without
+/// copying the blob data into a single buffer.
+#[derive(Debug)]
+pub(crate) struct Batch {
+    /// The header and blob of each blob command.
+    blobs: Vec<(Vec<u8>, Blob)>,
+
+    /// The total size of the pending blob commands.
+    size: usize,
+
+    /// The size at which the batch is written. Zero disables batching.
+    limit: usize,
+}
+
+impl Batch {
+    pub(crate) fn new(limit: usize) -> Self {
+        Self {
+            blobs: Vec::new(),
+            size: 0,
+            limit,
+        }
+    }
+
+    pub(crate) fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
+    /// Adds a blob to the batch, returning true if the batch is now full and
+    /// should be written.
+    pub(crate) fn push(&mut self, blob: Blob, mark: Mark) -> bool {
+        let header = format!("blob\nmark {}\ndata {}\n", mark, blob.data().len()).into_bytes();
+
+        self.size += header.len() + blob.data().len() + 1;
+        self.blobs.push((header, blob));
+
+        self.size >= self.limit || self.blobs.len() >= MAX_BLOBS
+    }
+
+    /// Writes any pending blobs to the given writer.
+    pub(crate) fn write(&mut self, writer: &mut impl Write) -> io::Result<()> {
+        if self.blobs.is_empty() {
+            return Ok(());
+        }
+
+        let bufs: Vec<&[u8]> = self
+            .blobs
+            .iter()
+            .flat_map(|(header, blob)| {
+                iter::once(header.as_slice())
+                    .chain(iter::once(blob.data()))
+                    .chain(iter::once(b"\n".as_ref()))
+            })
+            .collect();
+        write_all_vectored(writer, bufs)?;
+
+        self.blobs.clear();
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Writes every buffer to the writer, using as few vectored writes as the
+/// writer allows.
+fn write_all_vectored(writer: &mut impl Write, mut bufs: Vec<&[u8]>) -> io::Result<()> {
+    bufs.retain(|buf| !buf.is_empty());
+
+    let mut start = 0;
+    while start < bufs.len() {
+        let slices: Vec<IoSlice> = bufs[start..]
+            .iter()
+            .take(MAX_SLICES)
+            .map(|buf| IoSlice::new(buf))
+            .collect();
+
+        let mut written = match writer.write_vectored(&slices) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "failed to write whole buffer",
+                ))
+            }
+            Ok(written) => written,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        // Skip the buffers that were written in full, and then the part of the
+        // next buffer that was written, if any.
+        while start < bufs.len() && written >= bufs[start].len() {
+            written -= bufs[start].len();
+            start += 1;
+        }
+        if written > 0 {
+            bufs[start] = &bufs[start][written..];
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A writer that accepts at most `max` bytes from each write, and records
+    /// how many writes were made.
+    #[derive(Debug, Default)]
+    struct ShortWriter {
+        data: Vec<u8>,
+        max: usize,
+        writes: usize,
+    }
+
+    impl Write for ShortWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes += 1;
+            let n = buf.len().min(self.max);
+            self.data.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+            self.writes += 1;
+            let mut n = 0;
+            for buf in bufs {
+                let take = buf.len().min(self.max - n);
+                self.data.extend_from_slice(&buf[..take]);
+                n += take;
+                if n == self.max {
+                    break;
+                }
+            }
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_batch() {
+        let mut batch = Batch::new(64);
+        assert!(!batch.push(Blob::new(b"one"), Mark(1)));
+        assert!(!batch.push(Blob::new(b""), Mark(2)));
+        assert!(batch.push(Blob::new(&[b'x'; 40]), Mark(3)));
+
+        let mut expected = b"blob\nmark :1\ndata 3\none\nblob\nmark :2\ndata 0\n\n".to_vec();
+        expected.extend_from_slice(b"blob\nmark :3\ndata 40\n");
+        expected.extend_from_slice(&[b'x'; 40]);
+        expected.push(b'\n');
+
+        // Short writes must be resumed part way through a buffer.
+        for max in [1, 7, 1024] {
+            let mut batch = Batch::new(64);
+            batch.push(Blob::new(b"one"), Mark(1));
+            batch.push(Blob::new(b""), Mark(2));
+            batch.push(Blob::new(&[b'x'; 40]), Mark(3));
+
+            let mut writer = ShortWriter {
+                max,
+                ..Default::default()
+            };
+            batch.write(&mut writer).unwrap();
+            assert_eq!(writer.data, expected, "max {}", max);
+            if max == 1024 {
+                assert_eq!(writer.writes, 1);
+            }
+
+            // The batch is now empty.
+            batch.write(&mut writer).unwrap();
+            assert_eq!(writer.data, expected, "max {}", max);
+        }
+    }
+}
@@ -1,24 +1,103 @@
+use std::sync::Arc;
+
 use crate::{Command, Error};
 
 /// A `blob` command stores data in the Git repository.
-#[derive(Debug)]
+///
+/// Blobs constructed from a `Vec<u8>` or an `Arc<[u8]>` take or share the data
+/// without copying it, which matters when a blob is held until it's written,
+/// such as in a batch or while being sent to another thread.
+#[derive(Debug, Clone)]
 pub struct Blob {
-    data: Vec<u8>,
+    data: Data,
+}
+
+#[derive(Debug, Clone)]
+enum Data {
+    Owned(Vec<u8>),
+    Shared(Arc<[u8]>),
 }
 
 impl Blob {
-    /// Constructs a new blob from the given data.
+    /// Constructs a new blob from a copy of the given data.
     pub fn new(data: &[u8]) -> Self {
+        Self::from(Vec::from(data))
+    }
+
+    /// Returns the blob content.
+    pub fn data(&self) -> &[u8] {
+        match &self.data {
+            Data::Owned(data) => data,
+            Data::Shared(data) => data,
+        }
+    }
+
+    /// Consumes the blob, returning its content. This only copies the content
+    /// if it's shared.
+    pub fn into_data(self) -> Vec<u8> {
+        match self.data {
+            Data::Owned(data) => data,
+            Data::Shared(data) => data.to_vec(),
+        }
+    }
+}
+
+impl From<Vec<u8>> for Blob {
+    fn from(data: Vec<u8>) -> Self {
         Self {
-            data: Vec::from(data),
+            data: Data::Owned(data),
+        }
+    }
+}
+
+impl From<Arc<[u8]>> for Blob {
+    fn from(data: Arc<[u8]>) -> Self {
+        Self {
+            data: Data::Shared(data),
         }
     }
 }
 
 impl Command for Blob {
     fn write(&self, writer: &mut impl std::io::Write, mark: crate::Mark) -> Result<(), Error> {
-        writeln!(writer, "blob\nmark {}\ndata {}", mark, self.data.len())?;
-        writer.write_all(&self.data)?;
+        let data = self.data();
+        writeln!(writer, "blob\nmark {}\ndata {}", mark, data.len())?;
+        writer.write_all(data)?;
         Ok(writeln!(writer)?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blob_data() {
+        let shared: Arc<[u8]> = Arc::from(&b"shared"[..]);
+        for (blob, want) in [
+            (Blob::new(b"copied"), &b"copied"[..]),
+            (Blob::from(b"owned".to_vec()), b"owned"),
+            (Blob::from(shared.clone()), b"shared"),
+        ] {
+            assert_eq!(blob.data(), want);
+
+            let mut out = Vec::new();
+            blob.write(&mut out, crate::Mark(1)).unwrap();
+            assert_eq!(
+                out,
+                [
+                    format!("blob\nmark :1\ndata {}\n", want.len()).as_bytes(),
+                    want,
+                    b"\n"
+                ]
+                .concat()
+            );
+
+            assert_eq!(blob.into_data(), want);
+        }
+
+        // Blobs built from shared data don't copy it.
+        let blob = Blob::from(shared.clone());
+        assert_eq!(blob.data().as_ptr(), shared.as_ptr());
+    }
+}
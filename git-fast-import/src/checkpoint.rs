@@ -0,0 +1,12 @@
+use crate::{Error, UnmarkedCommand};
+
+/// A `checkpoint` command, which makes fast-import write out its pack file,
+/// refs, and marks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Checkpoint;
+
+impl UnmarkedCommand for Checkpoint {
+    fn write(&self, writer: &mut impl std::io::Write) -> Result<(), Error> {
+        Ok(writeln!(writer, "checkpoint")?)
+    }
+}
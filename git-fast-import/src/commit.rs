@@ -1,7 +1,8 @@
 use std::{
-    fmt::{Display, Write},
+    borrow::Cow,
+    fmt::Display,
     io,
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 use crate::{Command, Error, Identity, Mark};
@@ -13,44 +14,112 @@ pub struct Commit {
     author: Option<Identity>,
     committer: Identity,
     message: String,
-    from: Option<Mark>,
+    from: Option<From>,
     merge: Option<Mark>,
     commands: Vec<FileCommand>,
 }
 
+/// The previous commit that a commit extends from.
+#[derive(Debug, Clone)]
+enum From {
+    Mark(Mark),
+
+    /// An existing commit in the repository, given by its object name.
+    Object(String),
+}
+
 impl Command for Commit {
     fn write(&self, writer: &mut impl io::Write, mark: Mark) -> Result<(), Error> {
-        // Build up a buffer and then write.
-        let mut buf = String::new();
-        writeln!(buf, "commit {}", self.branch_ref)?;
-        writeln!(buf, "mark {}", mark)?;
+        // Commits can have a huge number of file commands, so they're written
+        // as they go rather than being built up in memory first.
+        writeln!(writer, "commit {}", self.branch_ref)?;
+        writeln!(writer, "mark {}", mark)?;
         if let Some(author) = &self.author {
-            writeln!(buf, "author {}", author)?;
+            writeln!(writer, "author {}", author)?;
         }
-        writeln!(buf, "committer {}", self.committer)?;
-        writeln!(buf, "data {}\n{}", self.message.len(), self.message)?;
-        if let Some(from) = &self.from {
-            writeln!(buf, "from {}", from)?;
+        writeln!(writer, "committer {}", self.committer)?;
+        writeln!(writer, "data {}", self.message.len())?;
+        writer.write_all(self.message.as_bytes())?;
+        writeln!(writer)?;
+        match &self.from {
+            Some(From::Mark(mark)) => writeln!(writer, "from {}", mark)?,
+            Some(From::Object(id)) => writeln!(writer, "from {}", id)?,
+            None => {}
         }
         if let Some(merge) = &self.merge {
-            writeln!(buf, "merge {}", merge)?;
+            writeln!(writer, "merge {}", merge)?;
         }
         for command in self.commands.iter() {
-            writeln!(buf, "{}", command)?;
+            command.write(writer)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Commit {
+    /// Returns the ref of the branch the commit is made on.
+    pub fn branch_ref(&self) -> &str {
+        &self.branch_ref
+    }
+
+    /// Returns the commit author, if one was set separately from the
+    /// committer.
+    pub fn author(&self) -> Option<&Identity> {
+        self.author.as_ref()
+    }
+
+    /// Returns the commit committer.
+    pub fn committer(&self) -> &Identity {
+        &self.committer
+    }
+
+    /// Returns the commit message.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Returns the previous commit that this commit extends from, if any.
+    ///
+    /// Commits that extend from an existing commit outside the import return
+    /// `None`: see [`from_object()`][Self::from_object].
+    pub fn from(&self) -> Option<Mark> {
+        match &self.from {
+            Some(From::Mark(mark)) => Some(*mark),
+            _ => None,
         }
+    }
 
-        Ok(write!(writer, "{}", buf)?)
+    /// Returns the object name of the existing commit that this commit
+    /// extends from, if it was set with
+    /// [`CommitBuilder::from_object()`].
+    pub fn from_object(&self) -> Option<&str> {
+        match &self.from {
+            Some(From::Object(id)) => Some(id),
+            _ => None,
+        }
+    }
+
+    /// Returns the commit that is merged into this commit, if any.
+    pub fn merge(&self) -> Option<Mark> {
+        self.merge
+    }
+
+    /// Iterates over the file commands in the commit, in the order they were
+    /// added.
+    pub fn file_command_iter(&self) -> impl Iterator<Item = &FileCommand> {
+        self.commands.iter()
     }
 }
 
 /// A builder to create a [`Commit`].
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CommitBuilder {
     branch_ref: String,
     author: Option<Identity>,
     committer: Option<Identity>,
     message: Option<String>,
-    from: Option<Mark>,
+    from: Option<From>,
     merge: Option<Mark>,
     commands: Vec<FileCommand>,
 }
@@ -91,7 +160,15 @@ impl CommitBuilder {
     ///
     /// Note that this is _not_ an implementation of the `From` trait.
     pub fn from(&mut self, from: Mark) -> &mut Self {
-        self.from = Some(from);
+        self.from = Some(From::Mark(from));
+        self
+    }
+
+    /// Sets the previous commit that this commit extends from to an existing
+    /// commit in the repository, given by its full object name, rather than a
+    /// commit sent during the import.
+    pub fn from_object(&mut self, id: String) -> &mut Self {
+        self.from = Some(From::Object(id));
         self
     }
 
@@ -163,6 +240,56 @@ pub enum FileCommand {
     DeleteAll,
 }
 
+impl FileCommand {
+    /// Writes the command in wire format. Unlike [`Display`], paths are
+    /// written as their raw bytes, so paths that aren't valid UTF-8 are
+    /// preserved.
+    fn write(&self, writer: &mut impl io::Write) -> io::Result<()> {
+        match self {
+            FileCommand::Modify { mode, mark, path } => {
+                write!(writer, "M {} {} ", mode, mark)?;
+                writer.write_all(&path_bytes(path))?;
+            }
+            FileCommand::Delete { path } => {
+                writer.write_all(b"D ")?;
+                writer.write_all(&path_bytes(path))?;
+            }
+            FileCommand::Copy { from, to } => {
+                writer.write_all(b"C ")?;
+                writer.write_all(&path_bytes(from))?;
+                writer.write_all(b" ")?;
+                writer.write_all(&path_bytes(to))?;
+            }
+            FileCommand::Rename { from, to } => {
+                writer.write_all(b"R ")?;
+                writer.write_all(&path_bytes(from))?;
+                writer.write_all(b" ")?;
+                writer.write_all(&path_bytes(to))?;
+            }
+            FileCommand::DeleteAll => writer.write_all(b"deleteall")?,
+        }
+        writeln!(writer)
+    }
+}
+
+/// Returns the raw bytes of a path.
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+
+    Cow::Borrowed(path.as_os_str().as_bytes())
+}
+
+/// Returns the bytes of a path, replacing anything that isn't valid Unicode,
+/// since other platforms don't expose the raw bytes.
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    match path.to_string_lossy() {
+        Cow::Borrowed(path) => Cow::Borrowed(path.as_bytes()),
+        Cow::Owned(path) => Cow::Owned(path.into_bytes()),
+    }
+}
+
 impl Display for FileCommand {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -200,3 +327,51 @@ impl Display for Mode {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+
+    // Paths that aren't valid Unicode can only be built on Unix.
+    #[cfg(unix)]
+    #[test]
+    fn test_write() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut builder = CommitBuilder::new("refs/heads/main".into());
+        builder
+            .committer(
+                Identity::new(None, "adam".into(), UNIX_EPOCH + Duration::from_secs(1000)).unwrap(),
+            )
+            .message("message\n".into())
+            .from(Mark(1))
+            .add_file_command(FileCommand::Modify {
+                mode: Mode::Normal,
+                mark: Mark(2),
+                path: "dir/a.txt".into(),
+            })
+            .add_file_command(FileCommand::Delete {
+                path: std::ffi::OsStr::from_bytes(b"caf\xe9.txt").into(),
+            })
+            .add_file_command(FileCommand::Rename {
+                from: "b".into(),
+                to: "c".into(),
+            });
+
+        let mut buf = Vec::new();
+        builder.build().unwrap().write(&mut buf, Mark(3)).unwrap();
+        assert_eq!(
+            buf,
+            b"commit refs/heads/main\n\
+              mark :3\n\
+              committer <adam> 1000 +0000\n\
+              data 8\nmessage\n\n\
+              from :1\n\
+              M 100644 :2 dir/a.txt\n\
+              D caf\xe9.txt\n\
+              R b c\n"
+        );
+    }
+}
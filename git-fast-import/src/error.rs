@@ -5,6 +5,9 @@ pub enum Error {
     #[error(transparent)]
     Fmt(#[from] std::fmt::Error),
 
+    #[error("invalid identity {0:?}: names and e-mails cannot contain <, >, or newlines")]
+    InvalidIdentity(String),
+
     #[error(transparent)]
     Io(#[from] std::io::Error),
 
@@ -16,4 +19,7 @@ pub enum Error {
 
     #[error("a commit message must be provided")]
     MissingCommitMessage,
+
+    #[error(transparent)]
+    Time(#[from] std::time::SystemTimeError),
 }
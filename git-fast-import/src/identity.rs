@@ -1,7 +1,10 @@
-use std::{
-    fmt::Display,
-    time::{SystemTime, SystemTimeError},
-};
+use std::{fmt::Display, time::SystemTime};
+
+use crate::Error;
+
+/// The characters that can't appear in the name or e-mail of an identity,
+/// since they delimit the fields of an identity in the fast-import format.
+const ILLEGAL: &[char] = &['<', '>', '\n'];
 
 /// A Git identity.
 ///
@@ -17,17 +20,84 @@ pub struct Identity {
 
 impl Identity {
     /// Constructs a new identity.
-    pub fn new(
-        name: Option<String>,
-        email: String,
-        when: SystemTime,
-    ) -> Result<Self, SystemTimeError> {
+    ///
+    /// The name and e-mail are rejected if they contain characters that can't
+    /// appear in an identity: see [`Identity::is_valid`].
+    pub fn new(name: Option<String>, email: String, when: SystemTime) -> Result<Self, Error> {
+        for part in name.iter().chain(std::iter::once(&email)) {
+            if !Self::is_valid(part) {
+                return Err(Error::InvalidIdentity(part.clone()));
+            }
+        }
+
         Ok(Self {
             name,
             email,
             when: when.duration_since(SystemTime::UNIX_EPOCH)?.as_secs(),
         })
     }
+
+    /// Constructs a new identity, removing any characters from the name and
+    /// e-mail that can't appear in an identity, as `git` itself does.
+    pub fn sanitized(name: Option<&str>, email: &str, when: SystemTime) -> Result<Self, Error> {
+        Self::new(name.map(sanitize), sanitize(email), when)
+    }
+
+    /// Parses an identity in the form `Name <email>`. The name is optional,
+    /// and a value without angle brackets is treated as a bare e-mail, such as
+    /// the username that CVS records as the author of each revision.
+    pub fn parse(value: &str, when: SystemTime) -> Result<Self, Error> {
+        let invalid = || Error::InvalidIdentity(value.to_string());
+
+        // Check for newlines before trimming, since they'd otherwise be trimmed
+        // away rather than rejected.
+        if value.contains('\n') {
+            return Err(invalid());
+        }
+
+        let value = value.trim();
+        match value.strip_suffix('>') {
+            Some(rest) => {
+                let (name, email) = rest.split_once('<').ok_or_else(invalid)?;
+                let name = name.trim();
+                Self::new(
+                    if name.is_empty() {
+                        None
+                    } else {
+                        Some(name.to_string())
+                    },
+                    email.to_string(),
+                    when,
+                )
+            }
+            None => Self::new(None, value.to_string(), when),
+        }
+        .map_err(|e| match e {
+            Error::InvalidIdentity(_) => invalid(),
+            e => e,
+        })
+    }
+
+    /// Returns true if the value can be used as the name or e-mail of an
+    /// identity.
+    pub fn is_valid(value: &str) -> bool {
+        !value.contains(ILLEGAL)
+    }
+
+    /// Returns the real name of the identity, if any.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns the e-mail of the identity.
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+
+    /// Returns the time of the action, in seconds since the Unix epoch.
+    pub fn when(&self) -> u64 {
+        self.when
+    }
 }
 
 impl Display for Identity {
@@ -38,3 +108,72 @@ impl Display for Identity {
         write!(f, "<{}> {} +0000", self.email, self.when)
     }
 }
+
+fn sanitize(value: &str) -> String {
+    value.replace(ILLEGAL, "")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn test_parse() {
+        for (value, name, email) in [
+            ("adam", None, "adam"),
+            ("<adam@example.com>", None, "adam@example.com"),
+            (
+                "Adam Harvey <adam@example.com>",
+                Some("Adam Harvey"),
+                "adam@example.com",
+            ),
+            ("  Adam   <adam> ", Some("Adam"), "adam"),
+            ("Adam <>", Some("Adam"), ""),
+        ] {
+            let identity = Identity::parse(value, at(1000)).unwrap();
+            assert_eq!(identity.name(), name, "{:?}", value);
+            assert_eq!(identity.email(), email, "{:?}", value);
+            assert_eq!(identity.when(), 1000);
+        }
+
+        for value in [
+            "Adam adam>",
+            "Adam <adam",
+            "Adam <<adam>",
+            "A<dam <adam>",
+            "Adam\n <adam>",
+            "ad\nam",
+            "adam\n",
+        ] {
+            assert!(
+                matches!(
+                    Identity::parse(value, at(1000)),
+                    Err(Error::InvalidIdentity(v)) if v == value
+                ),
+                "{:?}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(Identity::new(Some("Adam".into()), "adam".into(), at(0)).is_ok());
+        assert!(Identity::new(Some("Adam <".into()), "adam".into(), at(0)).is_err());
+        assert!(Identity::new(None, "adam>".into(), at(0)).is_err());
+        assert!(matches!(
+            Identity::new(None, "adam".into(), UNIX_EPOCH - Duration::from_secs(1)),
+            Err(Error::Time(_))
+        ));
+
+        let identity =
+            Identity::sanitized(Some("A <d>\nam"), "a>dam\n@example.com", at(1000)).unwrap();
+        assert_eq!(identity.to_string(), "A dam <adam@example.com> 1000 +0000");
+    }
+}
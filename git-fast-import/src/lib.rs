@@ -3,9 +3,20 @@
 
 use std::{fmt::Debug, fs::File, io::Write, path::Path};
 
+use derive_more::From;
+
+mod alias;
+pub use alias::Alias;
+
+mod batch;
+use batch::Batch;
+
 mod blob;
 pub use blob::Blob;
 
+mod checkpoint;
+pub use checkpoint::Checkpoint;
+
 mod commit;
 pub use commit::{Commit, CommitBuilder, FileCommand, Mode};
 
@@ -19,10 +30,25 @@ mod mark;
 pub use mark::Mark;
 
 mod mark_file;
+pub use mark_file::{get_last_mark, read_marks};
+
+mod progress;
+pub use progress::Progress;
+
+mod reset;
+pub use reset::Reset;
 
 mod tag;
 pub use tag::Tag;
 
+/// The null object name, which deletes a ref when used as the `from` of a
+/// `reset` command.
+pub const NULL_SHA: &str = "0000000000000000000000000000000000000000";
+
+/// The default size at which batched blobs are written: see
+/// [`Writer::set_batch_size`].
+pub const DEFAULT_BATCH_SIZE: usize = 1024 * 1024;
+
 /// A writer that writes data in the [git-fast-import command
 /// format](https://git-scm.com/docs/git-fast-import).
 ///
@@ -37,6 +63,15 @@ pub use tag::Tag;
 /// As this type does not provide a way of specifying arbitrary features outside
 /// of naming the mark file, this is safe provided the mark file path is _not_
 /// user controlled.
+///
+/// Callers that manage marks themselves, such as when the commands are written
+/// to a file rather than to `git fast-import`, can use
+/// [`Writer::with_next_mark`] instead, which doesn't use a mark file, and
+/// [`Writer::finish`] to learn where the next writer should continue from.
+///
+/// Blobs sent with [`Writer::blob`] are batched, and written together once
+/// [`DEFAULT_BATCH_SIZE`] bytes are pending, before any other command is sent,
+/// or when [`Writer::flush`] is called.
 #[derive(Debug)]
 pub struct Writer<W>
 where
@@ -44,6 +79,10 @@ where
 {
     writer: W,
     next_mark: usize,
+    batch: Batch,
+
+    /// Whether the `done` command has been sent by [`Writer::finish`].
+    finished: bool,
 }
 
 impl<W> Writer<W>
@@ -65,20 +104,40 @@ where
             // default initial mark of 1 if we can't open it.
             next_mark: if let Ok(file) = File::open(&mark_file) {
                 let last_mark = mark_file::get_last_mark(&file)?;
-                last_mark.map(|mark| mark.0 + 1).unwrap_or(1)
+                last_mark.map(|mark| mark.next().0).unwrap_or(1)
             } else {
                 1
             },
+            batch: Batch::new(DEFAULT_BATCH_SIZE),
+            finished: false,
         }
         .send_generic_header()?
         .send_mark_header(mark_file)
     }
 
+    /// Constructs a new git-fast-import writer that wraps the given writer
+    /// without a mark file, creating marks from `next_mark` onwards.
+    ///
+    /// Marks start at 1, so a `next_mark` of 0 is treated as 1. As with
+    /// [`Writer::new`], `writer` must be ready to receive commands
+    /// immediately.
+    pub fn with_next_mark(writer: W, next_mark: usize) -> Result<Self, Error> {
+        Self {
+            writer,
+            next_mark: next_mark.max(1),
+            batch: Batch::new(DEFAULT_BATCH_SIZE),
+            finished: false,
+        }
+        .send_generic_header()
+    }
+
     /// Sends a command that returns a mark to fast-import.
     pub fn command<C>(&mut self, command: C) -> Result<Mark, Error>
     where
         C: Command,
     {
+        self.batch.write(&mut self.writer)?;
+
         let mark = Mark(self.next_mark);
         self.next_mark += 1;
 
@@ -86,24 +145,86 @@ where
         Ok(mark)
     }
 
+    /// Queues a `blob` command, which is written along with the other blobs
+    /// in the same batch.
+    ///
+    /// The mark is returned immediately, and can be used in later commands:
+    /// the batch is always written before any other command.
+    pub fn blob(&mut self, blob: Blob) -> Result<Mark, Error> {
+        let mark = Mark(self.next_mark);
+        self.next_mark += 1;
+
+        if self.batch.push(blob, mark) {
+            self.batch.write(&mut self.writer)?;
+        }
+        Ok(mark)
+    }
+
+    /// Sets the size in bytes at which batched blobs are written. A size of
+    /// zero writes each blob immediately.
+    pub fn set_batch_size(&mut self, size: usize) {
+        self.batch.set_limit(size);
+    }
+
+    /// Writes any batched blobs, and then flushes the underlying writer.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.batch.write(&mut self.writer)?;
+        Ok(self.writer.flush()?)
+    }
+
+    /// Sends a command that doesn't return a mark to fast-import.
+    pub fn send<C>(&mut self, command: C) -> Result<(), Error>
+    where
+        C: UnmarkedCommand,
+    {
+        self.batch.write(&mut self.writer)?;
+        command.write(&mut self.writer)
+    }
+
+    /// Sends each of the given commands to fast-import in order, returning the
+    /// mark of each command that creates one.
+    pub fn send_all<I>(&mut self, commands: I) -> Result<Vec<Option<Mark>>, Error>
+    where
+        I: IntoIterator<Item = AnyCommand>,
+    {
+        commands
+            .into_iter()
+            .map(|command| match command {
+                AnyCommand::Alias(alias) => self.command(alias).map(Some),
+                AnyCommand::Blob(blob) => self.blob(blob).map(Some),
+                AnyCommand::Checkpoint(checkpoint) => self.send(checkpoint).map(|_| None),
+                AnyCommand::Commit(commit) => self.command(commit).map(Some),
+                AnyCommand::Progress(progress) => self.send(progress).map(|_| None),
+                AnyCommand::Reset(reset) => self.send(reset).map(|_| None),
+                AnyCommand::Tag(tag) => self.command(tag).map(Some),
+            })
+            .collect()
+    }
+
+    /// Sends an `alias` command to fast-import, returning a new mark that
+    /// refers to the same object as `to`.
+    pub fn alias(&mut self, to: Mark) -> Result<Mark, Error> {
+        self.command(Alias::new(to))
+    }
+
     /// Sends a `checkpoint` command to fast-import.
     pub fn checkpoint(&mut self) -> Result<(), Error> {
-        Ok(writeln!(self.writer, "checkpoint")?)
+        self.send(Checkpoint)
     }
 
     /// Sends a `progress` command to fast-import.
     pub fn progress(&mut self, message: &str) -> Result<(), Error> {
-        Ok(writeln!(self.writer, "progress {}", message)?)
+        self.send(Progress::new(message.to_string()))
     }
 
     /// Sends a `reset` command to fast-import.
     pub fn reset(&mut self, branch_ref: &str, from: Option<Mark>) -> Result<(), Error> {
-        writeln!(self.writer, "reset {}", branch_ref)?;
-        if let Some(from) = from {
-            writeln!(self.writer, "from {}", from)?;
-        }
+        self.send(Reset::new(branch_ref.to_string(), from))
+    }
 
-        Ok(())
+    /// Sends a `reset` command to fast-import that deletes the given ref.
+    pub fn delete(&mut self, branch_ref: &str) -> Result<(), Error> {
+        self.send(Reset::delete(branch_ref.to_string()))
     }
 
     /// Returns the next mark that will be created.
@@ -111,6 +232,20 @@ where
         self.next_mark
     }
 
+    /// Writes any batched blobs and the `done` command, returning the next
+    /// mark that would have been created, which can be given to
+    /// [`Writer::with_next_mark`] to continue with a later writer.
+    ///
+    /// Unlike dropping the writer, errors are returned rather than ignored.
+    pub fn finish(mut self) -> Result<usize, Error> {
+        self.batch.write(&mut self.writer)?;
+        writeln!(self.writer, "done")?;
+        self.writer.flush()?;
+        self.finished = true;
+
+        Ok(self.next_mark)
+    }
+
     fn send_generic_header(mut self) -> Result<Self, Error> {
         writeln!(self.writer, "feature done")?;
         writeln!(self.writer, "feature date-format=raw")?;
@@ -136,7 +271,15 @@ where
     W: Write + Debug,
 {
     fn drop(&mut self) {
-        writeln!(self.writer, "done").unwrap();
+        if self.finished {
+            return;
+        }
+
+        // This is only a best effort: there's no way to report an error from
+        // here, and panicking while already unwinding would abort, so callers
+        // that need to know that the stream is complete use finish() instead.
+        let _ = self.batch.write(&mut self.writer);
+        let _ = writeln!(self.writer, "done");
     }
 }
 
@@ -145,3 +288,145 @@ pub trait Command {
     /// A function that writes the command in wire format to the given writer.
     fn write(&self, writer: &mut impl Write, mark: Mark) -> Result<(), Error>;
 }
+
+/// A `git fast-import` command that doesn't return a mark.
+pub trait UnmarkedCommand {
+    /// A function that writes the command in wire format to the given writer.
+    fn write(&self, writer: &mut impl Write) -> Result<(), Error>;
+}
+
+/// Any `git fast-import` command that [`Writer`] can send, which allows
+/// sequences of different commands to be built up and then sent with
+/// [`Writer::send_all`].
+#[derive(Debug, From)]
+pub enum AnyCommand {
+    Alias(Alias),
+    Blob(Blob),
+    Checkpoint(Checkpoint),
+    Commit(Commit),
+    Progress(Progress),
+    Reset(Reset),
+    Tag(Tag),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+
+    #[test]
+    fn test_send_all() {
+        let mut out = Vec::new();
+        {
+            let mut writer = Writer::new(&mut out, "/nonexistent/marks").unwrap();
+
+            let mut builder = CommitBuilder::new("refs/heads/main".into());
+            builder
+                .committer(
+                    Identity::new(None, "adam".into(), UNIX_EPOCH + Duration::from_secs(1000))
+                        .unwrap(),
+                )
+                .message("message\n".into())
+                .add_file_command(FileCommand::Modify {
+                    mode: Mode::Normal,
+                    mark: Mark(1),
+                    path: "file".into(),
+                });
+            let mut grafted = CommitBuilder::new("refs/heads/grafted".into());
+            grafted
+                .committer(
+                    Identity::new(None, "adam".into(), UNIX_EPOCH + Duration::from_secs(1000))
+                        .unwrap(),
+                )
+                .message("graft\n".into())
+                .from_object("0123456789012345678901234567890123456789".into());
+
+            let marks = writer
+                .send_all(vec![
+                    Blob::new(b"content").into(),
+                    Progress::new("blob sent".into()).into(),
+                    builder.build().unwrap().into(),
+                    grafted.build().unwrap().into(),
+                    Reset::new("refs/heads/other".into(), Some(Mark(2))).into(),
+                    Reset::delete("refs/heads/old".into()).into(),
+                    Checkpoint.into(),
+                ])
+                .unwrap();
+            assert_eq!(
+                marks,
+                vec![
+                    Some(Mark(1)),
+                    None,
+                    Some(Mark(2)),
+                    Some(Mark(3)),
+                    None,
+                    None,
+                    None
+                ]
+            );
+        }
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "feature done\n\
+                 feature date-format=raw\n\
+                 feature import-marks-if-exists=/nonexistent/marks\n\
+                 feature export-marks=/nonexistent/marks\n\
+                 blob\nmark :1\ndata 7\ncontent\n\
+                 progress blob sent\n\
+                 commit refs/heads/main\nmark :2\ncommitter <adam> 1000 +0000\ndata 8\nmessage\n\n\
+                 M 100644 :1 file\n\
+                 commit refs/heads/grafted\nmark :3\ncommitter <adam> 1000 +0000\ndata 6\ngraft\n\n\
+                 from 0123456789012345678901234567890123456789\n\
+                 reset refs/heads/other\nfrom :2\n\
+                 reset refs/heads/old\nfrom {}\n\
+                 checkpoint\n\
+                 done\n",
+                NULL_SHA
+            )
+        );
+    }
+
+    #[test]
+    fn test_next_mark() {
+        let mut out = Vec::new();
+
+        let mut writer = Writer::with_next_mark(&mut out, 0).unwrap();
+        assert_eq!(writer.blob(Blob::new(b"one")).unwrap(), Mark(1));
+        assert_eq!(writer.finish().unwrap(), 2);
+
+        let mut writer = Writer::with_next_mark(&mut out, 2).unwrap();
+        assert_eq!(writer.blob(Blob::new(b"two")).unwrap(), Mark(2));
+        assert_eq!(writer.blob(Blob::new(b"three")).unwrap(), Mark(3));
+        assert_eq!(writer.next_mark(), 4);
+        drop(writer);
+
+        // Neither writer sends mark features, and each sends done once.
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "feature done\n\
+             feature date-format=raw\n\
+             blob\nmark :1\ndata 3\none\n\
+             done\n\
+             feature done\n\
+             feature date-format=raw\n\
+             blob\nmark :2\ndata 3\ntwo\n\
+             blob\nmark :3\ndata 5\nthree\n\
+             done\n"
+        );
+    }
+
+    #[test]
+    fn test_drop_ignores_errors() {
+        // The buffer only has room for the header, so everything written after
+        // it fails.
+        let mut out = [0u8; 37];
+        let mut writer = Writer::with_next_mark(&mut out[..], 0).unwrap();
+
+        // The blob is batched, so it's only written when the writer is dropped.
+        assert_eq!(writer.blob(Blob::new(b"content")).unwrap(), Mark(1));
+        drop(writer);
+    }
+}
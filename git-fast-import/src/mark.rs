@@ -28,6 +28,11 @@ impl Mark {
     pub fn as_usize(&self) -> usize {
         self.0
     }
+
+    /// Returns the mark that follows this one.
+    pub fn next(&self) -> Self {
+        Self(self.0 + 1)
+    }
 }
 
 impl Display for Mark {
@@ -1,5 +1,6 @@
 use std::{
-    io::{BufReader, Read, Seek},
+    collections::BTreeMap,
+    io::{BufRead, BufReader, Read, Seek},
     num::ParseIntError,
     str::FromStr,
 };
@@ -7,15 +8,17 @@ use std::{
 use nom::{
     bytes::complete::tag,
     character::complete::{alphanumeric1, digit1, multispace1},
-    combinator::map_res,
-    sequence::{delimited, terminated},
+    combinator::{all_consuming, map_res},
+    sequence::{delimited, pair, terminated},
     Finish, IResult,
 };
 use rev_lines::RevLines;
 
 use crate::{Error, Mark};
 
-pub(crate) fn get_last_mark<R>(reader: R) -> Result<Option<Mark>, Error>
+/// Returns the last mark in a mark file exported by `git fast-import`, which
+/// [`Writer::new`](crate::Writer::new) continues from.
+pub fn get_last_mark<R>(reader: R) -> Result<Option<Mark>, Error>
 where
     R: Read + Seek,
 {
@@ -33,6 +36,37 @@ where
     }
 }
 
+/// Reads every mark in a mark file exported by `git fast-import`, returning a
+/// map of each mark to the object name it refers to.
+pub fn read_marks<R>(reader: R) -> Result<BTreeMap<Mark, String>, Error>
+where
+    R: BufRead,
+{
+    let mut marks = BTreeMap::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+
+        let (_, (mark, sha)) = Finish::finish(all_consuming(mark_sha_line)(&line))
+            .map_err(|e| Error::MarkParsingError(e.code))?;
+        marks.insert(mark, sha.to_string());
+    }
+
+    Ok(marks)
+}
+
+fn mark_sha_line(input: &str) -> IResult<&str, (Mark, &str)> {
+    pair(
+        map_res(
+            delimited(tag(":"), digit1, multispace1),
+            |raw| -> Result<Mark, ParseIntError> { Mark::from_str(raw) },
+        ),
+        alphanumeric1,
+    )(input)
+}
+
 fn mark_line(input: &str) -> IResult<&str, Mark> {
     map_res(
         terminated(delimited(tag(":"), digit1, multispace1), alphanumeric1),
@@ -77,4 +111,25 @@ mod tests {
         assert_get_last_mark_error!(b":25 \n");
         assert_get_last_mark_error!(b"25 xx");
     }
+
+    #[test]
+    fn test_read_marks() {
+        assert!(read_marks(Cursor::new(b"")).unwrap().is_empty());
+        assert_eq!(
+            read_marks(Cursor::new(
+                b":1 0123456789012345678901234567890123456789\n\n:25 abcdefabcdefabcdefabcdefabcdefabcdefabcd\n"
+            ))
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>(),
+            vec![
+                (Mark(1), "0123456789012345678901234567890123456789".to_string()),
+                (Mark(25), "abcdefabcdefabcdefabcdefabcdefabcdefabcd".to_string()),
+            ]
+        );
+
+        assert!(read_marks(Cursor::new(b"not a mark")).is_err());
+        assert!(read_marks(Cursor::new(b":25")).is_err());
+        assert!(read_marks(Cursor::new(b":25 xx yy")).is_err());
+    }
 }
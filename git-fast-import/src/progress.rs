@@ -0,0 +1,27 @@
+use crate::{Error, UnmarkedCommand};
+
+/// A `progress` command, which fast-import echoes to its standard output once
+/// the commands before it have been processed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Progress {
+    message: String,
+}
+
+impl Progress {
+    /// Constructs a progress command with the given message, which must not
+    /// contain a newline.
+    pub fn new(message: String) -> Self {
+        Self { message }
+    }
+
+    /// Returns the progress message.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl UnmarkedCommand for Progress {
+    fn write(&self, writer: &mut impl std::io::Write) -> Result<(), Error> {
+        Ok(writeln!(writer, "progress {}", self.message)?)
+    }
+}
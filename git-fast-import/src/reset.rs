@@ -0,0 +1,99 @@
+use crate::{Error, Mark, UnmarkedCommand, NULL_SHA};
+
+/// A `reset` command, which points a branch or tag at a commit, or deletes it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reset {
+    branch_ref: String,
+    from: From,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum From {
+    /// The ref is reset without a commit, so the next commit on it has no
+    /// parent.
+    Nothing,
+
+    Mark(Mark),
+
+    /// The ref is deleted, using the null object name.
+    Null,
+}
+
+impl Reset {
+    /// Constructs a reset of the given ref to `from`, or to no commit.
+    pub fn new(branch_ref: String, from: Option<Mark>) -> Self {
+        Self {
+            branch_ref,
+            from: match from {
+                Some(mark) => From::Mark(mark),
+                None => From::Nothing,
+            },
+        }
+    }
+
+    /// Constructs a reset that deletes the given ref.
+    pub fn delete(branch_ref: String) -> Self {
+        Self {
+            branch_ref,
+            from: From::Null,
+        }
+    }
+
+    /// Returns the ref being reset.
+    pub fn branch_ref(&self) -> &str {
+        &self.branch_ref
+    }
+
+    /// Returns the mark of the commit the ref is reset to, if any.
+    pub fn from(&self) -> Option<Mark> {
+        match self.from {
+            From::Mark(mark) => Some(mark),
+            From::Nothing | From::Null => None,
+        }
+    }
+
+    /// Returns true if the reset deletes the ref.
+    pub fn is_delete(&self) -> bool {
+        self.from == From::Null
+    }
+}
+
+impl UnmarkedCommand for Reset {
+    fn write(&self, writer: &mut impl std::io::Write) -> Result<(), Error> {
+        writeln!(writer, "reset {}", self.branch_ref)?;
+        match self.from {
+            From::Nothing => {}
+            From::Mark(mark) => writeln!(writer, "from {}", mark)?,
+            From::Null => writeln!(writer, "from {}", NULL_SHA)?,
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wire(reset: Reset) -> String {
+        let mut buf = Vec::new();
+        reset.write(&mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_write() {
+        assert_eq!(
+            wire(Reset::new("refs/heads/main".into(), None)),
+            "reset refs/heads/main\n"
+        );
+        assert_eq!(
+            wire(Reset::new("refs/heads/main".into(), Some(Mark(4)))),
+            "reset refs/heads/main\nfrom :4\n"
+        );
+        assert_eq!(
+            wire(Reset::delete("refs/tags/old".into())),
+            format!("reset refs/tags/old\nfrom {}\n", NULL_SHA)
+        );
+    }
+}
@@ -19,6 +19,26 @@ impl Tag {
             message,
         }
     }
+
+    /// Returns the tag name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the mark of the tagged commit.
+    pub fn from(&self) -> Mark {
+        self.from
+    }
+
+    /// Returns the tagger identity.
+    pub fn tagger(&self) -> &Identity {
+        &self.tagger
+    }
+
+    /// Returns the tag message.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
 }
 
 impl Command for Tag {
@@ -0,0 +1,126 @@
+use std::{fmt::Debug, io::Write, str::FromStr};
+
+use git_fast_import::{Blob, Commit, Mark, Tag, Writer};
+use thiserror::Error;
+
+use crate::Error;
+
+/// The backends that can be used to write to the Git repository.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// Spawns `git fast-import` and streams commands to it.
+    FastImport,
+
+    /// Writes objects and refs directly using gitoxide, without requiring a
+    /// `git` binary.
+    Gitoxide,
+}
+
+impl Backend {
+    pub(crate) const VARIANTS: &'static [&'static str] = &["fast-import", "gitoxide"];
+}
+
+impl FromStr for Backend {
+    type Err = UnknownBackendError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fast-import" => Ok(Self::FastImport),
+            "gitoxide" => Ok(Self::Gitoxide),
+            _ => Err(UnknownBackendError(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("unknown backend: {0}")]
+pub struct UnknownBackendError(String);
+
+/// `Importer` is implemented by each backend, and receives the commands sent
+/// to the [`Output`][crate::Output] worker.
+pub(crate) trait Importer {
+    /// Returns a new mark that refers to the same object as `to`.
+    fn alias(&mut self, to: Mark) -> Result<Mark, Error>;
+
+    fn blob(&mut self, blob: Blob) -> Result<Mark, Error>;
+
+    fn checkpoint(&mut self) -> Result<(), Error>;
+
+    fn commit(&mut self, commit: Commit) -> Result<Mark, Error>;
+
+    fn delete(&mut self, branch_ref: &str) -> Result<(), Error>;
+
+    fn progress(&mut self, message: &str) -> Result<(), Error>;
+
+    fn reset(&mut self, branch_ref: &str, from: Option<Mark>) -> Result<(), Error>;
+
+    fn tag(&mut self, tag: Tag) -> Result<Mark, Error>;
+
+    /// Completes the import: once this returns, all objects and refs must have
+    /// been written, along with the mark file.
+    fn finish(self) -> Result<(), Error>;
+}
+
+impl<W> Importer for Writer<W>
+where
+    W: Write + Debug,
+{
+    fn alias(&mut self, to: Mark) -> Result<Mark, Error> {
+        Ok(Writer::alias(self, to)?)
+    }
+
+    fn blob(&mut self, blob: Blob) -> Result<Mark, Error> {
+        Ok(Writer::blob(self, blob)?)
+    }
+
+    fn checkpoint(&mut self) -> Result<(), Error> {
+        // The checkpoint has to actually reach fast-import to have any effect,
+        // rather than waiting in a buffer.
+        Writer::checkpoint(self)?;
+        Ok(self.flush()?)
+    }
+
+    fn commit(&mut self, commit: Commit) -> Result<Mark, Error> {
+        Ok(self.command(commit)?)
+    }
+
+    fn delete(&mut self, branch_ref: &str) -> Result<(), Error> {
+        Ok(Writer::delete(self, branch_ref)?)
+    }
+
+    fn progress(&mut self, message: &str) -> Result<(), Error> {
+        // As with checkpoints, progress is only useful if it's timely.
+        Writer::progress(self, message)?;
+        Ok(self.flush()?)
+    }
+
+    fn reset(&mut self, branch_ref: &str, from: Option<Mark>) -> Result<(), Error> {
+        Ok(Writer::reset(self, branch_ref, from)?)
+    }
+
+    fn tag(&mut self, tag: Tag) -> Result<Mark, Error> {
+        Ok(self.command(tag)?)
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        // This sends the done command and flushes the writer; the caller still
+        // needs to wait for git fast-import to exit.
+        Writer::finish(self)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backend_from_str() {
+        assert_eq!(
+            Backend::from_str("fast-import").unwrap(),
+            Backend::FastImport
+        );
+        assert_eq!(Backend::from_str("gitoxide").unwrap(), Backend::Gitoxide);
+        assert!(Backend::from_str("libgit2").is_err());
+    }
+}
@@ -0,0 +1,65 @@
+use std::{
+    ffi::OsStr,
+    fmt::Display,
+    os::unix::prelude::OsStrExt,
+    process::{Command, Output},
+};
+
+use crate::Opt;
+
+/// Runs git in the repository with the given arguments, capturing its output.
+pub(crate) fn git<I, S>(opt: &Opt, args: I) -> std::io::Result<Output>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    Command::new(&opt.git_command)
+        .arg("-C")
+        .arg(&opt.git_repo)
+        .args(args)
+        .output()
+}
+
+/// A git command that exited unsuccessfully, along with everything that it
+/// wrote.
+#[derive(Debug)]
+pub struct CommandError {
+    command: String,
+    output: Output,
+}
+
+impl CommandError {
+    pub(crate) fn new<I, S>(opt: &Opt, args: I, output: Output) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut command = format!(
+            "{} -C {}",
+            String::from_utf8_lossy(opt.git_command.as_bytes()),
+            String::from_utf8_lossy(opt.git_repo.as_bytes())
+        );
+        for arg in args {
+            command.push(' ');
+            command.push_str(&String::from_utf8_lossy(arg.as_ref().as_bytes()));
+        }
+
+        Self { command, output }
+    }
+}
+
+impl Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "running {} failed with {}\n\nstdout:\n{}\n\nstderr:\n{}\n",
+            self.command,
+            match self.output.status.code() {
+                Some(code) => format!("exit code {}", code),
+                None => "signal".into(),
+            },
+            String::from_utf8_lossy(&self.output.stdout),
+            String::from_utf8_lossy(&self.output.stderr)
+        )
+    }
+}
@@ -0,0 +1,80 @@
+use std::{
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+/// A count of the bytes written to `git fast-import`, which can be read while
+/// the stream is still being written.
+///
+/// Like [`crate::Output`], this is cheap to clone, and all clones share the
+/// same count.
+#[derive(Debug, Clone, Default)]
+pub struct ByteCount(Arc<AtomicU64>);
+
+impl ByteCount {
+    /// Returns the number of bytes written so far.
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn add(&self, bytes: usize) {
+        self.0.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+/// `CountingWriter` wraps a writer, adding the bytes written through it to a
+/// [`ByteCount`].
+#[derive(Debug)]
+pub(crate) struct CountingWriter<W>
+where
+    W: Write,
+{
+    inner: W,
+    count: ByteCount,
+}
+
+impl<W> CountingWriter<W>
+where
+    W: Write,
+{
+    pub(crate) fn new(inner: W, count: ByteCount) -> Self {
+        Self { inner, count }
+    }
+}
+
+impl<W> Write for CountingWriter<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count.add(written);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counting_writer() -> io::Result<()> {
+        let count = ByteCount::default();
+        let mut writer = CountingWriter::new(Vec::new(), count.clone());
+        writer.write_all(b"blob\n")?;
+        writeln!(writer, "data {}", 3)?;
+        writer.flush()?;
+
+        assert_eq!(count.get(), 12);
+        assert_eq!(writer.inner, b"blob\ndata 3\n");
+
+        Ok(())
+    }
+}
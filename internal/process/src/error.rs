@@ -1,4 +1,4 @@
-use std::fmt::Debug;
+use std::{fmt::Debug, path::PathBuf};
 
 use git_fast_import::Mark;
 use thiserror::Error;
@@ -7,11 +7,14 @@ use tokio::{
     task::JoinError,
 };
 
-use crate::preflight;
+use crate::CommandError;
 
 /// Possible errors from the `process` module.
 #[derive(Debug, Error)]
 pub enum Error {
+    #[error("the work tree of the Git repository at {repo} has uncommitted changes, which would be left out of date by the import; commit or stash them, or import into a bare repository\n\n{status}")]
+    DirtyWorkTree { repo: String, status: String },
+
     #[error("exit due to signal {0:?}")]
     ExitSignal(Option<i32>),
 
@@ -21,6 +24,22 @@ pub enum Error {
     #[error(transparent)]
     GitFastImport(#[from] git_fast_import::Error),
 
+    #[error("{git} is git {found}, but git {minimum} or later is required; install a newer git, or use --git to point at one")]
+    GitVersion {
+        git: String,
+        found: String,
+        minimum: String,
+    },
+
+    #[error("gitoxide error: {0}")]
+    Gitoxide(#[source] Box<dyn std::error::Error + Send + Sync>),
+
+    #[error("cannot create the Git repository: {0}")]
+    Init(CommandError),
+
+    #[error("invalid mark file line: {0}")]
+    InvalidMarkLine(String),
+
     #[error(transparent)]
     Io(#[from] std::io::Error),
 
@@ -30,9 +49,15 @@ pub enum Error {
     #[error("cannot send mark back to caller: {0}")]
     MarkSend(Mark),
 
+    #[error("path does not exist in the parent commit: {0:?}")]
+    MissingPath(PathBuf),
+
     #[error(transparent)]
     OneshotRecv(#[from] oneshot::error::RecvError),
 
+    #[error("cannot check for objects in the Git repository: {0}")]
+    ObjectCheck(CommandError),
+
     #[error("cannot establish a {pipe} pipe to git fast-import: {err:?}")]
     OutputPipeCreate { err: std::io::Error, pipe: String },
 
@@ -40,7 +65,13 @@ pub enum Error {
     OutputPipeRead(std::io::Error),
 
     #[error("{0}")]
-    Preflight(preflight::Error),
+    Preflight(CommandError),
+
+    #[error("{0}")]
+    Repack(CommandError),
+
+    #[error("cannot point HEAD at {branch_ref}: {message}")]
+    SetHead { branch_ref: String, message: String },
 
     #[error("channel send error: {0}")]
     Send(String),
@@ -50,9 +81,31 @@ pub enum Error {
 
     #[error("cannot establish an input pipe to git fast-import")]
     StdinPipe,
+
+    #[error(transparent)]
+    SystemTime(#[from] std::time::SystemTimeError),
+
+    #[error("cannot parse the output of git --version: {0:?}")]
+    UnknownGitVersion(String),
+
+    #[error("{0:?} does not name a commit in the Git repository")]
+    UnknownCommit(String),
+
+    #[error("unknown mark: {0}")]
+    UnknownMark(Mark),
+
+    #[error("git fast-import doesn't support --allow-unsafe-features, which is needed to import marks; install a newer git, or use --git to point at one\n\n{0}")]
+    UnsafeFeatures(CommandError),
 }
 
 impl Error {
+    pub(crate) fn gitoxide<E>(err: E) -> Self
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        Self::Gitoxide(Box::new(err))
+    }
+
     pub(crate) fn stderr_pipe(err: std::io::Error) -> Self {
         Self::OutputPipeCreate {
             err,
@@ -0,0 +1,407 @@
+//! A backend that writes objects and refs directly to the Git repository using
+//! [gitoxide](https://github.com/Byron/gitoxide), rather than spawning `git
+//! fast-import`.
+//!
+//! This mirrors the semantics of `git fast-import` closely enough for our
+//! purposes: marks are allocated in the same way and persisted to the same mark
+//! file format, refs are only updated on checkpoint or once the import is
+//! complete, and refs are always force updated.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryFrom,
+    ffi::OsStr,
+    fs::{self, File},
+    io::{BufRead, BufReader, ErrorKind},
+    os::unix::prelude::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use git_fast_import::{Blob, Commit, FileCommand, Identity, Mark, Mode, Tag};
+use gix::{
+    bstr::{BStr, ByteSlice},
+    objs::tree::EntryKind,
+    refs::{
+        store::WriteReflog,
+        transaction::{Change, PreviousValue, RefEdit},
+        FullName, Target,
+    },
+    ObjectId,
+};
+
+use crate::{backend::Importer, Error};
+
+/// The reflog message used when updating refs.
+const REFLOG_MESSAGE: &str = "git-cvs-fast-import";
+
+/// `Repository` implements [`Importer`] on top of a gitoxide repository.
+pub(crate) struct Repository {
+    repo: gix::Repository,
+    mark_file: PathBuf,
+    marks: BTreeMap<Mark, ObjectId>,
+    next_mark: usize,
+
+    /// The branch table: this tracks the current head of each ref that has
+    /// been touched during the import. A value of `None` means that the ref
+    /// will be deleted.
+    refs: HashMap<String, Option<ObjectId>>,
+}
+
+impl Repository {
+    /// Opens the repository at `git_repo`, importing marks from `mark_file` if
+    /// it exists.
+    pub(crate) fn open(git_repo: &OsStr, mark_file: &Path) -> Result<Self, Error> {
+        let repo = gix::open(git_repo).map_err(Error::gitoxide)?;
+        let marks = read_marks(mark_file)?;
+        let next_mark = marks
+            .keys()
+            .next_back()
+            .map(|mark| mark.next().as_usize())
+            .unwrap_or(1);
+
+        Ok(Self {
+            repo,
+            mark_file: mark_file.to_path_buf(),
+            marks,
+            next_mark,
+            refs: HashMap::new(),
+        })
+    }
+
+    fn add_mark(&mut self, id: ObjectId) -> Mark {
+        let mark = Mark::from(self.next_mark);
+        self.next_mark += 1;
+        self.marks.insert(mark, id);
+
+        mark
+    }
+
+    fn resolve(&self, mark: Mark) -> Result<ObjectId, Error> {
+        self.marks
+            .get(&mark)
+            .copied()
+            .ok_or(Error::UnknownMark(mark))
+    }
+
+    fn write_refs(&self) -> Result<(), Error> {
+        for (name, target) in self.refs.iter() {
+            match target {
+                Some(id) => {
+                    self.repo
+                        .reference(name.as_str(), *id, PreviousValue::Any, REFLOG_MESSAGE)
+                        .map_err(Error::gitoxide)?;
+                }
+                None => {
+                    if let Some(reference) = self
+                        .repo
+                        .try_find_reference(name.as_str())
+                        .map_err(Error::gitoxide)?
+                    {
+                        reference.delete().map_err(Error::gitoxide)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_marks(&self) -> Result<(), Error> {
+        let mut buf = Vec::new();
+        for (mark, id) in self.marks.iter() {
+            buf.extend_from_slice(format!("{} {}\n", mark, id).as_bytes());
+        }
+
+        Ok(fs::write(&self.mark_file, buf)?)
+    }
+}
+
+impl Importer for Repository {
+    fn alias(&mut self, to: Mark) -> Result<Mark, Error> {
+        let id = self.resolve(to)?;
+        Ok(self.add_mark(id))
+    }
+
+    fn blob(&mut self, blob: Blob) -> Result<Mark, Error> {
+        let id = self
+            .repo
+            .write_blob(blob.data())
+            .map_err(Error::gitoxide)?
+            .detach();
+
+        Ok(self.add_mark(id))
+    }
+
+    fn checkpoint(&mut self) -> Result<(), Error> {
+        self.write_refs()?;
+        self.write_marks()
+    }
+
+    fn commit(&mut self, commit: Commit) -> Result<Mark, Error> {
+        // As with git fast-import, a commit without an explicit parent extends
+        // the current head of the branch, if any.
+        let parent = match (commit.from(), commit.from_object()) {
+            (Some(from), _) => Some(self.resolve(from)?),
+            (None, Some(id)) => Some(ObjectId::from_hex(id.as_bytes()).map_err(Error::gitoxide)?),
+            (None, None) => self.refs.get(commit.branch_ref()).copied().flatten(),
+        };
+        let merge = commit.merge().map(|mark| self.resolve(mark)).transpose()?;
+
+        let base_tree = match parent {
+            Some(parent) => self
+                .repo
+                .find_commit(parent)
+                .map_err(Error::gitoxide)?
+                .tree_id()
+                .map_err(Error::gitoxide)?
+                .detach(),
+            None => ObjectId::empty_tree(self.repo.object_hash()),
+        };
+
+        let mut editor = self.repo.edit_tree(base_tree).map_err(Error::gitoxide)?;
+        for command in commit.file_command_iter() {
+            match command {
+                FileCommand::Modify { mode, mark, path } => {
+                    editor
+                        .upsert(path_to_bstr(path), entry_kind(*mode), self.resolve(*mark)?)
+                        .map_err(Error::gitoxide)?;
+                }
+                FileCommand::Delete { path } => {
+                    editor.remove(path_to_bstr(path)).map_err(Error::gitoxide)?;
+                }
+                FileCommand::Copy { from, to } | FileCommand::Rename { from, to } => {
+                    // Tree IDs within the editor are only accurate once
+                    // written, so we'll write before looking up the source.
+                    editor.write().map_err(Error::gitoxide)?;
+                    let (kind, id) = match editor.get(path_to_bstr(from)) {
+                        Some(entry) => (entry.mode().kind(), entry.object_id()),
+                        None => return Err(Error::MissingPath(from.clone())),
+                    };
+
+                    editor
+                        .upsert(path_to_bstr(to), kind, id)
+                        .map_err(Error::gitoxide)?;
+                    if let FileCommand::Rename { .. } = command {
+                        editor.remove(path_to_bstr(from)).map_err(Error::gitoxide)?;
+                    }
+                }
+                FileCommand::DeleteAll => {
+                    editor
+                        .set_root(&self.repo.empty_tree())
+                        .map_err(Error::gitoxide)?;
+                }
+            }
+        }
+        let tree = editor.write().map_err(Error::gitoxide)?.detach();
+        drop(editor);
+
+        let committer = signature(commit.committer());
+        let author = commit
+            .author()
+            .map(signature)
+            .unwrap_or_else(|| committer.clone());
+
+        let id = self
+            .repo
+            .write_object(gix::objs::Commit {
+                tree,
+                parents: parent.into_iter().chain(merge).collect(),
+                author,
+                committer,
+                encoding: None,
+                message: commit.message().into(),
+                extra_headers: Vec::new(),
+            })
+            .map_err(Error::gitoxide)?
+            .detach();
+
+        self.refs.insert(commit.branch_ref().to_string(), Some(id));
+        Ok(self.add_mark(id))
+    }
+
+    fn delete(&mut self, branch_ref: &str) -> Result<(), Error> {
+        self.refs.insert(branch_ref.to_string(), None);
+
+        Ok(())
+    }
+
+    fn progress(&mut self, message: &str) -> Result<(), Error> {
+        log::debug!("progress {}", message);
+        Ok(())
+    }
+
+    fn reset(&mut self, branch_ref: &str, from: Option<Mark>) -> Result<(), Error> {
+        let target = from.map(|mark| self.resolve(mark)).transpose()?;
+        self.refs.insert(branch_ref.to_string(), target);
+
+        Ok(())
+    }
+
+    fn tag(&mut self, tag: Tag) -> Result<Mark, Error> {
+        let target = self.resolve(tag.from())?;
+        let id = self
+            .repo
+            .write_object(gix::objs::Tag {
+                target,
+                target_kind: gix::objs::Kind::Commit,
+                name: tag.name().into(),
+                tagger: Some(signature(tag.tagger())),
+                message: tag.message().into(),
+                pgp_signature: None,
+            })
+            .map_err(Error::gitoxide)?
+            .detach();
+
+        self.refs
+            .insert(format!("refs/tags/{}", tag.name()), Some(id));
+        Ok(self.add_mark(id))
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        self.write_refs()?;
+        self.write_marks()
+    }
+}
+
+/// Preflights the repository, ensuring that gitoxide can open it.
+pub(crate) fn preflight(git_repo: &OsStr) -> Result<(), Error> {
+    gix::open(git_repo).map_err(Error::gitoxide)?;
+    Ok(())
+}
+
+/// Creates a new repository at `git_repo`, with or without a work tree.
+pub(crate) fn init(git_repo: &OsStr, bare: bool) -> Result<(), Error> {
+    if bare {
+        gix::init_bare(git_repo).map_err(Error::gitoxide)?;
+    } else {
+        gix::init(git_repo).map_err(Error::gitoxide)?;
+    }
+    Ok(())
+}
+
+/// Resolves a full object name or ref name to the commit it refers to,
+/// returning `None` if there's no such commit.
+pub(crate) fn resolve_commit(git_repo: &OsStr, rev: &str) -> Result<Option<String>, Error> {
+    let repo = gix::open(git_repo).map_err(Error::gitoxide)?;
+    let object = match ObjectId::from_hex(rev.as_bytes()) {
+        Ok(id) => match repo.try_find_object(id).map_err(Error::gitoxide)? {
+            Some(object) => object,
+            None => return Ok(None),
+        },
+        Err(_) => match repo.try_find_reference(rev).map_err(Error::gitoxide)? {
+            Some(mut reference) => reference
+                .peel_to_id_in_place()
+                .map_err(Error::gitoxide)?
+                .object()
+                .map_err(Error::gitoxide)?,
+            None => return Ok(None),
+        },
+    };
+
+    // Tags and trees can't be used as a parent.
+    Ok(object
+        .peel_to_commit()
+        .ok()
+        .map(|commit| commit.id.to_string()))
+}
+
+/// Points `HEAD` at the given ref. As when gitoxide initialises a repository,
+/// no reflog entry is written for the change.
+pub(crate) fn set_head(git_repo: &OsStr, branch_ref: &str) -> Result<(), Error> {
+    let mut repo = gix::open(git_repo).map_err(Error::gitoxide)?;
+    let target = FullName::try_from(branch_ref).map_err(Error::gitoxide)?;
+
+    repo.refs.write_reflog = WriteReflog::Disable;
+    repo.edit_reference(RefEdit {
+        change: Change::Update {
+            log: Default::default(),
+            expected: PreviousValue::Any,
+            new: Target::Symbolic(target),
+        },
+        name: FullName::try_from("HEAD").map_err(Error::gitoxide)?,
+        deref: false,
+    })
+    .map_err(Error::gitoxide)?;
+
+    Ok(())
+}
+
+/// Reads a mark file in the format exported by `git fast-import`. A missing
+/// file is treated as an empty set of marks.
+fn read_marks(path: &Path) -> Result<BTreeMap<Mark, ObjectId>, Error> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut marks = BTreeMap::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+
+        let (mark, id) =
+            parse_mark_line(&line).ok_or_else(|| Error::InvalidMarkLine(line.clone()))?;
+        marks.insert(mark, id);
+    }
+
+    Ok(marks)
+}
+
+fn parse_mark_line(line: &str) -> Option<(Mark, ObjectId)> {
+    let (mark, id) = line.strip_prefix(':')?.split_once(' ')?;
+
+    Some((
+        Mark::from(mark.parse::<usize>().ok()?),
+        ObjectId::from_hex(id.as_bytes()).ok()?,
+    ))
+}
+
+fn entry_kind(mode: Mode) -> EntryKind {
+    match mode {
+        Mode::Normal => EntryKind::Blob,
+        Mode::Executable => EntryKind::BlobExecutable,
+        Mode::Symlink => EntryKind::Link,
+    }
+}
+
+fn path_to_bstr(path: &Path) -> &BStr {
+    path.as_os_str().as_bytes().as_bstr()
+}
+
+fn signature(identity: &Identity) -> gix::actor::Signature {
+    gix::actor::Signature {
+        name: identity.name().unwrap_or_default().into(),
+        email: identity.email().into(),
+        time: gix::date::Time::new(identity.when() as i64, 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mark_line() {
+        assert_eq!(
+            parse_mark_line(":25 0123456789012345678901234567890123456789"),
+            Some((
+                Mark::from(25),
+                ObjectId::from_hex(b"0123456789012345678901234567890123456789").unwrap()
+            ))
+        );
+
+        assert_eq!(
+            parse_mark_line("25 0123456789012345678901234567890123456789"),
+            None
+        );
+        assert_eq!(
+            parse_mark_line(":xx 0123456789012345678901234567890123456789"),
+            None
+        );
+        assert_eq!(parse_mark_line(":25 xx"), None);
+        assert_eq!(parse_mark_line(":25"), None);
+    }
+}
@@ -0,0 +1,26 @@
+use crate::{gitoxide, Backend, Error, Opt};
+
+/// Points `HEAD` in the Git repository at the given branch, whether or not the
+/// branch exists yet, so that clones check it out by default.
+pub fn set_head(opt: &Opt, branch: &str) -> Result<(), Error> {
+    let branch_ref = format!("refs/heads/{}", branch);
+    if opt.backend == Backend::Gitoxide {
+        return gitoxide::set_head(&opt.git_repo, &branch_ref);
+    }
+
+    let output = std::process::Command::new(&opt.git_command)
+        .arg("-C")
+        .arg(&opt.git_repo)
+        .arg("symbolic-ref")
+        .arg("HEAD")
+        .arg(&branch_ref)
+        .output()?;
+
+    match output.status.code() {
+        Some(0) => Ok(()),
+        _ => Err(Error::SetHead {
+            branch_ref,
+            message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }),
+    }
+}
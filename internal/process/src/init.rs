@@ -0,0 +1,34 @@
+use std::path::Path;
+
+use crate::{
+    command::{self, CommandError},
+    gitoxide, set_head, Backend, Error, Opt,
+};
+
+/// Creates the Git repository if nothing exists at its path yet, pointing
+/// `HEAD` at the given branch, and returns true if it was created.
+pub fn init(opt: &Opt, bare: bool, branch: &str) -> Result<bool, Error> {
+    let path = Path::new(&opt.git_repo);
+    if path.exists() {
+        return Ok(false);
+    }
+
+    if opt.backend == Backend::Gitoxide {
+        gitoxide::init(&opt.git_repo, bare)?;
+    } else {
+        // git init initialises the current directory, which saves having to
+        // give the path twice.
+        std::fs::create_dir_all(path)?;
+        let mut args = vec!["init", "--quiet"];
+        if bare {
+            args.push("--bare");
+        }
+        let output = command::git(opt, &args)?;
+        if !output.status.success() {
+            return Err(Error::Init(CommandError::new(opt, &args, output)));
+        }
+    }
+
+    set_head(opt, branch)?;
+    Ok(true)
+}
@@ -2,8 +2,10 @@
 //! send data to that process.
 
 use std::{
-    ffi::OsString,
+    ffi::{OsStr, OsString},
     fmt::Debug,
+    fs::File,
+    io::BufWriter,
     path::{Path, PathBuf},
 };
 
@@ -17,12 +19,33 @@ use tokio::{
     task::{self, JoinHandle},
 };
 
+mod backend;
+mod command;
+mod counter;
 mod error;
+mod gitoxide;
+mod head;
+mod init;
+mod objects;
 mod preflight;
 mod process;
+mod record;
+mod repack;
+mod resolve;
 
+pub use self::backend::Backend;
+use self::backend::Importer;
+pub use self::command::CommandError;
+pub use self::counter::ByteCount;
+use self::counter::CountingWriter;
 pub use self::error::Error;
+pub use self::head::set_head;
+pub use self::init::init;
+pub use self::objects::missing_objects;
 pub use self::preflight::preflight;
+use self::record::Recorder;
+pub use self::repack::repack;
+pub use self::resolve::resolve_commit;
 
 // Command line options that are required by the [`Output`] object.
 //
@@ -30,6 +53,20 @@ pub use self::preflight::preflight;
 // `flatten` attribute.
 #[derive(Clone, Debug, StructOpt)]
 pub struct Opt {
+    #[structopt(
+        long,
+        default_value = "fast-import",
+        possible_values = Backend::VARIANTS,
+        help = "the backend used to write to the Git repository: fast-import spawns git fast-import, whereas gitoxide writes objects and refs directly and doesn't require git to be installed"
+    )]
+    backend: Backend,
+
+    /// The size at which batched blobs are written to `git fast-import`. This
+    /// is set with [`Opt::set_blob_batch_size`] rather than parsed here, since
+    /// sizes are parsed by the caller.
+    #[structopt(skip)]
+    blob_batch_size: Option<usize>,
+
     #[structopt(
         long = "--git",
         default_value = "git",
@@ -51,12 +88,40 @@ pub struct Opt {
 
     #[structopt(short = "-g", long, help = "path to the Git repository to import into")]
     git_repo: OsString,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "record the normalised fast-import command stream to the given file, for use in regression tests"
+    )]
+    record: Option<PathBuf>,
+}
+
+impl Opt {
+    /// Sets the size at which blobs sent to `git fast-import` are written in
+    /// a batch. This has no effect with the gitoxide backend.
+    pub fn set_blob_batch_size(&mut self, size: usize) {
+        self.blob_batch_size = Some(size);
+    }
+
+    /// Returns the path to the git command.
+    pub fn git_command(&self) -> &OsStr {
+        &self.git_command
+    }
+
+    /// Returns the path to the Git repository being imported into.
+    pub fn git_repo(&self) -> &OsStr {
+        &self.git_repo
+    }
 }
 
 /// `Output` provides methods to send data to the `git fast-import` process.
 #[derive(Debug, Clone)]
 pub struct Output {
     tx: UnboundedSender<Command>,
+
+    /// The bytes written to `git fast-import`, if the backend uses it.
+    bytes: Option<ByteCount>,
 }
 
 /// Spawns a new `git fast-import` process, and returns an [`Output`] object
@@ -76,16 +141,31 @@ where
     let (tx, rx) = mpsc::unbounded_channel();
     let mark_file = mark_file_path.as_ref().to_path_buf();
     let opt = opt.clone();
+    let bytes = match opt.backend {
+        Backend::FastImport => Some(ByteCount::default()),
+        Backend::Gitoxide => None,
+    };
+    let count = bytes.clone().unwrap_or_default();
 
     (
-        Output { tx },
+        Output { tx, bytes },
         Worker {
-            handle: task::spawn(async move { worker(opt, rx, mark_file).await }),
+            handle: task::spawn(async move { worker(opt, rx, mark_file, count).await }),
         },
     )
 }
 
 impl Output {
+    /// Returns a new mark that refers to the same object as the given mark.
+    pub async fn alias(&self, to: Mark) -> Result<Mark, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.tx.send(Command::Alias(to, tx)).map_err(|e| {
+            log::error!("received command error: {}", &e);
+            e
+        })?;
+        Ok(rx.await?)
+    }
+
     pub async fn blob(&self, blob: git_fast_import::Blob) -> Result<Mark, Error> {
         let (tx, rx) = oneshot::channel();
         self.tx.send(Command::Blob(blob, tx)).map_err(|e| {
@@ -95,6 +175,13 @@ impl Output {
         Ok(rx.await?)
     }
 
+    /// Returns the count of bytes written to `git fast-import`, or `None` if
+    /// the backend doesn't use it. Unlike the output itself, the count can be
+    /// kept after the output is dropped.
+    pub fn byte_count(&self) -> Option<ByteCount> {
+        self.bytes.clone()
+    }
+
     pub async fn branch(&self, name: &str, head_mark: Mark) -> Result<(), Error> {
         Ok(self.tx.send(Command::Reset {
             branch_ref: format!("refs/heads/{}", name),
@@ -102,6 +189,10 @@ impl Output {
         })?)
     }
 
+    pub async fn checkpoint(&self) -> Result<(), Error> {
+        Ok(self.tx.send(Command::Checkpoint)?)
+    }
+
     pub async fn commit(&self, commit: git_fast_import::Commit) -> Result<Mark, Error> {
         let (tx, rx) = oneshot::channel();
         self.tx.send(Command::Commit(commit, tx)).map_err(|e| {
@@ -111,6 +202,20 @@ impl Output {
         Ok(rx.await?)
     }
 
+    /// Deletes the given branch.
+    pub async fn delete_branch(&self, name: &str) -> Result<(), Error> {
+        Ok(self.tx.send(Command::Delete {
+            branch_ref: format!("refs/heads/{}", name),
+        })?)
+    }
+
+    /// Deletes the given lightweight tag.
+    pub async fn delete_tag(&self, name: &str) -> Result<(), Error> {
+        Ok(self.tx.send(Command::Delete {
+            branch_ref: format!("refs/tags/{}", name),
+        })?)
+    }
+
     pub async fn lightweight_tag(&self, name: &str, commit_mark: Mark) -> Result<(), Error> {
         Ok(self.tx.send(Command::Reset {
             branch_ref: format!("refs/tags/{}", name),
@@ -149,12 +254,66 @@ impl Worker {
 
 async fn worker(
     opt: Opt,
-    mut rx: UnboundedReceiver<Command>,
+    rx: UnboundedReceiver<Command>,
     mark_file: PathBuf,
+    count: ByteCount,
 ) -> Result<(), Error> {
-    let process = process::Process::new(opt)?;
+    let record = opt.record.clone();
+    let blob_batch_size = opt.blob_batch_size;
+
+    match opt.backend {
+        Backend::FastImport => {
+            let process = process::Process::new(opt)?;
+
+            // Commands are written a line at a time, so we buffer them rather
+            // than making a system call for each line. The bytes are counted as
+            // they're written to the pipe.
+            let stdin = CountingWriter::new(process.stdin(), count);
+            let mut client = Writer::new(BufWriter::new(stdin), mark_file)?;
+            if let Some(size) = blob_batch_size {
+                client.set_batch_size(size);
+            }
+
+            // Finishing the client will send the done command, after which we
+            // wait for git to exit.
+            record_commands(client, rx, record.as_deref()).await?;
+            process.wait().await?;
+        }
+        Backend::Gitoxide => {
+            let repo = gitoxide::Repository::open(&opt.git_repo, &mark_file)?;
+            record_commands(repo, rx, record.as_deref()).await?;
+        }
+    }
+
+    Ok(())
+}
 
-    let mut client = Writer::new(process.stdin(), mark_file)?;
+/// Handles the commands sent to the worker, additionally recording them to the
+/// given file if one is provided.
+async fn record_commands<I>(
+    importer: I,
+    rx: UnboundedReceiver<Command>,
+    record: Option<&Path>,
+) -> Result<(), Error>
+where
+    I: Importer,
+{
+    match record {
+        Some(path) => {
+            let writer = BufWriter::new(File::create(path)?);
+            handle_commands(Recorder::new(importer, writer)?, rx).await
+        }
+        None => handle_commands(importer, rx).await,
+    }
+}
+
+async fn handle_commands<I>(
+    mut importer: I,
+    mut rx: UnboundedReceiver<Command>,
+) -> Result<(), Error>
+where
+    I: Importer,
+{
     let handle_send_result = |r| match r {
         Ok(_) => Ok(()),
         Err(mark) => Err(Error::MarkSend(mark)),
@@ -162,33 +321,34 @@ async fn worker(
 
     while let Some(command) = rx.recv().await {
         match command {
+            Command::Alias(to, tx) => {
+                handle_send_result(tx.send(importer.alias(to)?))?;
+            }
             Command::Blob(blob, tx) => {
-                handle_send_result(tx.send(client.command(blob)?))?;
+                handle_send_result(tx.send(importer.blob(blob)?))?;
             }
             Command::Checkpoint => {
-                client.checkpoint()?;
+                importer.checkpoint()?;
             }
             Command::Commit(commit, tx) => {
-                handle_send_result(tx.send(client.command(commit)?))?;
+                handle_send_result(tx.send(importer.commit(commit)?))?;
+            }
+            Command::Delete { branch_ref } => {
+                importer.delete(&branch_ref)?;
             }
             Command::Progress(message) => {
-                client.progress(&message)?;
+                importer.progress(&message)?;
             }
             Command::Reset { branch_ref, from } => {
-                client.reset(&branch_ref, from)?;
+                importer.reset(&branch_ref, from)?;
             }
             Command::Tag(tag, tx) => {
-                handle_send_result(tx.send(client.command(tag)?))?;
+                handle_send_result(tx.send(importer.tag(tag)?))?;
             }
         }
     }
 
-    // Destroy the client, which will send the done command, and then wait for
-    // git to exit.
-    drop(client);
-    process.wait().await?;
-
-    Ok(())
+    importer.finish()
 }
 
 type MarkSender = oneshot::Sender<Mark>;
@@ -196,9 +356,13 @@ type MarkSender = oneshot::Sender<Mark>;
 #[allow(dead_code)]
 #[derive(Debug)]
 enum Command {
+    Alias(Mark, MarkSender),
     Blob(git_fast_import::Blob, MarkSender),
     Checkpoint,
     Commit(git_fast_import::Commit, MarkSender),
+    Delete {
+        branch_ref: String,
+    },
     Progress(String),
     Reset {
         branch_ref: String,
@@ -0,0 +1,62 @@
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+    thread,
+};
+
+use gix::ObjectId;
+
+use crate::{command::CommandError, Backend, Error, Opt};
+
+/// Returns the object names that aren't in the Git repository, in the order
+/// they were given.
+///
+/// With the git backend, all of the names are checked by a single
+/// `git cat-file --batch-check`, so this is reasonably quick even for a large
+/// import.
+pub fn missing_objects(opt: &Opt, names: &[String]) -> Result<Vec<String>, Error> {
+    if opt.backend == Backend::Gitoxide {
+        let repo = gix::open(&opt.git_repo).map_err(Error::gitoxide)?;
+        return names
+            .iter()
+            .filter_map(|name| match ObjectId::from_hex(name.as_bytes()) {
+                Ok(id) if repo.has_object(id) => None,
+                Ok(_) => Some(Ok(name.clone())),
+                Err(e) => Some(Err(Error::gitoxide(e))),
+            })
+            .collect();
+    }
+
+    let args = ["cat-file", "--batch-check"];
+    let mut child = Command::new(&opt.git_command)
+        .arg("-C")
+        .arg(&opt.git_repo)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // The names are written from another thread, since git writes its output
+    // as it goes, and would otherwise block once the pipe is full.
+    let mut stdin = child.stdin.take().unwrap();
+    let input: Vec<u8> = names
+        .iter()
+        .flat_map(|name| name.bytes().chain(std::iter::once(b'\n')))
+        .collect();
+    let writer = thread::spawn(move || stdin.write_all(&input));
+
+    let output = child.wait_with_output()?;
+    writer.join().expect("writer thread panicked")?;
+    if !output.status.success() {
+        return Err(Error::ObjectCheck(CommandError::new(opt, args, output)));
+    }
+
+    // Each line is either the name, type, and size of an object, or the name
+    // followed by "missing".
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.strip_suffix(" missing"))
+        .map(String::from)
+        .collect())
+}
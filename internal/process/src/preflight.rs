@@ -1,55 +1,160 @@
-use std::{fmt::Display, os::unix::prelude::OsStrExt, process::Output};
+use std::{
+    fmt::Display,
+    os::unix::prelude::OsStrExt,
+    process::{Command, Stdio},
+    str::FromStr,
+};
 
-use crate::Opt;
+use crate::{
+    command::{self, CommandError},
+    gitoxide, Backend, Error, Opt,
+};
+
+/// The oldest git that supports `git fast-import --allow-unsafe-features`,
+/// which is needed for the `import-marks-if-exists` and `export-marks`
+/// features in the stream.
+const MINIMUM_VERSION: GitVersion = GitVersion(2, 24, 1);
+
+/// Preflights git using the given options, ensuring that git is new enough,
+/// the repository is valid, and importing into it won't leave a work tree out
+/// of date.
+///
+/// This is all checked before any files are parsed, since a large import can
+/// take hours to get as far as running `git fast-import`.
+pub fn preflight(opt: &Opt) -> Result<(), Error> {
+    if opt.backend == Backend::Gitoxide {
+        // There's no git binary to check, so we just need to ensure that the
+        // repository can be opened.
+        return gitoxide::preflight(&opt.git_repo);
+    }
+
+    check_version(opt)?;
 
-/// Preflights git using the given options, ensuring that git is executable and
-/// the repository is valid.
-pub fn preflight(opt: &Opt) -> Result<(), crate::Error> {
     // git rev-parse without further arguments will do nothing, successfully, as
     // long as the underlying repository is valid.
-    let output = std::process::Command::new(&opt.git_command)
+    let args = ["rev-parse"];
+    let output = command::git(opt, args)?;
+    if !output.status.success() {
+        return Err(Error::Preflight(CommandError::new(opt, args, output)));
+    }
+
+    check_unsafe_features(opt)?;
+    check_work_tree(opt)
+}
+
+/// Ensures that git is at least [`MINIMUM_VERSION`].
+fn check_version(opt: &Opt) -> Result<(), Error> {
+    let output = Command::new(&opt.git_command).arg("--version").output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let version = GitVersion::from_str(&stdout)
+        .map_err(|_| Error::UnknownGitVersion(stdout.trim().to_string()))?;
+
+    if version < MINIMUM_VERSION {
+        return Err(Error::GitVersion {
+            git: String::from_utf8_lossy(opt.git_command.as_bytes()).into_owned(),
+            found: version.to_string(),
+            minimum: MINIMUM_VERSION.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Ensures that `git fast-import` accepts `--allow-unsafe-features`. This is
+/// checked separately from the version, since a git patched by a distribution
+/// may not match the upstream release that it claims to be.
+///
+/// `git fast-import` does nothing at all with an empty stream.
+fn check_unsafe_features(opt: &Opt) -> Result<(), Error> {
+    let args = ["fast-import", "--allow-unsafe-features", "--quiet"];
+    let output = Command::new(&opt.git_command)
         .arg("-C")
         .arg(&opt.git_repo)
-        .arg("rev-parse")
+        .args(args)
+        .stdin(Stdio::null())
         .output()?;
 
-    match output.status.code() {
-        Some(code) if code == 0 => Ok(()),
-        _ => Err(crate::Error::Preflight(Error::new(opt, output))),
+    if !output.status.success() {
+        return Err(Error::UnsafeFeatures(CommandError::new(opt, args, output)));
     }
+    Ok(())
 }
 
-#[derive(Debug)]
-pub struct Error {
-    command: String,
-    output: Output,
-}
+/// Ensures that the repository is either bare, or has no uncommitted changes
+/// to tracked files, since `git fast-import` updates refs without touching the
+/// work tree.
+fn check_work_tree(opt: &Opt) -> Result<(), Error> {
+    let args = ["rev-parse", "--is-bare-repository"];
+    let output = command::git(opt, args)?;
+    if !output.status.success() {
+        return Err(Error::Preflight(CommandError::new(opt, args, output)));
+    }
+    if String::from_utf8_lossy(&output.stdout).trim() == "true" {
+        return Ok(());
+    }
 
-impl Error {
-    fn new(opt: &Opt, output: Output) -> Self {
-        Self {
-            command: format!(
-                "{} -C {} rev-parse",
-                String::from_utf8_lossy(opt.git_command.as_bytes()),
-                String::from_utf8_lossy(opt.git_repo.as_bytes())
-            ),
-            output,
-        }
+    let args = ["status", "--porcelain", "--untracked-files=no"];
+    let output = command::git(opt, args)?;
+    if !output.status.success() {
+        return Err(Error::Preflight(CommandError::new(opt, args, output)));
+    }
+    let status = String::from_utf8_lossy(&output.stdout);
+    if !status.trim().is_empty() {
+        return Err(Error::DirtyWorkTree {
+            repo: String::from_utf8_lossy(opt.git_repo.as_bytes()).into_owned(),
+            status: status.into_owned(),
+        });
     }
+    Ok(())
 }
 
-impl Display for Error {
+/// The version of git, as reported by `git --version`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct GitVersion(u32, u32, u32);
+
+impl Display for GitVersion {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "running {} failed with {}\n\nstdout:\n{}\n\nstderr:\n{}\n",
-            self.command,
-            match self.output.status.code() {
-                Some(code) => format!("exit code {}", code),
-                None => "signal".into(),
-            },
-            String::from_utf8_lossy(&self.output.stdout),
-            String::from_utf8_lossy(&self.output.stderr)
-        )
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
+impl FromStr for GitVersion {
+    type Err = ();
+
+    /// Parses the output of `git --version`, such as `git version 2.39.2`,
+    /// ignoring anything after the patch version, as in
+    /// `git version 2.37.1 (Apple Git-137.1)` or `git version 2.38.1.windows.1`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let version = s.trim().strip_prefix("git version ").ok_or(())?;
+        let mut parts = version
+            .split(|c: char| c == '.' || c.is_whitespace())
+            .map(|part| part.parse::<u32>());
+
+        let mut next = || parts.next().unwrap_or(Ok(0)).map_err(|_| ());
+        Ok(Self(next()?, next()?, next()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_git_version() {
+        for (s, want) in [
+            ("git version 2.39.2\n", GitVersion(2, 39, 2)),
+            ("git version 2.24.1", GitVersion(2, 24, 1)),
+            ("git version 2.37.1 (Apple Git-137.1)", GitVersion(2, 37, 1)),
+            ("git version 2.38.1.windows.1", GitVersion(2, 38, 1)),
+            ("git version 3.0", GitVersion(3, 0, 0)),
+        ] {
+            assert_eq!(GitVersion::from_str(s), Ok(want), "{:?}", s);
+        }
+        for s in ["", "hg version 2.39.2", "git version two"] {
+            assert!(GitVersion::from_str(s).is_err(), "{:?}", s);
+        }
+
+        assert!(GitVersion(2, 24, 0) < MINIMUM_VERSION);
+        assert!(GitVersion(2, 3, 10) < MINIMUM_VERSION);
+        assert!(GitVersion(2, 100, 0) > MINIMUM_VERSION);
     }
 }
@@ -0,0 +1,315 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use git_fast_import::{
+    Alias, Blob, Command, Commit, CommitBuilder, FileCommand, Identity, Mark, Reset, Tag,
+    UnmarkedCommand,
+};
+
+use crate::{backend::Importer, Error};
+
+/// `Recorder` is a tee layer around another [`Importer`]: every command is
+/// passed through to the wrapped importer, and is also written to a recording
+/// in the fast-import format.
+///
+/// The recording is normalised so that it's stable between runs, which makes it
+/// suitable for comparison against golden files:
+///
+/// * Marks are renumbered from 1 in the order they first appear, so recordings
+///   don't depend on the marks allocated by earlier imports.
+/// * Identity timestamps at or after the time the recording started are
+///   replaced with the Unix epoch: these can only have come from the wall
+///   clock, rather than from the CVS history.
+#[derive(Debug)]
+pub(crate) struct Recorder<I, W>
+where
+    I: Importer,
+    W: Write,
+{
+    inner: I,
+    writer: W,
+    marks: HashMap<Mark, Mark>,
+    next_mark: usize,
+    started: u64,
+}
+
+impl<I, W> Recorder<I, W>
+where
+    I: Importer,
+    W: Write,
+{
+    pub(crate) fn new(inner: I, mut writer: W) -> Result<Self, Error> {
+        writeln!(writer, "feature done")?;
+        writeln!(writer, "feature date-format=raw")?;
+
+        Ok(Self {
+            inner,
+            writer,
+            marks: HashMap::new(),
+            next_mark: 1,
+            started: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        })
+    }
+
+    /// Returns the normalised mark for the given mark, allocating a new one if
+    /// it hasn't been seen before.
+    fn mark(&mut self, mark: Mark) -> Mark {
+        let next_mark = &mut self.next_mark;
+        *self.marks.entry(mark).or_insert_with(|| {
+            let normalised = Mark::from(*next_mark);
+            *next_mark += 1;
+            normalised
+        })
+    }
+
+    fn identity(&self, identity: &Identity) -> Result<Identity, Error> {
+        let when = if identity.when() >= self.started {
+            0
+        } else {
+            identity.when()
+        };
+
+        Ok(Identity::new(
+            identity.name().map(String::from),
+            identity.email().to_string(),
+            UNIX_EPOCH + Duration::from_secs(when),
+        )?)
+    }
+
+    fn normalise_commit(&mut self, commit: &Commit) -> Result<Commit, Error> {
+        let mut builder = CommitBuilder::new(commit.branch_ref().to_string());
+        builder
+            .committer(self.identity(commit.committer())?)
+            .message(commit.message().to_string());
+        if let Some(author) = commit.author() {
+            builder.author(self.identity(author)?);
+        }
+        if let Some(from) = commit.from() {
+            builder.from(self.mark(from));
+        }
+        if let Some(id) = commit.from_object() {
+            builder.from_object(id.to_string());
+        }
+        if let Some(merge) = commit.merge() {
+            builder.merge(self.mark(merge));
+        }
+        for command in commit.file_command_iter() {
+            builder.add_file_command(match command {
+                FileCommand::Modify { mode, mark, path } => FileCommand::Modify {
+                    mode: *mode,
+                    mark: self.mark(*mark),
+                    path: path.clone(),
+                },
+                command => command.clone(),
+            });
+        }
+
+        Ok(builder.build()?)
+    }
+
+    fn normalise_tag(&mut self, tag: &Tag) -> Result<Tag, Error> {
+        Ok(Tag::new(
+            tag.name().to_string(),
+            self.mark(tag.from()),
+            self.identity(tag.tagger())?,
+            tag.message().to_string(),
+        ))
+    }
+
+    fn record<C: Command>(&mut self, command: C, mark: Mark) -> Result<Mark, Error> {
+        let normalised = self.mark(mark);
+        command.write(&mut self.writer, normalised)?;
+
+        Ok(mark)
+    }
+}
+
+impl<I, W> Importer for Recorder<I, W>
+where
+    I: Importer,
+    W: Write,
+{
+    fn alias(&mut self, to: Mark) -> Result<Mark, Error> {
+        let recorded = Alias::new(self.mark(to));
+        let mark = self.inner.alias(to)?;
+        self.record(recorded, mark)
+    }
+
+    fn blob(&mut self, blob: Blob) -> Result<Mark, Error> {
+        let recorded = blob.clone();
+        let mark = self.inner.blob(blob)?;
+        self.record(recorded, mark)
+    }
+
+    fn checkpoint(&mut self) -> Result<(), Error> {
+        // Checkpoints only affect when git writes its pack files, so there's no
+        // need to record them.
+        self.inner.checkpoint()
+    }
+
+    fn commit(&mut self, commit: Commit) -> Result<Mark, Error> {
+        let recorded = self.normalise_commit(&commit)?;
+        let mark = self.inner.commit(commit)?;
+        self.record(recorded, mark)
+    }
+
+    fn delete(&mut self, branch_ref: &str) -> Result<(), Error> {
+        Reset::delete(branch_ref.to_string()).write(&mut self.writer)?;
+
+        self.inner.delete(branch_ref)
+    }
+
+    fn progress(&mut self, message: &str) -> Result<(), Error> {
+        self.inner.progress(message)
+    }
+
+    fn reset(&mut self, branch_ref: &str, from: Option<Mark>) -> Result<(), Error> {
+        let recorded = from.map(|from| self.mark(from));
+        Reset::new(branch_ref.to_string(), recorded).write(&mut self.writer)?;
+
+        self.inner.reset(branch_ref, from)
+    }
+
+    fn tag(&mut self, tag: Tag) -> Result<Mark, Error> {
+        let recorded = self.normalise_tag(&tag)?;
+        let mark = self.inner.tag(tag)?;
+        self.record(recorded, mark)
+    }
+
+    fn finish(mut self) -> Result<(), Error> {
+        writeln!(self.writer, "done")?;
+        self.writer.flush()?;
+
+        self.inner.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// A fake importer that allocates marks from an arbitrary starting point,
+    /// as would happen in an incremental import.
+    #[derive(Debug)]
+    struct Fake {
+        next_mark: usize,
+    }
+
+    impl Fake {
+        fn mark(&mut self) -> Mark {
+            self.next_mark += 1;
+            Mark::from(self.next_mark)
+        }
+    }
+
+    impl Importer for Fake {
+        fn alias(&mut self, _to: Mark) -> Result<Mark, Error> {
+            Ok(self.mark())
+        }
+
+        fn blob(&mut self, _blob: Blob) -> Result<Mark, Error> {
+            Ok(self.mark())
+        }
+
+        fn checkpoint(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn commit(&mut self, _commit: Commit) -> Result<Mark, Error> {
+            Ok(self.mark())
+        }
+
+        fn delete(&mut self, _branch_ref: &str) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn progress(&mut self, _message: &str) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn reset(&mut self, _branch_ref: &str, _from: Option<Mark>) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn tag(&mut self, _tag: Tag) -> Result<Mark, Error> {
+            Ok(self.mark())
+        }
+
+        fn finish(self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_recorder() {
+        let mut recording = Vec::new();
+        let mut recorder = Recorder::new(Fake { next_mark: 41 }, &mut recording).unwrap();
+
+        let blob = recorder.blob(Blob::new(b"foo")).unwrap();
+        assert_eq!(blob, Mark::from(42));
+        let alias = recorder.alias(blob).unwrap();
+        assert_eq!(alias, Mark::from(43));
+
+        let mut builder = CommitBuilder::new("refs/heads/main".into());
+        builder
+            .committer(
+                Identity::new(None, "adam".into(), UNIX_EPOCH + Duration::from_secs(1000)).unwrap(),
+            )
+            .message("message".into())
+            .add_file_command(FileCommand::Modify {
+                mode: git_fast_import::Mode::Normal,
+                mark: alias,
+                path: PathBuf::from("foo"),
+            });
+        let commit = recorder.commit(builder.build().unwrap()).unwrap();
+        assert_eq!(commit, Mark::from(44));
+
+        recorder
+            .tag(Tag::new(
+                "tag".into(),
+                commit,
+                Identity::new(Some("Tagger".into()), "tagger".into(), SystemTime::now()).unwrap(),
+                "tag message".into(),
+            ))
+            .unwrap();
+        recorder.reset("refs/tags/foo", Some(commit)).unwrap();
+        recorder.delete("refs/heads/old").unwrap();
+        recorder.finish().unwrap();
+
+        assert_eq!(
+            String::from_utf8(recording).unwrap(),
+            "feature done
+feature date-format=raw
+blob
+mark :1
+data 3
+foo
+alias
+mark :2
+to :1
+commit refs/heads/main
+mark :3
+committer <adam> 1000 +0000
+data 7
+message
+M 100644 :2 foo
+tag tag
+mark :4
+from :3
+tagger Tagger <tagger> 0 +0000
+data 11
+tag message
+reset refs/tags/foo
+from :3
+reset refs/heads/old
+from 0000000000000000000000000000000000000000
+done
+"
+        );
+    }
+}
@@ -0,0 +1,27 @@
+use crate::{
+    command::{self, CommandError},
+    Error, Opt,
+};
+
+/// Repacks every object in the Git repository into a single pack with
+/// `git repack -a -d -f`, replacing the packs written by each run and
+/// checkpoint, and deltifying the objects again using the given depth and
+/// window.
+///
+/// This always runs git, even with the gitoxide backend.
+pub fn repack(opt: &Opt, depth: u32, window: u32) -> Result<(), Error> {
+    let args = [
+        "repack".to_string(),
+        "-a".to_string(),
+        "-d".to_string(),
+        "-f".to_string(),
+        format!("--depth={}", depth),
+        format!("--window={}", window),
+    ];
+    let output = command::git(opt, &args)?;
+
+    match output.status.code() {
+        Some(0) => Ok(()),
+        _ => Err(Error::Repack(CommandError::new(opt, &args, output))),
+    }
+}
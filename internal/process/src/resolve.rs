@@ -0,0 +1,28 @@
+use crate::{gitoxide, Backend, Error, Opt};
+
+/// Resolves a ref or object name in the Git repository to the full object name
+/// of the commit it refers to, before the import starts.
+///
+/// With the gitoxide backend, only full object names and ref names are
+/// supported, whereas `git rev-parse` also accepts abbreviated object names
+/// and other revision syntax.
+pub fn resolve_commit(opt: &Opt, rev: &str) -> Result<String, Error> {
+    if opt.backend == Backend::Gitoxide {
+        return gitoxide::resolve_commit(&opt.git_repo, rev)?
+            .ok_or_else(|| Error::UnknownCommit(rev.to_string()));
+    }
+
+    let output = std::process::Command::new(&opt.git_command)
+        .arg("-C")
+        .arg(&opt.git_repo)
+        .arg("rev-parse")
+        .arg("--verify")
+        .arg("--quiet")
+        .arg(format!("{}^{{commit}}", rev))
+        .output()?;
+
+    match output.status.code() {
+        Some(0) => Ok(String::from_utf8_lossy(&output.stdout).trim().to_string()),
+        _ => Err(Error::UnknownCommit(rev.to_string())),
+    }
+}
@@ -0,0 +1,64 @@
+//! Measures file revision throughput when many workers add revisions to the
+//! state concurrently, as happens during discovery.
+
+use std::{path::PathBuf, time::SystemTime};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use git_cvs_fast_import_state::Manager;
+
+const FILES: usize = 256;
+const REVISIONS_PER_FILE: usize = 16;
+
+async fn add_file_revisions(workers: usize) {
+    let state = Manager::new();
+    let time = SystemTime::now();
+
+    let handles: Vec<_> = (0..workers)
+        .map(|worker| {
+            let state = state.clone();
+            tokio::spawn(async move {
+                for file in (worker..FILES).step_by(workers) {
+                    let path = PathBuf::from(format!("dir/file{}.c,v", file));
+                    for revision in 1..=REVISIONS_PER_FILE {
+                        let revision = format!("1.{}", revision);
+                        state
+                            .add_file_revision(
+                                &path,
+                                &revision,
+                                None,
+                                std::iter::empty::<&[u8]>(),
+                                "author",
+                                "message",
+                                &time,
+                            )
+                            .await
+                            .unwrap();
+                        state.get_file_revision(&path, &revision).await.unwrap();
+                    }
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.await.unwrap();
+    }
+}
+
+fn bench_add_file_revisions(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread().build().unwrap();
+
+    let mut group = c.benchmark_group("add_file_revisions");
+    group.throughput(Throughput::Elements((FILES * REVISIONS_PER_FILE) as u64));
+    for workers in [1, 4, 16] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(workers),
+            &workers,
+            |b, &workers| b.to_async(&runtime).iter(|| add_file_revisions(workers)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_add_file_revisions);
+criterion_main!(benches);
@@ -0,0 +1,110 @@
+//! Measures writing the state to a store and reading it back, as happens at
+//! the end and the start of every run.
+
+use std::{
+    io::Cursor,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use git_cvs_fast_import_state::{Compression, Manager};
+use git_fast_import::Mark;
+
+const FILES: usize = 10_000;
+const REVISIONS_PER_FILE: usize = 10;
+const FILES_PER_PATCHSET: usize = 10;
+
+/// Builds a state with `FILES` files of `REVISIONS_PER_FILE` revisions each,
+/// committed in patchsets of `FILES_PER_PATCHSET` files, with a tag on the
+/// first revision of every file and a mark for every blob and commit.
+async fn state() -> Manager {
+    let state = Manager::new();
+    let mut marks = String::new();
+    let mut next_mark = 1;
+
+    for revision in 1..=REVISIONS_PER_FILE {
+        let revision_str = format!("1.{}", revision);
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(86400 * revision as u64);
+        for first in (0..FILES).step_by(FILES_PER_PATCHSET) {
+            let mut ids = Vec::with_capacity(FILES_PER_PATCHSET);
+            for file in first..first + FILES_PER_PATCHSET {
+                let blob = Mark::from(next_mark);
+                marks.push_str(&format!(":{} {:040x}\n", next_mark, next_mark));
+                next_mark += 1;
+
+                let id = state
+                    .add_file_revision(
+                        &PathBuf::from(format!("dir{}/file{}.c,v", file % 100, file)),
+                        &revision_str,
+                        Some(blob),
+                        [b"main"].iter(),
+                        "author",
+                        &format!("change {}", first),
+                        &time,
+                    )
+                    .await
+                    .unwrap();
+                if revision == 1 {
+                    state.add_tag(b"REL1", id).await;
+                }
+                ids.push(id);
+            }
+
+            let commit = Mark::from(next_mark);
+            marks.push_str(&format!(":{} {:040x}\n", next_mark, next_mark));
+            next_mark += 1;
+            state
+                .add_patchset(commit, b"main", &time, ids.into_iter())
+                .await;
+        }
+    }
+    state.set_raw_marks(marks.as_bytes()).await.unwrap();
+
+    state
+}
+
+fn bench_serialize(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let state = runtime.block_on(state());
+
+    let mut group = c.benchmark_group("store");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements((FILES * REVISIONS_PER_FILE) as u64));
+    for (name, compression) in [
+        ("none", Compression::None),
+        ("zstd", Compression::default()),
+    ] {
+        group.bench_with_input(
+            BenchmarkId::new("serialize", name),
+            &compression,
+            |b, &compression| {
+                b.to_async(&runtime).iter(|| async {
+                    let mut store = Vec::new();
+                    state
+                        .clone()
+                        .serialize_into(&mut store, compression)
+                        .await
+                        .unwrap();
+                    store
+                })
+            },
+        );
+
+        let mut store = Vec::new();
+        runtime
+            .block_on(state.clone().serialize_into(&mut store, compression))
+            .unwrap();
+        group.bench_with_input(BenchmarkId::new("deserialize", name), &store, |b, store| {
+            b.to_async(&runtime)
+                .iter(|| async { Manager::deserialize_from(Cursor::new(store)).await.unwrap() })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_serialize);
+criterion_main!(benches);
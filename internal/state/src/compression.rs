@@ -0,0 +1,97 @@
+use std::{fmt::Display, str::FromStr};
+
+use thiserror::Error;
+
+/// How the store is compressed when it's written with
+/// [`Manager::serialize_into`](crate::Manager::serialize_into).
+///
+/// Stores are read the same way whatever compression they were written with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// The store is written as is, which is quickest, but largest.
+    None,
+
+    /// The store is compressed with zstd at the given level, where 0 is zstd's
+    /// default level, across the given number of worker threads, where 0
+    /// compresses on the calling thread.
+    Zstd { level: i32, workers: u32 },
+}
+
+impl Compression {
+    /// Returns the same compression using the given number of worker threads.
+    pub fn with_workers(self, workers: u32) -> Self {
+        match self {
+            Self::None => Self::None,
+            Self::Zstd { level, .. } => Self::Zstd { level, workers },
+        }
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::Zstd {
+            level: 0,
+            workers: 0,
+        }
+    }
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::None => write!(f, "none"),
+            Self::Zstd { level, .. } => write!(f, "{}", level),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = CompressionError;
+
+    /// Parses `none`, or a zstd compression level.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "none" {
+            return Ok(Self::None);
+        }
+
+        match s.parse() {
+            Ok(level) if level == 0 || zstd::compression_level_range().contains(&level) => {
+                Ok(Self::Zstd { level, workers: 0 })
+            }
+            _ => Err(CompressionError(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("invalid state compression {0:?}: expected none or a zstd level from {min} to {max}", min = zstd::compression_level_range().start(), max = zstd::compression_level_range().end())]
+pub struct CompressionError(String);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compression_parse() {
+        assert_eq!("none".parse::<Compression>().unwrap(), Compression::None);
+        assert_eq!("0".parse::<Compression>().unwrap(), Compression::default());
+        assert_eq!(
+            "19".parse::<Compression>().unwrap(),
+            Compression::Zstd {
+                level: 19,
+                workers: 0
+            }
+        );
+        assert_eq!(
+            "19".parse::<Compression>().unwrap().with_workers(4),
+            Compression::Zstd {
+                level: 19,
+                workers: 4
+            }
+        );
+
+        for s in ["", "zstd", "1.5", "1000"] {
+            assert!(s.parse::<Compression>().is_err(), "{:?}", s);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// The set of directories that have been imported, relative to the CVSROOT.
+///
+/// An empty path represents the whole repository. Directories are only ever
+/// added: once a directory has been imported, its patchsets are in Git, so
+/// later runs need to keep importing it.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Directories {
+    directories: BTreeSet<PathBuf>,
+}
+
+impl Directories {
+    /// Adds a directory. Directories within another directory in the set are
+    /// removed, since they're now redundant.
+    pub fn add(&mut self, directory: &Path) {
+        if self.covers(directory) {
+            return;
+        }
+
+        self.directories.retain(|have| !have.starts_with(directory));
+        self.directories.insert(directory.to_path_buf());
+    }
+
+    /// Returns true if the given path is within one of the directories.
+    pub fn covers(&self, path: &Path) -> bool {
+        self.directories
+            .iter()
+            .any(|directory| path.starts_with(directory))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.directories.is_empty()
+    }
+
+    /// Returns true if the whole repository has been imported.
+    pub fn is_everything(&self) -> bool {
+        self.directories.contains(Path::new(""))
+    }
+
+    /// Iterates over the directories in path order.
+    pub fn iter(&self) -> impl Iterator<Item = &Path> {
+        self.directories.iter().map(|directory| directory.as_path())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add() {
+        let mut directories = Directories::default();
+        assert!(directories.is_empty());
+        assert!(!directories.covers(Path::new("a/file")));
+
+        directories.add(Path::new("a/b"));
+        directories.add(Path::new("c"));
+        assert!(directories.covers(Path::new("a/b/file")));
+        assert!(!directories.covers(Path::new("a/file")));
+        assert!(!directories.covers(Path::new("cc/file")));
+
+        // Adding a parent directory replaces its children, and adding a child
+        // of an existing directory does nothing.
+        directories.add(Path::new("a"));
+        directories.add(Path::new("c/d"));
+        assert_eq!(
+            directories.iter().collect::<Vec<&Path>>(),
+            vec![Path::new("a"), Path::new("c")]
+        );
+        assert!(directories.covers(Path::new("a/file")));
+        assert!(!directories.is_everything());
+
+        directories.add(Path::new(""));
+        assert!(directories.is_everything());
+        assert!(directories.covers(Path::new("anything")));
+        assert_eq!(directories.iter().count(), 1);
+    }
+}
@@ -13,6 +13,19 @@ pub enum Error {
     #[error("error loading from store: {0}")]
     Load(String),
 
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("error parsing marks: {0}")]
+    Marks(#[from] git_fast_import::Error),
+
+    #[error("{count} mark(s) in the store are not in its Git marks, starting with {first}; the next import would allocate marks from {next}")]
+    MissingMarks {
+        count: usize,
+        first: git_fast_import::Mark,
+        next: git_fast_import::Mark,
+    },
+
     #[error("no file revision exists for ID {0}")]
     NoFileRevisionForID(file_revision::ID),
 
@@ -25,15 +38,24 @@ pub enum Error {
     #[error("no patchset exists for mark {0}")]
     NoPatchSetForMark(patchset::Mark),
 
+    #[error("no commit SHA is known for patchset mark {0}")]
+    NoShaForPatchSetMark(patchset::Mark),
+
     #[error("tag {0} does not exist")]
     NoTag(String),
 
+    #[error("cannot write a state that was only partially loaded")]
+    PartialState,
+
     #[error("serialisation error: {0:?}")]
     Serialisation(#[from] bincode::Error),
 
     #[error("speedy error: {0:?}")]
     Speedy(#[from] speedy::Error),
 
+    #[error("unknown portable state version: {0}")]
+    UnknownPortableVersion(u32),
+
     #[error("unknown serialised data version: {0}")]
     UnknownSerialisationVersion(u8),
 }
@@ -1,14 +1,22 @@
 use std::{
     borrow::Borrow,
-    collections::{BTreeMap, HashMap},
-    hash::Hash,
+    collections::{hash_map::RandomState, BTreeMap, HashMap},
+    hash::{BuildHasher, Hash},
+    iter::FromIterator,
+    ops::Deref,
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
     time::SystemTime,
 };
 
 use derive_more::{Display, From, Into};
-use serde::{Deserialize, Serialize};
+use serde::{
+    ser::{self, SerializeMap, SerializeStruct},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
 
 use crate::{v1, Error};
 
@@ -110,21 +118,48 @@ pub struct FileRevision {
     pub time: SystemTime,
 }
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+/// The number of shards used within [`Store`].
+const SHARDS: usize = 64;
+
+/// Storage for file revisions.
+///
+/// File revisions are added concurrently by every discovery worker, so rather
+/// than protecting the entire store with a single lock, revisions are sharded:
+/// lookups by key use a shard selected by the hash of the file path, and
+/// lookups by ID use a shard selected by the ID itself. IDs are allocated
+/// atomically, and remain dense.
+#[derive(Debug)]
 pub(crate) struct Store {
-    /// Base storage for file revisions.
-    file_revisions: Vec<Arc<FileRevision>>,
+    /// The next ID to be allocated.
+    next_id: AtomicUsize,
 
-    /// Access to revisions by key.
-    by_key: HashMap<Key, ID>,
+    /// Base storage for file revisions, sharded by ID.
+    by_id: Vec<RwLock<HashMap<ID, Arc<FileRevision>>>>,
+
+    /// Access to revisions by key, sharded by path.
+    by_key: Vec<RwLock<HashMap<Key, ID>>>,
 
     /// Access to revisions by mark.
-    by_mark: BTreeMap<Mark, ID>,
+    by_mark: Mutex<BTreeMap<Mark, ID>>,
+
+    hasher: RandomState,
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Self {
+            next_id: AtomicUsize::new(0),
+            by_id: (0..SHARDS).map(|_| RwLock::default()).collect(),
+            by_key: (0..SHARDS).map(|_| RwLock::default()).collect(),
+            by_mark: Mutex::default(),
+            hasher: RandomState::new(),
+        }
+    }
 }
 
 impl Store {
     pub(crate) fn add<I>(
-        &mut self,
+        &self,
         key: Key,
         mark: Option<Mark>,
         branches: I,
@@ -136,76 +171,299 @@ impl Store {
         I: Iterator,
         I::Item: AsRef<[u8]>,
     {
+        // The key shard lock is held until the revision has been fully
+        // inserted, which ensures that concurrent adds of the same key can't
+        // allocate two IDs.
+        let mut by_key = self.key_shard(&key.path).write().unwrap();
+
         // Short circuit: if this revision has already been seen, then we don't
         // need to insert it again.
-        if let Some(id) = self.by_key.get(&key) {
+        if let Some(id) = by_key.get(&key) {
             return Ok(*id);
         }
 
-        let id = self.file_revisions.len().into();
+        let id = ID(self.next_id.fetch_add(1, Ordering::SeqCst));
 
-        self.file_revisions.push(Arc::new(FileRevision {
-            key: key.clone(),
-            mark,
-            branches: branches.map(|branch| branch.as_ref().to_vec()).collect(),
-            author: author.to_string(),
-            message: message.to_string(),
-            time: *time,
-        }));
+        self.id_shard(id).write().unwrap().insert(
+            id,
+            Arc::new(FileRevision {
+                key: key.clone(),
+                mark,
+                branches: branches.map(|branch| branch.as_ref().to_vec()).collect(),
+                author: author.to_string(),
+                message: message.to_string(),
+                time: *time,
+            }),
+        );
 
-        self.by_key.insert(key, id);
         if let Some(mark) = mark {
-            self.by_mark.insert(mark, id);
+            self.by_mark.lock().unwrap().insert(mark, id);
         }
+        by_key.insert(key, id);
 
         Ok(id)
     }
 
     pub(crate) fn get_by_id(&self, id: ID) -> Option<Arc<FileRevision>> {
-        self.file_revisions.get(id.0).cloned()
+        self.id_shard(id).read().unwrap().get(&id).cloned()
     }
 
     pub(crate) fn get_by_key(&self, path: &Path, revision: &str) -> Option<Arc<FileRevision>> {
-        self.by_key
+        self.get_id_by_key(path, revision)
+            .and_then(|id| self.get_by_id(id))
+    }
+
+    pub(crate) fn get_id_by_key(&self, path: &Path, revision: &str) -> Option<ID> {
+        self.key_shard(path)
+            .read()
+            .unwrap()
             .get((path, revision).borrow() as &dyn Keyer)
-            .map(|id| self.get_by_id(*id))
-            .flatten()
+            .copied()
+    }
+
+    /// Returns every file revision along with its ID, in ID order.
+    pub(crate) fn get_all(&self) -> Vec<(ID, Arc<FileRevision>)> {
+        let mut all: Vec<_> = self
+            .by_id
+            .iter()
+            .flat_map(|shard| {
+                shard
+                    .read()
+                    .unwrap()
+                    .iter()
+                    .map(|(id, file_revision)| (*id, file_revision.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        all.sort_by_key(|(id, _file_revision)| *id);
+
+        all
+    }
+
+    /// Returns the IDs of the file revisions that have content, in mark order.
+    pub(crate) fn get_ids_with_marks(&self) -> Vec<ID> {
+        self.by_mark.lock().unwrap().values().copied().collect()
+    }
+
+    /// Returns the marks of the file revisions that have content, in order.
+    pub(crate) fn get_marks(&self) -> Vec<Mark> {
+        self.by_mark.lock().unwrap().keys().copied().collect()
+    }
+
+    fn id_shard(&self, id: ID) -> &RwLock<HashMap<ID, Arc<FileRevision>>> {
+        &self.by_id[id.0 % SHARDS]
+    }
+
+    fn key_shard(&self, path: &Path) -> &RwLock<HashMap<Key, ID>> {
+        &self.by_key[self.hasher.hash_one(path) as usize % SHARDS]
+    }
+
+    fn insert(&mut self, id: ID, file_revision: Arc<FileRevision>) {
+        let next_id = self.next_id.get_mut();
+        *next_id = (*next_id).max(id.0 + 1);
+        if let Some(mark) = file_revision.mark {
+            self.by_mark.get_mut().unwrap().insert(mark, id);
+        }
+        let key = file_revision.key.clone();
+        self.id_shard(id).write().unwrap().insert(id, file_revision);
+        self.key_shard(&key.path).write().unwrap().insert(key, id);
+    }
+}
+
+/// The persisted form of [`Store`], which predates sharding and is retained so
+/// that existing state files can still be read.
+#[derive(Deserialize)]
+struct Persisted {
+    file_revisions: Vec<Arc<FileRevision>>,
+    #[allow(dead_code)]
+    by_key: HashMap<Key, ID>,
+    #[allow(dead_code)]
+    by_mark: BTreeMap<Mark, ID>,
+}
+
+impl FromIterator<(ID, Arc<FileRevision>)> for Store {
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = (ID, Arc<FileRevision>)>,
+    {
+        let mut store = Store::default();
+        for (id, file_revision) in iter {
+            store.insert(id, file_revision);
+        }
+
+        store
+    }
+}
+
+impl From<Persisted> for Store {
+    fn from(persisted: Persisted) -> Self {
+        // The indices can be rebuilt from the revisions themselves.
+        let mut store = Store::default();
+        for (id, file_revision) in persisted.file_revisions.into_iter().enumerate() {
+            store.insert(ID(id), file_revision);
+        }
+
+        store
+    }
+}
+
+impl<'de> Deserialize<'de> for Store {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Persisted::deserialize(deserializer).map(Store::from)
+    }
+}
+
+impl Serialize for Store {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let by_id: Vec<_> = self.by_id.iter().map(|s| s.read().unwrap()).collect();
+        let by_key: Vec<_> = self.by_key.iter().map(|s| s.read().unwrap()).collect();
+        let by_mark = self.by_mark.lock().unwrap();
+
+        // IDs are dense, so the revisions can be written out in ID order.
+        let len = by_id.iter().map(|shard| shard.len()).sum();
+        let file_revisions: Vec<_> = (0..len)
+            .map(|id| by_id[id % SHARDS].get(&ID(id)))
+            .collect::<Option<_>>()
+            .ok_or_else(|| ser::Error::custom("file revision IDs are not contiguous"))?;
+
+        let mut state = serializer.serialize_struct("Store", 3)?;
+        state.serialize_field("file_revisions", &file_revisions)?;
+        state.serialize_field("by_key", &Shards(&by_key))?;
+        state.serialize_field("by_mark", &*by_mark)?;
+        state.end()
+    }
+}
+
+/// A helper to serialise sharded maps as a single map.
+struct Shards<'a, G>(&'a [G]);
+
+impl<'a, G, K, V> Serialize for Shards<'a, G>
+where
+    G: Deref<Target = HashMap<K, V>>,
+    K: Serialize,
+    V: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map =
+            serializer.serialize_map(Some(self.0.iter().map(|shard| shard.len()).sum()))?;
+        for shard in self.0.iter() {
+            for (k, v) in shard.iter() {
+                map.serialize_entry(k, v)?;
+            }
+        }
+        map.end()
     }
 }
 
 impl From<v1::file_revision::Store> for Store {
     fn from(v1: v1::file_revision::Store) -> Self {
-        let mut v2 = Store {
-            file_revisions: Vec::new(),
-            by_key: HashMap::new(),
-            by_mark: BTreeMap::new(),
-        };
+        let mut v2 = Store::default();
 
-        for v1_file_revision in v1.file_revisions.into_iter() {
+        for (id, v1_file_revision) in v1.file_revisions.into_iter().enumerate() {
             let v1_file_revision = Arc::try_unwrap(v1_file_revision).unwrap();
 
-            let v2_key = Key {
-                path: v1_file_revision.key.path.into(),
-                revision: String::from_utf8_lossy(&v1_file_revision.key.revision).into_owned(),
-            };
-
-            let v2_file_revision = Arc::new(FileRevision {
-                key: v2_key.clone(),
-                mark: v1_file_revision.mark,
-                branches: v1_file_revision.branches,
-                author: v1_file_revision.author,
-                message: v1_file_revision.message,
-                time: v1_file_revision.time,
-            });
-
-            let id = v2.file_revisions.len().into();
-            v2.file_revisions.push(v2_file_revision);
-            v2.by_key.insert(v2_key, id);
-            if let Some(mark) = v1_file_revision.mark {
-                v2.by_mark.insert(mark, id);
-            }
+            v2.insert(
+                ID(id),
+                Arc::new(FileRevision {
+                    key: Key {
+                        path: v1_file_revision.key.path.into(),
+                        revision: String::from_utf8_lossy(&v1_file_revision.key.revision)
+                            .into_owned(),
+                    },
+                    mark: v1_file_revision.mark,
+                    branches: v1_file_revision.branches,
+                    author: v1_file_revision.author,
+                    message: v1_file_revision.message,
+                    time: v1_file_revision.time,
+                }),
+            );
         }
 
         v2
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    fn add(store: &Store, path: &str, revision: &str, mark: Option<usize>) -> ID {
+        store
+            .add(
+                Key {
+                    path: path.into(),
+                    revision: revision.into(),
+                },
+                mark.map(|mark| Mark(mark.into())),
+                std::iter::empty::<&[u8]>(),
+                "author",
+                "message",
+                &SystemTime::UNIX_EPOCH,
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn test_concurrent_add() {
+        let store = Arc::new(Store::default());
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let store = store.clone();
+                thread::spawn(move || {
+                    (0..100)
+                        .map(|i| add(&store, &format!("file{},v", i), "1.1", None))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let ids: Vec<Vec<ID>> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        // Every thread should have seen the same ID for each key, and the IDs
+        // should be dense.
+        assert!(ids.iter().all(|thread_ids| thread_ids == &ids[0]));
+        let mut sorted = ids[0].clone();
+        sorted.sort();
+        assert_eq!(sorted, (0..100).map(ID).collect::<Vec<_>>());
+
+        let revision = store.get_by_key(Path::new("file42,v"), "1.1").unwrap();
+        assert_eq!(revision.key.path, PathBuf::from("file42,v"));
+        assert!(store.get_by_key(Path::new("file42,v"), "1.2").is_none());
+    }
+
+    #[test]
+    fn test_serialisation() {
+        let store = Store::default();
+        let a = add(&store, "a,v", "1.1", Some(1));
+        let b = add(&store, "b,v", "1.1", None);
+        let c = add(&store, "a,v", "1.2", Some(2));
+
+        let store: Store = bincode::deserialize(&bincode::serialize(&store).unwrap()).unwrap();
+        assert_eq!(
+            store.get_by_key(Path::new("a,v"), "1.1").unwrap().mark,
+            Some(Mark(1.into()))
+        );
+        assert_eq!(store.get_by_id(b).unwrap().key.path, PathBuf::from("b,v"));
+        assert_eq!(store.get_by_id(c).unwrap().key.revision, "1.2");
+        assert_eq!(
+            *store.by_mark.lock().unwrap(),
+            vec![(Mark(1.into()), a), (Mark(2.into()), c)]
+                .into_iter()
+                .collect()
+        );
+
+        // New IDs should follow on from the deserialised revisions.
+        assert_eq!(add(&store, "c,v", "1.1", None), ID(3));
+    }
+}
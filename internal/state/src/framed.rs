@@ -0,0 +1,219 @@
+//! Version 4 of the store, in which each section is written separately after a
+//! table of where each one starts, so that reading one section never reads or
+//! decompresses the others.
+
+use std::{
+    collections::BTreeMap,
+    io::{Read, Seek, SeekFrom, Write},
+};
+
+use speedy::{LittleEndian, Readable, Writable};
+
+use crate::{Compression, Error};
+
+/// The version of `Header`, which is also the first byte of the store.
+pub(crate) const VERSION: u8 = 4;
+
+/// The sections of a version 4 store, in the order they're written.
+///
+/// The discriminants are written to the store, so they can't be changed, and
+/// new sections should be added to the end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Section {
+    FileRevisions = 0,
+    Patchsets = 1,
+    Tags = 2,
+    RawMarks = 3,
+    Directories = 4,
+    CommitTimes = 5,
+    TagDigests = 6,
+    Runs = 7,
+    SkippedFiles = 8,
+    PathNormalization = 9,
+}
+
+/// The start of the store, which locates each section within the rest of it.
+#[derive(Readable, Writable)]
+struct Header {
+    version: u8,
+
+    /// Whether each section is a separate zstd frame.
+    compressed: bool,
+
+    entries: Vec<Entry>,
+}
+
+/// Where a section is, relative to the end of the header.
+#[derive(Readable, Writable)]
+struct Entry {
+    section: u8,
+    offset: u64,
+    len: u64,
+}
+
+/// Writes the given sections as a version 4 store, compressing each one
+/// separately.
+pub(crate) fn write<W>(
+    mut writer: W,
+    sections: Vec<(Section, Vec<u8>)>,
+    compression: Compression,
+) -> Result<(), Error>
+where
+    W: Write,
+{
+    let bodies = sections
+        .into_iter()
+        .map(|(section, body)| Ok((section, compress(body, compression)?)))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let mut offset = 0;
+    let entries = bodies
+        .iter()
+        .map(|(section, body)| {
+            let entry = Entry {
+                section: *section as u8,
+                offset,
+                len: body.len() as u64,
+            };
+            offset += entry.len;
+            entry
+        })
+        .collect();
+
+    Header {
+        version: VERSION,
+        compressed: compression != Compression::None,
+        entries,
+    }
+    .write_to_stream(&mut writer)?;
+    for (_section, body) in bodies.iter() {
+        writer.write_all(body)?;
+    }
+
+    Ok(())
+}
+
+fn compress(body: Vec<u8>, compression: Compression) -> Result<Vec<u8>, Error> {
+    match compression {
+        Compression::None => Ok(body),
+        Compression::Zstd { level, workers } => {
+            let mut encoder = zstd::Encoder::new(Vec::new(), level)?;
+            if workers > 0 {
+                encoder.multithread(workers)?;
+            }
+            encoder.write_all(&body)?;
+            Ok(encoder.finish()?)
+        }
+    }
+}
+
+/// Reads individual sections from a version 4 store.
+pub(crate) struct Reader<R> {
+    reader: R,
+
+    /// The position of the end of the header, which section offsets are
+    /// relative to.
+    start: u64,
+
+    compressed: bool,
+    entries: BTreeMap<u8, (u64, u64)>,
+}
+
+impl<R> Reader<R>
+where
+    R: Read + Seek,
+{
+    /// Reads the header of the store, which must start at the reader's current
+    /// position.
+    pub(crate) fn new(mut reader: R) -> Result<Self, Error> {
+        let position = reader.stream_position()?;
+        let header = Header::read_from_stream_unbuffered(&mut reader)?;
+        if header.version != VERSION {
+            return Err(Error::UnknownSerialisationVersion(header.version));
+        }
+
+        let len = Writable::<LittleEndian>::bytes_needed(&header)?;
+
+        Ok(Self {
+            start: position + len as u64,
+            compressed: header.compressed,
+            entries: header
+                .entries
+                .into_iter()
+                .map(|entry| (entry.section, (entry.offset, entry.len)))
+                .collect(),
+            reader,
+        })
+    }
+
+    /// Reads and decompresses the given section, returning `None` if the store
+    /// doesn't have it, such as when it was written before the section was
+    /// added.
+    pub(crate) fn read(&mut self, section: Section) -> Result<Option<Vec<u8>>, Error> {
+        let (offset, len) = match self.entries.get(&(section as u8)) {
+            Some(entry) => *entry,
+            None => return Ok(None),
+        };
+
+        self.reader.seek(SeekFrom::Start(self.start + offset))?;
+        let mut body = Vec::new();
+        (&mut self.reader).take(len).read_to_end(&mut body)?;
+        if body.len() as u64 != len {
+            return Err(Error::Load(format!(
+                "{:?} section is truncated: expected {} bytes, but only read {}",
+                section,
+                len,
+                body.len()
+            )));
+        }
+
+        if self.compressed {
+            body = zstd::decode_all(body.as_slice())?;
+        }
+        Ok(Some(body))
+    }
+
+    /// Returns the range of bytes that the section occupies in the store.
+    #[cfg(test)]
+    pub(crate) fn range(&self, section: Section) -> Option<std::ops::Range<usize>> {
+        self.entries.get(&(section as u8)).map(|(offset, len)| {
+            let start = (self.start + offset) as usize;
+            start..start + *len as usize
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_round_trip() -> Result<(), Error> {
+        for compression in [Compression::None, Compression::default()] {
+            let mut store = Vec::new();
+            write(
+                &mut store,
+                vec![
+                    (Section::FileRevisions, b"file revisions".to_vec()),
+                    (Section::RawMarks, Vec::new()),
+                    (Section::Runs, b"runs".to_vec()),
+                ],
+                compression,
+            )?;
+            assert_eq!(store[0], VERSION);
+
+            let mut reader = Reader::new(Cursor::new(&store))?;
+            assert_eq!(reader.read(Section::Runs)?, Some(b"runs".to_vec()));
+            assert_eq!(
+                reader.read(Section::FileRevisions)?,
+                Some(b"file revisions".to_vec())
+            );
+            assert_eq!(reader.read(Section::RawMarks)?, Some(Vec::new()));
+            assert_eq!(reader.read(Section::Tags)?, None);
+        }
+
+        Ok(())
+    }
+}
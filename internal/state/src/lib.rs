@@ -1,14 +1,15 @@
 //! State management for `git-cvs-fast-import`.
 
 use std::{
-    collections::BTreeSet,
+    collections::{BTreeMap, BTreeSet, HashMap},
     io::{Read, Seek, Write},
-    path::Path,
+    path::{Path, PathBuf},
     sync::Arc,
     time::SystemTime,
 };
 
 use git_fast_import::Mark;
+use serde::de::DeserializeOwned;
 use speedy::{Readable, Writable};
 use tokio::{
     io::{AsyncRead, AsyncWrite},
@@ -16,15 +17,29 @@ use tokio::{
     task,
 };
 
+mod compression;
+pub use compression::{Compression, CompressionError};
+
+mod directory;
+pub use directory::Directories;
+
 mod error;
 pub use self::error::Error;
 
+mod framed;
+use framed::Section;
+
 mod file_revision;
-pub use file_revision::{FileRevision, ID as FileRevisionID};
+pub use file_revision::{FileRevision, Key as FileRevisionKey, ID as FileRevisionID};
 
 mod patchset;
 pub use patchset::PatchSet;
 
+mod portable;
+
+mod run;
+pub use run::Run;
+
 mod tag;
 
 mod v1;
@@ -32,13 +47,83 @@ mod v1;
 /// The top level in-memory state manager.
 #[derive(Debug, Clone, Default)]
 pub struct Manager {
-    file_revisions: Arc<RwLock<file_revision::Store>>,
+    file_revisions: Arc<file_revision::Store>,
     patchsets: Arc<RwLock<patchset::Store>>,
     tags: Arc<RwLock<tag::Store>>,
     raw_marks: Arc<RwLock<Vec<u8>>>,
+    directories: Arc<RwLock<Directories>>,
+
+    /// The times that patchsets were committed at, where they differ from the
+    /// patchset time.
+    commit_times: Arc<RwLock<BTreeMap<patchset::Mark, SystemTime>>>,
+
+    /// The runs that have updated the store, oldest first.
+    runs: Arc<RwLock<Vec<Run>>>,
+
+    /// The RCS files that were skipped by the last skip list given, keyed by
+    /// path, along with the reason each one was skipped.
+    skipped_files: Arc<RwLock<BTreeMap<PathBuf, String>>>,
+
+    /// The Unicode normalization form that paths were converted to, if it's
+    /// been recorded. Stores written before this was recorded didn't
+    /// normalize paths at all.
+    path_normalization: Arc<RwLock<Option<String>>>,
+
+    /// The object names of the commits created for each patchset, as parsed
+    /// from `raw_marks`. This isn't persisted, since it can always be rebuilt
+    /// from the raw marks.
+    shas: Arc<RwLock<BTreeMap<patchset::Mark, String>>>,
+
+    /// The sections that were loaded from the store.
+    sections: Sections,
+}
+
+/// The sections of the store, which can be loaded separately with
+/// [`Manager::deserialize_sections_from`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sections {
+    pub file_revisions: bool,
+
+    /// Patchsets, including the branches they're on and the times they were
+    /// committed at. The imported directories are always loaded.
+    pub patchsets: bool,
+
+    pub tags: bool,
+
+    /// The marks exported by `git fast-import`, which include every blob, and
+    /// so are usually the largest section.
+    pub marks: bool,
 }
 
-/// The wrapper data structure used to persist the state in `Manager` to disk.
+impl Sections {
+    pub const ALL: Self = Self {
+        file_revisions: true,
+        patchsets: true,
+        tags: true,
+        marks: true,
+    };
+
+    pub const NONE: Self = Self {
+        file_revisions: false,
+        patchsets: false,
+        tags: false,
+        marks: false,
+    };
+}
+
+impl Default for Sections {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+/// The first byte of the magic number that starts a zstd frame, which can't
+/// be mistaken for a version of `Ser`.
+const ZSTD_MAGIC_START: u8 = 0x28;
+
+/// The wrapper data structure that versions 2 and 3 of the store were
+/// persisted as. Newer stores are written by [`framed`], but these can still
+/// be read.
 ///
 /// We use speedy to actually read and write this structure to disk: previously
 /// we used bincode, but speedy is many many multiples quicker at dumping and
@@ -52,6 +137,161 @@ struct Ser {
     patchsets: Vec<u8>,
     tags: Vec<u8>,
     raw_marks: Vec<u8>,
+
+    /// Added in version 3: stores from earlier versions don't know which
+    /// directories were imported.
+    #[speedy(default_on_eof)]
+    directories: Vec<u8>,
+
+    /// Also added in version 3, and empty if no commit times were adjusted.
+    #[speedy(default_on_eof)]
+    commit_times: Vec<u8>,
+
+    /// Also added in version 3: tags in stores without digests are compared
+    /// using the patchset of their fake commit instead.
+    #[speedy(default_on_eof)]
+    tag_digests: Vec<u8>,
+
+    /// Also added in version 3: marks that are too long to be written as a
+    /// single section, such as after importing hundreds of millions of blobs,
+    /// are split into chunks here, and `raw_marks` is left empty.
+    #[speedy(default_on_eof)]
+    raw_mark_chunks: Vec<Vec<u8>>,
+
+    /// Also added in version 3: stores written before runs were recorded
+    /// don't have any.
+    #[speedy(default_on_eof)]
+    runs: Vec<u8>,
+
+    /// Also added in version 3, for the same reason.
+    #[speedy(default_on_eof)]
+    skipped_files: Vec<u8>,
+
+    /// Also added in version 3, for the same reason.
+    #[speedy(default_on_eof)]
+    path_normalization: Vec<u8>,
+}
+
+/// The serialised sections of a store, before they're deserialised.
+///
+/// The large sections are `None` if they weren't asked for. The others are
+/// always read, and are empty if the store was written before they were added.
+#[derive(Default)]
+struct RawSections {
+    file_revisions: Option<Vec<u8>>,
+    patchsets: Option<Vec<u8>>,
+    tags: Option<Vec<u8>>,
+    raw_marks: Option<Vec<u8>>,
+    directories: Vec<u8>,
+    commit_times: Vec<u8>,
+    tag_digests: Vec<u8>,
+    runs: Vec<u8>,
+    skipped_files: Vec<u8>,
+    path_normalization: Vec<u8>,
+}
+
+impl RawSections {
+    /// Reads the given sections from a version 4 store, without reading the
+    /// others at all.
+    fn read_framed<R>(reader: R, sections: Sections) -> Result<Self, Error>
+    where
+        R: Read + Seek,
+    {
+        let mut reader = framed::Reader::new(reader)?;
+        let mut read = |section, wanted| -> Result<Option<Vec<u8>>, Error> {
+            match wanted {
+                true => Ok(Some(reader.read(section)?.unwrap_or_default())),
+                false => Ok(None),
+            }
+        };
+
+        Ok(Self {
+            file_revisions: read(Section::FileRevisions, sections.file_revisions)?,
+            patchsets: read(Section::Patchsets, sections.patchsets)?,
+            tags: read(Section::Tags, sections.tags)?,
+            raw_marks: read(Section::RawMarks, sections.marks)?,
+            directories: read(Section::Directories, true)?.unwrap_or_default(),
+            commit_times: read(Section::CommitTimes, true)?.unwrap_or_default(),
+            tag_digests: read(Section::TagDigests, sections.tags)?.unwrap_or_default(),
+            runs: read(Section::Runs, true)?.unwrap_or_default(),
+            skipped_files: read(Section::SkippedFiles, true)?.unwrap_or_default(),
+            path_normalization: read(Section::PathNormalization, true)?.unwrap_or_default(),
+        })
+    }
+
+    /// Reads a version 2 or 3 store, which has to be read in full, and keeps
+    /// the given sections.
+    fn read_ser<R>(reader: R, compressed: bool, sections: Sections) -> Result<Self, Error>
+    where
+        R: Read,
+    {
+        log::debug!("reading from speedy");
+        let ser = if compressed {
+            Ser::read_from_stream_buffered(zstd::Decoder::new(reader)?)?
+        } else {
+            Ser::read_from_stream_buffered(reader)?
+        };
+        log::debug!("reading from speedy complete");
+
+        let Ser {
+            version,
+            file_revisions,
+            patchsets,
+            tags,
+            raw_marks,
+            directories,
+            commit_times,
+            tag_digests,
+            raw_mark_chunks,
+            runs,
+            skipped_files,
+            path_normalization,
+        } = ser;
+        if version != 2 && version != 3 {
+            return Err(Error::UnknownSerialisationVersion(version));
+        }
+        let raw_marks = if raw_mark_chunks.is_empty() {
+            raw_marks
+        } else {
+            raw_mark_chunks.concat()
+        };
+
+        Ok(Self {
+            file_revisions: Some(file_revisions).filter(|_| sections.file_revisions),
+            patchsets: Some(patchsets).filter(|_| sections.patchsets),
+            tags: Some(tags).filter(|_| sections.tags),
+            raw_marks: Some(raw_marks).filter(|_| sections.marks),
+            directories,
+            commit_times,
+            tag_digests,
+            runs,
+            skipped_files,
+            path_normalization,
+        })
+    }
+}
+
+/// Deserialises a section, or returns its default if it's empty because the
+/// store was written before the section was added.
+fn deserialize_or_default<T>(section: &[u8]) -> Result<T, Error>
+where
+    T: DeserializeOwned + Default,
+{
+    if section.is_empty() {
+        Ok(T::default())
+    } else {
+        Ok(bincode::deserialize(section)?)
+    }
+}
+
+/// Deserialises a section if it was read.
+fn deserialize_if_read<T>(section: Option<Vec<u8>>) -> Result<Option<T>, bincode::Error>
+where
+    T: DeserializeOwned,
+{
+    section
+        .map(|section| bincode::deserialize(&section))
+        .transpose()
 }
 
 impl Manager {
@@ -60,7 +300,26 @@ impl Manager {
     }
 
     /// Read the state from disk.
-    pub async fn deserialize_from<R>(mut reader: R) -> Result<Self, Error>
+    pub async fn deserialize_from<R>(reader: R) -> Result<Self, Error>
+    where
+        R: Read + Seek,
+    {
+        Self::deserialize_sections_from(reader, Sections::ALL).await
+    }
+
+    /// Read only the given sections of the state from disk, leaving the
+    /// others empty, which is quicker for operations that don't need them.
+    /// More sections can be loaded later with [`Manager::load_sections`].
+    ///
+    /// A partially loaded state can't be written back to disk, since that
+    /// would lose the sections that weren't loaded. Sections that aren't
+    /// loaded are never read from stores written by this version, but stores
+    /// written by earlier versions have to be read in full, and stores written
+    /// by version 0.1.0 are always loaded in full.
+    pub async fn deserialize_sections_from<R>(
+        mut reader: R,
+        sections: Sections,
+    ) -> Result<Self, Error>
     where
         R: Read + Seek,
     {
@@ -72,47 +331,120 @@ impl Manager {
         reader.rewind()?;
         if buffer[0] == 1 {
             log::info!("detected v1 state store; migrating to v2");
-            return v1::deserialize_from(reader).await;
+            let manager = v1::deserialize_from(reader).await?;
+            manager.index_shas().await?;
+            return Ok(manager);
         }
 
-        log::debug!("reading from speedy");
-        let ser = Ser::read_from_stream_buffered(zstd::Decoder::new(reader)?)?;
-        log::debug!("reading from speedy complete");
+        // Version 4 starts with its version, followed by the location of each
+        // section. Versions 2 and 3 are usually compressed with zstd, but can
+        // also be written without compression, in which case the first byte is
+        // the version of `Ser` rather than the start of the zstd magic number.
+        let raw = if buffer[0] == framed::VERSION {
+            RawSections::read_framed(reader, sections)?
+        } else {
+            RawSections::read_ser(reader, buffer[0] == ZSTD_MAGIC_START, sections)?
+        };
 
-        if ser.version != 2 {
-            return Err(Error::UnknownSerialisationVersion(ser.version));
-        }
+        let directories: Directories = deserialize_or_default(&raw.directories)?;
+        let commit_times = deserialize_or_default(&raw.commit_times)?;
+        let runs = deserialize_or_default(&raw.runs)?;
+        let skipped_files = deserialize_or_default(&raw.skipped_files)?;
+        let path_normalization = deserialize_or_default(&raw.path_normalization)?;
+        let tag_digests = deserialize_or_default(&raw.tag_digests)?;
 
-        let file_revisions = ser.file_revisions;
-        let patchsets = ser.patchsets;
-        let tags = ser.tags;
-        let raw_marks = ser.raw_marks;
+        let RawSections {
+            file_revisions,
+            patchsets,
+            tags,
+            raw_marks,
+            ..
+        } = raw;
 
-        log::debug!("starting deserialisation");
+        log::debug!("starting deserialisation of {:?}", sections);
         // We'll parallelise the individual data structure deserialisations,
-        // since CPU is generally the blocker here.
+        // since CPU is generally the blocker here. Sections that weren't asked
+        // for weren't read, and are skipped entirely.
         let (file_revisions, patchsets, tags, raw_marks) = tokio::try_join!(
-            task::spawn(async move { bincode::deserialize(&file_revisions) }),
-            task::spawn(async move { bincode::deserialize(&patchsets) }),
-            task::spawn(async move { bincode::deserialize(&tags) }),
-            task::spawn(async move { bincode::deserialize(&raw_marks) }),
+            task::spawn(async move { deserialize_if_read(file_revisions) }),
+            task::spawn(async move { deserialize_if_read(patchsets) }),
+            task::spawn(async move { deserialize_if_read(tags) }),
+            task::spawn(async move { deserialize_if_read(raw_marks) }),
         )
         .unwrap();
         log::debug!("deserialisation complete");
 
-        Ok(Self {
-            file_revisions: Arc::new(RwLock::new(file_revisions?)),
-            patchsets: Arc::new(RwLock::new(patchsets?)),
-            tags: Arc::new(RwLock::new(tags?)),
-            raw_marks: Arc::new(RwLock::new(raw_marks?)),
-        })
+        let mut tags: tag::Store = tags?.unwrap_or_default();
+        if sections.tags {
+            tags.set_digests(tag_digests);
+        }
+
+        let manager = Self {
+            file_revisions: Arc::new(file_revisions?.unwrap_or_default()),
+            patchsets: Arc::new(RwLock::new(patchsets?.unwrap_or_default())),
+            tags: Arc::new(RwLock::new(tags)),
+            raw_marks: Arc::new(RwLock::new(raw_marks?.unwrap_or_default())),
+            directories: Arc::new(RwLock::new(directories)),
+            commit_times: Arc::new(RwLock::new(commit_times)),
+            runs: Arc::new(RwLock::new(runs)),
+            skipped_files: Arc::new(RwLock::new(skipped_files)),
+            path_normalization: Arc::new(RwLock::new(path_normalization)),
+            shas: Default::default(),
+            sections,
+        };
+        manager.index_shas().await?;
+
+        Ok(manager)
     }
 
-    /// Write the state to disk.
-    pub async fn serialize_into<W>(self, writer: W) -> Result<(), Error>
+    /// Loads the given sections from the store that a partially loaded state
+    /// was read from, so that each operation can load the sections it needs
+    /// when it needs them. Sections that are already loaded are left alone,
+    /// along with any changes made to them since.
+    pub async fn load_sections<R>(&mut self, reader: R, sections: Sections) -> Result<(), Error>
+    where
+        R: Read + Seek,
+    {
+        let missing = Sections {
+            file_revisions: sections.file_revisions && !self.sections.file_revisions,
+            patchsets: sections.patchsets && !self.sections.patchsets,
+            tags: sections.tags && !self.sections.tags,
+            marks: sections.marks && !self.sections.marks,
+        };
+        if missing == Sections::NONE {
+            return Ok(());
+        }
+
+        let loaded = Self::deserialize_sections_from(reader, missing).await?;
+        if missing.file_revisions {
+            self.file_revisions = loaded.file_revisions;
+            self.sections.file_revisions = true;
+        }
+        if missing.patchsets {
+            self.patchsets = loaded.patchsets;
+            self.sections.patchsets = true;
+        }
+        if missing.tags {
+            self.tags = loaded.tags;
+            self.sections.tags = true;
+        }
+        if missing.marks {
+            self.raw_marks = loaded.raw_marks;
+            self.sections.marks = true;
+        }
+
+        self.index_shas().await
+    }
+
+    /// Write the state to disk, compressing each section as given.
+    pub async fn serialize_into<W>(self, writer: W, compression: Compression) -> Result<(), Error>
     where
         W: Write,
     {
+        if self.sections != Sections::ALL {
+            return Err(Error::PartialState);
+        }
+
         let file_revisions = self.file_revisions.clone();
         let patchsets = self.patchsets.clone();
         let tags = self.tags.clone();
@@ -123,10 +455,10 @@ impl Manager {
         // CPU is generally the blocker here.
         //
         // Note that we use bincode here: although bincode is slower than speedy
-        // (which is what we use for the outer wrapper `Ser`), it supports types
-        // behind `Arc`, and the parallelisation means this isn't _so_ bad.
+        // (which is what we use for the header), it supports types behind
+        // `Arc`, and the parallelisation means this isn't _so_ bad.
         let (file_revisions, patchsets, tags, raw_marks) = tokio::try_join!(
-            task::spawn(async move { bincode::serialize(&*file_revisions.read().await) }),
+            task::spawn(async move { bincode::serialize(&*file_revisions) }),
             task::spawn(async move { bincode::serialize(&*patchsets.read().await) }),
             task::spawn(async move { bincode::serialize(&*tags.read().await) }),
             task::spawn(async move { bincode::serialize(&*raw_marks.read().await) }),
@@ -134,24 +466,60 @@ impl Manager {
         .unwrap();
         log::debug!("serialisation complete");
 
-        let ser = Ser {
-            version: 2,
-            file_revisions: file_revisions?,
-            patchsets: patchsets?,
-            tags: tags?,
-            raw_marks: raw_marks?,
-        };
+        let sections = vec![
+            (Section::FileRevisions, file_revisions?),
+            (Section::Patchsets, patchsets?),
+            (Section::Tags, tags?),
+            (Section::RawMarks, raw_marks?),
+            (
+                Section::Directories,
+                bincode::serialize(&*self.directories.read().await)?,
+            ),
+            (
+                Section::CommitTimes,
+                bincode::serialize(&*self.commit_times.read().await)?,
+            ),
+            (
+                Section::TagDigests,
+                bincode::serialize(self.tags.read().await.get_digests())?,
+            ),
+            (Section::Runs, bincode::serialize(&*self.runs.read().await)?),
+            (
+                Section::SkippedFiles,
+                bincode::serialize(&*self.skipped_files.read().await)?,
+            ),
+            (
+                Section::PathNormalization,
+                bincode::serialize(&*self.path_normalization.read().await)?,
+            ),
+        ];
 
-        log::debug!("writing to speedy");
-        {
-            let mut zstd_writer = zstd::Encoder::new(writer, 0)?;
-            ser.write_to_stream(&mut zstd_writer)?;
-            zstd_writer.finish()?;
-        }
-        log::debug!("writing to speedy complete");
+        log::debug!("writing sections with compression {:?}", compression);
+        framed::write(writer, sections, compression)?;
+        log::debug!("writing sections complete");
         Ok(())
     }
 
+    /// Writes the state as portable JSON, which can be read back with
+    /// [`Manager::import_json`] by this or any later version.
+    pub async fn export_json<W>(&self, writer: W) -> Result<(), Error>
+    where
+        W: Write,
+    {
+        let state = portable::State::from_manager(self).await;
+        Ok(serde_json::to_writer_pretty(writer, &state)?)
+    }
+
+    /// Reads the state from the JSON written by [`Manager::export_json`].
+    pub async fn import_json<R>(mut reader: R) -> Result<Self, Error>
+    where
+        R: Read,
+    {
+        let mut json = Vec::new();
+        reader.read_to_end(&mut json)?;
+        portable::State::from_json(&json)?.into_manager().await
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub async fn add_file_revision<I>(
         &self,
@@ -167,7 +535,7 @@ impl Manager {
         I: Iterator,
         I::Item: AsRef<[u8]>,
     {
-        self.file_revisions.write().await.add(
+        self.file_revisions.add(
             file_revision::Key {
                 path: path.to_path_buf(),
                 revision: revision.to_string(),
@@ -180,6 +548,37 @@ impl Manager {
         )
     }
 
+    /// Adds a batch of file revisions. The returned IDs are in the same order
+    /// as the given revisions.
+    ///
+    /// If a revision can't be added, the batch stops there, and the error is
+    /// returned along with that revision's index in the batch so that the
+    /// caller can say which revision it was.
+    pub async fn add_file_revisions<I>(
+        &self,
+        revisions: I,
+    ) -> Result<Vec<file_revision::ID>, (usize, Error)>
+    where
+        I: IntoIterator<Item = FileRevision>,
+    {
+        revisions
+            .into_iter()
+            .enumerate()
+            .map(|(index, revision)| {
+                self.file_revisions
+                    .add(
+                        revision.key,
+                        revision.mark,
+                        revision.branches.iter(),
+                        &revision.author,
+                        &revision.message,
+                        &revision.time,
+                    )
+                    .map_err(|e| (index, e))
+            })
+            .collect()
+    }
+
     pub async fn add_patchset<I>(
         &self,
         mark: Mark,
@@ -195,6 +594,59 @@ impl Manager {
             .add(mark.into(), branch, time, file_revision_iter)
     }
 
+    /// Adds another set of file revisions that should be considered to match
+    /// the content of an existing patchset.
+    ///
+    /// This is used when a patchset is split because only some of its files
+    /// were imported previously, so that the whole patchset is recognised as
+    /// having been sent on later runs.
+    pub async fn add_patchset_alias<I>(&self, mark: Mark, time: &SystemTime, file_revision_iter: I)
+    where
+        I: Iterator<Item = file_revision::ID>,
+    {
+        self.patchsets
+            .write()
+            .await
+            .add_alias(mark.into(), time, file_revision_iter)
+    }
+
+    /// Records a run that updated the store.
+    pub async fn add_run(&self, run: Run) {
+        self.runs.write().await.push(run);
+    }
+
+    /// Returns the runs that have updated the store, oldest first.
+    pub async fn get_runs(&self) -> Vec<Run> {
+        self.runs.read().await.clone()
+    }
+
+    /// Replaces the RCS files recorded as skipped, along with their reasons.
+    pub async fn set_skipped_files(&self, skipped_files: BTreeMap<PathBuf, String>) {
+        *self.skipped_files.write().await = skipped_files;
+    }
+
+    /// Returns the RCS files recorded as skipped, along with their reasons.
+    pub async fn get_skipped_files(&self) -> BTreeMap<PathBuf, String> {
+        self.skipped_files.read().await.clone()
+    }
+
+    /// Records the Unicode normalization form that paths are converted to.
+    pub async fn set_path_normalization(&self, form: &str) {
+        *self.path_normalization.write().await = Some(form.to_string());
+    }
+
+    /// Returns the Unicode normalization form that paths were converted to, if
+    /// it's been recorded.
+    pub async fn get_path_normalization(&self) -> Option<String> {
+        self.path_normalization.read().await.clone()
+    }
+
+    /// Records the time that the patchset with the given mark was committed
+    /// at, if it was adjusted from the patchset time.
+    pub async fn set_commit_time(&self, mark: Mark, time: &SystemTime) {
+        self.commit_times.write().await.insert(mark.into(), *time);
+    }
+
     pub async fn add_branch_to_patchset_mark(&self, mark: Mark, branch: &[u8]) {
         self.patchsets
             .write()
@@ -202,20 +654,70 @@ impl Manager {
             .add_branch_to_patchset(mark.into(), branch)
     }
 
+    /// Records that the given directories have been imported.
+    pub async fn add_directories<I, P>(&self, directories: I)
+    where
+        I: Iterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let mut store = self.directories.write().await;
+        for directory in directories {
+            store.add(directory.as_ref());
+        }
+    }
+
     pub async fn add_tag(&self, tag: &[u8], file_revision_id: file_revision::ID) {
         self.tags.write().await.add_tag(tag, file_revision_id)
     }
 
+    /// Adds the mark of the fake commit created for the tag's current file
+    /// revisions.
     pub async fn add_tag_mark(&self, tag: &[u8], mark: Mark) {
         self.tags.write().await.add_mark(tag, mark.into())
     }
 
+    /// Records that the fake commit for the tag matches its current file
+    /// revisions, for tags that were committed before digests were stored.
+    pub async fn record_tag_digest(&self, tag: &[u8]) {
+        self.tags.write().await.record_digest(tag)
+    }
+
+    /// Replaces the file revisions of every tag with the given file
+    /// revisions, such as those observed in the current run. Tags that aren't
+    /// included no longer have any file revisions, but keep the mark of their
+    /// fake commit until they're removed with [`Manager::remove_tag`].
+    pub async fn set_tag_file_revisions(
+        &self,
+        tags: HashMap<Vec<u8>, BTreeSet<file_revision::ID>>,
+    ) {
+        self.tags.write().await.set_file_revisions(tags)
+    }
+
+    /// Removes a tag, along with the branch its fake commits are recorded on.
+    pub async fn remove_tag(&self, tag: &[u8]) {
+        self.tags.write().await.remove(tag);
+        self.patchsets.write().await.remove_branch(tag);
+    }
+
+    /// Returns the name of every tag with a fake commit, in name order.
+    pub async fn get_tags_with_marks(&self) -> Vec<Vec<u8>> {
+        let mut tags: Vec<Vec<u8>> = self
+            .tags
+            .read()
+            .await
+            .get_tags_with_marks()
+            .map(Vec::from)
+            .collect();
+        tags.sort();
+        tags
+    }
+
     pub async fn get_file_revision(
         &self,
         path: &Path,
         revision: &str,
     ) -> Result<Arc<FileRevision>, Error> {
-        match self.file_revisions.read().await.get_by_key(path, revision) {
+        match self.file_revisions.get_by_key(path, revision) {
             Some(revision) => Ok(revision),
             None => Err(Error::NoFileRevisionForKey(file_revision::Key {
                 path: path.to_path_buf(),
@@ -224,20 +726,113 @@ impl Manager {
         }
     }
 
+    /// Returns the ID of the file revision with the given path and revision,
+    /// if it has been added.
+    pub async fn get_file_revision_id(
+        &self,
+        path: &Path,
+        revision: &str,
+    ) -> Option<file_revision::ID> {
+        self.file_revisions.get_id_by_key(path, revision)
+    }
+
     pub async fn get_file_revision_by_id(
         &self,
         id: file_revision::ID,
     ) -> Result<Arc<FileRevision>, Error> {
-        match self.file_revisions.read().await.get_by_id(id) {
+        match self.file_revisions.get_by_id(id) {
             Some(revision) => Ok(revision),
             None => Err(Error::NoFileRevisionForID(id)),
         }
     }
 
+    /// Returns the IDs of every file revision that has content, which excludes
+    /// revisions that delete the file.
+    pub async fn get_file_revision_ids_with_marks(&self) -> Vec<file_revision::ID> {
+        self.file_revisions.get_ids_with_marks()
+    }
+
+    /// Returns the directories that have been imported. This will be empty if
+    /// nothing has been imported yet, or if the state was created before the
+    /// directories were tracked.
+    pub async fn get_directories(&self) -> Directories {
+        self.directories.read().await.clone()
+    }
+
+    /// Returns the names of the branches with patchsets, in name order. Note
+    /// that the fake commits created for tags are also on branches named after
+    /// each tag.
+    pub async fn get_branches(&self) -> Vec<Vec<u8>> {
+        let mut branches: Vec<Vec<u8>> = self
+            .patchsets
+            .read()
+            .await
+            .get_branches()
+            .map(Vec::from)
+            .collect();
+        branches.sort();
+        branches
+    }
+
     pub async fn get_last_patchset_mark_on_branch(&self, branch: &[u8]) -> Option<patchset::Mark> {
         self.patchsets.read().await.get_last_mark_on_branch(branch)
     }
 
+    /// Returns the marks of the patchsets on the branch, in the order they were
+    /// committed.
+    pub async fn get_patchset_marks_on_branch(&self, branch: &[u8]) -> Vec<patchset::Mark> {
+        self.patchsets
+            .read()
+            .await
+            .get_marks_on_branch(branch)
+            .copied()
+            .collect()
+    }
+
+    /// Returns the latest revision of each file committed on the branch,
+    /// including files whose latest revision deletes them, keyed by path.
+    pub async fn get_files_on_branch(
+        &self,
+        branch: &[u8],
+    ) -> Result<BTreeMap<PathBuf, Arc<FileRevision>>, Error> {
+        let patchsets = self.patchsets.read().await;
+
+        // Revisions of a file are added in revision order, so the latest
+        // revision of each file has the highest ID.
+        let mut files: BTreeMap<PathBuf, (file_revision::ID, Arc<FileRevision>)> = BTreeMap::new();
+        for mark in patchsets.get_marks_on_branch(branch) {
+            let patchset = match patchsets.get_by_mark(mark) {
+                Some(patchset) => patchset,
+                None => return Err(Error::NoPatchSetForMark(*mark)),
+            };
+
+            for id in patchset.file_revisions.iter() {
+                let revision = match self.file_revisions.get_by_id(*id) {
+                    Some(revision) => revision,
+                    None => return Err(Error::NoFileRevisionForID(*id)),
+                };
+                match files.get(&revision.key.path) {
+                    Some((latest, _)) if latest > id => {}
+                    _ => {
+                        files.insert(revision.key.path.clone(), (*id, revision));
+                    }
+                }
+            }
+        }
+
+        Ok(files
+            .into_iter()
+            .map(|(path, (_id, revision))| (path, revision))
+            .collect())
+    }
+
+    /// Returns whether the tag's file revisions are unchanged since its fake
+    /// commit was created, without fetching the commit's patchset, or `None`
+    /// if that isn't known.
+    pub async fn is_tag_unchanged(&self, tag: &[u8]) -> Option<bool> {
+        self.tags.read().await.is_unchanged(tag)
+    }
+
     pub async fn get_mark_for_tag(&self, tag: &[u8]) -> Option<Mark> {
         self.tags.read().await.get_mark(tag).map(|mark| mark.into())
     }
@@ -266,6 +861,31 @@ impl Manager {
         }
     }
 
+    /// Returns the object name of the commit that was created for the
+    /// patchset with the given mark.
+    ///
+    /// This is only available once the marks exported by `git fast-import`
+    /// have been stored with [`Manager::set_raw_marks`].
+    pub async fn get_sha_for_patchset_mark(&self, mark: &Mark) -> Result<String, Error> {
+        let patchset_mark = patchset::Mark::from(*mark);
+        if let Some(sha) = self.shas.read().await.get(&patchset_mark) {
+            Ok(sha.clone())
+        } else {
+            Err(Error::NoShaForPatchSetMark(patchset_mark))
+        }
+    }
+
+    /// Returns the time that the patchset with the given mark was committed
+    /// at, which is the patchset time unless it was adjusted.
+    pub async fn get_commit_time(&self, mark: &Mark) -> Result<SystemTime, Error> {
+        let patchset_mark = patchset::Mark::from(*mark);
+        if let Some(time) = self.commit_times.read().await.get(&patchset_mark) {
+            return Ok(*time);
+        }
+
+        Ok(self.get_patchset_from_mark(mark).await?.time)
+    }
+
     pub async fn get_file_revisions_for_tag(&self, tag: &[u8]) -> TagFileRevisionIterator<'_> {
         TagFileRevisionIterator {
             guard: self.tags.read().await,
@@ -343,6 +963,92 @@ impl Manager {
         tokio::io::copy(&mut reader, &mut buf).await?;
 
         raw_marks.extend(buf.into_iter());
+        drop(raw_marks);
+
+        self.index_shas().await
+    }
+
+    /// Checks that every mark referenced by the file revisions and patchsets
+    /// in the state is in the raw marks, and is before the mark that the next
+    /// `git fast-import` will allocate, which follows the last raw mark.
+    ///
+    /// Otherwise, the state and the Git repository have diverged, such as when
+    /// an import is interrupted after the store is saved, and the next import
+    /// would refer to objects that Git doesn't have, or reuse their marks.
+    ///
+    /// This only checks the state against itself: the objects returned by
+    /// [`Manager::get_referenced_objects`] also need to be checked against the
+    /// Git repository.
+    pub async fn validate_marks(&self) -> Result<(), Error> {
+        let raw_marks = self.raw_marks.read().await;
+        let marks = git_fast_import::read_marks(raw_marks.as_slice())?;
+        let next = git_fast_import::get_last_mark(std::io::Cursor::new(raw_marks.as_slice()))?
+            .map(|mark| mark.next())
+            .unwrap_or_else(|| Mark::from(1));
+
+        let missing: Vec<Mark> = self
+            .referenced_marks()
+            .await
+            .into_iter()
+            .filter(|mark| *mark >= next || !marks.contains_key(mark))
+            .collect();
+        match missing.first() {
+            Some(first) => Err(Error::MissingMarks {
+                count: missing.len(),
+                first: *first,
+                next,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the object name of each mark referenced by the file revisions
+    /// and patchsets in the state, according to the raw marks, so that they
+    /// can be checked against the Git repository.
+    ///
+    /// Marks that aren't in the raw marks are left out, since
+    /// [`Manager::validate_marks`] reports those.
+    pub async fn get_referenced_objects(&self) -> Result<Vec<(Mark, String)>, Error> {
+        let mut marks = git_fast_import::read_marks(self.raw_marks.read().await.as_slice())?;
+
+        Ok(self
+            .referenced_marks()
+            .await
+            .into_iter()
+            .filter_map(|mark| marks.remove(&mark).map(|sha| (mark, sha)))
+            .collect())
+    }
+
+    /// Returns every mark referenced by the file revisions and patchsets.
+    async fn referenced_marks(&self) -> BTreeSet<Mark> {
+        let mut referenced: BTreeSet<Mark> = self
+            .file_revisions
+            .get_marks()
+            .into_iter()
+            .map(|mark| mark.into())
+            .collect();
+        referenced.extend(
+            self.patchsets
+                .read()
+                .await
+                .get_all()
+                .map(|(mark, _patchset)| Mark::from(mark)),
+        );
+
+        referenced
+    }
+
+    /// Rebuilds the patchset mark to object name mapping from the raw marks.
+    async fn index_shas(&self) -> Result<(), Error> {
+        let marks = git_fast_import::read_marks(self.raw_marks.read().await.as_slice())?;
+        let patchsets = self.patchsets.read().await;
+
+        // The mark file also includes every blob, which we don't need to keep.
+        *self.shas.write().await = marks
+            .into_iter()
+            .map(|(mark, sha)| (patchset::Mark::from(mark), sha))
+            .filter(|(mark, _sha)| patchsets.get_by_mark(mark).is_some())
+            .collect();
 
         Ok(())
     }
@@ -379,3 +1085,212 @@ impl<'a> TagFileRevisionIterator<'a> {
         self.guard.get_file_revisions(&self.tag)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_validate_marks() -> Result<(), Error> {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let manager = Manager::new();
+        manager.validate_marks().await?;
+
+        let a = manager
+            .add_file_revision(
+                Path::new("mod/a.txt,v"),
+                "1.1",
+                Some(Mark::from(1)),
+                [b"main"].iter(),
+                "adam",
+                "add files",
+                &time,
+            )
+            .await?;
+        manager
+            .add_patchset(Mark::from(2), b"main", &time, vec![a].into_iter())
+            .await;
+
+        // The patchset was committed, but its mark was never saved.
+        manager
+            .set_raw_marks(format!(":1 {}\n", "a".repeat(40)).as_bytes())
+            .await?;
+        assert!(matches!(
+            manager.validate_marks().await,
+            Err(Error::MissingMarks { count: 1, first, next }) if first == Mark::from(2) && next == Mark::from(2)
+        ));
+
+        manager
+            .set_raw_marks(format!(":1 {}\n:2 {}\n", "a".repeat(40), "b".repeat(40)).as_bytes())
+            .await?;
+        manager.validate_marks().await?;
+        assert_eq!(
+            manager.get_referenced_objects().await?,
+            vec![
+                (Mark::from(1), "a".repeat(40)),
+                (Mark::from(2), "b".repeat(40))
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_deserialize_sections() -> Result<(), Error> {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let manager = Manager::new();
+        let a = manager
+            .add_file_revision(
+                Path::new("mod/a.txt,v"),
+                "1.1",
+                Some(Mark::from(1)),
+                [b"main"].iter(),
+                "adam",
+                "add files",
+                &time,
+            )
+            .await?;
+        manager
+            .add_patchset(Mark::from(2), b"main", &time, vec![a].into_iter())
+            .await;
+        manager.add_tag(b"REL1", a).await;
+        manager
+            .set_raw_marks(format!(":1 {}\n:2 {}\n", "a".repeat(40), "b".repeat(40)).as_bytes())
+            .await?;
+
+        let mut store = Vec::new();
+        manager
+            .serialize_into(&mut store, Compression::default())
+            .await?;
+
+        let partial = Manager::deserialize_sections_from(
+            std::io::Cursor::new(&store),
+            Sections {
+                patchsets: true,
+                tags: true,
+                ..Sections::NONE
+            },
+        )
+        .await?;
+        assert_eq!(partial.get_branches().await, vec![b"main".to_vec()]);
+        assert_eq!(
+            partial
+                .get_tags()
+                .await
+                .iter()
+                .map(Vec::from)
+                .collect::<Vec<_>>(),
+            vec![b"REL1".to_vec()]
+        );
+        assert!(partial.get_file_revision_by_id(a).await.is_err());
+        assert!(partial
+            .get_sha_for_patchset_mark(&Mark::from(2))
+            .await
+            .is_err());
+        assert!(matches!(
+            partial
+                .serialize_into(Vec::new(), Compression::default())
+                .await,
+            Err(Error::PartialState)
+        ));
+
+        let full = Manager::deserialize_from(std::io::Cursor::new(&store)).await?;
+        assert!(full.get_file_revision_by_id(a).await.is_ok());
+        assert_eq!(
+            full.get_sha_for_patchset_mark(&Mark::from(2)).await?,
+            "b".repeat(40)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_chunked_marks() -> Result<(), Error> {
+        // Version 3 stores split marks that were too long for speedy into
+        // chunks, which are still joined back together when they're read.
+        let raw_marks = format!(":1 {}\n:2 {}\n", "a".repeat(40), "b".repeat(40));
+        let serialized = bincode::serialize(raw_marks.as_bytes())?;
+        let ser = Ser {
+            version: 3,
+            file_revisions: bincode::serialize(&file_revision::Store::default())?,
+            patchsets: bincode::serialize(&patchset::Store::default())?,
+            tags: bincode::serialize(&tag::Store::default())?,
+            raw_marks: Vec::new(),
+            directories: bincode::serialize(&Directories::default())?,
+            commit_times: Vec::new(),
+            tag_digests: Vec::new(),
+            raw_mark_chunks: serialized.chunks(16).map(Vec::from).collect(),
+            runs: Vec::new(),
+            skipped_files: Vec::new(),
+            path_normalization: Vec::new(),
+        };
+        let store = ser.write_to_vec()?;
+
+        let loaded = Manager::deserialize_from(std::io::Cursor::new(&store)).await?;
+        let mut loaded_marks = Vec::new();
+        loaded.get_raw_marks(&mut loaded_marks).await?;
+        assert_eq!(loaded_marks, raw_marks.as_bytes());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unread_sections() -> Result<(), Error> {
+        let raw_marks = format!(":1 {}\n", "a".repeat(40));
+        let manager = Manager::new();
+        manager.set_raw_marks(raw_marks.as_bytes()).await?;
+
+        let mut store = Vec::new();
+        manager
+            .serialize_into(&mut store, Compression::default())
+            .await?;
+
+        // Corrupting the file revisions only breaks loading them, since no
+        // other operation reads them.
+        let range = framed::Reader::new(std::io::Cursor::new(&store))?
+            .range(Section::FileRevisions)
+            .unwrap();
+        for byte in store[range].iter_mut() {
+            *byte = 0xff;
+        }
+
+        let marks = Sections {
+            marks: true,
+            ..Sections::NONE
+        };
+        let mut partial =
+            Manager::deserialize_sections_from(std::io::Cursor::new(&store), marks).await?;
+        let mut loaded_marks = Vec::new();
+        partial.get_raw_marks(&mut loaded_marks).await?;
+        assert_eq!(loaded_marks, raw_marks.as_bytes());
+
+        partial
+            .load_sections(
+                std::io::Cursor::new(&store),
+                Sections {
+                    patchsets: true,
+                    tags: true,
+                    ..marks
+                },
+            )
+            .await?;
+        assert_eq!(
+            partial.sections,
+            Sections {
+                file_revisions: false,
+                ..Sections::ALL
+            }
+        );
+        assert!(partial
+            .load_sections(std::io::Cursor::new(&store), Sections::ALL)
+            .await
+            .is_err());
+        assert!(Manager::deserialize_from(std::io::Cursor::new(&store))
+            .await
+            .is_err());
+
+        Ok(())
+    }
+}
@@ -60,6 +60,14 @@ impl Store {
         self.patchsets.insert(mark, patchset);
     }
 
+    pub(crate) fn add_alias<I>(&mut self, mark: Mark, time: &SystemTime, file_revision_iter: I)
+    where
+        I: Iterator<Item = file_revision::ID>,
+    {
+        self.by_content
+            .insert(Arc::new(build_patchset(*time, file_revision_iter)), mark);
+    }
+
     pub(crate) fn add_branch_to_patchset(&mut self, mark: Mark, branch: &[u8]) {
         self.by_branch
             .entry(branch.to_vec())
@@ -88,6 +96,51 @@ impl Store {
         self.by_file_revision.get(&id)
     }
 
+    pub(crate) fn get_branches(&self) -> impl Iterator<Item = &[u8]> {
+        self.by_branch.keys().map(|branch| branch.as_slice())
+    }
+
+    pub(crate) fn get_marks_on_branch(&self, branch: &[u8]) -> impl Iterator<Item = &Mark> {
+        self.by_branch.get(branch).into_iter().flatten()
+    }
+
+    /// Iterates over every patchset, in mark order.
+    pub(crate) fn get_all(&self) -> impl Iterator<Item = (Mark, &PatchSet)> {
+        self.patchsets
+            .iter()
+            .map(|(mark, patchset)| (*mark, patchset.as_ref()))
+    }
+
+    /// Iterates over the content added with [`Store::add_alias`]: that is,
+    /// content that maps to a patchset with different content.
+    pub(crate) fn get_aliases(&self) -> impl Iterator<Item = (Mark, &PatchSet)> {
+        self.by_content
+            .iter()
+            .filter(move |(content, mark)| self.patchsets.get(mark) != Some(content))
+            .map(|(content, mark)| (*mark, content.as_ref()))
+    }
+
+    /// Inserts a patchset without adding it to any branches.
+    pub(crate) fn insert(&mut self, mark: Mark, patchset: PatchSet) {
+        let patchset = Arc::new(patchset);
+        for id in patchset.file_revisions.iter() {
+            self.by_file_revision.entry(*id).or_default().push(mark);
+        }
+
+        self.by_content.insert(patchset.clone(), mark);
+        self.patchsets.insert(mark, patchset);
+    }
+
+    /// Replaces the marks of the patchsets on the given branch.
+    pub(crate) fn set_marks_on_branch(&mut self, branch: Vec<u8>, marks: Vec<Mark>) {
+        self.by_branch.insert(branch, marks);
+    }
+
+    /// Removes the given branch, leaving its patchsets in place.
+    pub(crate) fn remove_branch(&mut self, branch: &[u8]) {
+        self.by_branch.remove(branch);
+    }
+
     pub(crate) fn get_last_mark_on_branch(&self, branch: &[u8]) -> Option<Mark> {
         self.by_branch
             .get(branch)
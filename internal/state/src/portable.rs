@@ -0,0 +1,522 @@
+//! A portable JSON representation of the state.
+//!
+//! The store written by [`Manager::serialize_into`] depends on the internal
+//! layout of each data structure, so can only be read by a compatible version
+//! of `git-cvs-fast-import`. This format only includes the data itself, so it
+//! can be used to move a conversion between machines or versions, or to
+//! inspect it with tools such as `jq`.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ffi::OsString,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{file_revision, patchset, tag, Directories, Error, Manager, Run};
+
+/// The version of the format, which is incremented whenever a change is made
+/// that older versions can't read.
+const VERSION: u32 = 1;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct State {
+    version: u32,
+    file_revisions: Vec<FileRevision>,
+    patchsets: Vec<PatchSet>,
+
+    /// Patchset content that's recorded as having been sent as a different
+    /// patchset, such as when directories are added to an import.
+    aliases: Vec<PatchSet>,
+
+    branches: Vec<Branch>,
+    tags: Vec<Tag>,
+    directories: Vec<Bytes>,
+
+    /// Commit times that differ from the time of their patchset.
+    commit_times: Vec<CommitTime>,
+
+    /// The contents of the `git fast-import` mark file.
+    marks: Bytes,
+
+    /// The runs that updated the store, which are missing from JSON written
+    /// before they were recorded.
+    #[serde(default)]
+    runs: Vec<Run>,
+
+    /// The RCS files skipped by the last skip list given, which are also
+    /// missing from older JSON.
+    #[serde(default)]
+    skipped_files: Vec<SkippedFile>,
+
+    /// The Unicode normalization form that paths were converted to, which is
+    /// also missing from older JSON.
+    #[serde(default)]
+    path_normalization: Option<String>,
+}
+
+/// Just enough of [`State`] to check its version before parsing the rest.
+#[derive(Debug, Deserialize)]
+struct Header {
+    version: u32,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct FileRevision {
+    id: file_revision::ID,
+    path: Bytes,
+    revision: String,
+    mark: Option<file_revision::Mark>,
+    branches: Vec<Bytes>,
+    author: String,
+    message: String,
+    time: SystemTime,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct PatchSet {
+    mark: patchset::Mark,
+    time: SystemTime,
+    file_revisions: BTreeSet<file_revision::ID>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Branch {
+    name: Bytes,
+
+    /// The patchsets on the branch, in the order they were committed.
+    marks: Vec<patchset::Mark>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Tag {
+    name: Bytes,
+
+    /// The mark of the fake commit created for the tag, if any.
+    mark: Option<patchset::Mark>,
+
+    file_revisions: Option<BTreeSet<file_revision::ID>>,
+
+    /// The hex encoded digest of the file revisions on the tag when its fake
+    /// commit was created, if known.
+    digest: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct CommitTime {
+    mark: patchset::Mark,
+    time: SystemTime,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct SkippedFile {
+    path: Bytes,
+    reason: String,
+}
+
+/// A byte string, such as a path or a symbol name. These are written as JSON
+/// strings if they're valid UTF-8, and as arrays of bytes otherwise, so they
+/// survive the round trip unchanged.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+enum Bytes {
+    Text(String),
+    Raw(Vec<u8>),
+}
+
+impl Bytes {
+    fn as_bytes(&self) -> &[u8] {
+        match self {
+            Bytes::Text(text) => text.as_bytes(),
+            Bytes::Raw(raw) => raw,
+        }
+    }
+}
+
+impl From<&[u8]> for Bytes {
+    fn from(bytes: &[u8]) -> Self {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => Self::Text(text.to_string()),
+            Err(_) => Self::Raw(bytes.to_vec()),
+        }
+    }
+}
+
+impl From<&Path> for Bytes {
+    fn from(path: &Path) -> Self {
+        path.as_os_str().as_bytes().into()
+    }
+}
+
+impl From<Bytes> for Vec<u8> {
+    fn from(bytes: Bytes) -> Self {
+        match bytes {
+            Bytes::Text(text) => text.into_bytes(),
+            Bytes::Raw(raw) => raw,
+        }
+    }
+}
+
+impl From<Bytes> for PathBuf {
+    fn from(bytes: Bytes) -> Self {
+        OsString::from_vec(bytes.into()).into()
+    }
+}
+
+impl State {
+    pub(crate) async fn from_manager(manager: &Manager) -> Self {
+        let file_revisions = manager
+            .file_revisions
+            .get_all()
+            .into_iter()
+            .map(|(id, file_revision)| FileRevision {
+                id,
+                path: file_revision.key.path.as_path().into(),
+                revision: file_revision.key.revision.clone(),
+                mark: file_revision.mark,
+                branches: file_revision
+                    .branches
+                    .iter()
+                    .map(|branch| branch.as_slice().into())
+                    .collect(),
+                author: file_revision.author.clone(),
+                message: file_revision.message.clone(),
+                time: file_revision.time,
+            })
+            .collect();
+
+        let (patchsets, aliases, branches) = {
+            let store = manager.patchsets.read().await;
+            let convert = |(mark, patchset): (patchset::Mark, &patchset::PatchSet)| PatchSet {
+                mark,
+                time: patchset.time,
+                file_revisions: patchset.file_revisions.clone(),
+            };
+
+            let mut aliases: Vec<PatchSet> = store.get_aliases().map(convert).collect();
+            aliases.sort_by(|a, b| {
+                (a.mark, a.time, &a.file_revisions).cmp(&(b.mark, b.time, &b.file_revisions))
+            });
+
+            let mut branches: Vec<Branch> = store
+                .get_branches()
+                .map(|branch| Branch {
+                    name: branch.into(),
+                    marks: store.get_marks_on_branch(branch).copied().collect(),
+                })
+                .collect();
+            branches.sort_by(|a, b| a.name.as_bytes().cmp(b.name.as_bytes()));
+
+            (store.get_all().map(convert).collect(), aliases, branches)
+        };
+
+        let tags = {
+            let store = manager.tags.read().await;
+            let digests = store.get_digests();
+            store
+                .get_all_names()
+                .into_iter()
+                .map(|tag| Tag {
+                    name: tag.into(),
+                    mark: store.get_mark(tag),
+                    file_revisions: store.get_file_revisions(tag).cloned(),
+                    digest: digests.get(tag).map(|digest| hex(digest)),
+                })
+                .collect()
+        };
+
+        Self {
+            version: VERSION,
+            file_revisions,
+            patchsets,
+            aliases,
+            branches,
+            tags,
+            directories: manager
+                .directories
+                .read()
+                .await
+                .iter()
+                .map(Bytes::from)
+                .collect(),
+            commit_times: manager
+                .commit_times
+                .read()
+                .await
+                .iter()
+                .map(|(mark, time)| CommitTime {
+                    mark: *mark,
+                    time: *time,
+                })
+                .collect(),
+            marks: manager.raw_marks.read().await.as_slice().into(),
+            runs: manager.get_runs().await,
+            skipped_files: manager
+                .get_skipped_files()
+                .await
+                .into_iter()
+                .map(|(path, reason)| SkippedFile {
+                    path: path.as_path().into(),
+                    reason,
+                })
+                .collect(),
+            path_normalization: manager.get_path_normalization().await,
+        }
+    }
+
+    pub(crate) fn from_json(json: &[u8]) -> Result<Self, Error> {
+        let header: Header = serde_json::from_slice(json)?;
+        if header.version != VERSION {
+            return Err(Error::UnknownPortableVersion(header.version));
+        }
+
+        Ok(serde_json::from_slice(json)?)
+    }
+
+    pub(crate) async fn into_manager(self) -> Result<Manager, Error> {
+        // The store requires file revision IDs to be dense, as they are when
+        // they're allocated.
+        for (i, file_revision) in self.file_revisions.iter().enumerate() {
+            if usize::from(file_revision.id) != i {
+                return Err(Error::Load(format!(
+                    "expected file revision ID {}, but found {}",
+                    i, file_revision.id
+                )));
+            }
+        }
+        let file_revisions = self
+            .file_revisions
+            .into_iter()
+            .map(|file_revision| {
+                (
+                    file_revision.id,
+                    Arc::new(file_revision::FileRevision {
+                        key: file_revision::Key {
+                            path: file_revision.path.into(),
+                            revision: file_revision.revision,
+                        },
+                        mark: file_revision.mark,
+                        branches: file_revision.branches.into_iter().map(Vec::from).collect(),
+                        author: file_revision.author,
+                        message: file_revision.message,
+                        time: file_revision.time,
+                    }),
+                )
+            })
+            .collect();
+
+        let mut patchsets = patchset::Store::default();
+        for patchset in self.patchsets {
+            patchsets.insert(
+                patchset.mark,
+                patchset::PatchSet {
+                    time: patchset.time,
+                    file_revisions: patchset.file_revisions,
+                },
+            );
+        }
+        for alias in self.aliases {
+            patchsets.add_alias(alias.mark, &alias.time, alias.file_revisions.into_iter());
+        }
+        for branch in self.branches {
+            patchsets.set_marks_on_branch(branch.name.into(), branch.marks);
+        }
+
+        let mut tags = tag::Store::default();
+        for tag in self.tags {
+            let digest = tag
+                .digest
+                .as_deref()
+                .map(|digest| {
+                    unhex(digest)
+                        .ok_or_else(|| Error::Load(format!("invalid tag digest {:?}", digest)))
+                })
+                .transpose()?;
+            tags.insert(tag.name.into(), tag.mark, tag.file_revisions, digest);
+        }
+
+        let mut directories = Directories::default();
+        for directory in self.directories {
+            directories.add(&PathBuf::from(directory));
+        }
+
+        let manager = Manager {
+            file_revisions: Arc::new(file_revisions),
+            patchsets: Arc::new(patchsets.into()),
+            tags: Arc::new(tags.into()),
+            raw_marks: Arc::new(Vec::from(self.marks).into()),
+            directories: Arc::new(directories.into()),
+            commit_times: Arc::new(
+                self.commit_times
+                    .into_iter()
+                    .map(|commit_time| (commit_time.mark, commit_time.time))
+                    .collect::<BTreeMap<_, _>>()
+                    .into(),
+            ),
+            runs: Arc::new(self.runs.into()),
+            skipped_files: Arc::new(
+                self.skipped_files
+                    .into_iter()
+                    .map(|skipped| (skipped.path.into(), skipped.reason))
+                    .collect::<BTreeMap<_, _>>()
+                    .into(),
+            ),
+            path_normalization: Arc::new(self.path_normalization.into()),
+            shas: Default::default(),
+            sections: Default::default(),
+        };
+        manager.index_shas().await?;
+
+        Ok(manager)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(s: &str) -> Option<tag::Digest> {
+    let mut digest = tag::Digest::default();
+    if s.len() != digest.len() * 2 {
+        return None;
+    }
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(s.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+
+    Some(digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsStr, time::Duration};
+
+    use super::*;
+
+    fn mark(mark: usize) -> git_fast_import::Mark {
+        git_fast_import::Mark::from(mark)
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() -> Result<(), Error> {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let later = time + Duration::from_secs(60);
+        let odd_path = Path::new(OsStr::from_bytes(b"mod/caf\xe9.txt,v"));
+
+        let manager = Manager::new();
+        let a = manager
+            .add_file_revision(
+                Path::new("mod/a.txt,v"),
+                "1.1",
+                Some(mark(1)),
+                [b"main"].iter(),
+                "adam",
+                "add files",
+                &time,
+            )
+            .await?;
+        let b = manager
+            .add_file_revision(
+                odd_path,
+                "1.1",
+                None,
+                [&b"main"[..], b"BR"].iter(),
+                "adam",
+                "add files",
+                &time,
+            )
+            .await?;
+        manager
+            .add_patchset(mark(2), b"main", &time, vec![a].into_iter())
+            .await;
+        manager.add_branch_to_patchset_mark(mark(2), b"BR").await;
+        manager
+            .add_patchset_alias(mark(2), &time, vec![a, b].into_iter())
+            .await;
+        manager.set_commit_time(mark(2), &later).await;
+        manager.add_tag(b"REL1", a).await;
+        manager.add_tag_mark(b"REL1", mark(3)).await;
+        manager.add_directories(std::iter::once("mod")).await;
+        let run = Run {
+            id: "1b4e28ba-2fa1-4d2e-883f-0016d3cca427".into(),
+            version: "0.2.0".into(),
+            started: time,
+        };
+        manager.add_run(run.clone()).await;
+        manager
+            .set_skipped_files(
+                std::iter::once((PathBuf::from("mod/broken.c,v"), "truncated".to_string()))
+                    .collect(),
+            )
+            .await;
+        manager.set_path_normalization("nfc").await;
+        manager
+            .set_raw_marks(format!(":2 {}\n:3 {}\n", "a".repeat(40), "b".repeat(40)).as_bytes())
+            .await?;
+
+        let mut exported = Vec::new();
+        manager.export_json(&mut exported).await?;
+        let imported = Manager::import_json(exported.as_slice()).await?;
+
+        let mut reexported = Vec::new();
+        imported.export_json(&mut reexported).await?;
+        assert_eq!(
+            String::from_utf8_lossy(&exported),
+            String::from_utf8_lossy(&reexported)
+        );
+
+        assert_eq!(
+            imported.get_file_revision_by_id(b).await?.key.path,
+            odd_path
+        );
+        assert_eq!(
+            imported
+                .get_mark_from_patchset_content(&time, vec![a, b].into_iter())
+                .await,
+            Some(mark(2))
+        );
+        assert_eq!(imported.get_commit_time(&mark(2)).await?, later);
+        assert_eq!(
+            imported.get_last_patchset_mark_on_branch(b"BR").await,
+            Some(mark(2).into())
+        );
+        assert_eq!(imported.is_tag_unchanged(b"REL1").await, Some(true));
+        assert_eq!(
+            imported.get_sha_for_patchset_mark(&mark(2)).await?,
+            "a".repeat(40)
+        );
+        assert!(imported
+            .get_directories()
+            .await
+            .covers(Path::new("mod/a.txt,v")));
+        assert_eq!(imported.get_runs().await, vec![run]);
+        assert_eq!(
+            imported
+                .get_skipped_files()
+                .await
+                .get(Path::new("mod/broken.c,v")),
+            Some(&"truncated".to_string())
+        );
+        assert_eq!(
+            imported.get_path_normalization().await,
+            Some("nfc".to_string())
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unknown_version() {
+        // The version is checked before anything else, since later versions
+        // may have different fields.
+        let json = br#"{"version": 2, "something": "else"}"#;
+        assert!(matches!(
+            Manager::import_json(&json[..]).await,
+            Err(Error::UnknownPortableVersion(2))
+        ));
+    }
+}
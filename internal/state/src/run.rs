@@ -0,0 +1,15 @@
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+/// A run of `git-cvs-fast-import` that updated the store.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Run {
+    /// The unique ID of the run.
+    pub id: String,
+
+    /// The version of `git-cvs-fast-import` that made the run.
+    pub version: String,
+
+    pub started: SystemTime,
+}
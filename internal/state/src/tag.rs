@@ -2,6 +2,10 @@ use std::collections::{BTreeSet, HashMap};
 
 use crate::{file_revision, patchset::Mark, v1};
 use serde::{Deserialize, Serialize};
+use sha1::{Digest as _, Sha1};
+
+/// A digest of the file revisions on a tag.
+pub(crate) type Digest = [u8; 20];
 
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub(crate) struct Store {
@@ -10,11 +14,28 @@ pub(crate) struct Store {
 
     /// Track files that were observed during this run and need to be tagged.
     tags: HashMap<Vec<u8>, BTreeSet<file_revision::ID>>,
+
+    /// Track the digest of the file revisions on each tag when its fake commit
+    /// was created, so unchanged tags can be skipped without fetching the
+    /// patchset of the fake commit. These are persisted separately, since
+    /// they were added after this store's format was fixed.
+    #[serde(skip)]
+    digests: HashMap<Vec<u8>, Digest>,
 }
 
 impl Store {
+    /// Adds the mark of the fake commit for the tag, which is assumed to have
+    /// been created for the tag's current file revisions.
     pub(crate) fn add_mark(&mut self, tag: &[u8], mark: Mark) {
         self.marks.insert(Vec::from(tag), mark);
+        self.record_digest(tag);
+    }
+
+    /// Records the digest of the tag's current file revisions as the digest of
+    /// its fake commit.
+    pub(crate) fn record_digest(&mut self, tag: &[u8]) {
+        let digest = self.current_digest(tag);
+        self.digests.insert(Vec::from(tag), digest);
     }
 
     pub(crate) fn add_tag(&mut self, tag: &[u8], file_revision_id: file_revision::ID) {
@@ -35,12 +56,90 @@ impl Store {
     pub(crate) fn get_tags(&self) -> impl Iterator<Item = &[u8]> {
         self.tags.keys().map(|key| key.as_slice())
     }
+
+    /// Returns the name of every tag that has a fake commit.
+    pub(crate) fn get_tags_with_marks(&self) -> impl Iterator<Item = &[u8]> {
+        self.marks.keys().map(|key| key.as_slice())
+    }
+
+    /// Replaces the file revisions of every tag, leaving the marks and digests
+    /// of the fake commits in place so that changed tags can be detected.
+    pub(crate) fn set_file_revisions(
+        &mut self,
+        tags: HashMap<Vec<u8>, BTreeSet<file_revision::ID>>,
+    ) {
+        self.tags = tags;
+    }
+
+    /// Removes everything known about the given tag.
+    pub(crate) fn remove(&mut self, tag: &[u8]) {
+        self.marks.remove(tag);
+        self.tags.remove(tag);
+        self.digests.remove(tag);
+    }
+
+    /// Returns whether the tag's file revisions are the same as when its fake
+    /// commit was created, or `None` if there's no digest to compare against.
+    pub(crate) fn is_unchanged(&self, tag: &[u8]) -> Option<bool> {
+        self.digests
+            .get(tag)
+            .map(|digest| *digest == self.current_digest(tag))
+    }
+
+    /// Returns the name of every tag that has file revisions, a mark, or a
+    /// digest, in name order.
+    pub(crate) fn get_all_names(&self) -> BTreeSet<&[u8]> {
+        self.tags
+            .keys()
+            .chain(self.marks.keys())
+            .chain(self.digests.keys())
+            .map(|tag| tag.as_slice())
+            .collect()
+    }
+
+    /// Replaces everything known about the given tag.
+    pub(crate) fn insert(
+        &mut self,
+        tag: Vec<u8>,
+        mark: Option<Mark>,
+        file_revisions: Option<BTreeSet<file_revision::ID>>,
+        digest: Option<Digest>,
+    ) {
+        if let Some(mark) = mark {
+            self.marks.insert(tag.clone(), mark);
+        }
+        if let Some(file_revisions) = file_revisions {
+            self.tags.insert(tag.clone(), file_revisions);
+        }
+        if let Some(digest) = digest {
+            self.digests.insert(tag, digest);
+        }
+    }
+
+    pub(crate) fn get_digests(&self) -> &HashMap<Vec<u8>, Digest> {
+        &self.digests
+    }
+
+    pub(crate) fn set_digests(&mut self, digests: HashMap<Vec<u8>, Digest>) {
+        self.digests = digests;
+    }
+
+    fn current_digest(&self, tag: &[u8]) -> Digest {
+        // The file revisions are in a BTreeSet, so they're always hashed in
+        // the same order.
+        let mut hasher = Sha1::new();
+        for id in self.tags.get(tag).into_iter().flatten() {
+            hasher.update((usize::from(*id) as u64).to_le_bytes());
+        }
+        hasher.finalize().into()
+    }
 }
 
 impl From<v1::tag::Store> for Store {
     fn from(v1: v1::tag::Store) -> Self {
         Self {
             marks: HashMap::new(),
+            digests: HashMap::new(),
             tags: v1
                 .tags
                 .into_iter()
@@ -49,3 +148,45 @@ impl From<v1::tag::Store> for Store {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_digests() {
+        let mut store = Store::default();
+        store.add_tag(b"REL1", 1.into());
+        store.add_tag(b"REL1", 2.into());
+        store.add_tag(b"REL2", 1.into());
+        assert_eq!(store.is_unchanged(b"REL1"), None);
+
+        store.add_mark(b"REL1", git_fast_import::Mark::from(10).into());
+        assert_eq!(store.is_unchanged(b"REL1"), Some(true));
+        assert_eq!(store.is_unchanged(b"REL2"), None);
+
+        // Adding a file revision that's already on the tag is a no-op.
+        store.add_tag(b"REL1", 2.into());
+        assert_eq!(store.is_unchanged(b"REL1"), Some(true));
+
+        store.add_tag(b"REL1", 3.into());
+        assert_eq!(store.is_unchanged(b"REL1"), Some(false));
+
+        store.record_digest(b"REL1");
+        assert_eq!(store.is_unchanged(b"REL1"), Some(true));
+
+        // Replacing the file revisions keeps the digest of the fake commit,
+        // so a tag that moved back to its earlier file revisions is changed.
+        store.set_file_revisions(HashMap::from([(
+            b"REL1".to_vec(),
+            BTreeSet::from([1.into(), 2.into()]),
+        )]));
+        assert_eq!(store.is_unchanged(b"REL1"), Some(false));
+        assert!(store.get_file_revisions(b"REL2").is_none());
+
+        store.remove(b"REL1");
+        assert_eq!(store.is_unchanged(b"REL1"), None);
+        assert_eq!(store.get_mark(b"REL1"), None);
+        assert_eq!(store.get_tags_with_marks().count(), 0);
+    }
+}
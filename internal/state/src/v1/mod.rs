@@ -51,9 +51,16 @@ where
     .unwrap();
 
     Ok(Manager {
-        file_revisions: Arc::new(RwLock::new(file_revisions?)),
+        file_revisions: Arc::new(file_revisions?),
         patchsets: Arc::new(RwLock::new(patchsets?)),
         tags: Arc::new(RwLock::new(tags?)),
         raw_marks: Arc::new(RwLock::new(raw_marks?)),
+        directories: Default::default(),
+        commit_times: Default::default(),
+        runs: Default::default(),
+        skipped_files: Default::default(),
+        path_normalization: Default::default(),
+        shas: Default::default(),
+        sections: Default::default(),
     })
 }
@@ -0,0 +1,66 @@
+//! Measures patchset detection for a large repository, where every file
+//! revision in the CVSROOT passes through a detector.
+
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use patchset::Detector;
+
+const FILES: usize = 10_000;
+const PATCHSETS: usize = 100_000;
+const FILES_PER_PATCHSET: usize = 10;
+
+/// A file commit, as passed to [`Detector::add_file_commit`].
+type FileCommit = (PathBuf, usize, String, String, SystemTime);
+
+/// Builds the file commits of `PATCHSETS` patchsets a minute apart, each
+/// changing `FILES_PER_PATCHSET` files, ordered by file as discovery adds
+/// them.
+fn file_commits() -> Vec<FileCommit> {
+    let mut by_file: Vec<Vec<FileCommit>> = vec![Vec::new(); FILES];
+    for patchset in 0..PATCHSETS {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(60 * patchset as u64);
+        let author = format!("author{}", patchset % 20);
+        let message = format!("change {}", patchset % 500);
+        for i in 0..FILES_PER_PATCHSET {
+            let file = (patchset * 7 + i * (FILES / FILES_PER_PATCHSET)) % FILES;
+            by_file[file].push((
+                PathBuf::from(format!("dir{}/file{}.c", file % 100, file)),
+                patchset * FILES_PER_PATCHSET + i,
+                author.clone(),
+                message.clone(),
+                time,
+            ));
+        }
+    }
+
+    by_file.into_iter().flatten().collect()
+}
+
+fn bench_detect(c: &mut Criterion) {
+    let commits = file_commits();
+
+    let mut group = c.benchmark_group("detect");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(commits.len() as u64));
+    group.bench_function("file commits", |b| {
+        b.iter_batched(
+            || commits.clone(),
+            |commits| {
+                let mut detector = Detector::new(Duration::from_secs(300));
+                for (path, id, author, message, time) in commits.into_iter() {
+                    detector.add_file_commit(path, id, author, message, time, None);
+                }
+                detector.into_patchset_iter().count()
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_detect);
+criterion_main!(benches);
@@ -1,7 +1,13 @@
-//! Patchset detection based  time: (), author, message, files: ()  time: (), author, message, files: ()  time: (), author, message, files: () on a stream of file commits.
+//! Patchset detection based on a stream of file commits.
+//!
+//! Detection is deterministic: given the same file commits, a [`Detector`]
+//! yields the same patchsets in the same order, even if the commits of
+//! different files were added in a different order, and the files within each
+//! [`PatchSet`] are always iterated in path order. This keeps the resulting
+//! commits reproducible from one run to the next.
 
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
     fmt::Debug,
     hash::Hash,
     mem,
@@ -25,7 +31,8 @@ use thiserror::Error;
 /// Commits are considered to be linked into a single patchset when they have
 /// matching "commit keys" within a certain duration (represented by the `delta`
 /// argument to [`Detector::new()`]). The commit key is generated based on the
-/// commit message and author.
+/// commit message and author, along with an optional key provided by the
+/// caller, such as the CVS commit ID.
 ///
 /// The `ID` type parameter refers to the opaque ID used to represent a file:
 /// this will be passed back to the caller when yielding patchsets.
@@ -51,9 +58,13 @@ where
     //
     // So we use a BinaryHeap here to keep our commits sorted as we insert them,
     // and amortise the cost of sorting them later. Commit<ID> is defined with
-    // an ordering that is only based on the commit time, so this works as we
-    // need.
+    // an ordering that is based on the commit time, and then the order in which
+    // the commits were added, so this works as we need.
     file_commits: HashMap<CommitKey, BinaryHeap<Commit<ID>, MinComparator>>,
+
+    // The number of commits added so far, which is used to order commits with
+    // identical times.
+    sequence: usize,
 }
 
 impl<ID> Detector<ID>
@@ -69,6 +80,7 @@ where
         Self {
             delta,
             file_commits: HashMap::new(),
+            sequence: 0,
         }
     }
 
@@ -78,6 +90,16 @@ where
     /// responsibility of the caller to be able to map that back.
     ///
     /// If `id` is `None`, then this commit represents the file being deleted.
+    ///
+    /// `key` can be used to refine the grouping of file commits into
+    /// patchsets: file commits are only linked if their keys are equal, in
+    /// addition to their authors and messages. If `key` is `None`, then file
+    /// commits are grouped on their author and message alone.
+    ///
+    /// Commits of the same file with identical times are ordered in the order
+    /// they were added, so callers should add each file's commits in the order
+    /// they were made: for example, a file that is deleted and then re-added in
+    /// the same second must have its deletion added first.
     pub fn add_file_commit(
         &mut self,
         path: PathBuf,
@@ -85,9 +107,20 @@ where
         author: String,
         message: String,
         time: SystemTime,
+        key: Option<Vec<u8>>,
     ) {
-        let key = CommitKey { author, message };
-        let value = Commit { path, id, time };
+        let key = CommitKey {
+            author,
+            message,
+            extra: key,
+        };
+        let value = Commit {
+            path,
+            id,
+            time,
+            sequence: self.sequence,
+        };
+        self.sequence += 1;
 
         if let Some(v) = self.file_commits.get_mut(&key) {
             v.push(value);
@@ -100,62 +133,144 @@ where
 
     /// Consumes the detector and returns the detected patchsets in ascending
     /// time order.
-    pub fn into_patchset_iter(self) -> impl Iterator<Item = PatchSet<ID>> {
-        self.into_binary_heap().into_iter_sorted()
+    pub fn into_patchset_iter(mut self) -> impl Iterator<Item = PatchSet<ID>> {
+        order(self.take_patchsets(None)).into_iter()
     }
 
-    fn into_binary_heap(self) -> BinaryHeap<PatchSet<ID>, MinComparator> {
+    /// Removes and returns the patchsets that are complete as of `now`, in
+    /// ascending time order, leaving any other file commits in the detector.
+    ///
+    /// A patchset is complete when its last file commit is more than the delta
+    /// duration before `now`. This allows patchsets to be emitted while file
+    /// commits are still being added, provided that the caller only adds file
+    /// commits made at or after `now` from then on: earlier file commits may
+    /// otherwise belong to patchsets that have already been returned.
+    ///
+    /// Every patchset returned by later calls, or by
+    /// [`Detector::into_patchset_iter()`], will be no older than the patchsets
+    /// returned by this call.
+    pub fn flush(&mut self, now: SystemTime) -> impl Iterator<Item = PatchSet<ID>> {
+        order(self.take_patchsets(Some(now))).into_iter()
+    }
+
+    /// Returns true if the detector has no pending file commits.
+    pub fn is_empty(&self) -> bool {
+        self.file_commits.is_empty()
+    }
+
+    fn take_patchsets(
+        &mut self,
+        now: Option<SystemTime>,
+    ) -> BinaryHeap<OrderedPatchSet<ID>, MinComparator> {
+        let delta = self.delta;
         let mut patchsets = BinaryHeap::new_min();
 
-        for (key, commits) in self.file_commits.into_iter() {
-            let mut last = None;
-            let mut pending_files = HashMap::new();
-
-            for commit in commits.into_iter_sorted() {
-                if let Some(last) = last {
-                    if commit.time.duration_since(last).unwrap_or_default() > self.delta {
-                        patchsets.push(PatchSet {
-                            time: last,
-                            author: key.author.clone(),
-                            message: key.message.clone(),
-                            files: mem::take(&mut pending_files),
-                        });
+        self.file_commits.retain(|key, commits| {
+            let mut pending: Vec<Commit<ID>> = Vec::new();
+
+            while let Some(commit) = commits.peek() {
+                let time = commit.time;
+                if let Some(last) = pending.last() {
+                    if time.duration_since(last.time).unwrap_or_default() > delta {
+                        patchsets.push(OrderedPatchSet::new(key, mem::take(&mut pending)));
                     }
                 }
 
-                last = Some(commit.time);
-
-                // Add the new state of the file to the pending files. This
-                // effectively overwrites previous versions of the file within
-                // the same patchset, but that's generally what we want: it's
-                // not an exact commit-for-commit representation, but should
-                // accurately reflect what the user really did.
-                pending_files
-                    .entry(commit.path)
-                    .or_insert_with(Vec::new)
-                    .push(commit.id);
+                // Any patchset including this commit might still be extended
+                // by a later commit, so we have to stop here.
+                if let Some(now) = now {
+                    if now.duration_since(time).unwrap_or_default() <= delta {
+                        break;
+                    }
+                }
+
+                pending.push(commits.pop().unwrap());
             }
 
-            if !pending_files.is_empty() {
-                patchsets.push(PatchSet {
-                    time: last.unwrap(),
-                    author: key.author.clone(),
-                    message: key.message.clone(),
-                    files: pending_files,
-                });
+            if commits.is_empty() {
+                if !pending.is_empty() {
+                    patchsets.push(OrderedPatchSet::new(key, pending));
+                }
+                false
+            } else {
+                // The pending commits are linked to the commit we stopped at,
+                // so they go back into the heap to be returned later.
+                for commit in pending.into_iter() {
+                    commits.push(commit);
+                }
+                true
             }
-        }
+        });
 
         patchsets
     }
 }
 
+/// Returns the patchsets in the heap in ascending order.
+///
+/// Patchsets with identical times are ordered by author, message, and the
+/// paths of their files, as with [`PatchSet::cmp`], except that patchsets that
+/// modify the same file are kept in the order in which that file's commits were
+/// added, so that a file that's deleted and then re-added in the same second is
+/// deleted first. That order only depends on the order of each file's own
+/// commits, so the result doesn't change if the commits of different files are
+/// added in a different order.
+fn order<ID>(heap: BinaryHeap<OrderedPatchSet<ID>, MinComparator>) -> Vec<PatchSet<ID>>
+where
+    ID: Debug + Clone + Eq,
+{
+    let mut ordered = Vec::with_capacity(heap.len());
+    let mut group: Vec<OrderedPatchSet<ID>> = Vec::new();
+    for patchset in heap.into_iter_sorted() {
+        if let Some(first) = group.first() {
+            if first.patchset.time != patchset.patchset.time {
+                order_group(mem::take(&mut group), &mut ordered);
+            }
+        }
+        group.push(patchset);
+    }
+    order_group(group, &mut ordered);
+
+    ordered
+}
+
+/// Appends a group of patchsets with the same time, which must already be in
+/// ascending order, to `ordered`, moving any patchset that modifies a file
+/// after those that modified it first.
+fn order_group<ID>(mut group: Vec<OrderedPatchSet<ID>>, ordered: &mut Vec<PatchSet<ID>>)
+where
+    ID: Debug + Clone + Eq,
+{
+    // Most groups don't have any files in common, in which case there's
+    // nothing to move.
+    let mut paths = HashSet::new();
+    let shared = group
+        .iter()
+        .flat_map(|patchset| patchset.sequences.keys())
+        .any(|path| !paths.insert(path));
+
+    while !group.is_empty() {
+        let next = match shared {
+            true => group
+                .iter()
+                .position(|candidate| !group.iter().any(|other| other.modifies_before(candidate)))
+                .unwrap_or(0),
+            false => 0,
+        };
+        ordered.push(group.remove(next).patchset);
+    }
+}
+
 /// A `PatchSet` represents a single patchset detected by a [`Detector`].
 ///
 /// This contains the commit time, author, message, and the files that are
 /// modified by the patchset, along with all file IDs that were squashed into
 /// the patchset.
-#[derive(Debug, Clone, Eq)]
+///
+/// Files are kept in path order, so every iterator over the files in a
+/// patchset yields them in the same order, regardless of the order in which
+/// their file commits were added to the detector.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PatchSet<ID>
 where
     ID: Debug + Clone + Eq,
@@ -163,7 +278,7 @@ where
     pub time: SystemTime,
     pub author: String,
     pub message: String,
-    files: HashMap<PathBuf, Vec<ID>>,
+    files: BTreeMap<PathBuf, Vec<ID>>,
 }
 
 impl<ID> PatchSet<ID>
@@ -178,8 +293,8 @@ where
         }
     }
 
-    /// Iterates over each file in the patchset, in arbitrary order, along with
-    /// the content ID for the file. If the file is deleted in the patchset, the
+    /// Iterates over each file in the patchset, in path order, along with the
+    /// content ID for the file. If the file is deleted in the patchset, the
     /// ID will be None.
     pub fn file_content_iter(&self) -> impl Iterator<Item = (&PathBuf, &ID)> {
         self.files
@@ -187,13 +302,74 @@ where
             .filter_map(|(file, ids)| ids.last().map(|id| (file, id)))
     }
 
-    /// Iterates over each file in the patchset, in arbitrary order, and
+    /// Iterates over each file in the patchset, in path order, and
     /// provides the file and a Vec of all the content IDs that were squashed
     /// into the patchset for that file.
     pub fn file_revision_iter(&self) -> impl Iterator<Item = (&PathBuf, &Vec<ID>)> {
         self.files.iter()
     }
 
+    /// Returns true if the patchset doesn't include any files.
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Splits the patchset into two patchsets with the same time, author, and
+    /// message: the first contains the files for which `f` returns true, and
+    /// the second contains the remaining files.
+    pub fn partition<F>(&self, mut f: F) -> (Self, Self)
+    where
+        F: FnMut(&Path) -> bool,
+    {
+        let (matched, rest) = self
+            .files
+            .iter()
+            .map(|(file, ids)| (file.clone(), ids.clone()))
+            .partition(|(file, _ids)| f(file));
+
+        (
+            Self {
+                time: self.time,
+                author: self.author.clone(),
+                message: self.message.clone(),
+                files: matched,
+            },
+            Self {
+                time: self.time,
+                author: self.author.clone(),
+                message: self.message.clone(),
+                files: rest,
+            },
+        )
+    }
+
+    /// Merges a later patchset into this one, as if its file commits had been
+    /// detected as part of this patchset: the file content of `other` takes
+    /// precedence, and the time becomes the later of the two times.
+    ///
+    /// The author and message are left unchanged.
+    pub fn merge(&mut self, other: Self) {
+        for (file, ids) in other.files.into_iter() {
+            self.files.entry(file).or_default().extend(ids);
+        }
+        self.time = self.time.max(other.time);
+    }
+
+    /// Compares two patchsets by time alone, such as for sorting patchsets
+    /// chronologically with `sort_by`, without regard for their content.
+    pub fn by_time(a: &Self, b: &Self) -> std::cmp::Ordering {
+        a.time.cmp(&b.time)
+    }
+
+    /// Compares two patchsets by time, author, message, and the paths of their
+    /// files, without regard for the content IDs of those files.
+    fn cmp_without_ids(&self, other: &Self) -> std::cmp::Ordering {
+        Self::by_time(self, other)
+            .then_with(|| self.author.cmp(&other.author))
+            .then_with(|| self.message.cmp(&other.message))
+            .then_with(|| self.files.keys().cmp(other.files.keys()))
+    }
+
     fn content(ids: &[ID]) -> Result<&ID, Error> {
         match ids.last() {
             Some(id) => Ok(id),
@@ -216,37 +392,128 @@ where
     }
 }
 
+/// Patchsets are ordered by time, and then by author, message, and files, so
+/// that patchsets made in the same second are always ordered the same way and
+/// only identical patchsets compare as equal. Use [`PatchSet::by_time`] to
+/// compare times alone.
 impl<ID> Ord for PatchSet<ID>
 where
-    ID: Debug + Clone + Eq,
+    ID: Debug + Clone + Ord,
 {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.time.cmp(&other.time)
+        self.cmp_without_ids(other)
+            .then_with(|| self.files.cmp(&other.files))
     }
 }
 
 impl<ID> PartialOrd for PatchSet<ID>
+where
+    ID: Debug + Clone + Ord,
+{
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A wrapper around [`PatchSet`] that orders patchsets that are otherwise
+/// identical by the order in which their first commit was added to the
+/// [`Detector`].
+#[derive(Debug)]
+struct OrderedPatchSet<ID>
+where
+    ID: Debug + Clone + Eq,
+{
+    patchset: PatchSet<ID>,
+    sequence: usize,
+
+    /// The order in which the first commit of each file was added.
+    sequences: BTreeMap<PathBuf, usize>,
+}
+
+impl<ID> OrderedPatchSet<ID>
+where
+    ID: Debug + Clone + Eq,
+{
+    /// Builds a patchset from a non-empty group of linked commits, which must
+    /// be in time order.
+    fn new(key: &CommitKey, commits: Vec<Commit<ID>>) -> Self {
+        let sequence = commits[0].sequence;
+        let time = commits[commits.len() - 1].time;
+
+        // Add the new state of each file to the patchset. This effectively
+        // overwrites previous versions of the file within the same patchset,
+        // but that's generally what we want: it's not an exact
+        // commit-for-commit representation, but should accurately reflect what
+        // the user really did.
+        let mut files = BTreeMap::new();
+        let mut sequences = BTreeMap::new();
+        for commit in commits.into_iter() {
+            sequences
+                .entry(commit.path.clone())
+                .or_insert(commit.sequence);
+            files
+                .entry(commit.path)
+                .or_insert_with(Vec::new)
+                .push(commit.id);
+        }
+
+        Self {
+            patchset: PatchSet {
+                time,
+                author: key.author.clone(),
+                message: key.message.clone(),
+                files,
+            },
+            sequence,
+            sequences,
+        }
+    }
+
+    /// Returns true if this patchset modifies a file that `other` also
+    /// modifies, and its commit of that file was added first.
+    fn modifies_before(&self, other: &Self) -> bool {
+        self.sequences.iter().any(
+            |(path, sequence)| matches!(other.sequences.get(path), Some(other) if sequence < other),
+        )
+    }
+}
+
+impl<ID> Ord for OrderedPatchSet<ID>
+where
+    ID: Debug + Clone + Eq,
+{
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.patchset
+            .cmp_without_ids(&other.patchset)
+            .then_with(|| self.sequence.cmp(&other.sequence))
+    }
+}
+
+impl<ID> PartialOrd for OrderedPatchSet<ID>
 where
     ID: Debug + Clone + Eq,
 {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.time.partial_cmp(&other.time)
+        Some(self.cmp(other))
     }
 }
 
-impl<ID> PartialEq for PatchSet<ID>
+impl<ID> PartialEq for OrderedPatchSet<ID>
 where
     ID: Debug + Clone + Eq,
 {
     fn eq(&self, other: &Self) -> bool {
-        self.time == other.time
+        self.cmp(other) == std::cmp::Ordering::Equal
     }
 }
 
+impl<ID> Eq for OrderedPatchSet<ID> where ID: Debug + Clone + Eq {}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 struct CommitKey {
     author: String,
     message: String,
+    extra: Option<Vec<u8>>,
 }
 
 #[derive(Debug, Clone, Eq)]
@@ -257,6 +524,7 @@ where
     path: PathBuf,
     id: ID,
     time: SystemTime,
+    sequence: usize,
 }
 
 impl<ID> Ord for Commit<ID>
@@ -264,7 +532,7 @@ where
     ID: Debug + Clone + Eq,
 {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.time.cmp(&other.time)
+        (self.time, self.sequence).cmp(&(other.time, other.sequence))
     }
 }
 
@@ -273,7 +541,7 @@ where
     ID: Debug + Clone + Eq,
 {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.time.partial_cmp(&other.time)
+        Some(self.cmp(other))
     }
 }
 
@@ -282,7 +550,7 @@ where
     ID: Debug + Clone + Eq,
 {
     fn eq(&self, other: &Self) -> bool {
-        self.time == other.time
+        self.cmp(other) == std::cmp::Ordering::Equal
     }
 }
 
@@ -321,6 +589,7 @@ mod tests {
             author.clone(),
             message.clone(),
             timestamp(100),
+            None,
         );
 
         detector.add_file_commit(
@@ -329,6 +598,7 @@ mod tests {
             author.clone(),
             message.clone(),
             timestamp(101),
+            None,
         );
 
         // Mutate foo on a new commit.
@@ -338,6 +608,7 @@ mod tests {
             author.clone(),
             message.clone(),
             timestamp(300),
+            None,
         );
 
         // Add a file on a separate commit.
@@ -347,10 +618,18 @@ mod tests {
             author.clone(),
             String::from("this is a different message"),
             timestamp(90),
+            None,
         );
 
         // Re-add foo on the same commit as the first one.
-        detector.add_file_commit(path("foo"), 5, author.clone(), message, timestamp(120));
+        detector.add_file_commit(
+            path("foo"),
+            5,
+            author.clone(),
+            message,
+            timestamp(120),
+            None,
+        );
 
         let have: Vec<PatchSet<i32>> = detector.into_patchset_iter().collect();
         let want: Vec<PatchSet<i32>> = vec![
@@ -358,13 +637,13 @@ mod tests {
                 time: timestamp(90),
                 author: author.clone(),
                 message: String::from("this is a different message"),
-                files: HashMap::from_iter([(path("bar"), [4].to_vec())]),
+                files: BTreeMap::from_iter([(path("bar"), [4].to_vec())]),
             },
             PatchSet {
                 time: timestamp(120),
                 author: author.clone(),
                 message: String::from("message in a bottle"),
-                files: HashMap::from_iter([
+                files: BTreeMap::from_iter([
                     (path("foo"), [1, 5].to_vec()),
                     (path("bar"), [2].to_vec()),
                 ]),
@@ -373,12 +652,359 @@ mod tests {
                 time: timestamp(300),
                 author,
                 message: String::from("message in a bottle"),
-                files: HashMap::from_iter([(path("foo"), [3].to_vec())]),
+                files: BTreeMap::from_iter([(path("foo"), [3].to_vec())]),
             },
         ];
         assert_eq!(have, want);
     }
 
+    #[test]
+    fn test_detector_identical_times() {
+        let mut detector = Detector::new(Duration::from_secs(120));
+        let author = String::from("author");
+
+        // A file is deleted and re-added in the same second on different
+        // commits: the deletion must come first.
+        detector.add_file_commit(
+            path("foo"),
+            1,
+            author.clone(),
+            String::from("delete"),
+            timestamp(100),
+            None,
+        );
+        detector.add_file_commit(
+            path("foo"),
+            2,
+            author.clone(),
+            String::from("re-add"),
+            timestamp(100),
+            None,
+        );
+
+        // The same, but within a single commit.
+        detector.add_file_commit(
+            path("bar"),
+            3,
+            author.clone(),
+            String::from("both"),
+            timestamp(200),
+            None,
+        );
+        detector.add_file_commit(
+            path("bar"),
+            4,
+            author,
+            String::from("both"),
+            timestamp(200),
+            None,
+        );
+
+        let have: Vec<(String, Vec<i32>)> = detector
+            .into_patchset_iter()
+            .map(|patchset| {
+                (
+                    patchset.message.clone(),
+                    patchset
+                        .file_revision_iter()
+                        .flat_map(|(_path, ids)| ids.iter().copied())
+                        .collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            have,
+            vec![
+                (String::from("delete"), vec![1]),
+                (String::from("re-add"), vec![2]),
+                (String::from("both"), vec![3, 4]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_detector_identical_times_order() {
+        // Each commit is a separate patchset at the same time, added in two
+        // different orders: only the order of foo's own commits is the same.
+        let commits = [
+            ("foo", 1, "bob", "remove"),
+            ("bar", 2, "bob", "change"),
+            ("foo", 3, "adam", "add"),
+            ("baz", 4, "adam", "change"),
+            ("qux", 5, "adam", "change"),
+        ];
+        let detect = |order: &[usize]| -> Vec<i32> {
+            let mut detector = Detector::new(Duration::from_secs(120));
+            for i in order.iter() {
+                let (file, id, author, message) = commits[*i];
+                detector.add_file_commit(
+                    path(file),
+                    id,
+                    String::from(author),
+                    String::from(message),
+                    timestamp(100),
+                    None,
+                );
+            }
+            detector
+                .into_patchset_iter()
+                .flat_map(|patchset| {
+                    patchset
+                        .file_content_iter()
+                        .map(|(_path, id)| *id)
+                        .collect::<Vec<_>>()
+                })
+                .collect()
+        };
+
+        // Patchsets are ordered by author, message, and files, except that
+        // foo is removed before it's added back.
+        let want = vec![4, 5, 2, 1, 3];
+        assert_eq!(detect(&[0, 1, 2, 3, 4]), want);
+        assert_eq!(detect(&[4, 3, 0, 2, 1]), want);
+        assert_eq!(detect(&[1, 0, 4, 2, 3]), want);
+    }
+
+    #[test]
+    fn test_detector_key() {
+        let mut detector = Detector::new(Duration::from_secs(120));
+        let author = String::from("author");
+        let message = String::from("message");
+
+        // The same author and message within the delta, but with different
+        // keys, should be separate patchsets, and commits without a key
+        // shouldn't be linked with commits that have one.
+        for (id, key) in [
+            (1, Some(b"a".to_vec())),
+            (2, Some(b"b".to_vec())),
+            (3, Some(b"a".to_vec())),
+            (4, None),
+        ] {
+            detector.add_file_commit(
+                path(&format!("file{}", id)),
+                id,
+                author.clone(),
+                message.clone(),
+                timestamp(100 + id as u64),
+                key,
+            );
+        }
+
+        let have: Vec<Vec<i32>> = detector
+            .into_patchset_iter()
+            .map(|patchset| {
+                patchset
+                    .file_revision_iter()
+                    .flat_map(|(_path, ids)| ids.iter().copied())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(have, vec![vec![2], vec![1, 3], vec![4]]);
+    }
+
+    #[test]
+    fn test_detector_flush() {
+        // File commits in time order, with their authors and messages.
+        let commits = [
+            (100, "foo", "a"),
+            (110, "bar", "a"),
+            (150, "foo", "b"),
+            (200, "baz", "a"),
+            (400, "foo", "a"),
+            (410, "bar", "b"),
+            (500, "baz", "b"),
+        ];
+
+        let mut batch = Detector::new(Duration::from_secs(60));
+        let mut incremental = Detector::new(Duration::from_secs(60));
+        let mut flushed = Vec::new();
+        for (id, (time, file, message)) in commits.iter().enumerate() {
+            for detector in [&mut batch, &mut incremental] {
+                detector.add_file_commit(
+                    path(file),
+                    id,
+                    String::from("author"),
+                    message.to_string(),
+                    timestamp(*time),
+                    None,
+                );
+            }
+
+            // Flush as if the next commit is imminent.
+            let now = commits
+                .get(id + 1)
+                .map(|(time, _file, _message)| timestamp(*time))
+                .unwrap_or_else(|| timestamp(*time));
+            flushed.push(incremental.flush(now).collect::<Vec<_>>());
+        }
+
+        // Patchsets are only returned once nothing can be added to them.
+        let times: Vec<Vec<SystemTime>> = flushed
+            .iter()
+            .map(|patchsets| patchsets.iter().map(|patchset| patchset.time).collect())
+            .collect();
+        assert_eq!(
+            times,
+            vec![
+                vec![],
+                vec![],
+                vec![timestamp(110)],
+                vec![timestamp(150), timestamp(200)],
+                vec![],
+                vec![timestamp(400), timestamp(410)],
+                vec![],
+            ]
+        );
+        assert!(!incremental.is_empty());
+
+        // Overall, the same patchsets are detected in the same order as when
+        // all file commits are added up front.
+        let have: Vec<PatchSet<usize>> = flushed
+            .into_iter()
+            .flatten()
+            .chain(incremental.into_patchset_iter())
+            .collect();
+        let want: Vec<PatchSet<usize>> = batch.into_patchset_iter().collect();
+        assert_eq!(have.len(), want.len());
+        for (have, want) in have.iter().zip(want.iter()) {
+            assert_eq!(have.time, want.time);
+            assert_eq!(have.message, want.message);
+            assert_eq!(have.files, want.files);
+        }
+    }
+
+    #[test]
+    fn test_file_order() {
+        let mut detector = Detector::new(Duration::from_secs(120));
+        for (id, file) in ["c", "a/z", "b", "a/b", "a"].iter().enumerate() {
+            detector.add_file_commit(
+                path(file),
+                id,
+                String::from("author"),
+                String::from("message"),
+                timestamp(100 + id as u64),
+                None,
+            );
+        }
+
+        let patchset = detector.into_patchset_iter().next().unwrap();
+        let want: Vec<PathBuf> = ["a", "a/b", "a/z", "b", "c"]
+            .iter()
+            .map(|file| path(file))
+            .collect();
+        assert_eq!(
+            patchset
+                .file_content_iter()
+                .map(|(file, _id)| file.clone())
+                .collect::<Vec<_>>(),
+            want
+        );
+        assert_eq!(
+            patchset
+                .file_revision_iter()
+                .map(|(file, _ids)| file.clone())
+                .collect::<Vec<_>>(),
+            want
+        );
+    }
+
+    #[test]
+    fn test_partition() {
+        let patchset = PatchSet {
+            time: timestamp(100),
+            author: String::from("author"),
+            message: String::from("message"),
+            files: BTreeMap::from_iter([
+                (path("a/foo"), [1].to_vec()),
+                (path("b/bar"), [2, 3].to_vec()),
+                (path("a/baz"), [4].to_vec()),
+            ]),
+        };
+
+        let (a, rest) = patchset.partition(|file| file.starts_with("a"));
+        assert_eq!(
+            a.file_revision_iter()
+                .map(|(file, ids)| (file.clone(), ids.clone()))
+                .collect::<Vec<_>>(),
+            vec![(path("a/baz"), vec![4]), (path("a/foo"), vec![1])]
+        );
+        assert_eq!(
+            rest.file_revision_iter()
+                .map(|(file, ids)| (file.clone(), ids.clone()))
+                .collect::<Vec<_>>(),
+            vec![(path("b/bar"), vec![2, 3])]
+        );
+        assert_eq!(rest.time, patchset.time);
+        assert_eq!(rest.author, patchset.author);
+        assert_eq!(rest.message, patchset.message);
+
+        let (all, none) = patchset.partition(|_file| true);
+        assert!(!all.is_empty());
+        assert!(none.is_empty());
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut patchset = PatchSet {
+            time: timestamp(100),
+            author: String::from("author"),
+            message: String::from("first"),
+            files: BTreeMap::from_iter([(path("foo"), [1].to_vec()), (path("bar"), [2].to_vec())]),
+        };
+        patchset.merge(PatchSet {
+            time: timestamp(150),
+            author: String::from("author"),
+            message: String::from("second"),
+            files: BTreeMap::from_iter([(path("foo"), [3].to_vec()), (path("baz"), [4].to_vec())]),
+        });
+
+        assert_eq!(patchset.time, timestamp(150));
+        assert_eq!(patchset.message, "first");
+        assert_eq!(
+            patchset
+                .file_revision_iter()
+                .map(|(file, ids)| (file.clone(), ids.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (path("bar"), vec![2]),
+                (path("baz"), vec![4]),
+                (path("foo"), vec![1, 3]),
+            ]
+        );
+        assert_eq!(patchset.file_content(&path("foo")).unwrap(), &3);
+    }
+
+    #[test]
+    fn test_eq_ord() {
+        let patchset = |message: &str, files: Vec<(PathBuf, Vec<i32>)>| PatchSet {
+            time: timestamp(100),
+            author: String::from("author"),
+            message: String::from(message),
+            files: files.into_iter().collect(),
+        };
+        let a = patchset("a", vec![(path("foo"), vec![1])]);
+        let b = patchset("b", vec![(path("foo"), vec![1])]);
+        let c = patchset("a", vec![(path("foo"), vec![2])]);
+        let d = patchset("a", vec![(path("bar"), vec![3])]);
+
+        // Patchsets at the same time are only equal if they're identical.
+        assert_eq!(a, a.clone());
+        assert_ne!(a, b);
+        assert_ne!(a, c);
+        assert_eq!(PatchSet::by_time(&a, &b), std::cmp::Ordering::Equal);
+
+        let mut sorted = vec![b.clone(), c.clone(), a.clone(), d.clone()];
+        sorted.sort();
+        assert_eq!(sorted, vec![d, a.clone(), c, b.clone()]);
+
+        let later = PatchSet {
+            time: timestamp(101),
+            ..a.clone()
+        };
+        assert!(later > b);
+        assert_eq!(PatchSet::by_time(&a, &later), std::cmp::Ordering::Less);
+    }
+
     fn path(s: &str) -> PathBuf {
         PathBuf::from_str(s).unwrap()
     }
@@ -0,0 +1,99 @@
+//! Compares the strategies for applying ed scripts to large files, as happens
+//! for every revision of every file during discovery, and measures walking a
+//! chain of revisions in the same way.
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use rcs_ed::{ApplyStrategy, Command, File, Script};
+
+const LINES: usize = 100_000;
+
+/// The number of revisions walked in the chain benchmark.
+const REVISIONS: usize = 500;
+
+/// Builds a file with the given number of lines.
+fn file(lines: usize) -> File {
+    let content: String = (0..lines)
+        .map(|i| format!("line {} of a large file\n", i))
+        .collect();
+    File::new(content.as_bytes()).unwrap()
+}
+
+/// Builds a script that changes every `step`th line of a file with `lines`
+/// lines, in the same form as RCS: each changed line is deleted, and then
+/// replaced with `replacement` lines.
+fn script(lines: usize, step: usize, replacement: usize) -> Vec<Command> {
+    let mut script = String::new();
+    for position in (1..=lines).step_by(step) {
+        script.push_str(&format!("d{} 1\na{} {}\n", position, position, replacement));
+        for i in 0..replacement {
+            script.push_str(&format!("changed line {}.{}\n", position, i));
+        }
+    }
+    Script::parse(script.as_bytes())
+        .into_command_list()
+        .unwrap()
+}
+
+fn bench_apply(c: &mut Criterion) {
+    let original = file(LINES);
+
+    let mut group = c.benchmark_group("apply");
+    group.throughput(Throughput::Elements(LINES as u64));
+    for (name, commands) in [
+        ("few hunks", script(LINES, LINES / 8, 2)),
+        ("few same size hunks", script(LINES, LINES / 8, 1)),
+        ("many hunks", script(LINES, 20, 2)),
+    ] {
+        for strategy in [
+            ApplyStrategy::Auto,
+            ApplyStrategy::Splice,
+            ApplyStrategy::Rebuild,
+        ] {
+            group.bench_with_input(
+                BenchmarkId::new(name, format!("{:?}", strategy)),
+                &commands,
+                |b, commands| {
+                    b.iter_batched(
+                        || original.clone(),
+                        |mut file| {
+                            file.apply_in_place_with(commands, strategy).unwrap();
+                            file
+                        },
+                        BatchSize::LargeInput,
+                    )
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+/// Applies a script to each revision in turn, as discovery does when walking
+/// back along the trunk, where most revisions only change a few lines of a
+/// file of a few thousand.
+fn bench_chain(c: &mut Criterion) {
+    let lines = 5_000;
+    let original = file(lines);
+    let scripts: Vec<Vec<Command>> = (0..REVISIONS)
+        .map(|revision| script(lines, lines / 3 + revision % 7, 1 + revision % 3))
+        .collect();
+
+    let mut group = c.benchmark_group("chain");
+    group.throughput(Throughput::Elements(REVISIONS as u64));
+    group.bench_function("trunk", |b| {
+        b.iter_batched(
+            || original.clone(),
+            |mut file| {
+                for commands in scripts.iter() {
+                    file.apply_in_place(commands).unwrap();
+                }
+                file
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_apply, bench_chain);
+criterion_main!(benches);
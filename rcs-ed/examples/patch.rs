@@ -19,11 +19,7 @@ fn main() -> anyhow::Result<()> {
     let commands = Script::parse(fs::File::open(opt.patch)?).into_command_list()?;
     let file = File::new(io::stdin())?;
 
-    let mut stdout = io::stdout();
-    for line in file.apply(&commands)? {
-        stdout.write_all(&line)?;
-        stdout.write_all(b"\n")?;
-    }
+    io::stdout().write_all(&file.apply(&commands)?.into_bytes())?;
 
     Ok(())
 }
@@ -2,7 +2,7 @@ use nom::{
     branch::alt,
     bytes::complete::tag,
     character::complete::digit1,
-    combinator::map,
+    combinator::{map, map_res},
     sequence::{delimited, tuple},
     Finish, IResult,
 };
@@ -41,28 +41,22 @@ impl Command {
 fn command(input: &[u8]) -> IResult<&[u8], Command> {
     alt((
         map(
-            tuple((delimited(tag(b"a"), digit1, tag(b" ")), digit1)),
-            |(position, lines): (&[u8], &[u8])| Command::Add {
-                position: digits_to_usize(position),
-                lines: digits_to_usize(lines),
-            },
+            tuple((delimited(tag(b"a"), number, tag(b" ")), number)),
+            |(position, lines)| Command::Add { position, lines },
         ),
         map(
-            tuple((delimited(tag(b"d"), digit1, tag(b" ")), digit1)),
-            |(position, lines): (&[u8], &[u8])| Command::Delete {
-                position: digits_to_usize(position),
-                lines: digits_to_usize(lines),
-            },
+            tuple((delimited(tag(b"d"), number, tag(b" ")), number)),
+            |(position, lines)| Command::Delete { position, lines },
         ),
     ))(input)
 }
 
-fn digits_to_usize(digits: &[u8]) -> usize {
-    // To state the obvious, this function is _wildly_ unsafe if the input is
-    // anything other than a slice of ASCII digits.
-    unsafe { std::str::from_utf8_unchecked(digits) }
-        .parse::<usize>()
-        .unwrap()
+fn number(input: &[u8]) -> IResult<&[u8], usize> {
+    // digit1 only matches ASCII digits, so the only way the conversion can fail
+    // is if the number overflows a usize.
+    map_res(digit1, |digits| {
+        String::from_utf8_lossy(digits).parse::<usize>()
+    })(input)
 }
 
 #[cfg(test)]
@@ -103,5 +97,10 @@ mod tests {
             Command::parse(b"x"),
             Err(Error::InvalidCommand(_))
         ));
+
+        assert!(matches!(
+            Command::parse(b"a1 99999999999999999999999"),
+            Err(Error::InvalidCommand(_))
+        ));
     }
 }
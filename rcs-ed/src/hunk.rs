@@ -0,0 +1,132 @@
+use crate::Command;
+
+/// A contiguous change to a file: the original lines from `start` to `end`
+/// (0-indexed, exclusive) are replaced by `insert`.
+#[derive(Debug)]
+struct Hunk<'a> {
+    start: usize,
+    end: usize,
+    insert: &'a [Vec<u8>],
+
+    /// Whether the last inserted line ends with a newline.
+    trailing_newline: bool,
+}
+
+/// The hunks of an ed script, in file order.
+///
+/// RCS writes the commands in each delta in file order, without overlaps, and
+/// with each add immediately following any delete of the lines it replaces.
+/// Scripts like that can be applied by splicing each hunk into the existing
+/// lines, which only moves the lines after each hunk that changes the length of
+/// the file, rather than rebuilding every line.
+#[derive(Debug)]
+pub(crate) struct Hunks<'a> {
+    hunks: Vec<Hunk<'a>>,
+}
+
+impl<'a> Hunks<'a> {
+    /// Converts the commands into hunks for a file with `n` lines, or returns
+    /// `None` if the commands are out of order, overlap, or are outside the
+    /// file. Those scripts need to be applied with `LineCommands` instead,
+    /// which also reports the errors.
+    pub(crate) fn new(n: usize, commands: &'a [Command]) -> Option<Self> {
+        let mut hunks: Vec<Hunk> = Vec::with_capacity(commands.len());
+
+        // The number of original lines that have been passed so far, and the
+        // position of the last add command, since only one add can follow each
+        // line.
+        let mut pos = 0;
+        let mut last_add = None;
+
+        for command in commands {
+            match command {
+                Command::Add {
+                    position,
+                    content,
+                    trailing_newline,
+                } => {
+                    if *position < pos || *position > n || last_add == Some(*position) {
+                        return None;
+                    }
+
+                    match hunks.last_mut() {
+                        Some(hunk) if hunk.end == *position && hunk.insert.is_empty() => {
+                            hunk.insert = content;
+                            hunk.trailing_newline = *trailing_newline;
+                        }
+                        _ => hunks.push(Hunk {
+                            start: *position,
+                            end: *position,
+                            insert: content,
+                            trailing_newline: *trailing_newline,
+                        }),
+                    }
+                    pos = *position;
+                    last_add = Some(*position);
+                }
+                Command::Delete { position, lines } => {
+                    let start = position.checked_sub(1)?;
+                    let end = start.checked_add(*lines)?;
+                    if start < pos || end > n {
+                        return None;
+                    }
+
+                    hunks.push(Hunk {
+                        start,
+                        end,
+                        insert: &[],
+                        trailing_newline: true,
+                    });
+                    pos = end;
+                }
+            }
+        }
+
+        Some(Self { hunks })
+    }
+
+    /// Returns the number of lines that splicing the hunks into a file with
+    /// `n` lines will move.
+    pub(crate) fn moved(&self, n: usize) -> usize {
+        self.hunks
+            .iter()
+            .filter(|hunk| hunk.insert.len() != hunk.end - hunk.start)
+            .map(|hunk| n - hunk.end)
+            .fold(0, usize::saturating_add)
+    }
+
+    /// Splices the hunks into `lines`, returning whether the last line of the
+    /// result ends with a newline.
+    pub(crate) fn splice(&self, lines: &mut Vec<Vec<u8>>, trailing_newline: bool) -> bool {
+        let n = lines.len();
+
+        // The last line of the result is either the last original line that
+        // survives, or the last inserted line, whichever comes later.
+        let mut last_newline = false;
+        let mut pos = 0;
+        for hunk in self.hunks.iter() {
+            if hunk.start > pos {
+                last_newline = hunk.start < n || trailing_newline;
+            }
+            if !hunk.insert.is_empty() {
+                last_newline = hunk.trailing_newline;
+            }
+            pos = hunk.end;
+        }
+        if pos < n {
+            last_newline = trailing_newline;
+        }
+
+        // Working backwards means that the positions of earlier hunks are
+        // unaffected by the hunks that have already been spliced.
+        for hunk in self.hunks.iter().rev() {
+            if hunk.insert.len() == hunk.end - hunk.start {
+                lines[hunk.start..hunk.end].clone_from_slice(hunk.insert);
+            } else {
+                lines.splice(hunk.start..hunk.end, hunk.insert.iter().cloned());
+            }
+        }
+
+        last_newline
+    }
+}
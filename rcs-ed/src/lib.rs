@@ -5,23 +5,63 @@ use std::{
 use thiserror::Error;
 
 mod command;
+mod hunk;
+use hunk::Hunks;
 
 mod script;
 pub use script::{Command, Script};
 
-#[derive(Debug, Clone)]
+/// The lines of a file that ed scripts can be applied to.
+///
+/// Lines are stored without their newlines. Whether the last line ends with a
+/// newline is tracked separately, so that files round trip exactly through
+/// [`File::new`] and [`File::as_bytes`], including when the last line is empty
+/// or missing its newline.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct File {
     lines: Vec<Vec<u8>>,
+
+    /// Whether the last line ends with a newline. This is always false for an
+    /// empty file.
+    trailing_newline: bool,
+}
+
+/// How [`File::apply_in_place_with`] applies an ed script.
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApplyStrategy {
+    /// Splices the script when it's in file order and only changes the length
+    /// of the file a few times, and rebuilds the file otherwise.
+    Auto,
+
+    /// Splices each hunk of the script into the existing lines, which only
+    /// moves the lines after each hunk that changes the length of the file.
+    /// Scripts that aren't in file order are rebuilt instead.
+    Splice,
+
+    /// Rebuilds every line of the file.
+    Rebuild,
 }
 
+/// The number of times that splicing can move each line of the file before
+/// rebuilding the file is faster. Moving a line is a cheap copy of its
+/// pointer, length, and capacity, whereas rebuilding also has to allocate and
+/// fill a command for every line: `benches/apply.rs` puts the difference at
+/// around ten times.
+const SPLICE_FACTOR: usize = 8;
+
 #[derive(Debug, Clone)]
 enum Line<'a> {
-    Add(Vec<&'a Vec<Vec<u8>>>),
+    Add(Vec<Content<'a>>),
     Delete,
     Keep,
-    Replace(Vec<&'a Vec<Vec<u8>>>),
+    Replace(Vec<Content<'a>>),
 }
 
+/// The content of an add command, and whether its last line ends with a
+/// newline.
+type Content<'a> = (&'a Vec<Vec<u8>>, bool);
+
 impl File {
     pub fn new<R: Read>(reader: R) -> anyhow::Result<Self> {
         // In theory, you'd think BufReader::split() would be sufficient here,
@@ -31,37 +71,64 @@ impl File {
 
         let mut r = BufReader::new(reader);
         let mut lines = Vec::new();
+        let mut trailing_newline = false;
 
         loop {
             let mut line = Vec::new();
-            r.read_until(b'\n', &mut line)?;
-
-            if line.is_empty() {
-                // Special case: last line of the file, and it's empty.
-                lines.push(b"".to_vec());
+            if r.read_until(b'\n', &mut line)? == 0 {
                 break;
             }
 
-            if line[line.len() - 1] != b'\n' {
-                // Also the last line of the file, but it's not empty.
-                lines.push(line);
-                break;
+            trailing_newline = line[line.len() - 1] == b'\n';
+            if trailing_newline {
+                line.pop();
             }
-
-            line.pop();
             lines.push(line);
         }
 
-        Ok(Self { lines })
+        Ok(Self {
+            lines,
+            trailing_newline,
+        })
     }
 
-    pub fn apply(&self, commands: &[Command]) -> anyhow::Result<Vec<Vec<u8>>> {
-        LineCommands::calculate(self.lines.len(), commands)?.apply(self.lines.to_vec())
+    pub fn apply(&self, commands: &[Command]) -> Result<File, Error> {
+        let mut file = self.clone();
+        file.apply_in_place(commands)?;
+
+        Ok(file)
     }
 
-    pub fn apply_in_place(&mut self, commands: &[Command]) -> anyhow::Result<()> {
-        let input = mem::take(&mut self.lines);
-        self.lines = LineCommands::calculate(input.len(), commands)?.apply(input)?;
+    pub fn apply_in_place(&mut self, commands: &[Command]) -> Result<(), Error> {
+        self.apply_in_place_with(commands, ApplyStrategy::Auto)
+    }
+
+    /// Applies the commands in place using the given strategy. This is only
+    /// public so that the strategies can be benchmarked against each other:
+    /// use [`File::apply_in_place`] instead.
+    #[doc(hidden)]
+    pub fn apply_in_place_with(
+        &mut self,
+        commands: &[Command],
+        strategy: ApplyStrategy,
+    ) -> Result<(), Error> {
+        let n = self.lines.len();
+        let hunks = match strategy {
+            ApplyStrategy::Auto => Hunks::new(n, commands)
+                .filter(|hunks| hunks.moved(n) <= n.saturating_mul(SPLICE_FACTOR)),
+            ApplyStrategy::Splice => Hunks::new(n, commands),
+            ApplyStrategy::Rebuild => None,
+        };
+
+        if let Some(hunks) = hunks {
+            self.trailing_newline = hunks.splice(&mut self.lines, self.trailing_newline);
+        } else {
+            let lines = LineCommands::calculate(n, commands)?;
+            let (lines, trailing_newline) =
+                lines.apply(mem::take(&mut self.lines), self.trailing_newline);
+            self.lines = lines;
+            self.trailing_newline = trailing_newline;
+        }
 
         Ok(())
     }
@@ -70,64 +137,125 @@ impl File {
         self.lines.iter()
     }
 
+    /// Returns the number of lines in the file, including a last line without
+    /// a newline.
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Returns true if the last line of the file ends with a newline.
+    pub fn has_trailing_newline(&self) -> bool {
+        self.trailing_newline
+    }
+
+    /// Sets whether the last line of the file ends with a newline. This has no
+    /// effect on an empty file.
+    pub fn set_trailing_newline(&mut self, trailing_newline: bool) {
+        self.trailing_newline = trailing_newline && !self.lines.is_empty();
+    }
+
     pub fn as_bytes(&self) -> Vec<u8> {
-        self.lines.join(&b'\n')
+        let mut bytes = self.lines.join(&b'\n');
+        if self.trailing_newline {
+            bytes.push(b'\n');
+        }
+        bytes
     }
 
     pub fn into_bytes(self) -> Vec<u8> {
-        self.lines.join(&b'\n')
+        self.as_bytes()
+    }
+}
+
+/// Appends the content of add commands to `output`, updating `last_newline` if
+/// any lines were added.
+fn extend(output: &mut Vec<Vec<u8>>, last_newline: &mut bool, contents: &[Content]) {
+    for (content, newline) in contents {
+        if !content.is_empty() {
+            output.extend(content.iter().cloned());
+            *last_newline = *newline;
+        }
     }
 }
 
 struct LineCommands<'a> {
     lines: Vec<Line<'a>>,
-    prepend: Vec<Vec<u8>>,
+    prepend: Option<Content<'a>>,
 }
 
 impl<'a> LineCommands<'a> {
-    fn apply(self, mut input: Vec<Vec<u8>>) -> anyhow::Result<Vec<Vec<u8>>> {
-        let mut output = self.prepend;
-        output.reserve(self.lines.len());
+    /// Applies the commands to the given lines, returning the new lines and
+    /// whether the last of them ends with a newline.
+    ///
+    /// The last line of the output keeps the newline state of wherever it came
+    /// from: the last line of the input, or the last line of an add command.
+    /// Any other line is followed by another line, so it must end with one.
+    fn apply(self, mut input: Vec<Vec<u8>>, trailing_newline: bool) -> (Vec<Vec<u8>>, bool) {
+        let n = input.len();
+        let mut output = Vec::with_capacity(self.lines.len());
+        let mut last_newline = false;
+
+        if let Some(prepend) = self.prepend {
+            extend(&mut output, &mut last_newline, &[prepend]);
+        }
 
-        for (orig, cmd) in input.drain(..).zip(self.lines.into_iter()) {
+        for (i, (orig, cmd)) in input.drain(..).zip(self.lines.into_iter()).enumerate() {
             match cmd {
                 Line::Add(contents) => {
                     output.push(orig);
-                    output.extend(contents.iter().flat_map(|content| content.iter()).cloned());
+                    last_newline = i + 1 < n || trailing_newline;
+                    extend(&mut output, &mut last_newline, &contents);
                 }
                 Line::Delete => {}
                 Line::Keep => {
                     output.push(orig);
+                    last_newline = i + 1 < n || trailing_newline;
                 }
                 Line::Replace(contents) => {
-                    output.extend(contents.iter().flat_map(|content| content.iter()).cloned());
+                    extend(&mut output, &mut last_newline, &contents);
                 }
             }
         }
 
-        Ok(output)
+        (output, last_newline)
     }
 
-    fn calculate(n: usize, commands: &'a [Command]) -> Result<Self, LineCommandError> {
+    fn calculate(n: usize, commands: &'a [Command]) -> Result<Self, Error> {
         let mut line_commands = LineCommands {
             lines: vec![Line::Keep; n],
-            prepend: Vec::new(),
+            prepend: None,
         };
 
         for command in commands {
             match command {
-                Command::Add { position, content } if *position > 0 => {
-                    match &mut line_commands.lines[position - 1] {
+                Command::Add {
+                    position,
+                    content,
+                    trailing_newline,
+                } if *position > 0 => {
+                    let line = line_commands.lines.get_mut(position - 1).ok_or(
+                        Error::InvalidPosition {
+                            position: *position,
+                            len: n,
+                        },
+                    )?;
+
+                    let content = (content, *trailing_newline);
+                    match line {
                         Line::Add(_commands) => {
                             // We can't add the same line twice! (Or can we? No, no,
                             // we can't.)
-                            return Err(LineCommandError::ConflictingAppends(*position));
+                            return Err(Error::ConflictingAppends(*position));
                         }
                         Line::Delete => {
-                            line_commands.lines[position - 1] = Line::Replace(vec![content]);
+                            *line = Line::Replace(vec![content]);
                         }
                         Line::Keep => {
-                            line_commands.lines[position - 1] = Line::Add(vec![content]);
+                            *line = Line::Add(vec![content]);
                         }
                         Line::Replace(commands) => {
                             commands.push(content);
@@ -137,19 +265,31 @@ impl<'a> LineCommands<'a> {
                 Command::Add {
                     position: _,
                     content,
+                    trailing_newline,
                 } => {
                     // Special case: insert at the start of the commands.
-                    if !line_commands.prepend.is_empty() {
-                        return Err(LineCommandError::ConflictingAppends(0));
+                    if matches!(line_commands.prepend, Some((prepend, _)) if !prepend.is_empty()) {
+                        return Err(Error::ConflictingAppends(0));
                     }
 
-                    line_commands.prepend.extend(content.iter().cloned());
+                    line_commands.prepend = Some((content, *trailing_newline));
                 }
                 Command::Delete { position, lines } => {
-                    line_commands.lines.splice(
-                        position - 1..position + lines - 1,
-                        vec![Line::Delete; *lines],
-                    );
+                    // Deletions are 1-indexed, and must be entirely within the
+                    // original file.
+                    let invalid = Error::InvalidPosition {
+                        position: *position,
+                        len: n,
+                    };
+                    let start = position.checked_sub(1).ok_or_else(|| invalid.clone())?;
+                    let end = match start.checked_add(*lines) {
+                        Some(end) if end <= n => end,
+                        _ => return Err(invalid),
+                    };
+
+                    line_commands
+                        .lines
+                        .splice(start..end, vec![Line::Delete; *lines]);
                 }
             }
         }
@@ -158,10 +298,14 @@ impl<'a> LineCommands<'a> {
     }
 }
 
-#[derive(Debug, Error)]
-enum LineCommandError {
+/// Errors that can occur when applying an ed script to a [`File`].
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum Error {
     #[error("multiple append commands were found for the same line: {0}")]
     ConflictingAppends(usize),
+
+    #[error("ed command position {position} is outside the file, which has {len} line(s)")]
+    InvalidPosition { position: usize, len: usize },
 }
 
 #[cfg(test)]
@@ -171,6 +315,8 @@ mod tests {
         path::{Path, PathBuf},
     };
 
+    use proptest::prelude::*;
+
     use super::*;
 
     #[test]
@@ -181,7 +327,7 @@ mod tests {
                     &Script::parse(include_bytes!("fixtures/script.ed").as_ref())
                         .into_command_list()?
                 )?
-                .join(&b'\n'),
+                .as_bytes(),
             include_bytes!("fixtures/tzu")
         );
 
@@ -201,6 +347,93 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_line_counts() -> anyhow::Result<()> {
+        let commands = Script::parse(b"d1 2\na3 1\nnew\nd5 1\n".as_ref()).into_command_list()?;
+        assert_eq!(commands.iter().map(Command::lines_added).sum::<usize>(), 1);
+        assert_eq!(
+            commands.iter().map(Command::lines_deleted).sum::<usize>(),
+            3
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_invalid_position() -> anyhow::Result<()> {
+        let file = File::new(b"a\nb\nc".as_ref())?;
+
+        for (script, position) in [
+            (b"a4 1\nd\n".as_ref(), 4),
+            (b"d0 1\n".as_ref(), 0),
+            (b"d3 2\n".as_ref(), 3),
+            (b"d1 18446744073709551615\n".as_ref(), 1),
+        ] {
+            assert_eq!(
+                file.apply(&Script::parse(script).into_command_list()?),
+                Err(Error::InvalidPosition { position, len: 3 })
+            );
+        }
+
+        // The end of the file is still a valid position to append to.
+        assert_eq!(
+            file.apply(&Script::parse(b"a3 1\nd\n".as_ref()).into_command_list()?)?
+                .as_bytes(),
+            b"a\nb\nc\nd\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_trailing_newline() -> anyhow::Result<()> {
+        for (input, len, trailing_newline) in [
+            (b"".as_ref(), 0, false),
+            (b"\n".as_ref(), 1, true),
+            (b"a".as_ref(), 1, false),
+            (b"a\n".as_ref(), 1, true),
+            (b"a\n\n".as_ref(), 2, true),
+            (b"a\nb".as_ref(), 2, false),
+        ] {
+            let file = File::new(input)?;
+            assert_eq!(file.len(), len, "{:?}", input);
+            assert_eq!(file.has_trailing_newline(), trailing_newline, "{:?}", input);
+            assert_eq!(file.as_bytes(), input);
+        }
+
+        let apply = |input: &[u8], script: &[u8]| -> anyhow::Result<Vec<u8>> {
+            Ok(File::new(input)?
+                .apply(&Script::parse(script).into_command_list()?)?
+                .into_bytes())
+        };
+
+        // Adding a last line without a newline, as RCS does when the new
+        // revision doesn't end with one.
+        assert_eq!(apply(b"a\nb\n", b"d2 1\na2 1\nc")?, b"a\nc");
+        assert_eq!(apply(b"a\nb", b"d2 1\na2 1\nb\n")?, b"a\nb\n");
+
+        // The last line keeps its state when the lines around it change.
+        assert_eq!(apply(b"a\nb", b"d1 1\na0 2\nx\ny\n")?, b"x\ny\nb");
+        assert_eq!(apply(b"a\nb\n", b"d1 1\n")?, b"b\n");
+
+        // A line that's no longer last gains a newline.
+        assert_eq!(apply(b"a", b"a1 1\nb\n")?, b"a\nb\n");
+
+        // Deleting everything leaves an empty file.
+        assert_eq!(apply(b"a\nb\n", b"d1 2\n")?, b"");
+        assert_eq!(apply(b"", b"a0 1\na")?, b"a");
+
+        let mut file = File::new(b"a".as_ref())?;
+        file.set_trailing_newline(true);
+        assert_eq!(file.as_bytes(), b"a\n");
+
+        let mut file = File::new(b"".as_ref())?;
+        file.set_trailing_newline(true);
+        assert!(!file.has_trailing_newline());
+
+        Ok(())
+    }
+
     #[test]
     fn test_add_first_line() -> anyhow::Result<()> {
         let mut file = File::new(include_bytes!("fixtures/a0/1.15").as_ref())?;
@@ -232,4 +465,135 @@ mod tests {
 
         buf
     }
+
+    /// An edit to a single line of the original file: whether the line is
+    /// deleted, and what lines (if any) are appended after it.
+    type Edit = (bool, Option<Vec<String>>);
+
+    fn lines() -> impl Strategy<Value = Vec<String>> {
+        proptest::collection::vec("[a-z ]{0,4}", 1..4)
+    }
+
+    /// Builds an ed script and the expected output for the given edits, which
+    /// are in the same order as the lines in the original file.
+    fn build_script(
+        original: &[String],
+        prepend: &Option<Vec<String>>,
+        edits: &[Edit],
+    ) -> (Vec<u8>, Vec<Vec<u8>>) {
+        let mut script = Vec::new();
+        let mut expected = Vec::new();
+
+        let add = |script: &mut Vec<u8>, position: usize, content: &[String]| {
+            script.extend(format!("a{} {}\n", position, content.len()).bytes());
+            for line in content {
+                script.extend(line.bytes());
+                script.push(b'\n');
+            }
+        };
+
+        if let Some(content) = prepend {
+            add(&mut script, 0, content);
+            expected.extend(content.iter().map(|line| line.as_bytes().to_vec()));
+        }
+
+        for (i, (line, (delete, append))) in original.iter().zip(edits.iter()).enumerate() {
+            if *delete {
+                script.extend(format!("d{} 1\n", i + 1).bytes());
+            } else {
+                expected.push(line.as_bytes().to_vec());
+            }
+            if let Some(content) = append {
+                add(&mut script, i + 1, content);
+                expected.extend(content.iter().map(|line| line.as_bytes().to_vec()));
+            }
+        }
+
+        (script, expected)
+    }
+
+    proptest! {
+        #[test]
+        fn test_apply_generated_script(
+            (original, edits) in proptest::collection::vec("[a-z ]{0,4}", 1..16)
+                .prop_flat_map(|original| {
+                    let n = original.len();
+                    (
+                        Just(original),
+                        proptest::collection::vec(
+                            (any::<bool>(), proptest::option::of(lines())),
+                            n,
+                        ),
+                    )
+                }),
+            prepend in proptest::option::of(lines()),
+        ) {
+            let (script, expected) = build_script(&original, &prepend, &edits);
+            let file = File::new(
+                original
+                    .iter()
+                    .map(|line| format!("{}\n", line))
+                    .collect::<String>()
+                    .as_bytes(),
+            )
+            .unwrap();
+            let commands = Script::parse(script.as_slice()).into_command_list().unwrap();
+
+            for strategy in [ApplyStrategy::Auto, ApplyStrategy::Splice, ApplyStrategy::Rebuild] {
+                let mut have = file.clone();
+                have.apply_in_place_with(&commands, strategy).unwrap();
+                prop_assert_eq!(have.iter().cloned().collect::<Vec<Vec<u8>>>(), expected.clone());
+            }
+        }
+
+        #[test]
+        fn test_strategies_agree(
+            (original, edits) in proptest::collection::vec("[a-z ]{0,4}", 1..16)
+                .prop_flat_map(|original| {
+                    let n = original.len();
+                    (
+                        Just(original),
+                        proptest::collection::vec(
+                            (any::<bool>(), proptest::option::of(lines())),
+                            n,
+                        ),
+                    )
+                }),
+            prepend in proptest::option::of(lines()),
+            trailing_newline in any::<bool>(),
+            truncate in any::<bool>(),
+            shuffle in any::<bool>(),
+        ) {
+            // Splicing has to agree with rebuilding on the trailing newline,
+            // and on scripts that aren't in file order.
+            let (mut script, _expected) = build_script(&original, &prepend, &edits);
+            // An empty last line can't be missing its newline.
+            if truncate && script.ends_with(b"\n") && !script.ends_with(b"\n\n") {
+                script.pop();
+            }
+            let mut commands = Script::parse(script.as_slice()).into_command_list().unwrap();
+            if shuffle {
+                commands.reverse();
+            }
+
+            let mut file = File::new(original.join("\n").as_bytes()).unwrap();
+            file.set_trailing_newline(trailing_newline);
+
+            let mut spliced = file.clone();
+            let mut rebuilt = file.clone();
+            prop_assert_eq!(
+                spliced.apply_in_place_with(&commands, ApplyStrategy::Splice),
+                rebuilt.apply_in_place_with(&commands, ApplyStrategy::Rebuild)
+            );
+            prop_assert_eq!(spliced, rebuilt);
+        }
+
+        #[test]
+        fn test_script_arbitrary_input(
+            input in proptest::collection::vec(any::<u8>(), 0..256),
+        ) {
+            // We don't care about the result, only that we don't panic.
+            let _ = Script::parse(input.as_slice()).into_command_list();
+        }
+    }
 }
@@ -1,13 +1,13 @@
-use std::{
-    io::{BufRead, BufReader, Read, Split},
-    iter::Enumerate,
-};
+use std::io::{BufRead, BufReader, Read};
 use thiserror::Error;
 
 use crate::command;
 
 pub struct Script<R: Read> {
-    reader: Enumerate<Split<BufReader<R>>>,
+    reader: BufReader<R>,
+
+    /// The number of lines read so far.
+    line: usize,
 }
 
 /// Command is the external representation of an ed command, including its
@@ -17,6 +17,11 @@ pub enum Command {
     Add {
         position: usize,
         content: Vec<Vec<u8>>,
+
+        /// Whether the last line of the content ends with a newline. This is
+        /// only false for the last command in a script, when the file it
+        /// creates doesn't end with a newline.
+        trailing_newline: bool,
     },
     Delete {
         position: usize,
@@ -24,6 +29,24 @@ pub enum Command {
     },
 }
 
+impl Command {
+    /// Returns the number of lines that the command adds to the file.
+    pub fn lines_added(&self) -> usize {
+        match self {
+            Self::Add { content, .. } => content.len(),
+            Self::Delete { .. } => 0,
+        }
+    }
+
+    /// Returns the number of lines that the command deletes from the file.
+    pub fn lines_deleted(&self) -> usize {
+        match self {
+            Self::Add { .. } => 0,
+            Self::Delete { lines, .. } => *lines,
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("command parsing error on line {line}: {error}")]
@@ -43,13 +66,32 @@ pub enum Error {
 impl<R: Read> Script<R> {
     pub fn parse(reader: R) -> Self {
         Self {
-            reader: BufReader::new(reader).split(b'\n').enumerate(),
+            reader: BufReader::new(reader),
+            line: 0,
         }
     }
 
     pub fn into_command_list(self) -> Result<Vec<Command>, Error> {
         self.into_iter().collect()
     }
+
+    /// Reads the next line, without its newline, along with whether it had
+    /// one. Only the last line in the script can be missing its newline.
+    fn read_line(&mut self) -> Option<std::io::Result<(Vec<u8>, bool)>> {
+        let mut line = Vec::new();
+        match self.reader.read_until(b'\n', &mut line) {
+            Ok(0) => None,
+            Ok(_) => {
+                self.line += 1;
+                let newline = line.last() == Some(&b'\n');
+                if newline {
+                    line.pop();
+                }
+                Some(Ok((line, newline)))
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
 }
 
 impl<R: Read> Iterator for Script<R> {
@@ -57,30 +99,34 @@ impl<R: Read> Iterator for Script<R> {
 
     fn next(&mut self) -> Option<Self::Item> {
         // We need to get the next line, which should be a command line.
-        match self.reader.next() {
-            Some((line, Ok(raw))) => match command::Command::parse(&raw) {
+        match self.read_line() {
+            Some(Ok((raw, _newline))) => match command::Command::parse(&raw) {
                 // We got an Add command: this means that we need to read the
                 // next chunk of lines to get the actual content to be added.
                 Ok(command::Command::Add { position, lines }) => {
-                    match (&mut self.reader)
-                        .take(lines)
-                        .map(|(_line, content)| content)
-                        .collect::<Result<Vec<Vec<u8>>, std::io::Error>>()
-                    {
-                        Ok(content) if content.len() == lines => {
-                            Some(Ok(Command::Add { position, content }))
+                    let mut content = Vec::new();
+                    let mut trailing_newline = true;
+                    while content.len() < lines {
+                        match self.read_line() {
+                            Some(Ok((line, newline))) => {
+                                content.push(line);
+                                trailing_newline = newline;
+                            }
+                            Some(Err(e)) => return Some(Err(Error::Io(e))),
+                            None => {
+                                return Some(Err(Error::EndOfFile {
+                                    have: content.len(),
+                                    want: lines,
+                                }))
+                            }
                         }
-                        Ok(content) if content.len() < lines => Some(Err(Error::EndOfFile {
-                            have: content.len(),
-                            want: lines,
-                        })),
-                        Ok(content) => panic!(
-                            "read {} lines when only expected a maximum of {}",
-                            content.len(),
-                            lines
-                        ),
-                        Err(e) => Some(Err(Error::Io(e))),
                     }
+
+                    Some(Ok(Command::Add {
+                        position,
+                        content,
+                        trailing_newline,
+                    }))
                 }
                 // We got a Delete command, which is simpler: we just need to
                 // return the position and lines to be deleted.
@@ -91,10 +137,10 @@ impl<R: Read> Iterator for Script<R> {
                 // error annotated with the 1-indexed line number.
                 Err(e) => Some(Err(Error::Command {
                     error: e,
-                    line: line + 1,
+                    line: self.line,
                 })),
             },
-            Some((_line, Err(e))) => Some(Err(e.into())),
+            Some(Err(e)) => Some(Err(e.into())),
             None => None,
         }
     }
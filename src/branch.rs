@@ -1,4 +1,12 @@
-use std::collections::HashSet;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    time::Duration,
+};
+
+use regex::Regex;
+use thiserror::Error;
 
 pub(crate) struct BranchFilter {
     branches: Option<HashSet<Vec<u8>>>,
@@ -32,6 +40,149 @@ impl BranchFilter {
     }
 }
 
+/// A patchset detection delta that overrides the default for one branch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BranchDelta {
+    pub branch: Vec<u8>,
+    pub delta: Duration,
+}
+
+impl FromStr for BranchDelta {
+    type Err = BranchDeltaError;
+
+    /// Parses a `BRANCH=DURATION` pair, such as `BR=600s`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (branch, delta) = match s.rsplit_once('=') {
+            Some((branch, delta)) if !branch.is_empty() => (branch, delta),
+            _ => return Err(BranchDeltaError::Syntax(s.to_string())),
+        };
+
+        Ok(Self {
+            branch: branch.as_bytes().to_vec(),
+            delta: crate::units::parse_duration(delta).map_err(|e| BranchDeltaError::Duration {
+                branch: branch.to_string(),
+                message: e.to_string(),
+            })?,
+        })
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum BranchDeltaError {
+    #[error("invalid delta for branch {branch}: {message}")]
+    Duration { branch: String, message: String },
+
+    #[error("invalid branch delta {0:?}: expected BRANCH=DURATION")]
+    Syntax(String),
+}
+
+/// A rule that imports the CVS branches matching a regular expression as Git
+/// branches with a different name, given as `OLD=NEW`, such as
+/// `proj_rel_(\d+)_(\d+)_fixes=release/$1.$2`.
+///
+/// `OLD` must match the whole branch name, and can't contain `=`. `NEW` can
+/// refer to the groups captured by `OLD` as `$1` or `${name}`.
+#[derive(Debug, Clone)]
+pub(crate) struct BranchRename {
+    pattern: Regex,
+    replacement: String,
+}
+
+impl FromStr for BranchRename {
+    type Err = BranchRenameError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, replacement) = match s.split_once('=') {
+            Some((pattern, replacement)) if !pattern.is_empty() && !replacement.is_empty() => {
+                (pattern, replacement)
+            }
+            _ => return Err(BranchRenameError::Syntax(s.to_string())),
+        };
+
+        Ok(Self {
+            pattern: Regex::new(&format!("^(?:{})$", pattern)).map_err(|e| {
+                BranchRenameError::Pattern {
+                    pattern: pattern.to_string(),
+                    message: e.to_string(),
+                }
+            })?,
+            replacement: replacement.to_string(),
+        })
+    }
+}
+
+/// The branch renames to apply when writing branches to Git. Renames are tried
+/// in the order they were given, and the first that matches is used.
+///
+/// The state always refers to branches by their CVS name, so the same renames
+/// need to be given on every run to keep updating the same Git branches.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BranchRenames {
+    renames: Vec<BranchRename>,
+}
+
+impl BranchRenames {
+    pub(crate) fn new(renames: Vec<BranchRename>) -> Self {
+        Self { renames }
+    }
+
+    /// Returns the name of the Git branch that the given CVS branch is
+    /// imported as.
+    pub(crate) fn git_branch<'a>(&self, branch: &'a str) -> Cow<'a, str> {
+        for rename in self.renames.iter() {
+            if rename.pattern.is_match(branch) {
+                return rename.pattern.replace(branch, rename.replacement.as_str());
+            }
+        }
+
+        Cow::Borrowed(branch)
+    }
+
+    /// Checks that no two of the given CVS branches are imported as the same
+    /// Git branch.
+    pub(crate) fn check<I>(&self, branches: I) -> Result<(), BranchRenameError>
+    where
+        I: Iterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let mut seen: HashMap<String, String> = HashMap::new();
+        for branch in branches {
+            let branch = String::from_utf8_lossy(branch.as_ref()).into_owned();
+            let git_branch = self.git_branch(&branch).into_owned();
+            match seen.get(&git_branch) {
+                Some(other) if other != &branch => {
+                    return Err(BranchRenameError::Conflict {
+                        git_branch,
+                        first: other.clone(),
+                        second: branch,
+                    });
+                }
+                _ => {
+                    seen.insert(git_branch, branch);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum BranchRenameError {
+    #[error("branches {first} and {second} would both be imported as {git_branch}; check --branch-rename")]
+    Conflict {
+        git_branch: String,
+        first: String,
+        second: String,
+    },
+
+    #[error("invalid branch rename pattern {pattern:?}: {message}")]
+    Pattern { pattern: String, message: String },
+
+    #[error("invalid branch rename {0:?}: expected OLD=NEW")]
+    Syntax(String),
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -52,4 +203,56 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_branch_delta() {
+        assert_eq!(
+            "BR=600s".parse::<BranchDelta>().unwrap(),
+            BranchDelta {
+                branch: b"BR".to_vec(),
+                delta: Duration::from_secs(600),
+            }
+        );
+        assert_eq!(
+            "a=b=1m".parse::<BranchDelta>().unwrap(),
+            BranchDelta {
+                branch: b"a=b".to_vec(),
+                delta: Duration::from_secs(60),
+            }
+        );
+
+        assert!("BR".parse::<BranchDelta>().is_err());
+        assert!("=600s".parse::<BranchDelta>().is_err());
+        assert!("BR=soon".parse::<BranchDelta>().is_err());
+    }
+
+    #[test]
+    fn test_branch_renames() {
+        let renames = BranchRenames::new(
+            [
+                "proj_rel_(\\d+)_(\\d+)_fixes=release/$1.$2",
+                "BR=feature/br",
+                "B.*=other/$0",
+            ]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect(),
+        );
+        assert_eq!(renames.git_branch("proj_rel_2_3_fixes"), "release/2.3");
+        assert_eq!(renames.git_branch("BR"), "feature/br");
+        assert_eq!(renames.git_branch("BR2"), "other/BR2");
+        assert_eq!(renames.git_branch("main"), "main");
+        // Patterns must match the whole name.
+        assert_eq!(
+            renames.git_branch("xproj_rel_2_3_fixes"),
+            "xproj_rel_2_3_fixes"
+        );
+
+        assert!(renames.check(["main", "BR", "BR2", "BR"].iter()).is_ok());
+        assert!(renames.check(["feature/br", "BR"].iter()).is_err());
+
+        for s in ["", "BR", "=x", "BR=", "(=x"] {
+            assert!(s.parse::<BranchRename>().is_err(), "{:?}", s);
+        }
+    }
 }
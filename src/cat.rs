@@ -0,0 +1,118 @@
+//! The `cat` subcommand, which prints the content of a single revision of a
+//! file in a CVSROOT without importing it.
+
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use comma_v::{DateQuirks, Num};
+use structopt::StructOpt;
+
+use crate::{
+    discovery::{munge_raw_path, PathNormalization},
+    source,
+    spot_check::revision_contents,
+};
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "git-cvs-fast-import cat",
+    about = "Prints the content of a single revision of a file in a CVSROOT, reconstructed in the same way as an import, without importing anything."
+)]
+pub(crate) struct Opt {
+    #[structopt(
+        short,
+        long,
+        env = "CVSROOT",
+        parse(from_os_str),
+        help = "the CVSROOT, which must be a local directory, a tar archive of one (optionally gzipped), or a remote CVSROOT such as :pserver:user@host:/cvsroot; if omitted, the $CVSROOT environment variable will be used"
+    )]
+    cvsroot: PathBuf,
+
+    #[structopt(
+        long,
+        default_value = "cvs",
+        parse(from_os_str),
+        help = "the cvs binary to use when accessing a remote CVSROOT"
+    )]
+    cvs: OsString,
+
+    #[structopt(
+        long,
+        default_value = "none",
+        help = "deviations from the RCS date format to accept, as with an import: a comma separated list of padding, tm-years, month-names, and overflow, or all or none"
+    )]
+    date_quirks: DateQuirks,
+
+    #[structopt(
+        name = "PATH",
+        parse(from_os_str),
+        help = "the path of the file relative to the CVSROOT, such as mod/a.txt; the ,v suffix and any Attic directory may be omitted"
+    )]
+    path: PathBuf,
+
+    #[structopt(name = "REVISION", help = "the revision to print, such as 1.2")]
+    revision: Num,
+}
+
+/// Prints the content of the requested revision to `out`.
+///
+/// Only the directory containing the file is walked, but the whole RCS file
+/// still has to be parsed and the deltas applied from the head revision, so
+/// this is only quick for a single revision.
+pub(crate) fn run(opt: &Opt, out: &mut dyn Write) -> anyhow::Result<()> {
+    if let Num::Branch(_) = opt.revision {
+        anyhow::bail!("{} is a branch, not a revision", opt.revision);
+    }
+
+    let source = source::open(&opt.cvsroot, &opt.cvs)?;
+    let path = munge_raw_path(&opt.path, Path::new(""), PathNormalization::None);
+    let directories: Vec<PathBuf> = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .map(|parent| parent.to_path_buf())
+        .into_iter()
+        .collect();
+
+    let prefix = source.prefix().to_path_buf();
+    let mut content = None;
+    source.walk(&directories, &mut |entry| {
+        if content.is_some() || entry.is_dir()? || !entry.is_comma_v() {
+            return Ok(());
+        }
+        if munge_raw_path(entry.path(), &prefix, PathNormalization::None) != path {
+            return Ok(());
+        }
+
+        let (raw_path, contents) = entry.into_contents()?;
+        let cv = comma_v::parse_with_quirks(&contents, opt.date_quirks)
+            .with_context(|| format!("cannot parse {}", raw_path.display()))?;
+        let wanted: HashSet<Num> = std::iter::once(opt.revision.clone()).collect();
+        content = Some(
+            revision_contents(&cv, &wanted)
+                .with_context(|| format!("cannot reconstruct revisions in {}", raw_path.display()))?
+                .remove(&opt.revision)
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "revision {} was not found in {}",
+                        opt.revision,
+                        raw_path.display()
+                    )
+                })?,
+        );
+        Ok(())
+    })?;
+
+    match content {
+        Some(content) => Ok(out.write_all(&content)?),
+        None => anyhow::bail!(
+            "{} was not found in {}",
+            path.display(),
+            source.locations(&directories).join(", ")
+        ),
+    }
+}
@@ -0,0 +1,132 @@
+//! ChangeLog entries, recorded with `--changelog-messages` so that patchsets
+//! without a useful log message can use the entry that was added to a
+//! `ChangeLog` file in the same commit instead.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// The name of the files that entries are recorded for.
+const CHANGELOG: &str = "ChangeLog";
+
+/// The message CVS records when a commit is made without one.
+const EMPTY_LOG_MESSAGE: &str = "*** empty log message ***";
+
+/// A shared registry of the entry added by each revision of each `ChangeLog`
+/// file, keyed by the path of the file within the repository and the revision.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ChangeLogRegistry {
+    entries: Arc<Mutex<HashMap<(PathBuf, String), String>>>,
+}
+
+impl ChangeLogRegistry {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns true if entries are recorded for the file at the given path.
+    pub(crate) fn tracks(&self, path: &Path) -> bool {
+        path.file_name() == Some(CHANGELOG.as_ref())
+    }
+
+    /// Records the entry added by a revision, given its content and the
+    /// content of the revision it was derived from.
+    pub(crate) fn record(&self, path: &Path, revision: &str, content: &[u8], previous: &[u8]) {
+        if let Some(entry) = added_entry(content, previous) {
+            self.entries
+                .lock()
+                .unwrap()
+                .insert((path.to_path_buf(), revision.to_string()), entry);
+        }
+    }
+
+    /// Returns the entry added by the given revision, if any.
+    pub(crate) fn get(&self, path: &Path, revision: &str) -> Option<String> {
+        self.entries
+            .lock()
+            .unwrap()
+            .get(&(path.to_path_buf(), revision.to_string()))
+            .cloned()
+    }
+}
+
+/// Returns true if a log message doesn't say anything useful, so a ChangeLog
+/// entry should be used instead: that is, if it's empty, CVS's placeholder for
+/// an empty message, or only punctuation.
+pub(crate) fn is_trivial(message: &str) -> bool {
+    let message = message.trim();
+    message == EMPTY_LOG_MESSAGE || !message.chars().any(char::is_alphanumeric)
+}
+
+/// Returns the lines added to the top of a ChangeLog, which is where entries
+/// are added, with the leading tab of each line removed.
+///
+/// Changes anywhere else in the file, such as corrections to old entries,
+/// aren't treated as an entry, since they don't describe the commit.
+fn added_entry(content: &[u8], previous: &[u8]) -> Option<String> {
+    let added = content.strip_suffix(previous)?;
+    let added = String::from_utf8_lossy(added);
+    let entry = added
+        .lines()
+        .map(|line| line.strip_prefix('\t').unwrap_or(line).trim_end())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let entry = entry.trim_matches('\n');
+
+    if entry.trim().is_empty() {
+        None
+    } else {
+        Some(format!("{}\n", entry))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_trivial() {
+        for message in ["", " \n", "*** empty log message ***\n", ".", "-\n"] {
+            assert!(is_trivial(message), "{:?}", message);
+        }
+        for message in ["fix", "Fix the build.\n", "1"] {
+            assert!(!is_trivial(message), "{:?}", message);
+        }
+    }
+
+    #[test]
+    fn test_added_entry() {
+        let previous = b"2021-01-01  adam\n\n\t* a.c: Add a.\n\n";
+        let content =
+            b"2021-01-02  bob\n\n\t* a.c (main): Fix a crash.\n\t* b.c: Add b.\n\n2021-01-01  adam\n\n\t* a.c: Add a.\n\n";
+        assert_eq!(
+            added_entry(content, previous).as_deref(),
+            Some("2021-01-02  bob\n\n* a.c (main): Fix a crash.\n* b.c: Add b.\n")
+        );
+
+        // Edits to old entries, or no change at all, aren't entries.
+        assert_eq!(
+            added_entry(b"2021-01-01  adam\n\n\t* a.c: Add A.\n\n", previous),
+            None
+        );
+        assert_eq!(added_entry(previous, previous), None);
+        assert_eq!(added_entry(b"\n\n", b""), None);
+    }
+
+    #[test]
+    fn test_registry() {
+        let registry = ChangeLogRegistry::new();
+        assert!(registry.tracks(Path::new("mod/ChangeLog")));
+        assert!(!registry.tracks(Path::new("mod/ChangeLog.old")));
+        assert!(!registry.tracks(Path::new("mod/a.c")));
+
+        registry.record(Path::new("mod/ChangeLog"), "1.2", b"new\nold\n", b"old\n");
+        assert_eq!(
+            registry.get(Path::new("mod/ChangeLog"), "1.2").as_deref(),
+            Some("new\n")
+        );
+        assert_eq!(registry.get(Path::new("mod/ChangeLog"), "1.1"), None);
+    }
+}
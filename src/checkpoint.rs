@@ -0,0 +1,142 @@
+use std::{
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use git_cvs_fast_import_process::Output;
+use thiserror::Error;
+
+use crate::units::{self, UnitError};
+
+/// How often to send a `checkpoint` command while sending commits, given as a
+/// number of commits with a `c` suffix, a duration, or both separated by a
+/// slash, such as `500c`, `10m`, or `500c/10m`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CheckpointInterval {
+    commits: Option<usize>,
+    duration: Option<Duration>,
+}
+
+impl FromStr for CheckpointInterval {
+    type Err = CheckpointIntervalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut interval = Self {
+            commits: None,
+            duration: None,
+        };
+
+        for part in s.split('/').map(str::trim) {
+            if let Some(commits) = part.strip_suffix('c') {
+                let commits = match commits.trim().parse() {
+                    Ok(commits) if commits > 0 && interval.commits.is_none() => commits,
+                    _ => return Err(CheckpointIntervalError::Syntax(s.to_string())),
+                };
+                interval.commits = Some(commits);
+            } else {
+                let duration = units::parse_duration(part)?;
+                if duration.is_zero() || interval.duration.is_some() {
+                    return Err(CheckpointIntervalError::Syntax(s.to_string()));
+                }
+                interval.duration = Some(duration);
+            }
+        }
+
+        Ok(interval)
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum CheckpointIntervalError {
+    #[error(transparent)]
+    Duration(#[from] UnitError),
+
+    #[error("invalid checkpoint interval {0:?}: expected a number of commits such as 500c, a duration such as 10m, or both, such as 500c/10m")]
+    Syntax(String),
+}
+
+/// Sends a `checkpoint` command once enough commits have been sent, or enough
+/// time has passed, since the last one.
+#[derive(Debug)]
+pub(crate) struct Checkpointer {
+    interval: CheckpointInterval,
+
+    /// The number of commits since the last checkpoint, and when it was sent.
+    last: Mutex<(usize, Instant)>,
+}
+
+impl Checkpointer {
+    pub(crate) fn new(interval: CheckpointInterval) -> Self {
+        Self {
+            interval,
+            last: Mutex::new((0, Instant::now())),
+        }
+    }
+
+    /// Records that a commit was sent, and sends a checkpoint if one is due.
+    pub(crate) async fn commit(&self, output: &Output) -> anyhow::Result<()> {
+        let due = {
+            let mut last = self.last.lock().unwrap();
+            last.0 += 1;
+
+            let due = matches!(self.interval.commits, Some(n) if last.0 >= n)
+                || matches!(self.interval.duration, Some(duration) if last.1.elapsed() >= duration);
+            if due {
+                *last = (0, Instant::now());
+            }
+            due
+        };
+
+        if due {
+            log::debug!("sending checkpoint");
+            output.checkpoint().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_interval() {
+        assert_eq!(
+            "500c".parse::<CheckpointInterval>().unwrap(),
+            CheckpointInterval {
+                commits: Some(500),
+                duration: None,
+            }
+        );
+        assert_eq!(
+            "10m".parse::<CheckpointInterval>().unwrap(),
+            CheckpointInterval {
+                commits: None,
+                duration: Some(Duration::from_secs(600)),
+            }
+        );
+        assert_eq!(
+            "500c/10m".parse::<CheckpointInterval>().unwrap(),
+            CheckpointInterval {
+                commits: Some(500),
+                duration: Some(Duration::from_secs(600)),
+            }
+        );
+        assert_eq!(
+            "1h / 20 c".parse::<CheckpointInterval>().unwrap(),
+            CheckpointInterval {
+                commits: Some(20),
+                duration: Some(Duration::from_secs(3600)),
+            }
+        );
+
+        for s in ["", "0c", "c", "500c/600c", "10m/1h", "0s", "500c/"] {
+            assert!(s.parse::<CheckpointInterval>().is_err(), "{:?}", s);
+        }
+        assert!(matches!(
+            "10x".parse::<CheckpointInterval>(),
+            Err(CheckpointIntervalError::Duration(UnitError::Unit { .. }))
+        ));
+    }
+}
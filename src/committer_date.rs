@@ -0,0 +1,126 @@
+use std::{
+    convert::TryFrom,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use chrono::DateTime;
+use thiserror::Error;
+
+/// The date to use for the committer of each commit.
+///
+/// CVS only records a single time for each file commit, which is always used
+/// as the author date. By default, it's also used as the committer date, which
+/// means that Git doesn't record an author separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CommitterDate {
+    /// The committer date is the CVS commit time.
+    Cvs,
+
+    /// The committer date is the time the import started.
+    Now,
+
+    /// The committer date is a fixed time.
+    Fixed(SystemTime),
+}
+
+impl CommitterDate {
+    /// Returns the committer date for a commit made in CVS at `cvs`, or `None`
+    /// if the committer date is the same as the author date.
+    pub(crate) fn time(&self, cvs: &SystemTime, now: &SystemTime) -> Option<SystemTime> {
+        match self {
+            Self::Cvs => None,
+            Self::Now => Some(*now),
+            Self::Fixed(fixed) => Some(*fixed),
+        }
+        .filter(|time| time != cvs)
+    }
+}
+
+impl FromStr for CommitterDate {
+    type Err = CommitterDateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cvs" => Ok(Self::Cvs),
+            "now" => Ok(Self::Now),
+            _ => match s.strip_prefix("fixed:") {
+                Some(fixed) => Ok(Self::Fixed(parse_time(fixed)?)),
+                None => Err(CommitterDateError::Unknown(s.to_string())),
+            },
+        }
+    }
+}
+
+/// Parses a time given as either seconds since the Unix epoch or an RFC 3339
+/// timestamp.
+fn parse_time(s: &str) -> Result<SystemTime, CommitterDateError> {
+    if let Ok(seconds) = s.parse::<u64>() {
+        return Ok(UNIX_EPOCH + Duration::from_secs(seconds));
+    }
+
+    let time = DateTime::parse_from_rfc3339(s)
+        .map_err(|_| CommitterDateError::InvalidTime(s.to_string()))?;
+    match u64::try_from(time.timestamp()) {
+        Ok(seconds) => Ok(UNIX_EPOCH + Duration::from_secs(seconds)),
+        Err(_) => Err(CommitterDateError::InvalidTime(s.to_string())),
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum CommitterDateError {
+    #[error("invalid fixed committer date {0:?}: expected seconds since the Unix epoch or an RFC 3339 timestamp")]
+    InvalidTime(String),
+
+    #[error("unknown committer date {0:?}: expected cvs, now, or fixed:<time>")]
+    Unknown(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_str() {
+        let at = |seconds| UNIX_EPOCH + Duration::from_secs(seconds);
+
+        assert_eq!("cvs".parse::<CommitterDate>().unwrap(), CommitterDate::Cvs);
+        assert_eq!("now".parse::<CommitterDate>().unwrap(), CommitterDate::Now);
+        assert_eq!(
+            "fixed:1629480866".parse::<CommitterDate>().unwrap(),
+            CommitterDate::Fixed(at(1629480866))
+        );
+        assert_eq!(
+            "fixed:2021-08-20T17:34:26Z"
+                .parse::<CommitterDate>()
+                .unwrap(),
+            CommitterDate::Fixed(at(1629480866))
+        );
+        assert_eq!(
+            "fixed:2021-08-20T18:34:26+01:00"
+                .parse::<CommitterDate>()
+                .unwrap(),
+            CommitterDate::Fixed(at(1629480866))
+        );
+
+        assert!("fixed:yesterday".parse::<CommitterDate>().is_err());
+        assert!("fixed:1969-01-01T00:00:00Z"
+            .parse::<CommitterDate>()
+            .is_err());
+        assert!("later".parse::<CommitterDate>().is_err());
+    }
+
+    #[test]
+    fn test_time() {
+        let cvs = UNIX_EPOCH + Duration::from_secs(100);
+        let now = UNIX_EPOCH + Duration::from_secs(200);
+
+        assert_eq!(CommitterDate::Cvs.time(&cvs, &now), None);
+        assert_eq!(CommitterDate::Now.time(&cvs, &now), Some(now));
+        assert_eq!(CommitterDate::Now.time(&now, &now), None);
+        assert_eq!(
+            CommitterDate::Fixed(UNIX_EPOCH).time(&cvs, &now),
+            Some(UNIX_EPOCH)
+        );
+    }
+}
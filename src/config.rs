@@ -0,0 +1,331 @@
+//! Support for reading command line options from a TOML file.
+
+use std::{
+    ffi::{OsStr, OsString},
+    fs,
+    os::unix::prelude::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use structopt::clap::{App, AppSettings, ArgMatches, ErrorKind};
+use thiserror::Error;
+use toml::{value::Table, Value};
+
+/// The key used in the config file for the positional arguments.
+const POSITIONAL_KEY: &str = "directories";
+
+#[derive(Debug, Error)]
+pub(crate) enum Error {
+    #[error("cannot read config file {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("cannot parse config file {path}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+
+    #[error("invalid value for `{key}` in config file {path}: {message}")]
+    Value {
+        path: PathBuf,
+        key: String,
+        message: String,
+    },
+}
+
+/// A parsed config file, converted into command line arguments.
+#[derive(Debug)]
+struct Config {
+    path: PathBuf,
+
+    /// The arguments for each option, in key order.
+    options: Vec<(String, Vec<OsString>)>,
+
+    /// The positional arguments, if any.
+    positionals: Option<Vec<OsString>>,
+}
+
+impl Config {
+    fn read(path: &Path) -> Result<Self, Error> {
+        let raw = fs::read_to_string(path).map_err(|source| Error::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Self::parse(path, &raw)
+    }
+
+    fn parse(path: &Path, raw: &str) -> Result<Self, Error> {
+        let table: Table = toml::from_str(raw).map_err(|source| Error::Parse {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        let value_error = |key: &str, message: &str| Error::Value {
+            path: path.to_path_buf(),
+            key: key.to_string(),
+            message: message.to_string(),
+        };
+
+        let mut config = Self {
+            path: path.to_path_buf(),
+            options: Vec::new(),
+            positionals: None,
+        };
+        for (key, value) in table.into_iter() {
+            if key == POSITIONAL_KEY {
+                config.positionals = Some(match value {
+                    Value::Array(values) => values
+                        .iter()
+                        .map(|value| {
+                            scalar(value).ok_or_else(|| value_error(&key, "expected a string"))
+                        })
+                        .collect::<Result<_, _>>()?,
+                    _ => return Err(value_error(&key, "expected an array of strings")),
+                });
+                continue;
+            }
+
+            if key == "config" {
+                return Err(value_error(
+                    &key,
+                    "config files cannot include other config files",
+                ));
+            }
+
+            let flag = OsString::from(format!("--{}", key));
+            let args = match &value {
+                Value::Boolean(true) => vec![flag],
+                Value::Boolean(false) => Vec::new(),
+                Value::Array(values) => {
+                    let mut args = Vec::new();
+                    for value in values.iter() {
+                        args.push(flag.clone());
+                        args.push(scalar(value).ok_or_else(|| {
+                            value_error(&key, "expected an array of strings or numbers")
+                        })?);
+                    }
+                    args
+                }
+                value => vec![
+                    flag,
+                    scalar(value).ok_or_else(|| {
+                        value_error(&key, "expected a string, number, boolean, or array")
+                    })?,
+                ],
+            };
+            config.options.push((key, args));
+        }
+
+        Ok(config)
+    }
+}
+
+/// Converts a scalar value into an argument.
+fn scalar(value: &Value) -> Option<OsString> {
+    match value {
+        Value::String(s) => Some(s.into()),
+        Value::Integer(i) => Some(i.to_string().into()),
+        Value::Float(f) => Some(f.to_string().into()),
+        _ => None,
+    }
+}
+
+/// Parses the command line arguments with `app`, including any options from
+/// the TOML file given with `--config`.
+///
+/// Each key in the file is the long name of an option, such as
+/// `ignore-file-errors = true` or `branch = ["main", "BRANCH"]`, and the
+/// positional arguments can be given as an array with the `directories` key.
+/// Options given on the command line take precedence over the file.
+///
+/// As with [`App::get_matches_from`], errors in the command line arguments
+/// cause the process to exit with a usage message.
+pub(crate) fn get_matches<'a>(
+    app: App<'a, '_>,
+    args: Vec<OsString>,
+    positional: &str,
+) -> anyhow::Result<ArgMatches<'a>> {
+    // Single valued options given more than once take the last value, which
+    // lets the command line override the file. Options with multiple values
+    // are dropped from the file instead if they're on the command line.
+    let app = app.setting(AppSettings::AllArgsOverrideSelf);
+
+    let config = match config_path(&args) {
+        Some(path) => Config::read(&path)?,
+        None => return Ok(app.get_matches_from(args)),
+    };
+
+    let (program, cli) = args.split_at(1.min(args.len()));
+    let options: Vec<&(String, Vec<OsString>)> = config
+        .options
+        .iter()
+        .filter(|(key, _args)| !cli.iter().any(|arg| is_long_flag(arg, key)))
+        .collect();
+
+    let mut argv: Vec<OsString> = program.to_vec();
+    argv.extend(options.iter().flat_map(|(_key, args)| args.iter().cloned()));
+    argv.extend(cli.iter().cloned());
+
+    let result = app
+        .clone()
+        .get_matches_from_safe(argv.clone())
+        .and_then(|matches| {
+            // Positional arguments can only be added once we know that there
+            // aren't any on the command line.
+            match &config.positionals {
+                Some(positionals) if matches.values_of_os(positional).is_none() => {
+                    argv.push("--".into());
+                    argv.extend(positionals.iter().cloned());
+                    app.get_matches_from_safe(argv)
+                }
+                _ => Ok(matches),
+            }
+        });
+
+    match result {
+        Ok(matches) => Ok(matches),
+        Err(e)
+            if matches!(
+                e.kind,
+                ErrorKind::HelpDisplayed | ErrorKind::VersionDisplayed
+            ) =>
+        {
+            e.exit()
+        }
+        Err(e) => {
+            // If the error refers to an option from the file, we'll report it
+            // against the key, rather than as a command line error.
+            let first_line = e.message.lines().next().unwrap_or_default();
+            for (key, _args) in options.iter() {
+                let flag = format!("--{}", key);
+                if e.info
+                    .iter()
+                    .flatten()
+                    .any(|info| info == &flag || info.starts_with(&format!("{}=", flag)))
+                    || first_line.contains(&format!("'{} ", flag))
+                    || first_line.contains(&format!("'{}'", flag))
+                {
+                    return Err(Error::Value {
+                        path: config.path,
+                        key: key.clone(),
+                        message: first_line.trim_start_matches("error: ").to_string(),
+                    }
+                    .into());
+                }
+            }
+
+            e.exit()
+        }
+    }
+}
+
+/// Finds the value of `--config` in the raw arguments, if any.
+fn config_path(args: &[OsString]) -> Option<PathBuf> {
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        if arg == "--" {
+            break;
+        } else if arg == "--config" {
+            return iter.next().map(PathBuf::from);
+        } else if let Some(path) = arg.as_bytes().strip_prefix(b"--config=") {
+            return Some(PathBuf::from(OsStr::from_bytes(path)));
+        }
+    }
+
+    None
+}
+
+fn is_long_flag(arg: &OsStr, key: &str) -> bool {
+    let arg = arg.as_bytes();
+    match arg.strip_prefix(b"--") {
+        Some(name) => {
+            name == key.as_bytes()
+                || name.starts_with(key.as_bytes()) && name.get(key.len()) == Some(&b'=')
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use structopt::StructOpt;
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, StructOpt)]
+    struct Opt {
+        #[structopt(long)]
+        branch: Vec<String>,
+
+        #[structopt(long)]
+        config: Option<PathBuf>,
+
+        #[structopt(short, long)]
+        cvsroot: String,
+
+        #[structopt(long)]
+        jobs: Option<usize>,
+
+        #[structopt(long)]
+        strict: bool,
+
+        #[structopt(name = "DIRECTORY")]
+        directories: Vec<String>,
+    }
+
+    fn parse(config: &str, args: &[&str]) -> anyhow::Result<Opt> {
+        let mut file = NamedTempFile::new()?;
+        file.write_all(config.as_bytes())?;
+
+        let mut argv: Vec<OsString> = vec!["test".into(), "--config".into(), file.path().into()];
+        argv.extend(args.iter().map(OsString::from));
+
+        Ok(Opt::from_clap(&get_matches(
+            Opt::clap(),
+            argv,
+            "DIRECTORY",
+        )?))
+    }
+
+    #[test]
+    fn test_get_matches() {
+        let config = r#"
+            branch = ["a", "b"]
+            cvsroot = "/cvs"
+            jobs = 4
+            strict = true
+            directories = ["x"]
+        "#;
+
+        let opt = parse(config, &[]).unwrap();
+        assert_eq!(opt.branch, vec!["a", "b"]);
+        assert_eq!(opt.cvsroot, "/cvs");
+        assert_eq!(opt.jobs, Some(4));
+        assert!(opt.strict);
+        assert_eq!(opt.directories, vec!["x"]);
+
+        // Command line options override the file.
+        let opt = parse(config, &["--branch", "c", "-c", "/other", "--jobs=2", "y"]).unwrap();
+        assert_eq!(opt.branch, vec!["c"]);
+        assert_eq!(opt.cvsroot, "/other");
+        assert_eq!(opt.jobs, Some(2));
+        assert_eq!(opt.directories, vec!["y"]);
+    }
+
+    #[test]
+    fn test_get_matches_errors() {
+        let message = |config: &str| parse(config, &[]).unwrap_err().to_string();
+
+        assert!(message("cvsroot = \"/cvs\"\njobs = \"many\"\n").contains("`jobs`"));
+        assert!(message("cvsroot = \"/cvs\"\nunknown = 1\n").contains("`unknown`"));
+        assert!(message("cvsroot = \"/cvs\"\n[jobs]\nx = 1\n").contains("`jobs`"));
+        assert!(message("cvsroot = \"/cvs\"\ndirectories = \"x\"\n").contains("`directories`"));
+        assert!(message("cvsroot = ").contains("cannot parse config file"));
+    }
+}
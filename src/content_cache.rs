@@ -0,0 +1,179 @@
+//! An on-disk cache of reconstructed file revision contents.
+
+use std::{
+    fmt::Write as _,
+    fs,
+    io::{self, Write as _},
+    os::unix::prelude::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use sha1::{Digest, Sha1};
+use tempfile::NamedTempFile;
+
+/// A content-addressable cache of file revision contents, keyed by the RCS
+/// file they were reconstructed from and the revision number.
+///
+/// Reconstructing the content of each revision means applying every delta
+/// between it and the HEAD revision, which dominates the time taken to re-run
+/// an import that failed part way through. With a cache, revisions that were
+/// reconstructed by an earlier run are read from disk instead.
+#[derive(Debug, Clone)]
+pub(crate) struct ContentCache {
+    root: PathBuf,
+}
+
+impl ContentCache {
+    /// Opens the cache in the given directory, creating it if necessary.
+    pub fn open(root: &Path) -> io::Result<Self> {
+        fs::create_dir_all(root)?;
+        Ok(Self {
+            root: root.to_path_buf(),
+        })
+    }
+
+    /// Returns the content of the given revision of the file, if it's in the
+    /// cache.
+    ///
+    /// Errors other than the revision not being cached are logged, and then
+    /// treated as a cache miss, since the content can always be reconstructed.
+    pub fn get(&self, file: &FileKey, revision: &str) -> Option<Vec<u8>> {
+        let entry = self.entry_path(file, revision);
+        match fs::read(&entry) {
+            Ok(content) => Some(content),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => {
+                log::warn!(
+                    "{} {}: cannot read {} from the content cache: {}",
+                    file.path.display(),
+                    revision,
+                    entry.display(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Adds the content of the given revision to the cache.
+    ///
+    /// Entries are written to a temporary file and then renamed into place, so
+    /// an interrupted run can't leave a partial entry behind. As with
+    /// [`ContentCache::get`], errors are logged rather than returned.
+    pub fn put(&self, file: &FileKey, revision: &str, content: &[u8]) {
+        let entry = self.entry_path(file, revision);
+        if let Err(e) = write_entry(&entry, content) {
+            log::warn!(
+                "{} {}: cannot write {} to the content cache: {}",
+                file.path.display(),
+                revision,
+                entry.display(),
+                e
+            );
+        }
+    }
+
+    /// Calculates the path of the cache entry for the given revision.
+    ///
+    /// Entries are spread across subdirectories named after the first byte of
+    /// the key, in the same way as Git's loose objects, to avoid creating a
+    /// single enormous directory.
+    fn entry_path(&self, file: &FileKey, revision: &str) -> PathBuf {
+        let mut hasher = Sha1::new();
+        hasher.update(&file.digest);
+        hasher.update(revision.as_bytes());
+
+        let mut key = String::with_capacity(40);
+        for byte in hasher.finalize().iter() {
+            // Writing to a String can't fail.
+            let _ = write!(key, "{:02x}", byte);
+        }
+
+        self.root.join(&key[..2]).join(&key[2..])
+    }
+}
+
+/// Identifies a single RCS file, as it was when it was read, within the cache.
+///
+/// The key covers the path of the RCS file, including any `Attic` directory,
+/// and a hash of its contents. A file in the `Attic` therefore never shares
+/// entries with a live file of the same name, and rewriting the history of a
+/// file, such as with `cvs admin -o`, leaves its old entries unused rather than
+/// returning stale content.
+#[derive(Debug, Clone)]
+pub(crate) struct FileKey {
+    path: PathBuf,
+    digest: Vec<u8>,
+}
+
+impl FileKey {
+    pub fn new(path: &Path, contents: &[u8]) -> Self {
+        let mut hasher = Sha1::new();
+        hasher.update(path.as_os_str().as_bytes());
+        hasher.update(b"\0");
+        hasher.update(Sha1::digest(contents));
+
+        Self {
+            path: path.to_path_buf(),
+            digest: hasher.finalize().to_vec(),
+        }
+    }
+}
+
+fn write_entry(entry: &Path, content: &[u8]) -> io::Result<()> {
+    // Entry paths always have a parent, since they're within the cache root.
+    let dir = entry.parent().unwrap_or_else(|| Path::new("."));
+    fs::create_dir_all(dir)?;
+
+    let mut file = NamedTempFile::new_in(dir)?;
+    file.write_all(content)?;
+    file.persist(entry)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ContentCache::open(&dir.path().join("cache")).unwrap();
+        let a = FileKey::new(Path::new("a/file,v"), b"a");
+        let b = FileKey::new(Path::new("b/file,v"), b"b");
+
+        assert_eq!(cache.get(&a, "1.1"), None);
+
+        cache.put(&a, "1.1", b"one\n");
+        cache.put(&a, "1.2", b"");
+        cache.put(&b, "1.1", b"other");
+        assert_eq!(cache.get(&a, "1.1"), Some(b"one\n".to_vec()));
+        assert_eq!(cache.get(&a, "1.2"), Some(Vec::new()));
+        assert_eq!(cache.get(&b, "1.1"), Some(b"other".to_vec()));
+
+        // Entries survive reopening the cache.
+        let cache = ContentCache::open(&dir.path().join("cache")).unwrap();
+        assert_eq!(cache.get(&a, "1.1"), Some(b"one\n".to_vec()));
+    }
+
+    #[test]
+    fn test_file_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ContentCache::open(dir.path()).unwrap();
+
+        // A live file and a file in the Attic with the same name don't share
+        // entries, even if their contents are identical.
+        let live = FileKey::new(Path::new("mod/a.txt,v"), b"head 1.1;");
+        let attic = FileKey::new(Path::new("mod/Attic/a.txt,v"), b"head 1.1;");
+        cache.put(&live, "1.1", b"live");
+        assert_eq!(cache.get(&attic, "1.1"), None);
+        cache.put(&attic, "1.1", b"attic");
+        assert_eq!(cache.get(&live, "1.1"), Some(b"live".to_vec()));
+        assert_eq!(cache.get(&attic, "1.1"), Some(b"attic".to_vec()));
+
+        // Nor does a file whose history has been rewritten.
+        let rewritten = FileKey::new(Path::new("mod/a.txt,v"), b"head 1.2;");
+        assert_eq!(cache.get(&rewritten, "1.1"), None);
+    }
+}
@@ -0,0 +1,309 @@
+//! Line counts for each file revision, recorded when `--report` is given so
+//! that the report can include a diffstat for each commit.
+//!
+//! The counts come from the ed scripts that RCS stores each revision as, so no
+//! diffs have to be computed: on a branch, the script for a revision turns the
+//! previous revision into it, whereas on the trunk, which RCS stores in
+//! reverse, the script turns the next revision back into the one before it.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use rcs_ed::Command;
+use serde::Serialize;
+
+/// The changes a file revision makes to the revision it was derived from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct FileStat {
+    pub added: usize,
+    pub deleted: usize,
+
+    /// True if either revision is binary, in which case no lines are counted.
+    pub binary: bool,
+}
+
+/// The diffstat of a commit, as included in the report.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub(crate) struct DiffStat {
+    /// The number of files added, modified, or deleted.
+    pub files: usize,
+
+    /// The number of lines added and deleted in text files.
+    pub added: usize,
+    pub deleted: usize,
+
+    /// The number of files that are binary, which aren't included in the line
+    /// counts.
+    pub binary: usize,
+}
+
+impl DiffStat {
+    /// Adds a file to the diffstat. Files without a recorded stat, such as
+    /// those whose RCS file couldn't be read, are still counted as a file.
+    pub(crate) fn add(&mut self, stat: Option<FileStat>) {
+        self.files += 1;
+        match stat {
+            Some(stat) if stat.binary => self.binary += 1,
+            Some(stat) => {
+                self.added += stat.added;
+                self.deleted += stat.deleted;
+            }
+            None => {}
+        }
+    }
+}
+
+/// A shared registry of the stat of each revision of each file, keyed by the
+/// path of the file within the repository and the revision.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DiffStatRegistry {
+    stats: Arc<Mutex<HashMap<(PathBuf, String), FileStat>>>,
+}
+
+impl DiffStatRegistry {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn get(&self, path: &Path, revision: &str) -> Option<FileStat> {
+        self.stats
+            .lock()
+            .unwrap()
+            .get(&(path.to_path_buf(), revision.to_string()))
+            .copied()
+    }
+
+    fn record(&self, path: &Path, revision: &str, stat: FileStat) {
+        self.stats
+            .lock()
+            .unwrap()
+            .insert((path.to_path_buf(), revision.to_string()), stat);
+    }
+}
+
+/// A revision visited while walking the revision tree of a file.
+#[derive(Debug, Clone)]
+pub(crate) struct Visited {
+    revision: String,
+    lines: usize,
+    dead: bool,
+    binary: bool,
+}
+
+impl Visited {
+    /// Describes a revision, given its content and the number of lines in it.
+    pub(crate) fn new(revision: String, content: &[u8], lines: usize, dead: bool) -> Self {
+        Self {
+            revision,
+            lines,
+            dead,
+            binary: content.contains(&0),
+        }
+    }
+}
+
+/// Records the stat of each revision of a file as one line of its revision
+/// tree is walked, either from the head revision back along the trunk, or
+/// forwards along a branch from its branch point.
+#[derive(Debug)]
+pub(crate) struct Walk<'a> {
+    registry: &'a DiffStatRegistry,
+    path: &'a Path,
+    trunk: bool,
+
+    /// The revision visited before the current one.
+    last: Option<Visited>,
+}
+
+impl<'a> Walk<'a> {
+    /// Starts walking the trunk from the head revision.
+    pub(crate) fn trunk(registry: &'a DiffStatRegistry, path: &'a Path) -> Self {
+        Self {
+            registry,
+            path,
+            trunk: true,
+            last: None,
+        }
+    }
+
+    /// Starts walking a branch from the given branch point.
+    pub(crate) fn branch(
+        registry: &'a DiffStatRegistry,
+        path: &'a Path,
+        branch_point: Visited,
+    ) -> Self {
+        Self {
+            registry,
+            path,
+            trunk: false,
+            last: Some(branch_point),
+        }
+    }
+
+    /// Visits the next revision, given the commands of the ed script that
+    /// created it from the last revision visited, which are ignored for the
+    /// head revision.
+    pub(crate) fn visit(&mut self, visited: Visited, commands: &[Command]) {
+        let added = commands.iter().map(Command::lines_added).sum();
+        let deleted = commands.iter().map(Command::lines_deleted).sum();
+
+        match self.last.take() {
+            // On the trunk, the script turned the last revision, which is newer,
+            // into this one, so its additions are deletions by the newer one.
+            Some(newer) if self.trunk => {
+                let stat = stat(&visited, &newer, deleted, added);
+                self.registry.record(self.path, &newer.revision, stat);
+            }
+            Some(previous) => {
+                let stat = stat(&previous, &visited, added, deleted);
+                self.registry.record(self.path, &visited.revision, stat);
+            }
+            None => {}
+        }
+        self.last = Some(visited);
+    }
+
+    /// Finishes the walk, recording the oldest trunk revision as adding every
+    /// line in it.
+    pub(crate) fn finish(self) {
+        if let (true, Some(oldest)) = (self.trunk, self.last) {
+            let stat = FileStat {
+                added: if oldest.dead { 0 } else { oldest.lines },
+                deleted: 0,
+                binary: oldest.binary,
+            };
+            self.registry.record(self.path, &oldest.revision, stat);
+        }
+    }
+}
+
+/// Returns the stat of a revision derived from `base`, given the lines that
+/// the ed script between them adds and deletes. Files that are deleted or
+/// re-added in the revision count every line in the file instead.
+fn stat(base: &Visited, revision: &Visited, added: usize, deleted: usize) -> FileStat {
+    let binary = if revision.dead {
+        base.binary
+    } else {
+        revision.binary
+    };
+    let (added, deleted) = match (base.dead, revision.dead) {
+        _ if binary => (0, 0),
+        (true, true) => (0, 0),
+        (false, true) => (0, base.lines),
+        (true, false) => (revision.lines, 0),
+        (false, false) => (added, deleted),
+    };
+
+    FileStat {
+        added,
+        deleted,
+        binary,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rcs_ed::Script;
+
+    use super::*;
+
+    fn visited(revision: &str, content: &[u8], dead: bool) -> Visited {
+        let lines = content.iter().filter(|byte| **byte == b'\n').count();
+        Visited::new(revision.to_string(), content, lines, dead)
+    }
+
+    fn commands(script: &[u8]) -> Vec<Command> {
+        Script::parse(script).into_command_list().unwrap()
+    }
+
+    #[test]
+    fn test_trunk() {
+        let registry = DiffStatRegistry::new();
+        let path = Path::new("mod/a.txt");
+
+        // 1.3 deletes the file, 1.2 changes one line and adds another, and 1.1
+        // adds the file.
+        let mut walk = Walk::trunk(&registry, path);
+        walk.visit(visited("1.3", b"one\ntwo\nthree\n", true), &[]);
+        walk.visit(visited("1.2", b"one\ntwo\nthree\n", false), &[]);
+        walk.visit(
+            visited("1.1", b"one\n2\n", false),
+            &commands(b"d2 2\na3 1\n2\n"),
+        );
+        walk.finish();
+
+        let stat = |revision| registry.get(path, revision).unwrap();
+        assert_eq!(
+            stat("1.3"),
+            FileStat {
+                deleted: 3,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            stat("1.2"),
+            FileStat {
+                added: 2,
+                deleted: 1,
+                binary: false,
+            }
+        );
+        assert_eq!(
+            stat("1.1"),
+            FileStat {
+                added: 2,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_branch() {
+        let registry = DiffStatRegistry::new();
+        let path = Path::new("mod/a.bin");
+
+        let mut walk = Walk::branch(&registry, path, visited("1.1", b"one\n", false));
+        walk.visit(
+            visited("1.1.2.1", b"one\ntwo\n", false),
+            &commands(b"a1 1\ntwo\n"),
+        );
+        walk.visit(
+            visited("1.1.2.2", b"\0\n", false),
+            &commands(b"d1 2\na2 1\n\0\n"),
+        );
+        walk.finish();
+
+        assert_eq!(
+            registry.get(path, "1.1.2.1"),
+            Some(FileStat {
+                added: 1,
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            registry.get(path, "1.1.2.2"),
+            Some(FileStat {
+                binary: true,
+                ..Default::default()
+            })
+        );
+        assert_eq!(registry.get(path, "1.1"), None);
+
+        let mut diffstat = DiffStat::default();
+        diffstat.add(registry.get(path, "1.1.2.1"));
+        diffstat.add(registry.get(path, "1.1.2.2"));
+        diffstat.add(None);
+        assert_eq!(
+            diffstat,
+            DiffStat {
+                files: 3,
+                added: 1,
+                deleted: 0,
+                binary: 1,
+            }
+        );
+    }
+}
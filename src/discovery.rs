@@ -1,24 +1,48 @@
 //! RCS file discovery and parsing.
 
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     ffi::OsStr,
-    fs,
+    fmt::Display,
+    mem,
+    num::NonZeroUsize,
     os::unix::prelude::OsStrExt,
     path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
 };
 
+use anyhow::Context;
 use async_recursion::async_recursion;
-use comma_v::{Delta, DeltaText, Num, Sym};
+use comma_v::{Checksum, DateQuirks, Delta, IntString, LazyFile, Num, Sym};
 use flume::{Receiver, Sender};
 use git_cvs_fast_import_process::Output;
-use git_cvs_fast_import_state::Manager;
+use git_cvs_fast_import_state::{FileRevisionID, Manager};
 use git_fast_import::{Blob, Mark};
 use log::Level;
 use rcs_ed::{File, Script};
-use tokio::task;
+use thiserror::Error;
+use tokio::{sync::OnceCell, task::JoinSet};
+use tracing::Instrument;
+use unicode_normalization::UnicodeNormalization;
 
-use crate::observer::Observer;
+use crate::{
+    changelog::ChangeLogRegistry,
+    content_cache::{ContentCache, FileKey},
+    diffstat::{DiffStatRegistry, Visited, Walk},
+    errors::{is_recoverable, ErrorRegistry, StructuralError},
+    exclusions::SymbolExclusions,
+    losses::{LossKind, LossRegistry},
+    metrics::Metrics,
+    observer::{self, FileRevision, Observer},
+    provenance::ProvenanceRegistry,
+    skip_list::SkipList,
+    source::{Entry, Source},
+    throttle::ReadLimiter,
+};
 
 /// A task that parses each file it's given.
 ///
@@ -30,152 +54,742 @@ use crate::observer::Observer;
 ///    the file revision later.
 /// 3. Sending each file revision to the `Observer`, which will in turn persist
 ///    the revision to the state and store.
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub(crate) struct Discovery {
-    tx: Sender<PathBuf>,
+    tx: Sender<Job>,
+    hard_links: HardLinks,
+    workers: JoinSet<anyhow::Result<()>>,
+
+    /// The number of RCS files that the workers have been given.
+    found: Arc<AtomicUsize>,
+}
+
+/// The blobs sent for each revision of each path in the repository, shared
+/// between the workers.
+///
+/// More than one RCS file can map to the same path, such as a file that's in
+/// both its directory and the `Attic`, and these may be parsed by different
+/// workers at the same time. Before the state knows about a revision, the first
+/// worker to claim it sends its blob, and any other worker that claims it
+/// waits for, and then uses, the same mark.
+///
+/// Claims are only needed until the state knows about the revisions, so each
+/// path's claims are dropped once no worker is handling a file at that path.
+#[derive(Debug, Clone, Default)]
+struct BlobClaims {
+    paths: Arc<Mutex<HashMap<PathBuf, PathClaims>>>,
+}
+
+/// The claims for a single path.
+#[derive(Debug, Default)]
+struct PathClaims {
+    /// The number of workers currently handling a file at the path.
+    handlers: usize,
+    revisions: HashMap<Num, Claim>,
+}
+
+/// The mark of the blob sent for a revision, once the claiming worker has sent
+/// it.
+type Claim = Arc<OnceCell<Mark>>;
+
+impl BlobClaims {
+    /// Registers a worker as handling a file at the given path. The path's
+    /// claims are kept until every guard for it has been dropped, which should
+    /// only happen once the file's revisions have been sent to the observer.
+    fn enter(&self, path: &Path) -> ClaimGuard {
+        self.paths
+            .lock()
+            .unwrap()
+            .entry(path.to_path_buf())
+            .or_default()
+            .handlers += 1;
+
+        ClaimGuard {
+            claims: self.clone(),
+            path: path.to_path_buf(),
+        }
+    }
+
+    /// Returns the mark of the blob for the given revision, calling `send` to
+    /// send it if nothing else has. If `send` fails, the next claim will try
+    /// again.
+    async fn claim<F, Fut>(&self, path: &Path, revision: &Num, send: F) -> anyhow::Result<Mark>
+    where
+        F: FnOnce() -> Fut,
+        Fut: std::future::Future<Output = anyhow::Result<Mark>>,
+    {
+        let cell = self
+            .paths
+            .lock()
+            .unwrap()
+            .entry(path.to_path_buf())
+            .or_default()
+            .revisions
+            .entry(revision.clone())
+            .or_default()
+            .clone();
+
+        Ok(*cell.get_or_try_init(send).await?)
+    }
+}
+
+/// Drops a path's claims when the last worker handling a file at that path is
+/// done with it.
+#[derive(Debug)]
+struct ClaimGuard {
+    claims: BlobClaims,
+    path: PathBuf,
+}
+
+impl Drop for ClaimGuard {
+    fn drop(&mut self) {
+        let mut paths = self.claims.paths.lock().unwrap();
+        if let Some(claims) = paths.get_mut(&self.path) {
+            claims.handlers -= 1;
+            if claims.handlers == 0 {
+                paths.remove(&self.path);
+            }
+        }
+    }
+}
+
+/// An entry queued for parsing, along with any other paths that are hard links
+/// to the same RCS file.
+#[derive(Debug)]
+struct Job {
+    entry: Entry,
+    aliases: Vec<PathBuf>,
+}
+
+/// How RCS files that are hard linked into more than one directory are
+/// imported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum HardLinks {
+    /// The file is parsed once, and its history is imported at every path.
+    Alias,
+
+    /// The file is only imported at the first path, in name order.
+    First,
+}
+
+impl HardLinks {
+    pub(crate) const VARIANTS: &'static [&'static str] = &["alias", "first"];
+}
+
+impl FromStr for HardLinks {
+    type Err = UnknownHardLinksError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "alias" => Ok(Self::Alias),
+            "first" => Ok(Self::First),
+            _ => Err(UnknownHardLinksError(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("unknown hard link handling: {0}")]
+pub(crate) struct UnknownHardLinksError(String);
+
+/// How symbols that point to revisions that don't exist in the RCS file, such
+/// as revisions removed with `rcs -o`, are imported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MissingSymbolRevisions {
+    /// Tags are moved to the nearest ancestor of the revision that exists,
+    /// and branches grow from it.
+    Ancestor,
+
+    /// The symbol is ignored for the file.
+    Drop,
+}
+
+impl MissingSymbolRevisions {
+    pub(crate) const VARIANTS: &'static [&'static str] = &["ancestor", "drop"];
+}
+
+impl FromStr for MissingSymbolRevisions {
+    type Err = UnknownMissingSymbolRevisionsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ancestor" => Ok(Self::Ancestor),
+            "drop" => Ok(Self::Drop),
+            _ => Err(UnknownMissingSymbolRevisionsError(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("unknown missing symbol revision handling: {0}")]
+pub(crate) struct UnknownMissingSymbolRevisionsError(String);
+
+/// The Unicode normalization form that paths are converted to as they're
+/// imported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PathNormalization {
+    /// Composed characters, as used by most Linux and Windows tools.
+    Nfc,
+
+    /// Decomposed characters, as written by older versions of macOS.
+    Nfd,
+
+    /// Paths are imported exactly as they're found.
+    None,
+}
+
+impl PathNormalization {
+    pub(crate) const VARIANTS: &'static [&'static str] = &["nfc", "nfd", "none"];
+
+    /// Normalizes the given path. Paths that aren't valid UTF-8 are returned
+    /// unchanged, since there's nothing to normalize them against.
+    pub(crate) fn normalize(&self, path: PathBuf) -> PathBuf {
+        let normalize: fn(&str) -> String = match self {
+            Self::Nfc => |s| s.nfc().collect(),
+            Self::Nfd => |s| s.nfd().collect(),
+            Self::None => return path,
+        };
+
+        match path.to_str() {
+            Some(s) => PathBuf::from(normalize(s)),
+            None => path,
+        }
+    }
+}
+
+impl Display for PathNormalization {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Nfc => "nfc",
+            Self::Nfd => "nfd",
+            Self::None => "none",
+        })
+    }
+}
+
+impl FromStr for PathNormalization {
+    type Err = UnknownPathNormalizationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nfc" => Ok(Self::Nfc),
+            "nfd" => Ok(Self::Nfd),
+            "none" => Ok(Self::None),
+            _ => Err(UnknownPathNormalizationError(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("unknown path normalization: {0}")]
+pub(crate) struct UnknownPathNormalizationError(String);
+
+/// Options controlling how files are discovered and parsed.
+#[derive(Debug, Clone)]
+pub(crate) struct Options {
+    /// The cache of reconstructed file revision contents, if any.
+    pub content_cache: Option<ContentCache>,
+
+    /// The branches and tags that are skipped as each file is parsed. The head
+    /// branch is never excluded.
+    pub exclusions: SymbolExclusions,
+
+    /// How to import RCS files that are hard linked into more than one
+    /// directory.
+    pub hard_links: HardLinks,
+
+    /// What to name the default HEAD branch.
+    pub head_branch: String,
+
+    /// If true, errors are recorded and then the file is skipped, rather than
+    /// the worker terminating.
+    pub ignore_errors: bool,
+
+    /// The number of worker tasks to create.
+    pub jobs: usize,
+
+    /// The maximum number of trunk revisions imported for each file, if any.
+    /// Older revisions are squashed into a single revision.
+    pub max_revisions_per_file: Option<NonZeroUsize>,
+
+    /// How to import symbols that point to revisions that don't exist.
+    pub missing_symbol_revisions: MissingSymbolRevisions,
+
+    /// The prefix to strip from each path to calculate the path of the file
+    /// within the repository.
+    pub prefix: PathBuf,
+
+    /// The Unicode normalization form that paths within the repository are
+    /// converted to.
+    pub path_normalization: PathNormalization,
+
+    /// If set, the RCS admin metadata of each file is recorded here.
+    pub provenance: Option<ProvenanceRegistry>,
+
+    /// If set, the entry added by each revision of each ChangeLog is recorded
+    /// here.
+    pub changelogs: Option<ChangeLogRegistry>,
+
+    /// If set, the line counts of each file revision are recorded here.
+    pub diffstats: Option<DiffStatRegistry>,
+
+    /// The RCS files that are skipped without being parsed, if any.
+    pub skip_list: Option<SkipList>,
+
+    /// If true, the dead trunk revision that CVS adds when a file is added on
+    /// a branch is skipped, rather than imported as a deletion.
+    pub skip_branch_add_placeholders: bool,
+
+    /// The limit on how quickly RCS files are read, if any.
+    pub read_limiter: Option<ReadLimiter>,
+
+    /// The size from which RCS files on the local filesystem are mapped into
+    /// memory, rather than read.
+    pub mmap_threshold: u64,
+
+    /// The deviations from the RCS date format that are accepted.
+    pub date_quirks: DateQuirks,
+
+    /// If true, the branch point of each branch without any revisions of its
+    /// own in a file is observed, so that empty branches can be created.
+    pub create_empty_branches: bool,
 }
 
 impl Discovery {
     /// Instantiates a new Discovery task.
     ///
-    /// Parallelism is controlled by the `jobs` argument, which specifies the
-    /// number of worker tasks to create.
+    /// Parallelism is controlled by `options.jobs`, which specifies the number
+    /// of worker tasks to create.
     pub fn new(
         state: &Manager,
         output: &Output,
         observer: &Observer,
-        head_branch: &str,
-        ignore_errors: bool,
-        jobs: usize,
-        prefix: &Path,
+        errors: &ErrorRegistry,
+        losses: &LossRegistry,
+        metrics: &Metrics,
+        options: &Options,
     ) -> Self {
         // This is a multi-producer, multi-consumer channel that we use to fan
-        // paths out to workers.
-        let (tx, rx) = flume::unbounded::<PathBuf>();
+        // entries out to workers. It's bounded by the number of workers, since
+        // entries from an archive have already been read into memory, and
+        // queueing them faster than they can be parsed would end up holding
+        // the whole archive in memory.
+        let (tx, rx) = flume::bounded::<Job>(options.jobs);
 
         // Start each worker.
-        for _i in 0..jobs {
+        let mut workers = JoinSet::new();
+        let found = Arc::new(AtomicUsize::new(0));
+        let claims = BlobClaims::default();
+        for _i in 0..options.jobs {
             let worker = Worker::new(
-                &rx,
-                observer,
-                output,
-                prefix,
-                state,
-                head_branch,
-                ignore_errors,
+                &rx, &found, &claims, observer, errors, losses, metrics, output, state, options,
             );
-            task::spawn(async move { worker.work().await });
+            workers.spawn(async move { worker.work().await });
         }
 
-        Self { tx }
+        Self {
+            tx,
+            hard_links: options.hard_links,
+            workers,
+            found,
+        }
     }
 
-    /// Queues the given path for parsing on the next available worker.
-    pub fn discover(&self, path: &Path) -> anyhow::Result<()> {
-        Ok(self.tx.send(path.to_path_buf())?)
+    /// Waits for the workers to parse every queued entry, returning the number
+    /// of RCS files that were found.
+    ///
+    /// File errors are recorded in the [`ErrorRegistry`] rather than returned
+    /// here, so an error means that a worker failed for some other reason, such
+    /// as a panic. The first such failure is returned as soon as it happens,
+    /// rather than once the other workers have finished.
+    pub async fn join(self) -> anyhow::Result<usize> {
+        let Self {
+            tx,
+            mut workers,
+            found,
+            ..
+        } = self;
+
+        // Closing the queue allows the workers to finish once it's empty.
+        drop(tx);
+        while let Some(result) = workers.join_next().await {
+            result.context("discovery worker failed")??;
+        }
+
+        Ok(found.load(Ordering::SeqCst))
+    }
+
+    /// Queues the given entry for parsing on the next available worker, waiting
+    /// for one to take it if the queue is full.
+    pub fn discover(&self, entry: Entry) -> anyhow::Result<()> {
+        Ok(self.tx.send(Job {
+            entry,
+            aliases: Vec::new(),
+        })?)
+    }
+
+    /// Walks the given source, queueing each entry for parsing.
+    ///
+    /// Files on the local filesystem are queued largest first once the walk is
+    /// complete: otherwise, a handful of large files found late in the walk
+    /// can end up serialising the tail of the import on a single worker.
+    /// Entries that have already been read into memory are queued immediately,
+    /// since holding them back would only increase memory usage.
+    ///
+    /// Queueing blocks until a worker is free to take the entry, so this should
+    /// be run with [`tokio::task::block_in_place`] when called from the
+    /// runtime.
+    ///
+    /// Files on the local filesystem that are hard linked into more than one
+    /// directory are only queued once, and handled according to the
+    /// [`HardLinks`] option.
+    pub fn discover_source(
+        &self,
+        source: &dyn Source,
+        directories: &[PathBuf],
+    ) -> anyhow::Result<()> {
+        let mut pending: Vec<(u64, Job)> = Vec::new();
+        let mut links: HashMap<(u64, u64), usize> = HashMap::new();
+        source.walk(directories, &mut |entry| match entry {
+            Entry::Path(_) => {
+                if let Some(id) = entry.hard_link_id()? {
+                    if let Some(index) = links.get(&id) {
+                        let job = &mut pending[*index].1;
+                        match self.hard_links {
+                            HardLinks::Alias => {
+                                log::debug!(
+                                    "{} is a hard link to {}; aliasing its history",
+                                    entry.path().display(),
+                                    job.entry.path().display()
+                                );
+                                job.aliases.push(entry.path().to_path_buf());
+                            }
+                            HardLinks::First => {
+                                log::info!(
+                                    "skipping {}, as it is a hard link to {}",
+                                    entry.path().display(),
+                                    job.entry.path().display()
+                                );
+                            }
+                        }
+                        return Ok(());
+                    }
+                    links.insert(id, pending.len());
+                }
+
+                pending.push((
+                    entry.size()?,
+                    Job {
+                        entry,
+                        aliases: Vec::new(),
+                    },
+                ));
+                Ok(())
+            }
+            Entry::Buffer { .. } => self.discover(entry),
+        })?;
+
+        pending.sort_by(|(a, _), (b, _)| b.cmp(a));
+        for (_size, job) in pending {
+            self.tx.send(job)?;
+        }
+
+        Ok(())
     }
 }
 
 /// Worker represents an individual worker task processing RCS files.
 struct Worker {
+    content_cache: Option<ContentCache>,
+    found: Arc<AtomicUsize>,
+    claims: BlobClaims,
+    exclusions: SymbolExclusions,
     observer: Observer,
+    errors: ErrorRegistry,
+    losses: LossRegistry,
+    metrics: Metrics,
     output: Output,
     prefix: PathBuf,
-    rx: Receiver<PathBuf>,
+    path_normalization: PathNormalization,
+    provenance: Option<ProvenanceRegistry>,
+    changelogs: Option<ChangeLogRegistry>,
+    diffstats: Option<DiffStatRegistry>,
+    skip_list: Option<SkipList>,
+    skip_branch_add_placeholders: bool,
+    read_limiter: Option<ReadLimiter>,
+    mmap_threshold: u64,
+    date_quirks: DateQuirks,
+    rx: Receiver<Job>,
     state: Manager,
     head_branch: Vec<u8>,
     ignore_errors: bool,
+    max_revisions_per_file: Option<NonZeroUsize>,
+    missing_symbol_revisions: MissingSymbolRevisions,
+    create_empty_branches: bool,
 }
 
 impl Worker {
     /// Instantiates a new worker.
+    #[allow(clippy::too_many_arguments)]
     fn new(
-        rx: &Receiver<PathBuf>,
+        rx: &Receiver<Job>,
+        found: &Arc<AtomicUsize>,
+        claims: &BlobClaims,
         observer: &Observer,
+        errors: &ErrorRegistry,
+        losses: &LossRegistry,
+        metrics: &Metrics,
         output: &Output,
-        prefix: &Path,
         state: &Manager,
-        head_branch: &str,
-        ignore_errors: bool,
+        options: &Options,
     ) -> Self {
         Self {
+            content_cache: options.content_cache.clone(),
+            found: found.clone(),
+            claims: claims.clone(),
+            exclusions: options.exclusions.clone(),
             observer: observer.clone(),
+            errors: errors.clone(),
+            losses: losses.clone(),
+            metrics: metrics.clone(),
             output: output.clone(),
-            prefix: prefix.to_path_buf(),
+            prefix: options.prefix.clone(),
+            path_normalization: options.path_normalization,
+            provenance: options.provenance.clone(),
+            changelogs: options.changelogs.clone(),
+            diffstats: options.diffstats.clone(),
+            skip_list: options.skip_list.clone(),
+            skip_branch_add_placeholders: options.skip_branch_add_placeholders,
+            read_limiter: options.read_limiter.clone(),
+            mmap_threshold: options.mmap_threshold,
+            date_quirks: options.date_quirks,
             rx: rx.clone(),
             state: state.clone(),
-            head_branch: head_branch.as_bytes().into(),
-            ignore_errors,
+            head_branch: options.head_branch.as_bytes().into(),
+            ignore_errors: options.ignore_errors,
+            max_revisions_per_file: options.max_revisions_per_file,
+            missing_symbol_revisions: options.missing_symbol_revisions,
+            create_empty_branches: options.create_empty_branches,
         }
     }
 
-    /// Listens on the worker queue for RCS paths and handles them.
+    /// Listens on the worker queue for RCS entries and handles them.
     async fn work(&self) -> anyhow::Result<()> {
         // recv_async() ultimately returns a RecvError in the error path, which
         // only has one possible value: Disconnected. Therefore we don't need to
         // interrogate an error return any further, it just means we should
         // terminate the worker.
-        while let Ok(path) = self.rx.recv_async().await {
-            if fs::metadata(&path)?.is_dir() {
+        while let Ok(Job { entry, aliases }) = self.rx.recv_async().await {
+            if entry.is_dir()? {
                 continue;
             }
 
-            if !path.as_os_str().as_bytes().ends_with(b",v") {
-                log::trace!("ignoring {} due to non-,v suffix", path.display());
+            if !entry.is_comma_v() {
+                log::trace!("ignoring {} due to non-,v suffix", entry.path().display());
                 continue;
             }
+            self.found.fetch_add(1, Ordering::SeqCst);
+
+            // Files on the skip list aren't even read, since they're usually
+            // there because they can't be parsed.
+            if let Some(skip_list) = &self.skip_list {
+                let path = entry.path();
+                if let Some(reason) =
+                    skip_list.skip(path.strip_prefix(&self.prefix).unwrap_or(path))
+                {
+                    tracing::info!(path = %path.display(), reason, "file skipped");
+                    log::info!(
+                        "skipping {}, as it is on the skip list: {}",
+                        path.display(),
+                        reason
+                    );
+                    continue;
+                }
+            }
 
+            // Only files on the local filesystem are limited, since other
+            // sources have already been read by the time they get here.
+            if let (Some(limiter), Entry::Path(_)) = (&self.read_limiter, &entry) {
+                limiter.acquire(entry.size()?).await;
+            }
+
+            let (path, contents) = entry.into_mapped_contents(self.mmap_threshold)?;
             log::trace!("processing {}", path.display());
-            if let Err(e) = self.handle_path(&path).await {
+            let span = tracing::info_span!("file", path = %path.display());
+            if let Err(e) = self
+                .handle_path(&path, &aliases, &contents)
+                .instrument(span.clone())
+                .await
+            {
+                // Structural problems only affect this file, so it's skipped
+                // whether or not we've been told to ignore errors.
+                let skip = self.ignore_errors || is_recoverable(&e);
+                if skip {
+                    tracing::warn!(parent: &span, error = %e, "file error");
+                } else {
+                    tracing::error!(parent: &span, error = %e, "file error");
+                }
+                // If the observer has stopped, nothing else can be imported,
+                // so the whole discovery phase fails now.
+                if e.chain().any(|cause| cause.is::<observer::Error>()) {
+                    return Err(e.context(format!("cannot observe {}", path.display())));
+                }
+
                 log::log!(
-                    if self.ignore_errors {
-                        Level::Warn
-                    } else {
-                        Level::Error
-                    },
+                    if skip { Level::Warn } else { Level::Error },
                     "error processing {}: {:?}",
                     path.display(),
                     e
                 );
-                if self.ignore_errors {
-                    continue;
-                } else {
-                    return Err(e);
-                }
+                // Fatal errors are reported along with any others once parsing
+                // is complete, so the worker keeps going either way: stopping
+                // would leave the rest of the queue unparsed, and the summary
+                // incomplete.
+                self.errors.record(&path, &e);
+                self.metrics.file_error();
+                continue;
             }
+            tracing::info!(parent: &span, "file parsed");
+            self.metrics.file_parsed();
         }
 
         Ok(())
     }
 
     /// Handles an individual RCS file.
-    async fn handle_path(&self, path: &Path) -> anyhow::Result<()> {
-        // Parse the ,v file.
-        let cv = comma_v::parse(&fs::read(path)?)?;
+    ///
+    /// `aliases` are other paths that are hard links to the same file: the
+    /// file is only parsed once, and each revision's content is only sent once,
+    /// but its history is imported at each path.
+    async fn handle_path(
+        &self,
+        path: &Path,
+        aliases: &[PathBuf],
+        contents: &[u8],
+    ) -> anyhow::Result<()> {
+        // Parse the ,v file. The log messages and delta texts are left in the
+        // contents, which may be mapped from disk, until each revision is
+        // handled, so that large files aren't copied into memory in full.
+        let cv = comma_v::parse_lazy_with_quirks(contents, self.date_quirks)?;
 
         // Set up an easier to display version of the path for logging purposes.
         let disp = path.display();
 
         // Calculate the real path of the file in the repository.
-        let real_path = munge_raw_path(path, &self.prefix);
+        let real_path = munge_raw_path(path, &self.prefix, self.path_normalization);
 
         // Branches and tags are defined as symbols in the RCS admin area, so we
         // have them up front rather than as we parse each revision. Let's set
         // up a revision -> tags map that we can use to send tags as we send
         // revisions, along with a branch -> head revision map for branches.
+        //
+        // A malformed file may define the same symbol more than once. RCS
+        // itself uses the first definition, so we do the same, but we record
+        // the others as losses rather than dropping them silently.
+        //
+        // Excluded symbols are dropped before anything else sees them, but we
+        // keep track of the excluded branches so that their revisions can be
+        // skipped, rather than reported as not being on a named branch.
         let mut branches: HashMap<Sym, Num> = HashMap::new();
+        let mut excluded_branches: Vec<Num> = Vec::new();
         let mut revision_tags: HashMap<Num, Vec<Sym>> = HashMap::new();
+        let mut seen: HashMap<&Sym, &Num> = HashMap::new();
         for (tag, revision) in cv.admin.symbols.iter() {
-            match revision {
-                Num::Branch(_) => {
+            let excluded = match revision {
+                Num::Branch(_) => self.exclusions.excludes_branch(tag),
+                Num::Commit(_) => self.exclusions.excludes_tag(tag),
+            };
+            if excluded {
+                log::trace!(
+                    "{}: excluding symbol {}",
+                    disp,
+                    String::from_utf8_lossy(tag)
+                );
+                if let Num::Branch(_) = revision {
+                    excluded_branches.push(revision.clone());
+                }
+                continue;
+            }
+
+            if let Some(first) = seen.get(tag) {
+                self.losses.record(
+                    &real_path,
+                    LossKind::DuplicateSymbol,
+                    format!(
+                        "ignoring {} pointing to {}, as it already points to {}",
+                        String::from_utf8_lossy(tag),
+                        revision,
+                        first
+                    ),
+                );
+                continue;
+            }
+            seen.insert(tag, revision);
+
+            if let Num::Commit(_) = revision {
+                if std::str::from_utf8(tag).is_err() {
+                    self.losses.record(
+                        &real_path,
+                        LossKind::SanitizedRefName,
+                        format!("tag {} isn't valid UTF-8", String::from_utf8_lossy(tag)),
+                    );
+                }
+            }
+
+            // Revisions removed with rcs -o leave gaps that symbols may still
+            // point into: a tag at the missing revision itself, or a branch
+            // at its branch point.
+            let missing = match revision {
+                Num::Branch(_) => revision
+                    .branch_point()
+                    .filter(|branch_point| !cv.delta.contains_key(branch_point)),
+                Num::Commit(_) if !cv.delta.contains_key(revision) => Some(revision.clone()),
+                Num::Commit(_) => None,
+            };
+            let ancestor = match (&missing, self.missing_symbol_revisions) {
+                (Some(missing), MissingSymbolRevisions::Ancestor) => nearest_ancestor(&cv, missing),
+                _ => None,
+            };
+            if let Some(missing) = &missing {
+                self.losses.record(
+                    &real_path,
+                    LossKind::MissingSymbolRevision,
+                    format!(
+                        "{} {} points to revision {}, which doesn't exist; {}",
+                        match revision {
+                            Num::Branch(_) => "branch",
+                            Num::Commit(_) => "tag",
+                        },
+                        String::from_utf8_lossy(tag),
+                        missing,
+                        match &ancestor {
+                            Some(ancestor) => format!("using {} instead", ancestor),
+                            None => "ignoring it".to_string(),
+                        }
+                    ),
+                );
+            }
+
+            match (revision, missing, ancestor) {
+                // The branch already contains every revision before its
+                // branch point, so it grows from the nearest one that exists
+                // without any help.
+                (Num::Branch(_), None, _) | (Num::Branch(_), Some(_), Some(_)) => {
                     branches.insert(tag.clone(), revision.clone());
                 }
-                Num::Commit(_) => {
+                (Num::Branch(_), Some(_), None) => {
+                    excluded_branches.push(revision.clone());
+                }
+                (Num::Commit(_), None, _) => {
                     revision_tags
                         .entry(revision.clone())
                         .or_default()
                         .push(tag.clone());
                 }
+                (Num::Commit(_), Some(_), Some(ancestor)) => {
+                    revision_tags.entry(ancestor).or_default().push(tag.clone());
+                }
+                (Num::Commit(_), Some(_), None) => {}
             }
         }
 
@@ -183,154 +797,630 @@ impl Worker {
         if let Some(ref head) = cv.admin.head {
             branches.insert(Sym::from(self.head_branch.clone()), head.to_branch());
         }
+        self.observer.branches(branches.keys());
 
-        // Set up the file revision handler.
-        let handler = FileRevisionHandler {
-            worker: self,
-            branches,
-            revision_tags,
-            real_path: &real_path,
-        };
+        // A branch created with `cvs tag -b` has no revisions until something
+        // is committed on it, so its branch point is the only record of where
+        // it starts.
+        let mut branch_points: HashMap<Num, Vec<(Sym, Sym)>> = HashMap::new();
+        if self.create_empty_branches {
+            for (name, branch) in branches.iter() {
+                let branch_point = match branch.branch_point() {
+                    Some(branch_point) if cv.delta.contains_key(&branch_point) => branch_point,
+                    _ => continue,
+                };
+                if cv.delta.keys().any(|num| num.to_branch() == *branch) {
+                    continue;
+                }
+
+                let parent = branch_point.to_branch();
+                let parent_name = branches
+                    .iter()
+                    .find(|(_name, num)| **num == parent)
+                    .map(|(name, _num)| name.clone())
+                    .or_else(|| {
+                        parent
+                            .is_on_trunk()
+                            .then(|| Sym::from(self.head_branch.clone()))
+                    });
+                if let Some(parent_name) = parent_name {
+                    branch_points
+                        .entry(branch_point)
+                        .or_default()
+                        .push((name.clone(), parent_name));
+                }
+            }
+        }
 
         // It's time to parse each revision and send each one to the various
         // places they need to go. Let's start at the HEAD.
         let head_num = match cv.head() {
             Some(num) => num,
-            None => anyhow::bail!("{}: cannot find HEAD revision", disp),
+            None => return Err(StructuralError::MissingHead.into()),
         };
         log::trace!("{}: found HEAD revision {}", disp, head_num);
 
-        handle_tree(&handler, &cv, path, None, head_num).await
+        // If the integrity string has checksums for any revisions, we'll
+        // check the content we reconstruct for those revisions against them.
+        let checksums = match cv.admin.integrity.as_ref().map(IntString::checksums) {
+            Some(Ok(checksums)) => checksums,
+            Some(Err(e)) => {
+                self.losses.record(
+                    &real_path,
+                    LossKind::FailedIntegrityCheck,
+                    format!("cannot read the integrity string: {}", e),
+                );
+                HashMap::new()
+            }
+            None => HashMap::new(),
+        };
+
+        // The blobs sent for each revision, which are shared between the paths
+        // that the file is imported at.
+        let blobs = Mutex::new(HashMap::new());
+
+        // Likewise, the content cache is keyed by the RCS file, not the path
+        // it's imported at. The path is relative to the CVSROOT, so that the
+        // cache can still be used if the CVSROOT moves.
+        let cache_key = self
+            .content_cache
+            .as_ref()
+            .map(|_| FileKey::new(path.strip_prefix(&self.prefix).unwrap_or(path), contents));
+
+        for real_path in std::iter::once(real_path).chain(
+            aliases
+                .iter()
+                .map(|alias| munge_raw_path(alias, &self.prefix, self.path_normalization)),
+        ) {
+            if let Some(provenance) = &self.provenance {
+                provenance.record(&real_path, &cv.admin);
+            }
+
+            // The path's blob claims are held until its revisions have been
+            // flushed, at which point the state knows about them.
+            let _claims = self.claims.enter(&real_path);
+
+            // Set up the file revision handler.
+            let handler = FileRevisionHandler {
+                worker: self,
+                branches: &branches,
+                excluded_branches: &excluded_branches,
+                revision_tags: &revision_tags,
+                branch_points: &branch_points,
+                real_path: &real_path,
+                cache_key: cache_key.as_ref(),
+                checksums: &checksums,
+                blobs: &blobs,
+                pending: Mutex::new(Vec::new()),
+                visited: Mutex::new(HashSet::new()),
+            };
+
+            handle_tree(&handler, &cv, path, None, head_num, None).await?;
+
+            // Finally, send the new revisions to the observer in one batch.
+            handler.flush().await?;
+        }
+
+        Ok(())
     }
 }
 
+/// Walks the revision tree from the given revision.
+///
+/// `branch_point` is the revision the walk is branching from, or `None` when
+/// walking the trunk from HEAD. Trunk revisions are walked from newest to
+/// oldest, whereas branch revisions are walked from oldest to newest.
 #[async_recursion]
 async fn handle_tree(
     handler: &FileRevisionHandler<'_>,
-    cv: &comma_v::File,
+    cv: &LazyFile<'_>,
     path: &Path,
     mut contents: Option<File>,
     revision: &Num,
+    branch_point: Option<&Num>,
 ) -> anyhow::Result<()> {
     let mut revision = revision;
+    let mut previous = branch_point;
+
+    // The number of trunk revisions newer than this one, which is only used
+    // when the number of revisions per file is capped.
+    let mut trunk_position = 0;
+
+    // If ChangeLog entries are being recorded for this file, the revision
+    // visited before this one and its content, which starts as the content of
+    // the branch point on a branch.
+    let changelogs = handler
+        .worker
+        .changelogs
+        .as_ref()
+        .filter(|changelogs| changelogs.tracks(handler.real_path));
+    let mut visited_content: Option<(String, Vec<u8>)> = match (changelogs, branch_point) {
+        (Some(_), Some(branch_point)) => contents
+            .as_ref()
+            .map(|contents| (branch_point.to_string(), contents.as_bytes())),
+        _ => None,
+    };
+
+    // If line counts are being recorded, the walk along this line of the
+    // revision tree, which starts from the branch point on a branch.
+    let mut walk =
+        handler
+            .worker
+            .diffstats
+            .as_ref()
+            .map(|registry| match (branch_point, contents.as_ref()) {
+                (Some(branch_point), Some(contents)) => Walk::branch(
+                    registry,
+                    handler.real_path,
+                    Visited::new(
+                        branch_point.to_string(),
+                        &contents.as_bytes(),
+                        contents.len(),
+                        cv.delta.get(branch_point).map(is_dead).unwrap_or_default(),
+                    ),
+                ),
+                _ => Walk::trunk(registry, handler.real_path),
+            });
 
     loop {
-        let (delta, delta_text) = cv.revision(revision).unwrap();
+        // A malformed file may reference the same revision more than once,
+        // which would otherwise send us around in circles.
+        if !handler.visited.lock().unwrap().insert(revision.clone()) {
+            return Err(StructuralError::RepeatedRevision(revision.clone()).into());
+        }
+
+        let (delta, delta_text) = match cv.revision(revision) {
+            Some(revision) => revision,
+            None => return Err(StructuralError::MissingRevision(revision.clone()).into()),
+        };
         log::trace!("{}: iterated to {}", path.display(), revision);
 
-        if let Some(ref mut contents) = contents {
-            let commands = Script::parse(delta_text.text.as_cursor()).into_command_list()?;
-            contents.apply_in_place(&commands)?;
-        } else {
-            contents = Some(File::new(delta_text.text.as_cursor())?);
+        // If the content is in the cache, we can skip applying the delta, and
+        // start again from the cached content for the next revision.
+        let cache = handler.worker.content_cache.as_ref();
+        let mut commands = Vec::new();
+        let revision_content = match cache
+            .zip(handler.cache_key)
+            .and_then(|(cache, key)| cache.get(key, revision.to_string().as_str()))
+        {
+            Some(cached) => {
+                // The delta is still needed for its line counts, if they're
+                // being recorded.
+                if walk.is_some() && contents.is_some() {
+                    commands = Script::parse(delta_text.text.reader()).into_command_list()?;
+                }
+                contents = Some(File::new(cached.as_slice())?);
+                cached
+            }
+            None => {
+                if let Some(ref mut contents) = contents {
+                    commands = Script::parse(delta_text.text.reader()).into_command_list()?;
+                    contents.apply_in_place(&commands).with_context(|| {
+                        format!("cannot apply the delta for revision {}", revision)
+                    })?;
+                } else {
+                    contents = Some(File::new(delta_text.text.reader())?);
+                }
+
+                let revision_content = match contents.as_ref() {
+                    Some(contents) => contents.as_bytes(),
+                    None => {
+                        anyhow::bail!("unexpected lack of contents")
+                    }
+                };
+                if let (Some(cache), Some(key)) = (cache, handler.cache_key) {
+                    cache.put(key, revision.to_string().as_str(), &revision_content);
+                }
+                revision_content
+            }
+        };
+
+        if let Some(checksum) = handler.checksums.get(revision) {
+            if !checksum.matches(&revision_content) {
+                handler.worker.losses.record(
+                    handler.real_path,
+                    LossKind::FailedIntegrityCheck,
+                    format!(
+                        "revision {} doesn't match its checksum {}",
+                        revision, checksum
+                    ),
+                );
+            }
         }
 
-        let revision_content = match contents.as_ref() {
-            Some(contents) => contents.as_bytes(),
-            None => {
-                anyhow::bail!("unexpected lack of contents")
+        if let (Some(walk), Some(contents)) = (walk.as_mut(), contents.as_ref()) {
+            walk.visit(
+                Visited::new(
+                    revision.to_string(),
+                    &revision_content,
+                    contents.len(),
+                    is_dead(delta),
+                ),
+                &commands,
+            );
+        }
+
+        if let Some(changelogs) = changelogs {
+            match (branch_point, visited_content.take()) {
+                // Trunk revisions are walked from newest to oldest, so this is
+                // the revision that the one visited before it was derived
+                // from.
+                (None, Some((newer, newer_content))) => {
+                    changelogs.record(handler.real_path, &newer, &newer_content, &revision_content)
+                }
+                (Some(_), Some((_, previous_content))) => changelogs.record(
+                    handler.real_path,
+                    &revision.to_string(),
+                    &revision_content,
+                    &previous_content,
+                ),
+                (_, None) => {}
             }
+            visited_content = Some((revision.to_string(), revision_content.clone()));
+        }
+
+        // The revision this one was derived from is the next revision on the
+        // trunk, since RCS stores the trunk in reverse, and the revision we
+        // just came from on a branch.
+        if branch_point.is_none() {
+            previous = delta.next.as_ref();
+        }
+        let is_placeholder = |revision: &Num| {
+            handler.worker.skip_branch_add_placeholders
+                && cv
+                    .revision(revision)
+                    .map(|(delta, delta_text)| {
+                        is_branch_add_placeholder(revision, delta, &delta_text.log.to_vstring())
+                    })
+                    .unwrap_or_default()
         };
+        let resurrected = !is_dead(delta)
+            && match previous {
+                Some(previous) => {
+                    cv.delta.get(previous).map(is_dead).unwrap_or_default()
+                        && !is_placeholder(previous)
+                }
+                None => false,
+            };
 
-        let mark = handler
-            .handle_revision(&revision_content, revision, delta, delta_text)
-            .await?;
-        log::trace!("{}: wrote {} to mark {:?}", path.display(), revision, mark);
+        let truncation = match (branch_point, handler.worker.max_revisions_per_file) {
+            (None, Some(max)) if trunk_position == max.get() => Truncation::Squash,
+            (None, Some(max)) if trunk_position > max.get() => Truncation::Drop,
+            _ => Truncation::Keep,
+        };
+        trunk_position += 1;
+
+        if is_placeholder(revision) {
+            log::trace!(
+                "{}: skipping {}, since it was added on a branch",
+                path.display(),
+                revision
+            );
+        } else {
+            let mark = handler
+                .handle_revision(
+                    revision_content,
+                    revision,
+                    delta,
+                    &delta_text.log.to_vstring(),
+                    resurrected,
+                    truncation,
+                )
+                .await?;
+            log::trace!("{}: wrote {} to mark {:?}", path.display(), revision, mark);
+        }
 
         // If there are branches upwards from here, we need to also handle them.
         for branch_revision in delta.branches.iter() {
+            if handler.is_excluded(branch_revision) {
+                log::trace!(
+                    "{}: skipping excluded branch from {}",
+                    path.display(),
+                    branch_revision
+                );
+                continue;
+            }
+
             // Note that we clone contents here: since we're modifying the contents in place each
             // time a new revision is seen, we have to have a separate state for each branch.
-            handle_tree(handler, cv, path, contents.clone(), branch_revision).await?;
+            handle_tree(
+                handler,
+                cv,
+                path,
+                contents.clone(),
+                branch_revision,
+                Some(revision),
+            )
+            .await?;
         }
 
         if let Some(next) = &delta.next {
+            previous = Some(revision);
             revision = next;
         } else {
+            if let Some(walk) = walk {
+                walk.finish();
+            }
             return Ok(());
         }
     }
 }
 
+/// How a trunk revision is imported when the number of revisions per file is
+/// capped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Truncation {
+    Keep,
+
+    /// The revision is imported with a message noting that the history before
+    /// it was truncated, so that its content stands in for every earlier
+    /// revision.
+    Squash,
+
+    /// The revision is older than the squashed revision, so isn't imported.
+    Drop,
+}
+
 /// Handles individual revisions of a single file.
 struct FileRevisionHandler<'a> {
     worker: &'a Worker,
-    branches: HashMap<Sym, Num>,
-    revision_tags: HashMap<Num, Vec<Sym>>,
+    branches: &'a HashMap<Sym, Num>,
+    revision_tags: &'a HashMap<Num, Vec<Sym>>,
+
+    /// The branches without any revisions of their own in the file, along
+    /// with the branch each was made from, keyed by their branch point.
+    branch_points: &'a HashMap<Num, Vec<(Sym, Sym)>>,
+
+    /// The branches that were excluded, which are skipped entirely unless an
+    /// included branch or tag is derived from them.
+    excluded_branches: &'a [Num],
     real_path: &'a Path,
+
+    /// The key of the RCS file in the content cache, if there is one.
+    cache_key: Option<&'a FileKey>,
+
+    /// The checksum of each revision that has one in the integrity string.
+    checksums: &'a HashMap<Num, Checksum>,
+
+    /// The blobs that have already been sent for each revision of the file.
+    blobs: &'a Mutex<HashMap<Num, Mark>>,
+
+    /// New revisions that haven't yet been sent to the observer.
+    pending: Mutex<Vec<(Num, FileRevision)>>,
+
+    /// Revisions that have been visited while walking the revision tree.
+    visited: Mutex<HashSet<Num>>,
 }
 
 impl FileRevisionHandler<'_> {
     /// Handles a single revision of a file.
+    ///
+    /// The content is taken, rather than borrowed, so that it can be sent as a
+    /// blob without being copied.
     async fn handle_revision(
         &self,
-        content: &[u8],
+        content: Vec<u8>,
         revision: &Num,
         delta: &Delta,
-        delta_text: &DeltaText,
+        log: &[u8],
+        resurrected: bool,
+        truncation: Truncation,
     ) -> anyhow::Result<Option<Mark>> {
-        // Check if this revision has already been seen.
-        if let Ok(revision) = self
+        // Check if this revision has already been seen. Its tags are still
+        // observed, since they may have been added or moved since.
+        if let Some(id) = self
             .worker
             .state
-            .get_file_revision(self.real_path, revision.to_string().as_str())
+            .get_file_revision_id(self.real_path, revision.to_string().as_str())
             .await
         {
-            return Ok(revision.mark.map(|mark| mark.into()));
+            self.tag(revision, id).await;
+            let file_revision = self.worker.state.get_file_revision_by_id(id).await?;
+            return Ok(file_revision.mark.map(|mark| mark.into()));
         }
 
-        let branch_iter = self.branches.iter().filter_map(|(name, head)| {
-            if head.contains(revision).unwrap() {
-                Some(name)
-            } else {
-                None
+        if truncation == Truncation::Drop {
+            return Ok(None);
+        }
+
+        let mut branches = Vec::new();
+        for (name, head) in self.branches.iter() {
+            if head.contains(revision)? {
+                branches.push(name);
             }
-        });
+        }
 
-        let mark = match &delta.state {
-            Some(state) if state == b"dead".as_ref() => None,
-            _ => Some(self.worker.output.blob(Blob::new(content)).await?),
+        let excluded = || -> anyhow::Result<bool> {
+            for branch in self.excluded_branches.iter() {
+                if branch.contains(revision)? {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
         };
-
-        let id = self
-            .worker
-            .observer
-            .file_revision(
+        if branches.is_empty() && !excluded()? {
+            self.worker.losses.record(
                 self.real_path,
-                revision,
-                branch_iter,
-                mark,
-                delta,
-                delta_text,
+                LossKind::SkippedRevision,
+                format!("revision {} isn't on a named branch", revision),
+            );
+        }
+
+        let mark = if is_dead(delta) {
+            None
+        } else {
+            let send = || async {
+                let blob = self.blobs.lock().unwrap().get(revision).copied();
+                match blob {
+                    // The content has already been sent for another path, so
+                    // we only need a new mark for it. Giving each path its own
+                    // mark keeps the mark file the same as it would be if the
+                    // paths had been imported separately.
+                    Some(mark) => Ok(self.worker.output.alias(mark).await?),
+                    None => {
+                        let mark = self.worker.output.blob(Blob::from(content)).await?;
+                        self.blobs.lock().unwrap().insert(revision.clone(), mark);
+                        Ok(mark)
+                    }
+                }
+            };
+            Some(
+                self.worker
+                    .claims
+                    .claim(self.real_path, revision, send)
+                    .await?,
             )
-            .await?;
+        };
 
+        let mut file_revision = FileRevision::new(
+            self.real_path,
+            revision,
+            branches.into_iter(),
+            mark,
+            delta,
+            log,
+            resurrected,
+        );
+        if truncation == Truncation::Squash {
+            log::debug!(
+                "{}: truncating the history before revision {}",
+                self.real_path.display(),
+                revision
+            );
+            file_revision.truncate_history();
+        }
+        self.pending
+            .lock()
+            .unwrap()
+            .push((revision.clone(), file_revision));
+
+        Ok(mark)
+    }
+
+    /// Returns true if the branch that starts with the given revision is
+    /// excluded, and nothing that's included is derived from it, in which case
+    /// none of its revisions need to be handled.
+    fn is_excluded(&self, branch_revision: &Num) -> bool {
+        let branch = branch_revision.to_branch();
+        if !self.excluded_branches.contains(&branch) {
+            return false;
+        }
+
+        // Anything on the branch, or on a branch from it, has the branch number
+        // as a prefix. This includes the branch itself, if another symbol that
+        // wasn't excluded also refers to it.
+        let prefix = match &branch {
+            Num::Branch(parts) | Num::Commit(parts) => parts,
+        };
+        let derived = |num: &Num| match num {
+            Num::Branch(parts) | Num::Commit(parts) => parts.starts_with(prefix),
+        };
+        !(self.branches.values().any(derived) || self.revision_tags.keys().any(derived))
+    }
+
+    /// Sends the pending revisions to the observer, and then sends the tags
+    /// for those revisions once their IDs are known.
+    ///
+    /// Revisions are sent in revision order, which places each revision after
+    /// the revisions it was derived from: the patchset detector relies on this
+    /// to order commits made within the same second, such as a deletion and
+    /// the subsequent re-add of a file.
+    async fn flush(&self) -> anyhow::Result<()> {
+        let mut pending = mem::take(&mut *self.pending.lock().unwrap());
+        pending.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let (revisions, file_revisions): (Vec<Num>, Vec<FileRevision>) =
+            pending.into_iter().unzip();
+
+        let ids = self.worker.observer.file_revisions(file_revisions).await?;
+
+        for (revision, id) in revisions.iter().zip(ids) {
+            self.tag(revision, id).await;
+        }
+
+        Ok(())
+    }
+
+    /// Observes the tags on the given revision, which has the given ID, along
+    /// with any empty branches made from it.
+    async fn tag(&self, revision: &Num, id: FileRevisionID) {
         if let Some(tags) = self.revision_tags.get(revision) {
             for tag in tags {
                 self.worker.observer.tag(tag, id).await;
             }
         }
+        if let Some(branch_points) = self.branch_points.get(revision) {
+            for (branch, parent) in branch_points {
+                self.worker.observer.branch_point(branch, parent, id);
+            }
+        }
+    }
+}
 
-        Ok(mark)
+/// Returns the nearest ancestor of the given revision that exists in the file,
+/// if any.
+fn nearest_ancestor(cv: &LazyFile<'_>, revision: &Num) -> Option<Num> {
+    let mut candidate = revision.parent_revision();
+    while let Some(revision) = candidate {
+        if cv.delta.contains_key(&revision) {
+            return Some(revision);
+        }
+        candidate = revision.parent_revision();
+    }
+    None
+}
+
+/// Returns true if the delta marks the file as deleted.
+fn is_dead(delta: &Delta) -> bool {
+    matches!(&delta.state, Some(state) if state == b"dead".as_ref())
+}
+
+/// The part of the log message of a placeholder revision between the file
+/// name and the branch name.
+const PLACEHOLDER_LOG: &[u8] = b" was initially added on branch ";
+
+/// Returns true if the revision is the placeholder that CVS adds to the trunk
+/// when a file is added on a branch: a dead first revision, with a branch
+/// growing from it, and a log message like `file foo.c was initially added on
+/// branch BR.`, which is the same pattern that cvs2git looks for.
+fn is_branch_add_placeholder(revision: &Num, delta: &Delta, log: &[u8]) -> bool {
+    if !revision.is_on_trunk()
+        || delta.next.is_some()
+        || delta.branches.is_empty()
+        || !is_dead(delta)
+    {
+        return false;
+    }
+
+    let log = log.strip_suffix(b"\n").unwrap_or(log);
+    match (log.strip_prefix(b"file "), log.last()) {
+        (Some(rest), Some(b'.')) => rest
+            .windows(PLACEHOLDER_LOG.len())
+            .any(|window| window == PLACEHOLDER_LOG),
+        _ => false,
     }
 }
 
 /// Strips CVSROOT-specific components of the file path: specifically, removing
 /// the ,v suffix if present and stripping the Attic if it's the last directory
-/// in the path. Returns a newly allocated OsString.
-fn munge_raw_path(input: &Path, prefix: &Path) -> PathBuf {
+/// in the path. The result is then normalized to the given Unicode form.
+/// Returns a newly allocated OsString.
+pub(crate) fn munge_raw_path(
+    input: &Path,
+    prefix: &Path,
+    normalization: PathNormalization,
+) -> PathBuf {
     let unprefixed = input.strip_prefix(prefix).unwrap_or(input);
 
-    if let Some(input_file) = unprefixed.file_name() {
+    let path = if let Some(input_file) = unprefixed.file_name() {
         let file = strip_comma_v_suffix(input_file).unwrap_or_else(|| PathBuf::from(input_file));
-        let path = strip_attic_suffix(unprefixed)
+        strip_attic_suffix(unprefixed)
             .map(|path| path.join(file))
-            .unwrap_or_else(|| input_file.into());
-
-        path
+            .unwrap_or_else(|| input_file.into())
     } else {
         unprefixed.into()
-    }
+    };
+
+    normalization.normalize(path)
 }
 
 fn strip_attic_suffix(path: &Path) -> Option<&Path> {
@@ -366,6 +1456,7 @@ mod tests {
                 munge_raw_path(
                     Path::new(OsStr::from_bytes($input)),
                     Path::new(OsStr::from_bytes($prefix)),
+                    PathNormalization::None,
                 ),
                 PathBuf::from(OsStr::from_bytes($want))
             )
@@ -405,4 +1496,102 @@ mod tests {
         assert_munge!(b"/foo/bar/Attic/quux,v", b"/foo/bar", b"quux");
         assert_munge!(b"/foo/bar/quux,v", b"/bar", b"/foo/bar/quux");
     }
+
+    #[test]
+    fn test_munge_raw_path_normalization() {
+        // "café" with a precomposed é, and with an e followed by a combining
+        // acute accent.
+        let nfc = "mod/caf\u{e9}/Attic/menu,v";
+        let nfd = "mod/cafe\u{301}/Attic/menu,v";
+
+        for input in [nfc, nfd] {
+            let munge =
+                |normalization| munge_raw_path(Path::new(input), Path::new(""), normalization);
+            assert_eq!(
+                munge(PathNormalization::Nfc),
+                PathBuf::from("mod/caf\u{e9}/menu")
+            );
+            assert_eq!(
+                munge(PathNormalization::Nfd),
+                PathBuf::from("mod/cafe\u{301}/menu")
+            );
+        }
+        assert_eq!(
+            munge_raw_path(Path::new(nfd), Path::new(""), PathNormalization::None),
+            PathBuf::from("mod/cafe\u{301}/menu")
+        );
+
+        // Paths that aren't UTF-8 are left alone.
+        assert_eq!(
+            munge_raw_path(
+                Path::new(OsStr::from_bytes(b"mod/\xe9t\xe9,v")),
+                Path::new(""),
+                PathNormalization::Nfc
+            ),
+            PathBuf::from(OsStr::from_bytes(b"mod/\xe9t\xe9"))
+        );
+
+        for normalization in [
+            PathNormalization::Nfc,
+            PathNormalization::Nfd,
+            PathNormalization::None,
+        ] {
+            assert_eq!(
+                normalization.to_string().parse::<PathNormalization>().ok(),
+                Some(normalization)
+            );
+        }
+        assert!("nfkc".parse::<PathNormalization>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_blob_claims() -> anyhow::Result<()> {
+        let claims = BlobClaims::default();
+        let sent = AtomicUsize::new(0);
+        let send = |mark: usize| {
+            let sent = &sent;
+            move || async move {
+                sent.fetch_add(1, Ordering::SeqCst);
+                tokio::task::yield_now().await;
+                Ok(Mark::from(mark))
+            }
+        };
+        let path = Path::new("mod/a.txt");
+        let revision = Num::from_str("1.1")?;
+        let first = claims.enter(path);
+        let second = claims.enter(path);
+
+        // Only one of the concurrent claims sends the blob, and both get its
+        // mark.
+        let (a, b) = tokio::join!(
+            claims.claim(path, &revision, send(1)),
+            claims.claim(path, &revision, send(2)),
+        );
+        assert_eq!(a?, b?);
+        assert_eq!(sent.load(Ordering::SeqCst), 1);
+
+        // Other paths and revisions are claimed separately, and a failed send
+        // leaves the revision unclaimed.
+        let other = Num::from_str("1.2")?;
+        assert!(claims
+            .claim(path, &other, || async { anyhow::bail!("failed") })
+            .await
+            .is_err());
+        assert_eq!(claims.claim(path, &other, send(3)).await?, Mark::from(3));
+        assert_eq!(
+            claims
+                .claim(Path::new("mod/b.txt"), &revision, send(4))
+                .await?,
+            Mark::from(4)
+        );
+        assert_eq!(sent.load(Ordering::SeqCst), 3);
+
+        // A path's claims are kept until every worker handling it is done.
+        drop(first);
+        assert!(claims.paths.lock().unwrap().contains_key(path));
+        drop(second);
+        assert!(!claims.paths.lock().unwrap().contains_key(path));
+
+        Ok(())
+    }
 }
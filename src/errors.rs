@@ -0,0 +1,251 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use comma_v::Num;
+use serde::Serialize;
+use thiserror::Error;
+
+/// A shared registry of the errors encountered while discovering and parsing
+/// files, so that they can be summarised once the import is complete.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ErrorRegistry {
+    errors: Arc<Mutex<BTreeMap<PathBuf, FileError>>>,
+}
+
+#[derive(Debug, Clone)]
+struct FileError {
+    category: String,
+    message: String,
+    recoverable: bool,
+}
+
+/// Returned when an import completed, but without every file, so that the
+/// process can exit with a distinct status once the runtime has shut down.
+#[derive(Debug, Error)]
+#[error("export complete, but {0} file(s) were skipped")]
+pub(crate) struct Incomplete(pub usize);
+
+/// Structural problems in an RCS file that parsed successfully, such as a
+/// revision that the file refers to but doesn't contain.
+///
+/// These only affect the file itself, so the file is skipped and the import
+/// carries on, even without `--ignore-file-errors`.
+#[derive(Debug, Error)]
+pub(crate) enum StructuralError {
+    #[error("cannot find HEAD revision")]
+    MissingHead,
+
+    #[error("cannot find revision {0}")]
+    MissingRevision(Num),
+
+    #[error("revision {0} is referenced more than once")]
+    RepeatedRevision(Num),
+}
+
+impl StructuralError {
+    fn category(&self) -> &'static str {
+        match self {
+            Self::MissingHead => "missing HEAD revision",
+            Self::MissingRevision(_) => "missing revision",
+            Self::RepeatedRevision(_) => "revision referenced more than once",
+        }
+    }
+}
+
+/// Returns true if the error only means that the file has to be skipped,
+/// rather than the import stopped.
+pub(crate) fn is_recoverable(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| cause.is::<StructuralError>())
+}
+
+/// A group of file errors with the same category.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct ErrorGroup {
+    pub category: String,
+    pub files: Vec<ErrorFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct ErrorFile {
+    pub path: String,
+    pub error: String,
+}
+
+impl ErrorRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records an error that occurred while handling the given file.
+    pub fn record(&self, path: &Path, error: &anyhow::Error) {
+        self.errors.lock().unwrap().insert(
+            path.to_path_buf(),
+            FileError {
+                category: category(error),
+                message: format!("{:#}", error),
+                recoverable: is_recoverable(error),
+            },
+        );
+    }
+
+    pub fn len(&self) -> usize {
+        self.errors.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of recorded errors that aren't recoverable.
+    pub fn fatal_len(&self) -> usize {
+        self.errors
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|error| !error.recoverable)
+            .count()
+    }
+
+    /// Returns the recorded errors, grouped by category.
+    pub fn groups(&self) -> Vec<ErrorGroup> {
+        let mut groups: BTreeMap<String, Vec<ErrorFile>> = BTreeMap::new();
+        for (path, error) in self.errors.lock().unwrap().iter() {
+            groups
+                .entry(error.category.clone())
+                .or_default()
+                .push(ErrorFile {
+                    path: path.to_string_lossy().into(),
+                    error: error.message.clone(),
+                });
+        }
+
+        groups
+            .into_iter()
+            .map(|(category, files)| ErrorGroup { category, files })
+            .collect()
+    }
+
+    /// Logs a summary of the recorded errors at the given level.
+    pub fn log_summary(&self, level: log::Level) {
+        let groups = self.groups();
+        if groups.is_empty() {
+            return;
+        }
+
+        log::log!(level, "{} file(s) could not be imported:", self.len());
+        for group in groups {
+            log::log!(level, "{} ({} file(s)):", group.category, group.files.len());
+            for file in group.files {
+                log::log!(level, "  {}: {}", file.path, file.error);
+            }
+        }
+    }
+}
+
+/// Categorises an error based on its root cause, since the error messages
+/// themselves generally include file specific details.
+fn category(error: &anyhow::Error) -> String {
+    for cause in error.chain() {
+        if let Some(e) = cause.downcast_ref::<StructuralError>() {
+            return e.category().into();
+        }
+        if let Some(e) = cause.downcast_ref::<comma_v::Error>() {
+            return match e {
+                comma_v::Error::ParseError { .. } => "RCS parse error".into(),
+                comma_v::Error::InvalidDate { .. } => "invalid RCS date".into(),
+                _ => "invalid RCS file".into(),
+            };
+        }
+        if cause.downcast_ref::<rcs_ed::Error>().is_some() {
+            return "invalid RCS delta".into();
+        }
+        if let Some(e) = cause.downcast_ref::<io::Error>() {
+            return format!("I/O error ({:?})", e.kind());
+        }
+    }
+
+    error.root_cause().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_groups() {
+        let registry = ErrorRegistry::new();
+        assert!(registry.is_empty());
+
+        registry.record(
+            Path::new("b,v"),
+            &anyhow::Error::new(io::Error::from(io::ErrorKind::NotFound)),
+        );
+        registry.record(
+            Path::new("a,v"),
+            &anyhow::Error::new(io::Error::from(io::ErrorKind::NotFound)),
+        );
+        registry.record(
+            Path::new("c,v"),
+            &anyhow::anyhow!("cannot find HEAD revision"),
+        );
+        registry.record(
+            Path::new("d,v"),
+            &anyhow::Error::new(rcs_ed::Error::InvalidPosition {
+                position: 4,
+                len: 3,
+            })
+            .context("cannot apply the delta for revision 1.1"),
+        );
+
+        registry.record(
+            Path::new("e,v"),
+            &anyhow::Error::new(StructuralError::MissingRevision("1.2".parse().unwrap())),
+        );
+
+        assert_eq!(registry.len(), 5);
+        assert_eq!(registry.fatal_len(), 4);
+        assert_eq!(
+            registry.groups(),
+            vec![
+                ErrorGroup {
+                    category: "I/O error (NotFound)".into(),
+                    files: vec![
+                        ErrorFile {
+                            path: "a,v".into(),
+                            error: "entity not found".into(),
+                        },
+                        ErrorFile {
+                            path: "b,v".into(),
+                            error: "entity not found".into(),
+                        },
+                    ],
+                },
+                ErrorGroup {
+                    category: "cannot find HEAD revision".into(),
+                    files: vec![ErrorFile {
+                        path: "c,v".into(),
+                        error: "cannot find HEAD revision".into(),
+                    }],
+                },
+                ErrorGroup {
+                    category: "invalid RCS delta".into(),
+                    files: vec![ErrorFile {
+                        path: "d,v".into(),
+                        error: "cannot apply the delta for revision 1.1: ed command position 4 is outside the file, which has 3 line(s)".into(),
+                    }],
+                },
+                ErrorGroup {
+                    category: "missing revision".into(),
+                    files: vec![ErrorFile {
+                        path: "e,v".into(),
+                        error: "cannot find revision 1.2".into(),
+                    }],
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,207 @@
+//! Structured events, written with `--events` so that tools wrapping the
+//! import can follow its progress without parsing the log.
+//!
+//! The import is instrumented with `tracing` spans and events; this module
+//! provides a layer that writes each event emitted by this crate as a line of
+//! JSON, along with the spans it was emitted within.
+
+use std::{
+    fmt::Debug,
+    fs::File,
+    io::{LineWriter, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use serde_json::{Map, Value};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id},
+    Event, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+/// The prefix of the target of every span and event emitted by this crate.
+const TARGET: &str = env!("CARGO_CRATE_NAME");
+
+/// A layer that writes each event as a line of JSON, such as:
+///
+/// ```json
+/// {"time":"2022-03-01T00:00:00.000000Z","level":"INFO","message":"patchset committed","fields":{"branch":"main","mark":3},"spans":[]}
+/// ```
+///
+/// Only events and spans from this crate are written, so the spans that tokio
+/// creates for its own tasks are omitted.
+pub(crate) struct JsonLines<W> {
+    writer: Mutex<W>,
+}
+
+impl JsonLines<LineWriter<File>> {
+    /// Creates a layer that writes to the given file, replacing it if it
+    /// exists. Each line is written as soon as its event is emitted.
+    pub(crate) fn create(path: &Path) -> std::io::Result<Self> {
+        Ok(Self::new(LineWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W> JsonLines<W>
+where
+    W: Write,
+{
+    pub(crate) fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+/// The fields of a span, stored in its extensions when it's created.
+struct SpanFields(Map<String, Value>);
+
+impl<S, W> Layer<S> for JsonLines<W>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: Write + Send + 'static,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if !attrs.metadata().target().starts_with(TARGET) {
+            return;
+        }
+
+        let mut fields = Map::new();
+        attrs.record(&mut Visitor(&mut fields));
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(fields));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        if !metadata.target().starts_with(TARGET) {
+            return;
+        }
+
+        let mut fields = Map::new();
+        event.record(&mut Visitor(&mut fields));
+        let message = fields.remove("message").unwrap_or(Value::Null);
+
+        let spans: Vec<Value> = ctx
+            .event_scope(event)
+            .into_iter()
+            .flat_map(|scope| scope.from_root())
+            .filter(|span| span.metadata().target().starts_with(TARGET))
+            .map(|span| {
+                let mut object = Map::new();
+                object.insert("name".into(), span.name().into());
+                if let Some(SpanFields(fields)) = span.extensions().get::<SpanFields>() {
+                    object.extend(fields.clone());
+                }
+                Value::Object(object)
+            })
+            .collect();
+
+        let mut line = Map::new();
+        line.insert(
+            "time".into(),
+            chrono::Utc::now()
+                .to_rfc3339_opts(chrono::SecondsFormat::Micros, true)
+                .into(),
+        );
+        line.insert("level".into(), metadata.level().as_str().into());
+        line.insert("message".into(), message);
+        line.insert("fields".into(), Value::Object(fields));
+        line.insert("spans".into(), Value::Array(spans));
+
+        // Events are informational, so there's nothing sensible to do if they
+        // can't be written, other than to carry on with the import.
+        let mut writer = self.writer.lock().unwrap();
+        if let Err(e) = serde_json::to_writer(&mut *writer, &line)
+            .map_err(std::io::Error::from)
+            .and_then(|()| writer.write_all(b"\n"))
+        {
+            log::debug!("cannot write event: {}", e);
+        }
+    }
+}
+
+/// Records the fields of a span or event into a JSON object.
+struct Visitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for Visitor<'_> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0
+            .insert(field.name().into(), format!("{:?}", value).into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+
+    /// A writer that can be read once the subscriber is done with it.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_lines() -> anyhow::Result<()> {
+        let shared = Shared::default();
+        let subscriber = tracing_subscriber::registry().with(JsonLines::new(shared.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            let outer = tracing::info_span!(target: "elsewhere", "outer");
+            let _outer = outer.enter();
+            let span = tracing::info_span!("file", path = "mod/a.txt,v");
+            let _entered = span.enter();
+            tracing::info!(revisions = 3u64, dead = false, "file parsed");
+            tracing::info!(target: "elsewhere", "ignored");
+        });
+
+        let output = String::from_utf8(shared.0.lock().unwrap().clone())?;
+        let lines: Vec<Value> = output
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["message"], "file parsed");
+        assert_eq!(
+            lines[0]["fields"],
+            serde_json::json!({"revisions": 3, "dead": false})
+        );
+        assert_eq!(
+            lines[0]["spans"],
+            serde_json::json!([{"name": "file", "path": "mod/a.txt,v"}])
+        );
+
+        Ok(())
+    }
+}
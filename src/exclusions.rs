@@ -0,0 +1,64 @@
+use glob::Pattern;
+
+/// Glob patterns for the branches and tags that shouldn't be imported.
+///
+/// Unlike `--branch`, which filters branches once their patchsets have been
+/// detected, exclusions are applied as each RCS file is parsed, so excluded
+/// symbols are never sent to the observer or persisted in the state.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SymbolExclusions {
+    branches: Vec<Pattern>,
+    tags: Vec<Pattern>,
+}
+
+impl SymbolExclusions {
+    pub(crate) fn new(branches: Vec<Pattern>, tags: Vec<Pattern>) -> Self {
+        Self { branches, tags }
+    }
+
+    pub(crate) fn excludes_branch(&self, name: &[u8]) -> bool {
+        matches(&self.branches, name)
+    }
+
+    pub(crate) fn excludes_tag(&self, name: &[u8]) -> bool {
+        matches(&self.tags, name)
+    }
+}
+
+fn matches(patterns: &[Pattern], name: &[u8]) -> bool {
+    if patterns.is_empty() {
+        return false;
+    }
+
+    let name = String::from_utf8_lossy(name);
+    patterns.iter().any(|pattern| pattern.matches(&name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exclusions() {
+        let exclusions = SymbolExclusions::default();
+        assert!(!exclusions.excludes_branch(b"BR"));
+        assert!(!exclusions.excludes_tag(b"REL1"));
+
+        let exclusions = SymbolExclusions::new(
+            vec![Pattern::new("tmp-*").unwrap()],
+            vec![
+                Pattern::new("nightly-*").unwrap(),
+                Pattern::new("build_[0-9]*").unwrap(),
+            ],
+        );
+        assert!(exclusions.excludes_branch(b"tmp-foo"));
+        assert!(!exclusions.excludes_branch(b"BR"));
+        assert!(!exclusions.excludes_branch(b"nightly-20210101"));
+
+        assert!(exclusions.excludes_tag(b"nightly-20210101"));
+        assert!(exclusions.excludes_tag(b"build_123"));
+        assert!(!exclusions.excludes_tag(b"build_x"));
+        assert!(!exclusions.excludes_tag(b"REL1"));
+        assert!(!exclusions.excludes_tag(b"tmp-foo"));
+    }
+}
@@ -0,0 +1,145 @@
+//! Placeholder files for directories that only contain deleted files.
+//!
+//! CVS never removes a directory from the repository, even once every file in
+//! it has been deleted and moved to the Attic, whereas Git doesn't track
+//! directories at all, so those directories would otherwise disappear from the
+//! imported history.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::Bound,
+    path::{Path, PathBuf},
+};
+
+/// The name of the placeholder file added to empty directories.
+pub(crate) const GITKEEP: &str = ".gitkeep";
+
+/// Tracks the files on a branch, and whether each one exists, in order to work
+/// out which directories need a placeholder.
+///
+/// A directory needs a placeholder if every file in it has been deleted. A
+/// directory that contains another directory never needs one, since the
+/// inner directory is either non-empty or has a placeholder of its own.
+#[derive(Debug, Default)]
+pub(crate) struct Placeholders {
+    /// Every file that has been committed, and whether it currently exists.
+    files: BTreeMap<PathBuf, bool>,
+}
+
+impl Placeholders {
+    /// Creates a tracker for a branch that already has the given files, along
+    /// with whether each one exists.
+    pub(crate) fn new<I>(files: I) -> Self
+    where
+        I: IntoIterator<Item = (PathBuf, bool)>,
+    {
+        Self {
+            files: files.into_iter().collect(),
+        }
+    }
+
+    /// Applies the files added, modified, or deleted in a commit, returning the
+    /// placeholders that need to be added, as `true`, or removed, as `false`,
+    /// as a result.
+    pub(crate) fn commit(&mut self, files: Vec<(PathBuf, bool)>) -> Vec<(PathBuf, bool)> {
+        let directories: BTreeSet<PathBuf> = files
+            .iter()
+            .flat_map(|(path, _exists)| {
+                path.ancestors()
+                    .skip(1)
+                    .take_while(|directory| !directory.as_os_str().is_empty())
+            })
+            .map(Path::to_path_buf)
+            .collect();
+        let before: Vec<bool> = directories
+            .iter()
+            .map(|directory| self.needs_placeholder(directory))
+            .collect();
+
+        self.files.extend(files);
+
+        directories
+            .into_iter()
+            .zip(before)
+            .filter_map(|(directory, before)| {
+                let after = self.needs_placeholder(&directory);
+                (before != after).then(|| (directory.join(GITKEEP), after))
+            })
+            .collect()
+    }
+
+    fn needs_placeholder(&self, directory: &Path) -> bool {
+        // Paths are ordered by component, so everything within the directory
+        // immediately follows it.
+        let mut any = false;
+        for (path, exists) in self
+            .files
+            .range::<Path, _>((Bound::Excluded(directory), Bound::Unbounded))
+        {
+            if !path.starts_with(directory) {
+                break;
+            }
+            if *exists || path.parent() != Some(directory) {
+                return false;
+            }
+            any = true;
+        }
+
+        any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(path: &str, exists: bool) -> (PathBuf, bool) {
+        (PathBuf::from(path), exists)
+    }
+
+    #[test]
+    fn test_placeholders() {
+        let mut placeholders = Placeholders::default();
+        assert_eq!(
+            placeholders.commit(vec![
+                change("a/b/one", true),
+                change("a/b/two", true),
+                change("a/c/three", true),
+                change("top", true),
+            ]),
+            vec![]
+        );
+
+        // Deleting one of two files leaves the directory non-empty.
+        assert_eq!(placeholders.commit(vec![change("a/b/one", false)]), vec![]);
+        assert_eq!(
+            placeholders.commit(vec![change("a/b/two", false)]),
+            vec![change("a/b/.gitkeep", true)]
+        );
+
+        // The parent directory still contains a/b, so never needs one, and
+        // neither does the top level.
+        assert_eq!(
+            placeholders.commit(vec![change("a/c/three", false), change("top", false)]),
+            vec![change("a/c/.gitkeep", true)]
+        );
+
+        // Re-adding a file removes the placeholder, as does adding a new
+        // directory within the empty directory.
+        assert_eq!(
+            placeholders.commit(vec![change("a/b/one", true)]),
+            vec![change("a/b/.gitkeep", false)]
+        );
+        assert_eq!(
+            placeholders.commit(vec![change("a/c/d/four", true)]),
+            vec![change("a/c/.gitkeep", false)]
+        );
+
+        // A directory that sorts just after another isn't within it.
+        let mut placeholders = Placeholders::new(vec![change("a/x", true), change("a-b/y", true)]);
+        assert_eq!(
+            placeholders.commit(vec![change("a/x", false)]),
+            vec![change("a/.gitkeep", true)]
+        );
+    }
+}
@@ -0,0 +1,221 @@
+//! Patchset post-processing and post-import hooks.
+
+use std::{
+    ffi::OsStr,
+    path::PathBuf,
+    process::Stdio,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    process::{Child, ChildStdin, ChildStdout},
+};
+
+use crate::report::ReportSignTag;
+
+/// A patchset as seen by a hook.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct HookPatchSet {
+    pub branch: String,
+
+    /// The patchset time, in seconds since the Unix epoch.
+    pub time: u64,
+
+    pub author: String,
+    pub message: String,
+
+    /// The paths modified or deleted in the patchset.
+    pub files: Vec<PathBuf>,
+}
+
+impl HookPatchSet {
+    pub fn new(
+        branch: &str,
+        time: &SystemTime,
+        author: &str,
+        message: &str,
+        files: Vec<PathBuf>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            branch: branch.to_string(),
+            time: time.duration_since(UNIX_EPOCH)?.as_secs(),
+            author: author.to_string(),
+            message: message.to_string(),
+            files,
+        })
+    }
+}
+
+/// A `Hook` is an external command that receives each patchset before it's
+/// sent to Git, and can modify its author or message, or drop it entirely.
+///
+/// The command is run once using `sh -c`, and is sent each patchset as a JSON
+/// object on its own line on stdin. For each patchset, it must write a single
+/// line to stdout containing either the patchset as a JSON object, with any
+/// changes to the author or message, or `null` to drop the patchset. Changes
+/// to any other field are ignored.
+#[derive(Debug)]
+pub(crate) struct Hook {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+}
+
+impl Hook {
+    pub fn spawn(command: &str) -> anyhow::Result<Self> {
+        let mut child = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("cannot open hook stdin"))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("cannot open hook stdout"))?;
+
+        Ok(Self {
+            child,
+            stdin,
+            stdout: BufReader::new(stdout).lines(),
+        })
+    }
+
+    /// Processes a patchset, returning `None` if the patchset should be
+    /// dropped.
+    pub async fn process(
+        &mut self,
+        patchset: HookPatchSet,
+    ) -> anyhow::Result<Option<HookPatchSet>> {
+        let mut line = serde_json::to_vec(&patchset)?;
+        line.push(b'\n');
+        self.stdin.write_all(&line).await?;
+        self.stdin.flush().await?;
+
+        match self.stdout.next_line().await? {
+            Some(line) => Ok(serde_json::from_str(&line)?),
+            None => anyhow::bail!("patchset hook exited without responding"),
+        }
+    }
+
+    /// Closes the hook's stdin and waits for it to exit.
+    pub async fn finish(mut self) -> anyhow::Result<()> {
+        drop(self.stdin);
+        let status = self.child.wait().await?;
+        if !status.success() {
+            anyhow::bail!("patchset hook exited with {}", status);
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs the post-import hook once the import is complete, using `sh -c` in
+/// the Git repository, and sends it each tag that should be signed as a JSON
+/// object on its own line on stdin.
+pub(crate) async fn post_import(
+    command: &str,
+    git_repo: &OsStr,
+    sign_tags: &[ReportSignTag],
+) -> anyhow::Result<()> {
+    let mut child = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .current_dir(git_repo)
+        .stdin(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow::anyhow!("cannot open hook stdin"))?;
+    for tag in sign_tags.iter() {
+        let mut line = serde_json::to_vec(tag)?;
+        line.push(b'\n');
+        stdin.write_all(&line).await?;
+    }
+    drop(stdin);
+
+    let status = child.wait().await?;
+    if !status.success() {
+        anyhow::bail!("post-import hook exited with {}", status);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn patchset(message: &str) -> HookPatchSet {
+        HookPatchSet::new(
+            "main",
+            &(UNIX_EPOCH + Duration::from_secs(1000)),
+            "adam",
+            message,
+            vec![PathBuf::from("foo")],
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_hook() -> anyhow::Result<()> {
+        // Drop patchsets with "drop" as their message, and rewrite the author
+        // of the rest.
+        let mut hook = Hook::spawn(
+            r#"while read -r line; do
+                case "$line" in
+                    *'"message":"drop"'*) echo null ;;
+                    *) printf '%s\n' "$line" | sed 's/"author":"adam"/"author":"eve"/' ;;
+                esac
+            done"#,
+        )?;
+
+        let have = hook.process(patchset("keep")).await?.unwrap();
+        assert_eq!(have, {
+            let mut want = patchset("keep");
+            want.author = "eve".into();
+            want
+        });
+        assert!(hook.process(patchset("drop")).await?.is_none());
+
+        hook.finish().await
+    }
+
+    #[tokio::test]
+    async fn test_post_import() -> anyhow::Result<()> {
+        // The hook is run in the Git repository, which is just a directory
+        // here.
+        let dir = tempfile::tempdir()?;
+        let tags = [ReportSignTag {
+            ref_name: "refs/tags/REL1".into(),
+            mark: 3,
+            sha: "0123456789abcdef0123456789abcdef01234567".into(),
+            reason: "new tag matching REL*".into(),
+        }];
+        post_import("cat > tags.json", dir.path().as_os_str(), &tags).await?;
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("tags.json"))?,
+            r#"{"ref":"refs/tags/REL1","mark":3,"sha":"0123456789abcdef0123456789abcdef01234567","reason":"new tag matching REL*"}
+"#
+        );
+
+        assert!(post_import("exit 1", dir.path().as_os_str(), &tags)
+            .await
+            .is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+//! The `list` subcommand, which lists the branches and tags in a CVSROOT
+//! without importing it.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    ffi::OsString,
+    io::Write,
+    path::PathBuf,
+};
+
+use comma_v::Num;
+use structopt::StructOpt;
+
+use crate::source;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "git-cvs-fast-import list",
+    about = "Lists the branches and tags defined in a CVSROOT, along with the number of files that define each one, without importing anything."
+)]
+pub(crate) struct Opt {
+    #[structopt(
+        short,
+        long,
+        env = "CVSROOT",
+        parse(from_os_str),
+        help = "the CVSROOT, which must be a local directory, a tar archive of one (optionally gzipped), or a remote CVSROOT such as :pserver:user@host:/cvsroot; if omitted, the $CVSROOT environment variable will be used"
+    )]
+    cvsroot: PathBuf,
+
+    #[structopt(
+        long,
+        default_value = "cvs",
+        parse(from_os_str),
+        help = "the cvs binary to use when accessing a remote CVSROOT"
+    )]
+    cvs: OsString,
+
+    #[structopt(
+        name = "DIRECTORY",
+        parse(from_os_str),
+        help = "the top level directories to list from the CVSROOT; if omitted, all directories will be listed"
+    )]
+    directories: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Kind {
+    Branch,
+    Tag,
+}
+
+impl std::fmt::Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Kind::Branch => "branch",
+            Kind::Tag => "tag",
+        })
+    }
+}
+
+/// Counts the files that define each symbol, keyed by the kind of symbol and
+/// its name.
+#[derive(Debug, Default)]
+struct Symbols {
+    files: BTreeMap<(Kind, Vec<u8>), usize>,
+}
+
+impl Symbols {
+    /// Adds the symbols defined in the given file. A symbol defined more than
+    /// once in a file is only counted once, using the first definition, as
+    /// RCS does.
+    fn add(&mut self, admin: &comma_v::Admin) {
+        let mut seen = HashSet::new();
+        for (symbol, num) in admin.symbols.iter() {
+            if !seen.insert(symbol) {
+                continue;
+            }
+
+            let kind = match num {
+                Num::Branch(_) => Kind::Branch,
+                Num::Commit(_) => Kind::Tag,
+            };
+            *self.files.entry((kind, symbol.to_vec())).or_default() += 1;
+        }
+    }
+
+    /// Writes one line for each symbol, with the kind of symbol, its name, and
+    /// the number of files, separated by tabs. Branches are listed first.
+    fn write_to(&self, out: &mut dyn Write) -> std::io::Result<()> {
+        for ((kind, name), files) in self.files.iter() {
+            writeln!(
+                out,
+                "{}\t{}\t{}",
+                kind,
+                String::from_utf8_lossy(name),
+                files
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Lists the symbols in the CVSROOT to `out`.
+///
+/// Only the admin section of each RCS file is parsed, or fetched from a remote
+/// CVSROOT, so this is much faster than an import. Files that can't be parsed are skipped with a warning on
+/// stderr, since `list` doesn't set up logging.
+pub(crate) fn run(opt: &Opt, out: &mut dyn Write) -> anyhow::Result<()> {
+    let source = source::open(&opt.cvsroot, &opt.cvs)?;
+
+    let mut symbols = Symbols::default();
+    let mut found = 0;
+    source.walk_admin(&opt.directories, &mut |entry| {
+        if entry.is_dir()? || !entry.is_comma_v() {
+            return Ok(());
+        }
+
+        let (path, contents) = entry.into_contents()?;
+        match comma_v::parse_admin(&contents) {
+            Ok(admin) => {
+                symbols.add(&admin);
+                found += 1;
+            }
+            Err(e) => eprintln!("skipping {}: {}", path.display(), e),
+        }
+        Ok(())
+    })?;
+
+    if found == 0 {
+        anyhow::bail!(
+            "no ,v files were found in {}",
+            source.locations(&opt.directories).join(", ")
+        );
+    }
+    Ok(symbols.write_to(out)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbols() -> anyhow::Result<()> {
+        let mut symbols = Symbols::default();
+        symbols.add(&comma_v::parse_admin(
+            b"head 1.2;\naccess;\nsymbols\n\tREL1:1.1\n\tBR:1.1.0.2\n\tREL1:1.2;\nlocks; strict;\n",
+        )?);
+        symbols.add(&comma_v::parse_admin(
+            b"head 1.1;\naccess;\nsymbols\n\tREL1:1.1\n\tVENDOR:1.1.1;\nlocks; strict;\n",
+        )?);
+
+        let mut out = Vec::new();
+        symbols.write_to(&mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "branch\tBR\t1\nbranch\tVENDOR\t1\ntag\tREL1\t2\n"
+        );
+
+        Ok(())
+    }
+}
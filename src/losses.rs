@@ -0,0 +1,157 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use serde::Serialize;
+
+/// A shared registry of the places where the import had to lose information
+/// from the CVS repository, so that they can be summarised once the import is
+/// complete, or treated as fatal with `--strict`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LossRegistry {
+    losses: Arc<Mutex<Vec<Loss>>>,
+}
+
+/// A single lossy conversion.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub(crate) struct Loss {
+    pub path: String,
+    pub kind: LossKind,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum LossKind {
+    /// A symbol was defined more than once, and only the first definition was
+    /// used.
+    DuplicateSymbol,
+
+    /// A revision isn't on any named branch, and therefore won't appear in any
+    /// commit.
+    SkippedRevision,
+
+    /// A symbol name isn't valid UTF-8, and will be converted lossily to form
+    /// a ref name.
+    SanitizedRefName,
+
+    /// An author or log message isn't valid UTF-8, and was converted lossily.
+    LossyUtf8,
+
+    /// An author contains characters that can't appear in a Git identity, and
+    /// they were removed.
+    SanitizedAuthor,
+
+    /// The content of a revision doesn't match its checksum in the integrity
+    /// string, or the integrity string couldn't be read, so the content may
+    /// not be what was committed to CVS.
+    FailedIntegrityCheck,
+
+    /// A symbol points to a revision that doesn't exist, such as one removed
+    /// with `rcs -o`, so it was moved to the nearest revision that does, or
+    /// ignored.
+    MissingSymbolRevision,
+}
+
+impl std::fmt::Display for LossKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            LossKind::DuplicateSymbol => "duplicate symbol",
+            LossKind::SkippedRevision => "skipped revision",
+            LossKind::SanitizedRefName => "sanitized ref name",
+            LossKind::LossyUtf8 => "lossy UTF-8 conversion",
+            LossKind::SanitizedAuthor => "sanitized author",
+            LossKind::FailedIntegrityCheck => "failed integrity check",
+            LossKind::MissingSymbolRevision => "missing symbol revision",
+        })
+    }
+}
+
+impl LossRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a lossy conversion in the given file. Losses are only logged
+    /// at debug level as they happen, since [`Self::log_summary`] repeats them
+    /// once the import is complete.
+    pub fn record(&self, path: &Path, kind: LossKind, detail: String) {
+        log::debug!("{}: {}: {}", path.display(), kind, detail);
+        tracing::warn!(path = %path.display(), %kind, %detail, "lossy conversion");
+        self.losses.lock().unwrap().push(Loss {
+            path: path.to_string_lossy().into(),
+            kind,
+            detail,
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.losses.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the recorded losses, ordered by path.
+    pub fn losses(&self) -> Vec<Loss> {
+        let mut losses = self.losses.lock().unwrap().clone();
+        losses.sort();
+        losses
+    }
+
+    /// Logs a summary of the recorded losses at the given level.
+    pub fn log_summary(&self, level: log::Level) {
+        let losses = self.losses();
+        if losses.is_empty() {
+            return;
+        }
+
+        log::log!(level, "{} lossy conversion(s) were required:", losses.len());
+        for loss in losses {
+            log::log!(level, "  {}: {}: {}", loss.path, loss.kind, loss.detail);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_losses() {
+        let registry = LossRegistry::new();
+        assert!(registry.is_empty());
+
+        registry.record(
+            Path::new("b"),
+            LossKind::LossyUtf8,
+            "revision 1.1 has an author that isn't valid UTF-8".into(),
+        );
+        registry.record(
+            Path::new("a"),
+            LossKind::SkippedRevision,
+            "revision 1.1.2.1 isn't on a named branch".into(),
+        );
+        registry.record(
+            Path::new("a"),
+            LossKind::DuplicateSymbol,
+            "TAG points to 1.1, but already points to 1.2".into(),
+        );
+
+        assert_eq!(registry.len(), 3);
+        assert_eq!(
+            registry
+                .losses()
+                .into_iter()
+                .map(|loss| (loss.path, loss.kind))
+                .collect::<Vec<_>>(),
+            vec![
+                ("a".into(), LossKind::DuplicateSymbol),
+                ("a".into(), LossKind::SkippedRevision),
+                ("b".into(), LossKind::LossyUtf8),
+            ]
+        );
+    }
+}
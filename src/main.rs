@@ -1,59 +1,265 @@
 use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashSet},
     ffi::OsString,
     fs::File,
-    io::ErrorKind,
+    io::{BufWriter, ErrorKind},
+    num::NonZeroUsize,
     os::unix::prelude::OsStrExt,
-    path::PathBuf,
-    time::{Duration, SystemTime},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+use anyhow::Context;
+use comma_v::DateQuirks;
 use discovery::Discovery;
 
 use flexi_logger::{AdaptiveFormat, Logger};
 use git_cvs_fast_import_process::Output;
-use git_cvs_fast_import_state::{FileRevisionID, Manager};
-use git_fast_import::{CommitBuilder, FileCommand, Identity, Mark};
-use observer::{Collector, Observer};
+use git_cvs_fast_import_state::{Compression, Directories, FileRevisionID, Manager};
+use git_fast_import::{Blob, CommitBuilder, FileCommand, Identity, Mark};
+use observer::{Collector, ObservationResult, Observer};
 use patchset::PatchSet;
 use structopt::StructOpt;
 use tempfile::NamedTempFile;
 use tokio::{fs::OpenOptions, io::AsyncWriteExt};
-use walkdir::WalkDir;
+use tracing_subscriber::layer::SubscriberExt;
 
-use crate::branch::BranchFilter;
+use crate::{
+    branch::{BranchDelta, BranchFilter, BranchRename, BranchRenames},
+    changelog::{is_trivial, ChangeLogRegistry},
+    checkpoint::{CheckpointInterval, Checkpointer},
+    committer_date::CommitterDate,
+    content_cache::ContentCache,
+    diffstat::{DiffStat, DiffStatRegistry},
+    errors::{ErrorRegistry, Incomplete},
+    exclusions::SymbolExclusions,
+    gitkeep::Placeholders,
+    hook::{Hook, HookPatchSet},
+    losses::LossRegistry,
+    metrics::Metrics,
+    progress::Progress,
+    provenance::ProvenanceRegistry,
+    reconcile::Reconciliation,
+    report::{
+        unix_seconds, Report, ReportClockSkew, ReportCommit, ReportRef, ReportRefKind,
+        ReportSignTag, ReportTag, ReportTagParent,
+    },
+    root::RewriteRoot,
+    skip_list::SkipList,
+    squash::SquashRule,
+    throttle::ReadLimiter,
+};
 
 mod branch;
+mod cat;
+mod changelog;
+mod checkpoint;
+mod committer_date;
+mod config;
+mod content_cache;
+mod diffstat;
 mod discovery;
+mod errors;
+mod events;
+mod exclusions;
+mod gitkeep;
+mod hook;
+mod list;
+mod losses;
+mod metrics;
 mod observer;
+mod priority;
+mod progress;
+mod provenance;
+mod reconcile;
+mod refs;
+mod report;
+mod rlog;
+mod root;
+mod run;
+mod skip_list;
+mod source;
+mod spot_check;
+mod squash;
+mod state;
 mod tag;
+mod throttle;
+mod units;
 
 #[derive(Debug, StructOpt)]
-#[structopt(about = "A Git importer for CVS repositories.")]
+#[structopt(
+    about = "A Git importer for CVS repositories.",
+    after_help = "To list the branches and tags in a CVSROOT without importing it, run git-cvs-fast-import list; see git-cvs-fast-import list --help for details. To export the store as JSON, or import it from JSON, run git-cvs-fast-import state export or state import."
+)]
 struct Opt {
+    #[structopt(
+        long,
+        help = "warn, rather than failing, if no ,v files are found in the CVSROOT"
+    )]
+    allow_empty: bool,
+
+    #[structopt(
+        long,
+        requires = "init",
+        help = "create the Git repository with --init as a bare repository"
+    )]
+    bare: bool,
+
+    #[structopt(
+        long,
+        parse(try_from_str = units::parse_size),
+        help = "the size at which blobs are written to git fast-import in a single batch, such as 512KiB or 10MB; 0 writes each blob immediately"
+    )]
+    blob_batch_size: Option<usize>,
+
     #[structopt(
         long,
         help = "branches to include; if no branches are specified, all branches will be imported"
     )]
     branch: Vec<OsString>,
 
+    #[structopt(
+        long,
+        help = "import CVS branches matching a regular expression under a different name in Git, given as OLD=NEW, such as 'proj_rel_(\\d+)_(\\d+)_fixes=release/$1.$2'; OLD must match the whole branch name, and NEW can refer to its groups as $1; may be given more than once, and the first match is used. This should be given on every run"
+    )]
+    branch_rename: Vec<BranchRename>,
+
+    #[structopt(
+        long,
+        help = "use the entry added to a ChangeLog file in the same patchset as the commit message when the CVS log message is empty or only punctuation"
+    )]
+    changelog_messages: bool,
+
+    #[structopt(
+        long,
+        help = "send a checkpoint to git fast-import, which writes out the pack and refs so far, after the given number of commits, such as 500c, after the given time, such as 10m, or whichever comes first, such as 500c/10m"
+    )]
+    checkpoint_every: Option<CheckpointInterval>,
+
+    #[structopt(
+        long,
+        default_value = "cvs",
+        help = "the committer date to use for each commit: cvs uses the CVS commit time, now uses the time the import started, and fixed:<time> uses the given time in seconds since the Unix epoch or as an RFC 3339 timestamp; the author date is always the CVS commit time"
+    )]
+    committer_date: CommitterDate,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "read options from the given TOML file, where each key is the long name of an option, and the DIRECTORY arguments can be given as an array named directories; options on the command line take precedence"
+    )]
+    config: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "cache the reconstructed content of each file revision in the given directory, so later runs can read it instead of applying the RCS deltas again"
+    )]
+    content_cache: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "create a Git branch for each CVS branch that has no commits of its own, such as one created with cvs tag -b that nothing has been committed to yet, pointing at the commit it was branched from"
+    )]
+    create_empty_branches: bool,
+
+    #[structopt(
+        long,
+        default_value = "cvs",
+        parse(from_os_str),
+        help = "the cvs binary to use when accessing a remote CVSROOT"
+    )]
+    cvs: OsString,
+
     #[structopt(
         short,
         long,
         env = "CVSROOT",
         parse(from_os_str),
-        help = "the CVSROOT, which must be a local directory; if omitted, the $CVSROOT environment variable will be used"
+        help = "the CVSROOT, which must be a local directory, a tar archive of one (optionally gzipped), or a remote CVSROOT such as :pserver:user@host:/cvsroot; if omitted, the $CVSROOT environment variable will be used"
     )]
     cvsroot: PathBuf,
 
+    #[structopt(
+        long,
+        default_value = "none",
+        help = "deviations from the RCS date format to accept, for RCS files written by other tools, as a comma separated list of padding (fields padded with spaces), tm-years (three digit years counted from 1900), month-names (English month abbreviations), and overflow (times past the end of their range carry over), or all or none"
+    )]
+    date_quirks: DateQuirks,
+
+    #[structopt(
+        long,
+        help = "delete Git branches from previous imports that are no longer in the CVS repository, or that are excluded by --branch"
+    )]
+    delete_obsolete_branches: bool,
+
     #[structopt(
         short,
         long,
         default_value = "120s",
-        parse(try_from_str = parse_duration::parse::parse),
-        help = "maximum time between file commits before they'll be considered different patch sets"
+        parse(try_from_str = units::parse_duration),
+        help = "maximum time between file commits before they'll be considered different patch sets, such as 90s, 2m, or 1h"
     )]
     delta: Duration,
 
+    #[structopt(
+        long,
+        help = "override --delta for a single branch, given as BRANCH=DURATION, such as BR=600s; may be given more than once"
+    )]
+    delta_branch: Vec<BranchDelta>,
+
+    #[structopt(
+        long,
+        help = "commit patchsets that don't change anything on their branch, such as those that only delete files that were never on it, as empty commits, rather than skipping them"
+    )]
+    emit_empty_commits: bool,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "write a JSON object to the given file for each file parsed, patchset committed, and tag committed, one per line, so that progress can be followed without parsing the log"
+    )]
+    events: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "a glob matching branches to skip while parsing each file, such as 'tmp-*', so they're never stored or imported; may be given more than once, and never excludes the head branch"
+    )]
+    exclude_branches: Vec<glob::Pattern>,
+
+    #[structopt(
+        long,
+        help = "a glob matching tags to skip while parsing each file, such as 'nightly-*', so they're never stored or imported; may be given more than once"
+    )]
+    exclude_tags: Vec<glob::Pattern>,
+
+    #[structopt(
+        long,
+        help = "reset branches whose tip in the Git repository isn't the commit they were last imported as, such as when commits were pushed to the Git repository, rather than refusing to import"
+    )]
+    force: bool,
+
+    #[structopt(
+        long,
+        help = "add a .gitkeep file to directories on each branch once every file in them has been deleted, since CVS keeps those directories; this should be given on every run"
+    )]
+    gitkeep: bool,
+
+    #[structopt(
+        long,
+        help = "extend the given ref or commit in the Git repository with the first commit imported on the head branch, rather than starting a new root commit; this only has an effect on the first run"
+    )]
+    graft_onto: Option<String>,
+
+    #[structopt(
+        long,
+        default_value = "alias",
+        possible_values = discovery::HardLinks::VARIANTS,
+        help = "how to import RCS files that are hard linked into more than one directory: alias parses the file once and imports its history at every path, whereas first only imports it at the first path in name order"
+    )]
+    hard_links: discovery::HardLinks,
+
     #[structopt(
         long,
         default_value = "main",
@@ -61,9 +267,27 @@ struct Opt {
     )]
     head_branch: String,
 
+    #[structopt(
+        long,
+        help = "run the import, and the git processes it starts, at idle CPU and I/O priority, so that it only uses a shared server's CPU and disks when nothing else needs them; the I/O priority is only lowered on Linux"
+    )]
+    idle_priority: bool,
+
     #[structopt(long, help = "treat file discovery and parsing errors as non-fatal")]
     ignore_file_errors: bool,
 
+    #[structopt(
+        long,
+        help = "add an X-CVS-Import trailer to the message of each commit, including the fake commits for tags, that identifies the version of git-cvs-fast-import and the run that made it"
+    )]
+    import_trailer: bool,
+
+    #[structopt(
+        long,
+        help = "create the Git repository if it doesn't exist, with HEAD pointing at the head branch as named in Git after any --branch-rename"
+    )]
+    init: bool,
+
     #[structopt(short, long, help = "number of parallel workers")]
     jobs: Option<usize>,
 
@@ -74,9 +298,222 @@ struct Opt {
     )]
     log: log::Level,
 
+    #[structopt(
+        long,
+        help = "split patchsets that change more than the given number of files into a series of commits on the same branch, each with the patchset's message and at most that many file changes, so that a change to a very large number of files doesn't produce a single enormous commit command"
+    )]
+    max_commit_files: Option<NonZeroUsize>,
+
+    #[structopt(
+        long,
+        default_value = "0",
+        parse(try_from_str = units::parse_size),
+        help = "the maximum rate at which RCS files are read from a local CVSROOT, such as 20MB; each file is still read in one go, but delays the reads after it by its size divided by the rate, and 0 disables the limit"
+    )]
+    max_read_bytes_per_sec: usize,
+
+    #[structopt(
+        long,
+        help = "only import the newest given number of trunk revisions of each file, squashing the content of the older revisions into a single revision with a message noting that the history was truncated; revisions on branches are always imported"
+    )]
+    max_revisions_per_file: Option<NonZeroUsize>,
+
+    #[cfg(feature = "metrics")]
+    #[structopt(
+        long,
+        help = "serve import statistics in the Prometheus text format on the given address, such as 127.0.0.1:9090, while the import is running"
+    )]
+    metrics_address: Option<String>,
+
+    #[structopt(
+        long,
+        default_value = "ancestor",
+        possible_values = discovery::MissingSymbolRevisions::VARIANTS,
+        help = "how to import branches and tags that point to revisions that don't exist in an RCS file, such as those removed with rcs -o: ancestor uses the nearest earlier revision that does exist, whereas drop leaves the file out of the branch or tag; either way, it's recorded as a lossy conversion"
+    )]
+    missing_symbol_revisions: discovery::MissingSymbolRevisions,
+
+    #[structopt(
+        long,
+        default_value = "64MiB",
+        parse(try_from_str = units::parse_size),
+        help = "the size from which RCS files in a local CVSROOT are mapped into memory rather than read, so that files larger than the available memory can still be imported, since each revision is only copied out of the file when it's converted; 0 maps every file"
+    )]
+    mmap_threshold: usize,
+
+    #[structopt(
+        long,
+        help = "import each CVS file revision as its own commit, rather than grouping file revisions with the same author and message into patchsets"
+    )]
+    no_patchsets: bool,
+
+    #[structopt(
+        long,
+        conflicts_with_all = &["delete-obsolete-branches", "prune-tags", "set-head"],
+        help = "leave the branches and tags in the Git repository alone, and write commits to refs under refs/git-cvs-fast-import/heads instead, so they can be merged manually; the marks and state are still updated"
+    )]
+    no_update_refs: bool,
+
+    #[structopt(
+        long,
+        help = "adjust the time of commits that would otherwise be earlier than their parent commit, such as after a clock jump on the CVS server, to the time of the parent; out of order commits are always logged and included in the report"
+    )]
+    normalize_times: bool,
+
+    #[structopt(
+        long,
+        default_value = "orphan",
+        possible_values = tag::OrphanTags::VARIANTS,
+        help = "what to parent the fake commit for a tag on when none of its file revisions are in a patchset: orphan leaves it as a root commit, empty-root parents it on a shared empty root commit, and head parents it on the head branch"
+    )]
+    orphan_tags: tag::OrphanTags,
+
     #[structopt(flatten)]
     output: git_cvs_fast_import_process::Opt,
 
+    #[structopt(
+        long,
+        help = "a command to run, using sh -c, that receives each patchset as a line of JSON on stdin, and must write a line to stdout with the patchset, optionally with a modified author or message, or null to drop it"
+    )]
+    patchset_hook: Option<String>,
+
+    #[structopt(
+        long,
+        default_value = "none",
+        possible_values = discovery::PathNormalization::VARIANTS,
+        help = "the Unicode normalization form to convert paths to as they're imported: nfc composes characters, nfd decomposes them, and none imports paths exactly as they're found; this must be the same on every run against a store"
+    )]
+    path_normalization: discovery::PathNormalization,
+
+    #[structopt(
+        long,
+        help = "a command to run, using sh -c in the Git repository, once the import is complete, which receives each tag to sign from --sign-tags as a line of JSON on stdin; the import fails if it fails"
+    )]
+    post_import_hook: Option<String>,
+
+    #[structopt(
+        long,
+        default_value = "30s",
+        parse(try_from_str = units::parse_duration),
+        help = "how often to log the amount of data written to git fast-import, and the rate it was written at over the interval, such as 30s or 5m; 0 disables this"
+    )]
+    progress_interval: Duration,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "write the RCS comment leader, integrity string, and keyword substitution mode of each file to the given file as JSON, keyed by path"
+    )]
+    provenance: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "compare the tags defined in the CVS repository with those imported by earlier runs, moving tags whose file revisions have changed and deleting tags that no longer exist; skipped if any files couldn't be imported"
+    )]
+    prune_tags: bool,
+
+    #[structopt(
+        long,
+        conflicts_with = "no-update-refs",
+        help = "once every patchset has been sent, compare the tree of each branch head in Git with the latest revision of each file on the branch, and commit any files that don't match; files that CVS doesn't know about are left alone, and the trees are listed with git ls-tree, so this runs git even with the gitoxide backend"
+    )]
+    reconcile: bool,
+
+    #[structopt(
+        long,
+        help = "once the import is complete, repack the Git repository into a single pack with git repack -a -d -f, replacing the packs written by each run and checkpoint; this runs git even with the gitoxide backend"
+    )]
+    repack: bool,
+
+    #[structopt(
+        long,
+        default_value = "50",
+        help = "the maximum delta depth used by --repack"
+    )]
+    repack_depth: u32,
+
+    #[structopt(
+        long,
+        default_value = "250",
+        help = "the number of objects that --repack considers as delta bases for each object"
+    )]
+    repack_window: u32,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "write a JSON report summarising the import to the given file"
+    )]
+    report: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "add a CVS-Resurrected trailer to the message of each commit that re-adds a file previously deleted in CVS"
+    )]
+    resurrection_trailer: bool,
+
+    #[structopt(
+        long,
+        help = "add a CVS-Revision trailer to the message of each commit for each CVS file revision it includes, given as PATH@REVISION, so the CVS state can be reconstructed from the Git history alone"
+    )]
+    revision_trailer: bool,
+
+    #[structopt(
+        long,
+        help = "import every file under the given directory in the Git repository, such as legacy/cvs, rather than at the top level; this must be the same on every run"
+    )]
+    rewrite_root: Option<RewriteRoot>,
+
+    #[structopt(
+        long,
+        help = "once the import is complete, point HEAD in the Git repository at the head branch, as named in Git after any --branch-rename, so that clones check it out by default"
+    )]
+    set_head: bool,
+
+    #[structopt(
+        long,
+        help = "a glob matching tags whose fake commits should be signed, such as 'REL*'; each tag created or moved by the import that matches is listed in the report and sent to --post-import-hook, and may be given more than once"
+    )]
+    sign_tags: Vec<glob::Pattern>,
+
+    #[structopt(
+        long,
+        help = "skip the dead 1.1 revision that CVS adds to the trunk when a file is added on a branch, as cvs2git does, rather than importing it as a deletion that comes before the file is added on the branch"
+    )]
+    skip_branch_add_placeholders: bool,
+
+    #[structopt(
+        long,
+        help = "only create or update the Git branch for a CVS branch if at least one of its patchsets was committed in this run, whether as a new commit or as an existing one; otherwise, the branch is left alone, rather than set to the last commit recorded for it in the store"
+    )]
+    skip_empty_branches: bool,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "skip the RCS files listed in the given file without parsing them; each line is the path of a ,v file relative to the CVSROOT, followed by the reason it's skipped, which is recorded in the store and the report"
+    )]
+    skip_list: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "once the import is complete, verify the blobs of the given number of random file revisions against their content in the RCS files; requires git to be installed"
+    )]
+    spot_check: Option<usize>,
+
+    #[structopt(
+        long,
+        help = "after patchset detection, merge consecutive patchsets on a branch with the same author and messages matching a glob, each no more than a duration after the last, given as BRANCH=DURATION:PATTERN, such as 'main=5m:Automated update*'; may be given more than once"
+    )]
+    squash_rule: Vec<SquashRule>,
+
+    #[structopt(
+        long,
+        default_value = "0",
+        help = "how to compress the store: none, which is quickest to write but largest, or a zstd level, where 0 is zstd's default level and higher levels are smaller but slower; compression uses one thread per job"
+    )]
+    state_compression: Compression,
+
     #[structopt(
         short,
         long,
@@ -85,6 +522,13 @@ struct Opt {
     )]
     store: PathBuf,
 
+    #[structopt(
+        long,
+        conflicts_with = "ignore-file-errors",
+        help = "fail the import if any information would be lost in the conversion, such as duplicate symbols, revisions that aren't on a named branch, authors that can't appear in a Git identity, or authors, log messages, and tag names that aren't valid UTF-8"
+    )]
+    strict: bool,
+
     #[structopt(
         long,
         default_value = "git-cvs-fast-import",
@@ -101,33 +545,152 @@ struct Opt {
     #[structopt(
         name = "DIRECTORY",
         parse(from_os_str),
-        help = "the top level directories to import from the CVSROOT; if omitted, all directories will be imported"
+        help = "the top level directories to import from the CVSROOT; if omitted, all directories will be imported. Directories imported by earlier runs are always included, so more can be added later"
     )]
     directories: Vec<PathBuf>,
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    // Parse command line arguments.
-    let opt = Opt::from_args();
+fn main() -> anyhow::Result<()> {
+    // The runtime is built by hand, rather than with #[tokio::main], so that
+    // it's shut down before exiting with a distinct status for an incomplete
+    // import, rather than being cut off along with everything running on it.
+    let result = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(try_main());
 
-    // Set up tokio-console in debug builds.
-    #[cfg(debug_assertions)]
-    console_subscriber::init();
+    match result {
+        Err(e) if e.is::<Incomplete>() => {
+            log::warn!("{}", e);
+            std::process::exit(2);
+        }
+        result => result,
+    }
+}
 
-    // Set up logging.
-    Logger::try_with_env_or_str(opt.log.as_str())?
-        .adaptive_format_for_stderr(AdaptiveFormat::Detailed)
-        .start()?;
+/// The subcommands, which can only be given as the first argument.
+const SUBCOMMANDS: &[&str] = &["cat", "list", "log", "state"];
 
-    // Preflight git to make sure we have a sane environment.
-    git_cvs_fast_import_process::preflight(&opt.output)?;
+/// Returns the subcommand named by the first argument, if any.
+///
+/// The first argument of an import can also be a directory to import, so a
+/// command line that's also a valid import is only treated as a subcommand if
+/// the CVSROOT doesn't have a directory of that name. Directories given after
+/// `--` are always imported.
+fn subcommand(args: &[OsString]) -> Option<&'static str> {
+    let name = args.get(1)?;
+    let subcommand = SUBCOMMANDS.iter().find(|subcommand| name == **subcommand)?;
 
-    // Set up our state manager, loading the store if it exists.
-    let state = match File::open(&opt.store) {
-        Ok(file) => {
-            log::info!("loading state from {}", opt.store.display());
-            Manager::deserialize_from(&file).await?
+    if let Ok(matches) = Opt::clap().get_matches_from_safe(args) {
+        if Opt::from_clap(&matches).cvsroot.join(name).is_dir() {
+            return None;
+        }
+    }
+
+    Some(subcommand)
+}
+
+async fn try_main() -> anyhow::Result<()> {
+    // The cat, list, log, and state subcommands don't import anything, so
+    // they're handled before the import options are parsed.
+    match subcommand(&std::env::args_os().collect::<Vec<_>>()) {
+        Some("cat") => {
+            let opt = cat::Opt::from_iter(std::env::args_os().skip(1));
+            return cat::run(&opt, &mut std::io::stdout().lock());
+        }
+        Some("list") => {
+            let opt = list::Opt::from_iter(std::env::args_os().skip(1));
+            return list::run(&opt, &mut std::io::stdout().lock());
+        }
+        Some("log") => {
+            let opt = rlog::Opt::from_iter(std::env::args_os().skip(1));
+            return rlog::run(&opt, &mut std::io::stdout().lock());
+        }
+        Some("state") => {
+            let opt = state::Opt::from_iter(std::env::args_os().skip(1));
+            return state::run(
+                &opt,
+                &mut std::io::stdin().lock(),
+                &mut std::io::stdout().lock(),
+            )
+            .await;
+        }
+        _ => {}
+    }
+
+    // Parse command line arguments, along with the config file, if any.
+    let mut opt = Opt::from_clap(&config::get_matches(
+        Opt::clap(),
+        std::env::args_os().collect(),
+        "DIRECTORY",
+    )?);
+    if let Some(size) = opt.blob_batch_size {
+        opt.output.set_blob_batch_size(size);
+    }
+
+    // Set up tracing: structured events are written with --events, and
+    // tokio-console is served in debug builds.
+    let subscriber = tracing_subscriber::registry().with(
+        opt.events
+            .as_deref()
+            .map(events::JsonLines::create)
+            .transpose()?,
+    );
+    #[cfg(debug_assertions)]
+    let subscriber = subscriber.with(console_subscriber::spawn());
+    tracing::subscriber::set_global_default(subscriber)?;
+
+    // Set up logging.
+    Logger::try_with_env_or_str(opt.log.as_str())?
+        .adaptive_format_for_stderr(AdaptiveFormat::Detailed)
+        .start()?;
+    if let Some(config) = &opt.config {
+        log::info!("read options from {}", config.display());
+    }
+    if opt.idle_priority {
+        priority::idle().context("cannot lower the priority of the import")?;
+        log::info!("running at idle priority");
+    }
+
+    let run = run::start();
+    log::info!("starting run {}", run.id);
+
+    let metrics = Metrics::new();
+    #[cfg(feature = "metrics")]
+    if let Some(address) = &opt.metrics_address {
+        metrics.serve(address).await?;
+    }
+
+    // Create the repository if we've been asked to, so that it's there to be
+    // preflighted.
+    let branch_renames = BranchRenames::new(opt.branch_rename.clone());
+    if opt.init {
+        let head = branch_renames.git_branch(&opt.head_branch);
+        if git_cvs_fast_import_process::init(&opt.output, opt.bare, &head)? {
+            log::info!(
+                "created Git repository at {}",
+                Path::new(opt.output.git_repo()).display()
+            );
+        }
+    }
+
+    // Preflight git to make sure we have a sane environment.
+    git_cvs_fast_import_process::preflight(&opt.output)?;
+    let graft_onto = opt
+        .graft_onto
+        .as_deref()
+        .map(|rev| git_cvs_fast_import_process::resolve_commit(&opt.output, rev))
+        .transpose()?;
+
+    // Read the skip list up front, so that a mistake in it is reported before
+    // anything is imported.
+    let skip_list = opt.skip_list.as_deref().map(SkipList::read).transpose()?;
+
+    // Set up our state manager, loading the store if it exists.
+    let state = match File::open(&opt.store) {
+        Ok(file) => {
+            log::info!("loading state from {}", opt.store.display());
+            Manager::deserialize_from(&file).await?
         }
         Err(e) if e.kind() == ErrorKind::NotFound => {
             log::info!("setting up new state");
@@ -136,38 +699,278 @@ async fn main() -> anyhow::Result<()> {
         Err(e) => anyhow::bail!(e),
     };
 
+    // Check that the store agrees with the marks that Git was given, and that
+    // every object they refer to is in the repository, so that a store that
+    // has diverged from the repository fails now, rather than partway through
+    // the import.
+    const DIVERGED: &str = "the store no longer matches the Git repository, which can happen if an earlier import was interrupted: restore the store and the repository from the same backup, or remove both and import again";
+    if let Err(e) = state.validate_marks().await {
+        anyhow::bail!("{}; {}", e, DIVERGED);
+    }
+    let referenced = state.get_referenced_objects().await?;
+    let names: Vec<String> = referenced.iter().map(|(_mark, sha)| sha.clone()).collect();
+    let missing = git_cvs_fast_import_process::missing_objects(&opt.output, &names)?;
+    if let Some(first) = missing.first() {
+        let mark = referenced
+            .iter()
+            .find(|(_mark, sha)| sha == first)
+            .map(|(mark, _sha)| *mark)
+            .unwrap();
+        anyhow::bail!(
+            "{} object(s) in the store are not in the Git repository, starting with {} for mark {}; {}",
+            missing.len(),
+            first,
+            mark,
+            DIVERGED
+        );
+    }
+
+    // Similarly, check that no branch has been moved in the Git repository
+    // since it was last imported, since the import would otherwise silently
+    // discard those commits.
+    let moved_branches = if opt.no_update_refs {
+        Vec::new()
+    } else {
+        moved_branches(&state, &opt.output, &branch_renames).await?
+    };
+    for moved in moved_branches.iter() {
+        log::warn!(
+            "{} is at {} in the Git repository, but was last imported as {}",
+            moved.name,
+            moved.tip,
+            moved.imported
+        );
+    }
+    if !moved_branches.is_empty() && !opt.force {
+        anyhow::bail!("{} branch(es) in the Git repository have moved since the last import; use --force to reset them to the commits they were last imported as", moved_branches.len());
+    }
+
+    // Paths are normalized as files are discovered, so changing the form after
+    // the first run would import every affected file again at a new path.
+    // Stores that don't record a form were imported without normalization.
+    let path_normalization = opt.path_normalization.to_string();
+    let previous_normalization = match state.get_path_normalization().await {
+        Some(previous) => Some(previous),
+        None if !state.get_branches().await.is_empty() => Some("none".to_string()),
+        None => None,
+    };
+    if let Some(previous) = previous_normalization {
+        if previous != path_normalization {
+            anyhow::bail!("the store was imported with --path-normalization {}, but this run uses {}; paths must be normalized the same way on every run against a store", previous, path_normalization);
+        }
+    }
+    state.set_path_normalization(&path_normalization).await;
+
+    // Figure out which directories we're importing, including any that were
+    // imported previously.
+    let (directories, previous_directories) = import_directories(&state, &opt.directories).await;
+
     // Set up the mark file for git-fast-import to import.
     let mark_file = dump_marks_to_file(&state).await?;
 
     // Set up our git-fast-import export using the marks, if any.
     let (output, worker) = git_cvs_fast_import_process::new(mark_file.as_ref(), &opt.output);
+    let progress = match output.byte_count() {
+        Some(bytes) if !opt.progress_interval.is_zero() => Some(Progress::spawn(
+            bytes,
+            metrics.clone(),
+            opt.progress_interval,
+        )),
+        _ => None,
+    };
+    for moved in moved_branches.into_iter() {
+        log::info!("resetting {} to {}", moved.name, moved.imported);
+        output.branch(&moved.name, moved.mark).await?;
+    }
 
     // Discover all files in the CVSROOT, and process each one into a new
     // Collector and the state.
     log::info!("starting file discovery");
-    let collector = discover_files(&state, &output, &opt)?;
-    log::info!("discovery phase done; parsing files");
+    let errors = ErrorRegistry::new();
+    let losses = LossRegistry::new();
+    let provenance = opt.provenance.as_ref().map(|_| ProvenanceRegistry::new());
+    let changelogs = opt.changelog_messages.then(ChangeLogRegistry::new);
+    let diffstats = opt.report.as_ref().map(|_| DiffStatRegistry::new());
+    let collector = discover_files(
+        &state,
+        &output,
+        &errors,
+        &losses,
+        &metrics,
+        provenance.as_ref(),
+        changelogs.as_ref(),
+        diffstats.as_ref(),
+        skip_list.as_ref(),
+        &opt,
+        &directories,
+    )
+    .await?;
 
     // Collect our observations into patchsets so we can send them.
     let result = collector.join().await?;
+    if let Some(skip_list) = &skip_list {
+        state.set_skipped_files(skip_list.entries().clone()).await;
+    }
+
+    // The provenance only depends on the RCS files, so we can write it out
+    // whether or not the rest of the import succeeds.
+    if let (Some(path), Some(provenance)) = (&opt.provenance, &provenance) {
+        log::info!("writing provenance to {}", path.display());
+        provenance.write_to(path)?;
+    }
+
+    // Unless we've been told to ignore them, file errors are fatal, but we'll
+    // only bail once every file has been parsed so that the summary is
+    // complete. Structural problems, such as a missing HEAD revision, only
+    // mean that the file is skipped, unless nothing may be lost in strict mode.
+    if !opt.ignore_file_errors && (errors.fatal_len() > 0 || (opt.strict && !errors.is_empty())) {
+        errors.log_summary(log::Level::Error);
+        write_report(
+            &opt,
+            Report::new(&run, &errors, &losses, skip_list.as_ref()),
+        )?;
+        anyhow::bail!("{} file(s) could not be imported", errors.len());
+    }
+
+    // In strict mode, any lossy conversion is also fatal. As with file errors,
+    // we only check once every file has been parsed, but before anything has
+    // been committed.
+    if opt.strict && !losses.is_empty() {
+        losses.log_summary(log::Level::Error);
+        write_report(
+            &opt,
+            Report::new(&run, &errors, &losses, skip_list.as_ref()),
+        )?;
+        anyhow::bail!(
+            "{} lossy conversion(s) would be required in strict mode",
+            losses.len()
+        );
+    }
     log::info!("file parsing complete; sending patchsets");
 
+    let mut hook = opt.patchset_hook.as_deref().map(Hook::spawn).transpose()?;
+    let commit_options = CommitOptions {
+        resurrection_trailer: opt.resurrection_trailer,
+        revision_trailer: opt.revision_trailer,
+        import_trailer: opt.import_trailer.then(|| run::trailer(&run)),
+        checkpointer: opt.checkpoint_every.map(Checkpointer::new),
+        gitkeep: opt.gitkeep,
+        graft_onto: graft_onto.map(|id| (opt.head_branch.as_bytes().to_vec(), id)),
+        rewrite_root: opt.rewrite_root.clone(),
+        max_commit_files: opt.max_commit_files,
+        committer_date: opt.committer_date,
+        normalize_times: opt.normalize_times,
+        started: SystemTime::now(),
+        update_refs: !opt.no_update_refs,
+        skip_empty_branches: opt.skip_empty_branches,
+        emit_empty_commits: opt.emit_empty_commits,
+        changelogs,
+        diffstats,
+        branch_renames,
+    };
     let branch_filter = BranchFilter::new(opt.branch.iter().map(|branch| branch.as_bytes()));
+    check_branch_renames(&state, &result, &commit_options.branch_renames).await?;
+    let mut commits: Vec<(String, Mark)> = Vec::new();
+    let mut diffstats: BTreeMap<Mark, DiffStat> = BTreeMap::new();
+    let mut clock_skews: Vec<ReportClockSkew> = Vec::new();
     for (branch, patchsets) in result
         .branch_iter()
         .filter(|(branch, _patchsets)| branch_filter.contains(branch))
     {
-        send_patchsets(&state, &output, branch, patchsets.iter()).await?;
+        let sent = send_patchsets(
+            &state,
+            &output,
+            &result,
+            &commit_options,
+            previous_directories.as_ref(),
+            hook.as_mut(),
+            branch,
+            patchsets.iter(),
+        )
+        .await?;
+
+        metrics.commits(
+            sent.marks.len(),
+            patchsets.iter().map(|patchset| &patchset.time).max(),
+        );
+        let branch = String::from_utf8_lossy(branch);
+        commits.extend(
+            sent.marks
+                .into_iter()
+                .map(|mark| (branch.to_string(), mark)),
+        );
+        clock_skews.extend(sent.clock_skews);
+        diffstats.extend(sent.diffstats);
+    }
+    if !clock_skews.is_empty() {
+        log::warn!(
+            "{} commit(s) have a CVS time earlier than their parent{}",
+            clock_skews.len(),
+            if opt.normalize_times {
+                "; their times have been adjusted"
+            } else {
+                ""
+            }
+        );
+    }
+    if let Some(hook) = hook {
+        hook.finish().await?;
+    }
+    if opt.create_empty_branches {
+        create_empty_branches(&state, &output, &result, &branch_filter, &commit_options).await?;
+    }
+
+    if opt.delete_obsolete_branches {
+        // A branch may only be defined in a file that couldn't be parsed, so
+        // we can't tell which branches are obsolete if there were errors.
+        if errors.is_empty() {
+            delete_obsolete_branches(
+                &state,
+                &output,
+                &result,
+                &branch_filter,
+                &commit_options.branch_renames,
+                opt.skip_empty_branches,
+            )
+            .await?;
+        } else {
+            log::warn!("not deleting obsolete branches, since some files could not be imported");
+        }
     }
     log::info!("patchsets sent; sending tags");
 
-    // Send up our tags.
+    // As with branches, a tag may only be defined in a file that couldn't be
+    // parsed, so tags are only pruned if every file was imported. Otherwise,
+    // the tags observed in this run are added to those from earlier runs.
+    let prune_tags = opt.prune_tags && errors.is_empty();
+    if opt.prune_tags && !prune_tags {
+        log::warn!("not pruning tags, since some files could not be imported");
+    }
+    if let (true, Some(seen_tags)) = (prune_tags, result.seen_tags()) {
+        state.set_tag_file_revisions(seen_tags.clone()).await;
+    }
+
+    // Send up our tags. The time of the tag identity is replaced with the time
+    // of each tag's content when its fake commit is made.
     let identity = Identity::new(
-        opt.tag_identity_name,
-        opt.tag_identity_email,
-        SystemTime::now(),
+        opt.tag_identity_name.clone(),
+        opt.tag_identity_email.clone(),
+        UNIX_EPOCH,
     )?;
-    send_tags(&state, &output, identity).await?;
+    let tags = send_tags(
+        &state,
+        &output,
+        identity,
+        opt.orphan_tags,
+        &opt.head_branch,
+        opt.rewrite_root.as_ref(),
+        commit_options.import_trailer.as_deref(),
+        !opt.no_update_refs,
+    )
+    .await?;
+    if prune_tags {
+        delete_obsolete_tags(&state, &output).await?;
+    }
     log::info!("tags sent");
 
     // We need to ensure all references to output are dropped before the output
@@ -176,6 +979,17 @@ async fn main() -> anyhow::Result<()> {
 
     // Now we wait for any remaining items to be written.
     worker.wait().await?;
+    if let Some(progress) = progress {
+        progress.finish();
+    }
+
+    // HEAD is a symbolic ref, which can't be set through git-fast-import, so
+    // it's set once the branches have been written.
+    if opt.set_head {
+        let head = commit_options.branch_renames.git_branch(&opt.head_branch);
+        log::info!("pointing HEAD at {}", head);
+        git_cvs_fast_import_process::set_head(&opt.output, &head)?;
+    }
 
     // git-fast-import wrote the marks to the mark file before exiting while we
     // were waiting for the output handle, so we can now store that in the
@@ -184,57 +998,403 @@ async fn main() -> anyhow::Result<()> {
     save_marks_from_file(&state, &mark_file).await?;
     mark_file.close()?;
 
-    // Finally, we can now store the in-memory state to the persistent store.
+    // With the marks saved, each branch head can be found in Git, so its tree
+    // can be checked against the state.
+    let reconciliations = if opt.reconcile {
+        log::info!("reconciling branch heads");
+        reconcile_branches(&state, &opt, &commit_options, &branch_filter).await?
+    } else {
+        Vec::new()
+    };
+
+    // Now that the marks are saved, we can map the new commits to the SHAs
+    // that git created for them.
+    let mut report = Report::new(&run, &errors, &losses, skip_list.as_ref());
+    report.reconciliations = reconciliations;
+    report.clock_skews = clock_skews;
+    for (branch, mark) in commits.into_iter() {
+        report.commits.push(ReportCommit {
+            branch,
+            mark: mark.as_usize(),
+            sha: state.get_sha_for_patchset_mark(&mark).await?,
+            diffstat: diffstats.remove(&mark).unwrap_or_default(),
+        });
+    }
+    report.refs = report_refs(
+        &state,
+        &result,
+        &branch_filter,
+        &commit_options.branch_renames,
+    )
+    .await?;
+    for (tag, mark, parent) in tags.into_iter() {
+        let parent_mark = parent.mark();
+        let tag = String::from_utf8_lossy(&tag).into_owned();
+        let sha = state.get_sha_for_patchset_mark(&mark).await?;
+        if let Some(pattern) = opt.sign_tags.iter().find(|pattern| pattern.matches(&tag)) {
+            report.sign_tags.push(ReportSignTag {
+                ref_name: format!("refs/tags/{}", tag),
+                mark: mark.as_usize(),
+                sha: sha.clone(),
+                reason: format!(
+                    "{} tag matching {}",
+                    match parent {
+                        tag::Parent::PreviousTag(_) => "moved",
+                        _ => "new",
+                    },
+                    pattern
+                ),
+            });
+        }
+        report.tags.push(ReportTag {
+            tag,
+            mark: mark.as_usize(),
+            sha,
+            parent: match parent {
+                tag::Parent::PreviousTag(_) => ReportTagParent::PreviousTag,
+                tag::Parent::FileContent { .. } => ReportTagParent::Patchset,
+                tag::Parent::BranchHead(_) => ReportTagParent::BranchHead,
+                tag::Parent::EmptyRoot(_) => ReportTagParent::EmptyRoot,
+                tag::Parent::None => ReportTagParent::None,
+            },
+            parent_mark: parent_mark.map(|mark| mark.as_usize()),
+            parent_sha: match parent_mark {
+                Some(mark) => Some(state.get_sha_for_patchset_mark(&mark).await?),
+                None => None,
+            },
+        });
+    }
+
+    // The marks also tell us which blob each file revision was written to, so
+    // we can verify a sample of them against the RCS files.
+    if let Some(count) = opt.spot_check {
+        log::info!("spot checking {} file revision(s)", count);
+        let source = source::open(&opt.cvsroot, &opt.cvs)?;
+        let spot_check = spot_check::spot_check(
+            &state,
+            source.as_ref(),
+            opt.date_quirks,
+            opt.path_normalization,
+            &directories,
+            opt.output.git_command(),
+            opt.output.git_repo(),
+            count,
+        )
+        .await?;
+        log::info!(
+            "spot checked {} file revision(s); {} mismatch(es)",
+            spot_check.checked,
+            spot_check.mismatches.len()
+        );
+        report.spot_check = Some(spot_check);
+    }
+
+    metrics.finish();
+
+    // Finally, we can now store the in-memory state to the persistent store,
+    // along with the run that updated it.
     log::info!("persisting state to {}", opt.store.display());
+    state.add_run(run).await;
     {
-        let file = File::create(&opt.store)?;
-        state.serialize_into(&file).await?;
+        let started = Instant::now();
+        let file = BufWriter::new(File::create(&opt.store)?);
+        let compression = opt
+            .state_compression
+            .with_workers(opt.jobs.unwrap_or_else(num_cpus::get) as u32);
+        state.serialize_into(file, compression).await?;
+        log::info!("persisted state in {:.1?}", started.elapsed());
+    }
+
+    let mismatches = report
+        .spot_check
+        .as_ref()
+        .map(|spot_check| spot_check.mismatches.len())
+        .unwrap_or_default();
+    let sign_tags = report.sign_tags.clone();
+    write_report(&opt, report)?;
+    losses.log_summary(log::Level::Warn);
+    if mismatches > 0 {
+        anyhow::bail!(
+            "{} spot checked file revision(s) do not match the RCS content",
+            mismatches
+        );
+    }
+
+    // The hook runs last, so that it can rely on the refs, the state, and the
+    // report all being up to date.
+    if let Some(command) = &opt.post_import_hook {
+        log::info!(
+            "running post-import hook with {} tag(s) to sign",
+            sign_tags.len()
+        );
+        hook::post_import(command, opt.output.git_repo(), &sign_tags).await?;
+    }
+
+    if opt.repack {
+        log::info!("repacking the Git repository");
+        let started = Instant::now();
+        git_cvs_fast_import_process::repack(&opt.output, opt.repack_depth, opt.repack_window)?;
+        log::info!("repacked in {:.1?}", started.elapsed());
+    }
+    if !errors.is_empty() {
+        // Exit with a distinct status so that scripts can tell that the import
+        // completed, but without every file.
+        errors.log_summary(log::Level::Warn);
+        return Err(Incomplete(errors.len()).into());
     }
 
     log::info!("export complete!");
     Ok(())
 }
 
-/// Discover all files in the given path input and parse them into a Collector.
+/// Maps each branch and tag to the Git ref and commit that it was imported as.
 ///
-/// If an item when iterating `opt.directories` returns an error, then that
-/// error will be returned from this function.
-fn discover_files(state: &Manager, output: &Output, opt: &Opt) -> Result<Collector, anyhow::Error> {
+/// Branches are included if they're defined in the CVS repository and included
+/// by the branch filter, and tags if they have been imported in this or any
+/// earlier run.
+async fn report_refs(
+    state: &Manager,
+    result: &ObservationResult,
+    branch_filter: &BranchFilter,
+    branch_renames: &BranchRenames,
+) -> anyhow::Result<Vec<ReportRef>> {
+    let mut refs = Vec::new();
+    for branch in result
+        .seen_branch_iter()
+        .filter(|branch| branch_filter.contains(branch))
+    {
+        if let Some(mark) = state.get_last_patchset_mark_on_branch(branch).await {
+            let mark = Mark::from(mark);
+            let symbol = String::from_utf8_lossy(branch).into_owned();
+            refs.push(ReportRef {
+                ref_name: format!("refs/heads/{}", branch_renames.git_branch(&symbol)),
+                symbol,
+                kind: ReportRefKind::Branch,
+                mark: mark.as_usize(),
+                sha: state.get_sha_for_patchset_mark(&mark).await?,
+                synthesized: false,
+            });
+        }
+    }
+
+    // As in send_tags, we can't hold the tag iterator while querying the
+    // state.
+    let mut tags: Vec<Vec<u8>> = state
+        .get_tags()
+        .await
+        .iter()
+        .map(|tag| tag.into())
+        .collect();
+    tags.sort();
+    for tag in tags.iter() {
+        if let Some(mark) = state.get_mark_for_tag(tag).await {
+            let symbol = String::from_utf8_lossy(tag).into_owned();
+            refs.push(ReportRef {
+                ref_name: format!("refs/tags/{}", symbol),
+                symbol,
+                kind: ReportRefKind::Tag,
+                mark: mark.as_usize(),
+                sha: state.get_sha_for_patchset_mark(&mark).await?,
+                synthesized: true,
+            });
+        }
+    }
+
+    Ok(refs)
+}
+
+/// Writes the JSON report, if one was requested.
+fn write_report(opt: &Opt, report: Report) -> anyhow::Result<()> {
+    if let Some(path) = &opt.report {
+        log::info!("writing report to {}", path.display());
+        report.write_to(path)?;
+    }
+
+    Ok(())
+}
+
+/// Works out which directories to import, and records them in the state.
+///
+/// Directories imported by earlier runs are always imported again, since their
+/// history is already in Git. If this run imports directories that weren't
+/// imported before, then the previously imported directories are also
+/// returned.
+///
+/// An empty list of directories means that the whole repository is imported.
+async fn import_directories(
+    state: &Manager,
+    requested: &[PathBuf],
+) -> (Vec<PathBuf>, Option<Directories>) {
+    let previous = state.get_directories().await;
+    let mut directories = previous.clone();
+    if requested.is_empty() {
+        directories.add(Path::new(""));
+    } else {
+        for directory in requested.iter() {
+            directories.add(directory);
+        }
+    }
+    state.add_directories(directories.iter()).await;
+
+    let expanded = if previous.is_empty() {
+        // Older states didn't record the directories, so the best we can do
+        // is to assume that nothing has changed.
+        if !state.get_branches().await.is_empty() {
+            log::info!("the state does not record which directories were imported previously; assuming they are the same as this run");
+        }
+        None
+    } else if previous != directories {
+        for directory in directories
+            .iter()
+            .filter(|directory| !previous.covers(directory))
+        {
+            log::info!("adding {} to the imported directories", display(directory));
+        }
+        Some(previous)
+    } else {
+        None
+    };
+
+    if !requested.is_empty() {
+        for directory in directories
+            .iter()
+            .filter(|directory| !requested.iter().any(|dir| directory.starts_with(dir)))
+        {
+            log::info!(
+                "also importing {}, since it was imported previously",
+                display(directory)
+            );
+        }
+    }
+
+    let directories = if directories.is_everything() {
+        Vec::new()
+    } else {
+        directories.iter().map(PathBuf::from).collect()
+    };
+    (directories, expanded)
+}
+
+/// Describes an imported directory for logging.
+fn display(directory: &Path) -> Cow<'_, str> {
+    if directory.as_os_str().is_empty() {
+        Cow::Borrowed("the whole repository")
+    } else {
+        directory.to_string_lossy()
+    }
+}
+
+/// Discover all files in the given directories and parse them into a
+/// Collector.
+///
+/// If an item when iterating `directories` returns an error, then that error
+/// will be returned from this function, as will the failure of a worker, such
+/// as a panic. Finding no RCS files at all is also an error, unless
+/// `--allow-empty` is given.
+#[allow(clippy::too_many_arguments)]
+async fn discover_files(
+    state: &Manager,
+    output: &Output,
+    errors: &ErrorRegistry,
+    losses: &LossRegistry,
+    metrics: &Metrics,
+    provenance: Option<&ProvenanceRegistry>,
+    changelogs: Option<&ChangeLogRegistry>,
+    diffstats: Option<&DiffStatRegistry>,
+    skip_list: Option<&SkipList>,
+    opt: &Opt,
+    directories: &[PathBuf],
+) -> Result<Collector, anyhow::Error> {
     // Set up the observer and collector that we'll use during file discovery to
     // persist file revisions and detect patchsets.
-    let (observer, collector) = Observer::new(opt.delta, state.clone());
+    let (observer, collector) = Observer::new(
+        opt.delta,
+        opt.delta_branch
+            .iter()
+            .map(|branch_delta| (branch_delta.branch.clone(), branch_delta.delta))
+            .collect(),
+        opt.no_patchsets,
+        opt.squash_rule.clone(),
+        opt.prune_tags,
+        state.clone(),
+        losses.clone(),
+    );
+
+    // Figure out where the RCS files are coming from.
+    let source = source::open(&opt.cvsroot, &opt.cvs)?;
+
+    let content_cache = match &opt.content_cache {
+        Some(dir) => Some(
+            ContentCache::open(dir)
+                .with_context(|| format!("cannot open the content cache in {}", dir.display()))?,
+        ),
+        None => None,
+    };
+
+    let read_limiter = ReadLimiter::new(opt.max_read_bytes_per_sec);
 
     // Create our discovery worker pool.
     let discovery = Discovery::new(
         state,
         output,
         &observer,
-        &opt.head_branch,
-        opt.ignore_file_errors,
-        opt.jobs.unwrap_or_else(num_cpus::get),
-        &opt.cvsroot,
+        errors,
+        losses,
+        metrics,
+        &discovery::Options {
+            content_cache,
+            exclusions: SymbolExclusions::new(
+                opt.exclude_branches.clone(),
+                opt.exclude_tags.clone(),
+            ),
+            hard_links: opt.hard_links,
+            head_branch: opt.head_branch.clone(),
+            ignore_errors: opt.ignore_file_errors,
+            jobs: opt.jobs.unwrap_or_else(num_cpus::get),
+            max_revisions_per_file: opt.max_revisions_per_file,
+            missing_symbol_revisions: opt.missing_symbol_revisions,
+            prefix: source.prefix().to_path_buf(),
+            path_normalization: opt.path_normalization,
+            provenance: provenance.cloned(),
+            changelogs: changelogs.cloned(),
+            diffstats: diffstats.cloned(),
+            skip_list: skip_list.cloned(),
+            skip_branch_add_placeholders: opt.skip_branch_add_placeholders,
+            read_limiter: read_limiter.clone(),
+            mmap_threshold: opt.mmap_threshold as u64,
+            date_quirks: opt.date_quirks,
+            create_empty_branches: opt.create_empty_branches,
+        },
     );
 
-    // Send all the input paths to the discovery workers.
-    let paths: Vec<PathBuf> = if opt.directories.is_empty() {
-        vec![opt.cvsroot.clone()]
-    } else {
-        opt.directories
-            .iter()
-            .map(|dir| {
-                let mut pb = PathBuf::new();
-                pb.push(&opt.cvsroot);
-                pb.push(dir);
-
-                pb
-            })
-            .collect()
-    };
-    for path in paths {
-        for entry in WalkDir::new(path) {
-            log::trace!("sending {:?} to discovery", &entry);
-            discovery.discover(entry?.path())?;
+    // Send all the input entries to the discovery workers. This blocks while
+    // the queue is full, and fails if every worker has already stopped, in
+    // which case the reason they stopped is more useful.
+    if let Err(e) =
+        tokio::task::block_in_place(|| discovery.discover_source(source.as_ref(), directories))
+    {
+        discovery.join().await?;
+        return Err(e);
+    }
+    log::info!("discovery phase done; parsing files");
+
+    // Wait for the workers to finish parsing, so that a worker that panics
+    // fails the import immediately, rather than leaving it incomplete.
+    if discovery.join().await? == 0 {
+        let message = format!(
+            "no ,v files were found in {}",
+            source.locations(directories).join(", ")
+        );
+        if !opt.allow_empty {
+            anyhow::bail!("{}; check the CVSROOT and the directories to import, or use --allow-empty to import nothing", message);
         }
+        log::warn!("{}", message);
+    }
+    if let Some(read_limiter) = &read_limiter {
+        log::info!(
+            "reading files was delayed for {:.1?} of wall clock time by --max-read-bytes-per-sec",
+            read_limiter.waited()
+        );
     }
 
     Ok(collector)
@@ -255,17 +1415,308 @@ async fn dump_marks_to_file(state: &Manager) -> anyhow::Result<NamedTempFile> {
     Ok(file)
 }
 
+/// Compares the tree of the head of each branch in Git with the files on the
+/// branch in the state, and commits the difference to each branch that
+/// doesn't match. Branches that don't exist in Git, such as those that have
+/// been deleted, are skipped.
+///
+/// The git-fast-import process used for the import has already exited by the
+/// time the marks are known, so the commits are sent by a new one.
+async fn reconcile_branches(
+    state: &Manager,
+    opt: &Opt,
+    options: &CommitOptions,
+    branch_filter: &BranchFilter,
+) -> anyhow::Result<Vec<Reconciliation>> {
+    let mut raw_marks = Vec::new();
+    state.get_raw_marks(&mut raw_marks).await?;
+    let shas = git_fast_import::read_marks(raw_marks.as_slice())?;
+
+    // As in moved_branches(), the fake commits for tags are skipped.
+    let tags: HashSet<Vec<u8>> = state
+        .get_tags()
+        .await
+        .iter()
+        .map(|tag| tag.into())
+        .collect();
+
+    let mut drifted = Vec::new();
+    for branch in state
+        .get_branches()
+        .await
+        .into_iter()
+        .filter(|branch| !tags.contains(branch) && branch_filter.contains(branch))
+    {
+        let head: Mark = match state.get_last_patchset_mark_on_branch(&branch).await {
+            Some(mark) => mark.into(),
+            None => continue,
+        };
+        let git_branch = options
+            .branch_renames
+            .git_branch(std::str::from_utf8(&branch)?)
+            .into_owned();
+        match git_cvs_fast_import_process::resolve_commit(
+            &opt.output,
+            &refs::commit_ref(&git_branch, true),
+        ) {
+            Ok(_) => {}
+            Err(git_cvs_fast_import_process::Error::UnknownCommit(_)) => continue,
+            Err(e) => return Err(e.into()),
+        }
+
+        let drift = reconcile::drift(
+            state,
+            &branch,
+            &state.get_sha_for_patchset_mark(&head).await?,
+            &shas,
+            opt.output.git_command(),
+            opt.output.git_repo(),
+            |path| options.git_path(path.to_path_buf()),
+        )
+        .await?;
+        if !drift.is_empty() {
+            log::warn!(
+                "{} does not match the state: {} file(s) to update and {} to delete",
+                git_branch,
+                drift.modified.len(),
+                drift.deleted.len()
+            );
+            drifted.push((branch, git_branch, head, drift));
+        }
+    }
+    if drifted.is_empty() {
+        log::info!("every branch head matches the state");
+        return Ok(Vec::new());
+    }
+
+    let mark_file = dump_marks_to_file(state).await?;
+    let (output, worker) = git_cvs_fast_import_process::new(mark_file.as_ref(), &opt.output);
+    let mut sent = Vec::new();
+    for (branch, git_branch, head, drift) in drifted.into_iter() {
+        // The commit takes the time of the branch head, so that it doesn't
+        // look like clock skew when the next patchset is committed on top.
+        let time = state.get_commit_time(&head).await?;
+        let identity = Identity::new(
+            opt.tag_identity_name.clone(),
+            opt.tag_identity_email.clone(),
+            time,
+        )?;
+        let message = format!(
+            "Reconcile {} with CVS\n\nThe tree of {} didn't match the files imported from CVS: {} file(s) were updated, and {} deleted.",
+            git_branch,
+            git_branch,
+            drift.modified.len(),
+            drift.deleted.len()
+        );
+        let trailers: Vec<(&str, String)> = options
+            .import_trailer
+            .iter()
+            .map(|trailer| (run::TRAILER, trailer.clone()))
+            .collect();
+
+        let mut builder = CommitBuilder::new(refs::commit_ref(&git_branch, true));
+        builder
+            .committer(identity)
+            .message(trailer_message(&message, &trailers))
+            .from(head);
+        for (path, _id, mark) in drift.modified.iter() {
+            builder.add_file_command(FileCommand::Modify {
+                mode: git_fast_import::Mode::Normal,
+                mark: *mark,
+                path: options.git_path(path.clone()),
+            });
+        }
+        for (path, _id) in drift.deleted.iter() {
+            builder.add_file_command(FileCommand::Delete {
+                path: options.git_path(path.clone()),
+            });
+        }
+
+        let mark = output.commit(builder.build()?).await?;
+        output.branch(&git_branch, mark).await?;
+        state
+            .add_patchset(mark, &branch, &time, drift.file_revision_ids().into_iter())
+            .await;
+        sent.push((git_branch, mark, drift));
+    }
+    drop(output);
+    worker.wait().await?;
+    save_marks_from_file(state, &mark_file).await?;
+    mark_file.close()?;
+
+    let mut reconciliations = Vec::new();
+    for (branch, mark, drift) in sent.into_iter() {
+        let display = |path: &PathBuf| path.to_string_lossy().into_owned();
+        reconciliations.push(Reconciliation {
+            branch,
+            mark: mark.as_usize(),
+            sha: state.get_sha_for_patchset_mark(&mark).await?,
+            modified: drift
+                .modified
+                .iter()
+                .map(|(path, _id, _mark)| display(path))
+                .collect(),
+            deleted: drift
+                .deleted
+                .iter()
+                .map(|(path, _id)| display(path))
+                .collect(),
+        });
+    }
+
+    Ok(reconciliations)
+}
+
+/// Options controlling how patchsets are turned into commits.
+#[derive(Debug)]
+struct CommitOptions {
+    /// If true, commits that re-add files that were previously deleted will
+    /// have a trailer added to their message for each such file.
+    resurrection_trailer: bool,
+
+    /// If true, commits will have a trailer added to their message for each
+    /// file revision they include.
+    revision_trailer: bool,
+
+    /// The value of the `X-CVS-Import` trailer added to the message of every
+    /// commit, if set.
+    import_trailer: Option<String>,
+
+    /// Sends checkpoints as commits are sent, if set.
+    checkpointer: Option<Checkpointer>,
+
+    /// If true, directories that only contain deleted files have a `.gitkeep`
+    /// placeholder added.
+    gitkeep: bool,
+
+    /// The branch, and the object name of the existing Git commit that the
+    /// first commit on that branch extends, if set.
+    graft_onto: Option<(Vec<u8>, String)>,
+
+    /// The directory that files are nested under in the Git repository, if
+    /// any.
+    rewrite_root: Option<RewriteRoot>,
+
+    /// The maximum number of file commands in each commit, if patchsets that
+    /// change more files should be split.
+    max_commit_files: Option<NonZeroUsize>,
+
+    committer_date: CommitterDate,
+
+    /// If true, commits are never earlier than their parent commit.
+    normalize_times: bool,
+
+    /// The time the import started, which is used as the committer date with
+    /// `CommitterDate::Now`.
+    started: SystemTime,
+
+    /// If false, commits are written to a ref in the scratch namespace rather
+    /// than to their branch, and the head of the branch isn't reset.
+    update_refs: bool,
+
+    /// If true, branches are only updated if at least one of their patchsets
+    /// was committed in this run.
+    skip_empty_branches: bool,
+
+    /// If true, patchsets that don't change anything on their branch are sent
+    /// as commits without any file commands, rather than skipped.
+    emit_empty_commits: bool,
+
+    /// The names that CVS branches are imported as in Git.
+    branch_renames: BranchRenames,
+
+    /// The entries added to ChangeLog files, which replace trivial log
+    /// messages, if set.
+    changelogs: Option<ChangeLogRegistry>,
+
+    /// The line counts of each file revision, from which the diffstat of each
+    /// commit is calculated for the report, if set.
+    diffstats: Option<DiffStatRegistry>,
+}
+
+impl CommitOptions {
+    /// Returns the path in the Git repository for a file in the CVS
+    /// repository.
+    fn git_path(&self, path: PathBuf) -> PathBuf {
+        match &self.rewrite_root {
+            Some(root) => root.join(&path),
+            None => path,
+        }
+    }
+
+    /// Sets the parent of a commit on the given branch to `from`. Without a
+    /// parent mark, this is the first commit on the branch, which may be
+    /// grafted onto an existing commit.
+    fn set_parent(&self, builder: &mut CommitBuilder, from: Option<Mark>, branch: &[u8]) {
+        match (from, &self.graft_onto) {
+            (Some(mark), _) => {
+                builder.from(mark);
+            }
+            (None, Some((graft_branch, id))) if graft_branch == branch => {
+                log::info!("grafting {} onto {}", String::from_utf8_lossy(branch), id);
+                builder.from_object(id.clone());
+            }
+            (None, _) => {}
+        }
+    }
+
+    /// Splits the file commands of a patchset into the file commands of each
+    /// commit that needs to be sent for it. There's always at least one
+    /// commit, even if there are no file commands.
+    fn split_file_commands(&self, commands: Vec<FileCommand>) -> Vec<Vec<FileCommand>> {
+        match self.max_commit_files {
+            Some(max) if commands.len() > max.get() => commands
+                .chunks(max.get())
+                .map(|chunk| chunk.to_vec())
+                .collect(),
+            _ => vec![commands],
+        }
+    }
+}
+
+/// The commits created by [`send_patchsets`].
+#[derive(Debug, Default)]
+struct Sent {
+    marks: Vec<Mark>,
+    clock_skews: Vec<ReportClockSkew>,
+
+    /// The diffstat of each commit, if line counts are being recorded.
+    diffstats: BTreeMap<Mark, DiffStat>,
+}
+
 /// Send patchsets to git-fast-import.
+///
+/// If `previous_directories` is given, then this run adds directories to the
+/// import, and patchsets that were partially sent by earlier runs because they
+/// also include files in the new directories will have a commit created with
+/// just those files.
+///
+/// If a `hook` is given, each patchset that hasn't already been sent is passed
+/// through it first. Files modified in patchsets dropped by the hook are
+/// included in the next commit on the branch instead.
+///
+/// Each commit is checked against the time of its parent: commits earlier than
+/// their parent are returned as clock skews, and are adjusted to the parent
+/// time if `options.normalize_times` is set.
+///
+/// Returns the marks of the commits that were sent, along with any clock
+/// skews and their diffstats.
+#[allow(clippy::too_many_arguments)]
 async fn send_patchsets<'a, I>(
     state: &Manager,
     output: &Output,
+    result: &ObservationResult,
+    options: &CommitOptions,
+    previous_directories: Option<&Directories>,
+    mut hook: Option<&mut Hook>,
     branch: &[u8],
     patchset_iter: I,
-) -> anyhow::Result<()>
+) -> anyhow::Result<Sent>
 where
     I: Iterator<Item = &'a PatchSet<FileRevisionID>>,
 {
     let branch_str = std::str::from_utf8(branch)?;
+    let git_branch = options.branch_renames.git_branch(branch_str);
 
     // All commits except for the very first one will refer to their parent via
     // the from marker, so let's set that up.
@@ -274,93 +1725,820 @@ where
         .await
         .map(|mark| mark.into());
 
+    // We also need the time of the parent commit to detect clock skew.
+    let mut parent_time = match from {
+        Some(mark) => Some(state.get_commit_time(&mark).await?),
+        None => None,
+    };
+
+    // If placeholders are enabled, we need to know which files already exist
+    // on the branch, along with the mark of the empty blob once it's sent.
+    let mut placeholders = match options.gitkeep {
+        true => Some(Placeholders::new(
+            state
+                .get_files_on_branch(branch)
+                .await?
+                .into_iter()
+                .map(|(path, revision)| (path, revision.mark.is_some())),
+        )),
+        false => None,
+    };
+    let mut empty_blob: Option<Mark> = None;
+
+    // The file content and file revision IDs of any patchsets dropped by the
+    // hook, which will be included in the next commit.
+    let mut dropped_content: BTreeMap<PathBuf, FileRevisionID> = BTreeMap::new();
+    let mut dropped_ids: Vec<FileRevisionID> = Vec::new();
+
+    // Whether any patchset was committed on the branch in this run, either as
+    // a new commit or an existing one.
+    let mut committed = false;
+
+    let mut sent = Sent::default();
     for patchset in patchset_iter {
-        // We have a patchset, so let's turn it into a Git commit.
-        let mut builder = CommitBuilder::new(format!("refs/heads/{}", branch_str));
-        builder
-            .committer(Identity::new(None, patchset.author.clone(), patchset.time)?)
-            .message(patchset.message.clone());
+        // Calculate the file revision IDs.
+        let mut file_revision_ids = patchset_file_revision_ids(&dropped_ids, patchset);
 
-        // As alluded to earlier, if we have a parent mark (and we usually
-        // will), we need to ensure that gets set up.
-        if let Some(mark) = from {
-            builder.from(mark);
+        // Check if we have already sent the commit to git-fast-import. New
+        // commits are only ever added to the end of the branch, so we never
+        // move back to an earlier commit here.
+        if let Some(mark) = state
+            .get_mark_from_patchset_content(&patchset.time, file_revision_ids.iter().copied())
+            .await
+        {
+            committed = true;
+            if from < Some(mark) {
+                from = Some(mark);
+                parent_time = Some(state.get_commit_time(&mark).await?);
+
+                if let Some(placeholders) = placeholders.as_mut() {
+                    let content = dropped_content.iter().chain(patchset.file_content_iter());
+                    placeholders.commit(file_states(state, content).await?);
+                }
+            }
+            dropped_content.clear();
+            dropped_ids.clear();
+
+            // Let's add this branch to the patchset.
+            state.add_branch_to_patchset_mark(mark, branch).await;
+            continue;
+        }
+
+        // If we're adding directories, then the files in the previously
+        // imported directories may have been sent already, in which case we
+        // only need to commit the files in the new directories. The whole
+        // patchset is recorded against the existing commit so that later runs
+        // know it has been sent.
+        let mut patchset = Cow::Borrowed(patchset);
+        if let Some(previous) = previous_directories {
+            let (imported, added) = patchset.partition(|path| previous.covers(path));
+            if !imported.is_empty() && !added.is_empty() {
+                if let Some(mark) = state
+                    .get_mark_from_patchset_content(
+                        &imported.time,
+                        patchset_file_revision_ids(&[], &imported).into_iter(),
+                    )
+                    .await
+                {
+                    log::debug!(
+                        "patchset at {:?} on {} was partially sent as {}; committing the files in the added directories",
+                        patchset.time,
+                        branch_str,
+                        mark
+                    );
+                    state.add_branch_to_patchset_mark(mark, branch).await;
+                    state
+                        .add_patchset_alias(
+                            mark,
+                            &patchset.time,
+                            patchset_file_revision_ids(&[], &patchset).into_iter(),
+                        )
+                        .await;
+
+                    if from < Some(mark) {
+                        from = Some(mark);
+                        parent_time = Some(state.get_commit_time(&mark).await?);
+
+                        if let Some(placeholders) = placeholders.as_mut() {
+                            placeholders
+                                .commit(file_states(state, imported.file_content_iter()).await?);
+                        }
+                    }
+                    file_revision_ids = patchset_file_revision_ids(&dropped_ids, &added);
+                    patchset = Cow::Owned(added);
+                }
+            }
         }
 
-        // Now we set up the file commands in the commit: the patchset will give
+        // Now we figure out the content of the commit: the patchset will give
         // us the file revision ID for each file that was modified or deleted in
-        // the commit. From there, we need to ascertain if that maps to a mark
-        // (in which case it's a modification, since there's content associated
-        // with the file revision) or not (in which case it's a deletion).
-        for (path, file_id) in patchset.file_content_iter() {
-            let revision = state.get_file_revision_by_id(*file_id).await?;
-            match revision.mark {
-                Some(mark) => builder.add_file_command(FileCommand::Modify {
+        // the commit, on top of anything left over from dropped patchsets.
+        let mut content = dropped_content.clone();
+        content.extend(
+            patchset
+                .file_content_iter()
+                .map(|(path, id)| (path.clone(), *id)),
+        );
+
+        let mut trailers: Vec<(&str, String)> = content
+            .iter()
+            .filter(|(_path, id)| options.resurrection_trailer && result.is_resurrection(**id))
+            .map(|(path, _id)| ("CVS-Resurrected", path.display().to_string()))
+            .collect();
+        if options.revision_trailer {
+            for id in file_revision_ids.iter() {
+                let key = &state.get_file_revision_by_id(*id).await?.key;
+                trailers.push((
+                    "CVS-Revision",
+                    format!("{}@{}", key.path.display(), key.revision),
+                ));
+            }
+        }
+        if let Some(import_trailer) = &options.import_trailer {
+            trailers.push((run::TRAILER, import_trailer.clone()));
+        }
+        let mut author = patchset.author.clone();
+        let mut message = trailer_message(
+            &changelog_message(state, options, &patchset, &file_revision_ids)
+                .await?
+                .unwrap_or_else(|| patchset.message.clone()),
+            &trailers,
+        );
+
+        if let Some(hook) = hook.as_mut() {
+            match hook
+                .process(HookPatchSet::new(
+                    branch_str,
+                    &patchset.time,
+                    &author,
+                    &message,
+                    content.keys().cloned().collect(),
+                )?)
+                .await?
+            {
+                Some(processed) => {
+                    author = processed.author;
+                    message = processed.message;
+                }
+                None => {
+                    log::debug!(
+                        "patchset hook dropped the patchset at {:?} on {}",
+                        patchset.time,
+                        branch_str
+                    );
+                    dropped_content = content;
+                    dropped_ids = file_revision_ids;
+                    continue;
+                }
+            }
+        }
+
+        // CVS server clocks can jump, so the patchset may be earlier than its
+        // parent.
+        let skewed_parent_time = parent_time.filter(|parent_time| *parent_time > patchset.time);
+        let time = match skewed_parent_time {
+            Some(parent_time) if options.normalize_times => parent_time,
+            _ => patchset.time,
+        };
+
+        // We have a patchset, so let's turn it into a Git commit.
+        let mut builder = CommitBuilder::new(refs::commit_ref(&git_branch, options.update_refs));
+        match options.committer_date.time(&time, &options.started) {
+            Some(committed) => builder
+                .author(author_identity(&author, time)?)
+                .committer(author_identity(&author, committed)?),
+            None => builder.committer(author_identity(&author, time)?),
+        }
+        .message(message);
+
+        // Each file revision ID either maps to a mark (in which case it's a
+        // modification, since there's content associated with the file
+        // revision) or not (in which case it's a deletion).
+        let mut commands = Vec::new();
+        let mut files = Vec::new();
+        let mut diffstat = options.diffstats.as_ref().map(|_| DiffStat::default());
+        for (path, file_id) in content.into_iter() {
+            let revision = state.get_file_revision_by_id(file_id).await?;
+            if let (Some(diffstat), Some(diffstats)) = (diffstat.as_mut(), &options.diffstats) {
+                diffstat.add(diffstats.get(&revision.key.path, &revision.key.revision));
+            }
+            if placeholders.is_some() {
+                files.push((path.clone(), revision.mark.is_some()));
+            }
+            commands.push(match revision.mark {
+                Some(mark) => FileCommand::Modify {
                     mode: git_fast_import::Mode::Normal,
                     mark: mark.into(),
-                    path: path.clone(),
-                }),
-                None => builder.add_file_command(FileCommand::Delete { path: path.clone() }),
-            };
+                    path: options.git_path(path),
+                },
+                None => FileCommand::Delete {
+                    path: options.git_path(path),
+                },
+            });
         }
 
-        // Calculate the file revision IDs.
-        let file_revision_ids = patchset
-            .file_revision_iter()
-            .map(|(_path, ids)| ids)
-            .flatten()
-            .copied()
-            .collect::<Vec<FileRevisionID>>();
-
-        // Check if we have already sent the commit to git-fast-import.
-        if let Some(mark) = state
-            .get_mark_from_patchset_content(&patchset.time, file_revision_ids.iter().copied())
+        // Directories that CVS keeps once their files are deleted get an empty
+        // placeholder file, until a file is added to them again.
+        let commands_from_content = commands.len();
+        if let Some(placeholders) = placeholders.as_mut() {
+            for (path, exists) in placeholders.commit(files) {
+                let path = options.git_path(path);
+                if !exists {
+                    commands.push(FileCommand::Delete { path });
+                    continue;
+                }
+
+                let mark = match empty_blob {
+                    Some(mark) => mark,
+                    None => *empty_blob.insert(output.blob(Blob::new(b"")).await?),
+                };
+                commands.push(FileCommand::Modify {
+                    mode: git_fast_import::Mode::Normal,
+                    mark,
+                    path,
+                });
+            }
+        }
+
+        // A patchset may not change anything on the branch, such as the dead
+        // revision that CVS adds to the trunk when a file is added on a
+        // branch. These are recorded against the parent commit and skipped,
+        // unless empty commits have been requested. Without a parent, the
+        // commit is needed to create the branch.
+        if let (Some(mark), true) = (from, commands_from_content == commands.len()) {
+            if is_no_op(state, branch, &file_revision_ids).await? {
+                if options.emit_empty_commits {
+                    commands.clear();
+                } else {
+                    log::debug!(
+                        "skipping the patchset at {:?} on {}, since it doesn't change anything",
+                        patchset.time,
+                        branch_str
+                    );
+                    state
+                        .add_patchset_alias(mark, &patchset.time, file_revision_ids.into_iter())
+                        .await;
+                    dropped_content.clear();
+                    dropped_ids.clear();
+                    continue;
+                }
+            }
+        }
+
+        // Patchsets that change too many files are sent as a series of
+        // commits. Only the last one is recorded as the patchset, so an
+        // interrupted import sends the whole series again.
+        let mut parts = options.split_file_commands(commands);
+        let last = parts.pop().unwrap_or_default();
+        if !parts.is_empty() {
+            log::debug!(
+                "splitting the patchset at {:?} on {} into {} commits",
+                patchset.time,
+                branch_str,
+                parts.len() + 1
+            );
+        }
+        for part in parts {
+            let mut builder = builder.clone();
+            options.set_parent(&mut builder, from, branch);
+            for command in part {
+                builder.add_file_command(command);
+            }
+            from = Some(output.commit(builder.build()?).await?);
+        }
+
+        // As alluded to earlier, if we have a parent mark (and we usually
+        // will), we need to ensure that gets set up.
+        options.set_parent(&mut builder, from, branch);
+        for command in last {
+            builder.add_file_command(command);
+        }
+
+        // Actually send the commit to git-fast-import and get the commit mark
+        // back.
+        let mark = output.commit(builder.build()?).await?;
+        if let Some(checkpointer) = &options.checkpointer {
+            checkpointer.commit(output).await?;
+        }
+
+        // Save the patchset and its mark to the state (and eventually the
+        // store).
+        state
+            .add_patchset(mark, branch, &patchset.time, file_revision_ids.into_iter())
+            .await;
+        if time != patchset.time {
+            state.set_commit_time(mark, &time).await;
+        }
+
+        tracing::info!(
+            branch = %branch_str,
+            mark = mark.as_usize(),
+            time = unix_seconds(&time),
+            "patchset committed"
+        );
+
+        // Clock skew is routine in CVS repositories, so each commit is only
+        // logged at debug level; the total is logged once all branches are
+        // sent.
+        if let Some(parent_time) = skewed_parent_time {
+            log::debug!(
+                "commit {} on {} at {} is earlier than its parent at {}{}",
+                mark,
+                branch_str,
+                unix_seconds(&patchset.time),
+                unix_seconds(&parent_time),
+                if options.normalize_times {
+                    "; adjusting to the parent time"
+                } else {
+                    ""
+                }
+            );
+            sent.clock_skews.push(ReportClockSkew {
+                branch: branch_str.to_string(),
+                mark: mark.as_usize(),
+                time: unix_seconds(&patchset.time),
+                parent_time: unix_seconds(&parent_time),
+                adjusted: options.normalize_times,
+            });
+        }
+
+        sent.marks.push(mark);
+        if let Some(diffstat) = diffstat {
+            sent.diffstats.insert(mark, diffstat);
+        }
+        committed = true;
+        from = Some(mark);
+        parent_time = Some(time);
+        dropped_content.clear();
+        dropped_ids.clear();
+    }
+
+    if !dropped_content.is_empty() {
+        log::warn!(
+            "the changes to {} file(s) in patchsets dropped at the end of {} have not been imported",
+            dropped_content.len(),
+            branch_str
+        );
+    }
+
+    // Set the HEAD of the branch in Git, unless nothing was committed on it and
+    // we've been told to leave such branches alone.
+    match (from, options.update_refs) {
+        (Some(head_mark), true) if committed || !options.skip_empty_branches => {
+            output.branch(&git_branch, head_mark).await?;
+        }
+        (Some(_), true) => {
+            log::info!(
+                "not updating {}, since none of its patchsets were committed",
+                git_branch
+            );
+        }
+        _ => {}
+    }
+
+    Ok(sent)
+}
+
+/// Builds the Git identity for a CVS author, which is usually a bare username,
+/// but may be a full `Name <email>` identity if it was set by a patchset hook.
+///
+/// Authors that can't be parsed, such as those that contain a stray `>`, have
+/// the offending characters removed rather than corrupting the commit.
+fn author_identity(author: &str, when: SystemTime) -> anyhow::Result<Identity> {
+    match Identity::parse(author, when) {
+        Ok(identity) => Ok(identity),
+        Err(git_fast_import::Error::InvalidIdentity(_)) => {
+            // The author was already recorded as a loss when it was observed,
+            // so there's no need to warn about it for every commit.
+            log::debug!("sanitizing invalid author {:?}", author);
+            Ok(Identity::sanitized(None, author, when)?)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Returns the file revision IDs in a patchset, after the given IDs left over
+/// from dropped patchsets.
+fn patchset_file_revision_ids(
+    dropped_ids: &[FileRevisionID],
+    patchset: &PatchSet<FileRevisionID>,
+) -> Vec<FileRevisionID> {
+    dropped_ids
+        .iter()
+        .chain(
+            patchset
+                .file_revision_iter()
+                .map(|(_path, ids)| ids)
+                .flatten(),
+        )
+        .copied()
+        .collect()
+}
+
+/// Returns true if committing the given file revisions on the branch wouldn't
+/// change anything, since they all delete files that don't exist on it.
+async fn is_no_op(
+    state: &Manager,
+    branch: &[u8],
+    file_revision_ids: &[FileRevisionID],
+) -> anyhow::Result<bool> {
+    let mut paths = Vec::new();
+    for id in file_revision_ids.iter() {
+        let revision = state.get_file_revision_by_id(*id).await?;
+        if revision.mark.is_some() {
+            return Ok(false);
+        }
+        paths.push(revision.key.path.clone());
+    }
+
+    // Only now that we know every revision is a deletion do we need the
+    // files on the branch, which are comparatively expensive to find.
+    let files = state.get_files_on_branch(branch).await?;
+    Ok(paths.iter().all(|path| match files.get(path) {
+        Some(revision) => revision.mark.is_none(),
+        None => true,
+    }))
+}
+
+/// Returns the path of each file in the given content, and whether the file
+/// exists as of its file revision.
+async fn file_states<'a, I>(state: &Manager, content: I) -> anyhow::Result<Vec<(PathBuf, bool)>>
+where
+    I: Iterator<Item = (&'a PathBuf, &'a FileRevisionID)>,
+{
+    let mut files = Vec::new();
+    for (path, id) in content {
+        let revision = state.get_file_revision_by_id(*id).await?;
+        files.push((path.clone(), revision.mark.is_some()));
+    }
+
+    Ok(files)
+}
+
+/// A branch whose tip in the Git repository isn't the commit it was last
+/// imported as.
+#[derive(Debug)]
+struct MovedBranch {
+    /// The name of the branch in Git.
+    name: String,
+    mark: Mark,
+    imported: String,
+    tip: String,
+}
+
+/// Returns the branches in the state whose tip in the Git repository isn't a
+/// commit that was imported on the branch, such as when commits were pushed to
+/// the branch in Git. Branches that have been deleted from the Git repository
+/// aren't included, since the import restores them.
+async fn moved_branches(
+    state: &Manager,
+    output_opt: &git_cvs_fast_import_process::Opt,
+    branch_renames: &BranchRenames,
+) -> anyhow::Result<Vec<MovedBranch>> {
+    // The fake commits for tags are recorded on branches in the state, but
+    // are only written to tags.
+    let tags: HashSet<Vec<u8>> = state
+        .get_tags()
+        .await
+        .iter()
+        .map(|tag| tag.into())
+        .collect();
+
+    let branches: Vec<Vec<u8>> = state
+        .get_branches()
+        .await
+        .into_iter()
+        .filter(|branch| !tags.contains(branch))
+        .collect();
+
+    // Two branches renamed to the same Git branch would look like they had
+    // moved, so that's checked first.
+    branch_renames.check(branches.iter())?;
+
+    let mut moved = Vec::new();
+    for branch in branches.into_iter() {
+        let mark: Mark = match state.get_last_patchset_mark_on_branch(&branch).await {
+            Some(mark) => mark.into(),
+            None => continue,
+        };
+
+        let name = branch_renames
+            .git_branch(std::str::from_utf8(&branch)?)
+            .into_owned();
+        let tip = match git_cvs_fast_import_process::resolve_commit(
+            output_opt,
+            &refs::commit_ref(&name, true),
+        ) {
+            Ok(tip) => tip,
+            Err(git_cvs_fast_import_process::Error::UnknownCommit(_)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        // A branch that's behind the commit it was last imported as, such as
+        // after an import with --no-update-refs, is simply fast forwarded.
+        let imported = state.get_sha_for_patchset_mark(&mark).await?;
+        if tip == imported {
+            continue;
+        }
+        let mut behind = false;
+        for earlier in state.get_patchset_marks_on_branch(&branch).await {
+            if state.get_sha_for_patchset_mark(&earlier.into()).await? == tip {
+                behind = true;
+                break;
+            }
+        }
+        if !behind {
+            moved.push(MovedBranch {
+                name,
+                mark,
+                imported,
+                tip,
+            });
+        }
+    }
+
+    Ok(moved)
+}
+
+/// Checks that no two branches, whether they were imported by this run or an
+/// earlier one, are imported as the same Git branch once they're renamed.
+async fn check_branch_renames(
+    state: &Manager,
+    result: &ObservationResult,
+    branch_renames: &BranchRenames,
+) -> anyhow::Result<()> {
+    // As in delete_obsolete_branches, the fake commits for tags are recorded
+    // on branches in the state, but aren't renamed.
+    let tags: HashSet<Vec<u8>> = state
+        .get_tags()
+        .await
+        .iter()
+        .map(|tag| tag.into())
+        .collect();
+    let mut branches: BTreeSet<Vec<u8>> = state
+        .get_branches()
+        .await
+        .into_iter()
+        .filter(|branch| !tags.contains(branch))
+        .collect();
+    branches.extend(result.seen_branch_iter().map(|branch| branch.to_vec()));
+
+    Ok(branch_renames.check(branches.iter())?)
+}
+
+/// Creates the branches that have no commits of their own at the newest commit
+/// on the branch they were made from that includes one of their branch points.
+///
+/// The branch is recorded in the state as sharing every commit up to that one,
+/// just as a branch with its own commits shares those before its first, so
+/// that later commits on it have the right parent.
+async fn create_empty_branches(
+    state: &Manager,
+    output: &Output,
+    result: &ObservationResult,
+    branch_filter: &BranchFilter,
+    options: &CommitOptions,
+) -> anyhow::Result<()> {
+    for (branch, branch_points) in result
+        .branch_point_iter()
+        .filter(|(branch, _branch_points)| branch_filter.contains(branch))
+    {
+        if state
+            .get_last_patchset_mark_on_branch(branch)
             .await
+            .is_some()
         {
-            from = Some(mark);
+            continue;
+        }
 
-            // Let's add this branch to the patchset.
-            state.add_branch_to_patchset_mark(mark, branch).await;
-        } else {
-            // Actually send the commit to git-fast-import and get the commit
-            // mark back.
-            let mark = output.commit(builder.build()?).await?;
+        // Files are almost always branched from the same branch, but if they
+        // aren't, the first in name order is used.
+        let parents: BTreeSet<&Vec<u8>> =
+            branch_points.iter().map(|(parent, _id)| parent).collect();
+        let parent = match parents.iter().next() {
+            Some(parent) => *parent,
+            None => continue,
+        };
+        if parents.len() > 1 {
+            log::warn!(
+                "{} was branched from more than one branch; creating it from {}",
+                String::from_utf8_lossy(branch),
+                String::from_utf8_lossy(parent)
+            );
+        }
+        let ids: HashSet<FileRevisionID> = branch_points
+            .iter()
+            .filter(|(other, _id)| other == parent)
+            .map(|(_parent, id)| *id)
+            .collect();
+
+        let marks = state.get_patchset_marks_on_branch(parent).await;
+        let mut branch_point = None;
+        for (i, mark) in marks.iter().enumerate().rev() {
+            let patchset = state.get_patchset_from_mark(&(*mark).into()).await?;
+            if patchset.file_revisions.iter().any(|id| ids.contains(id)) {
+                branch_point = Some(i);
+                break;
+            }
+        }
+        let branch_point = match branch_point {
+            Some(i) => i,
+            None => {
+                log::warn!(
+                    "cannot find the commit that {} was branched from",
+                    String::from_utf8_lossy(branch)
+                );
+                continue;
+            }
+        };
 
-            // Save the patchset and its mark to the state (and eventually the
-            // store).
+        for mark in marks[..=branch_point].iter() {
             state
-                .add_patchset(mark, branch, &patchset.time, file_revision_ids.into_iter())
+                .add_branch_to_patchset_mark((*mark).into(), branch)
                 .await;
+        }
+
+        let git_branch = options
+            .branch_renames
+            .git_branch(std::str::from_utf8(branch)?);
+        log::info!("creating empty branch {}", git_branch);
+        if options.update_refs {
+            output
+                .branch(&git_branch, marks[branch_point].into())
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Deletes the branches in the state that weren't seen in the CVS repository,
+/// or that are excluded by the branch filter.
+///
+/// Branches remain in the state once deleted, so that they're restored if they
+/// reappear or are included again on a later run, even if they don't have any
+/// new patchsets, unless `skip_empty_branches` is set.
+async fn delete_obsolete_branches(
+    state: &Manager,
+    output: &Output,
+    result: &ObservationResult,
+    branch_filter: &BranchFilter,
+    branch_renames: &BranchRenames,
+    skip_empty_branches: bool,
+) -> anyhow::Result<()> {
+    // The fake commits for tags are also recorded on branches in the state, so
+    // we need to make sure we don't delete those.
+    let tags: HashSet<Vec<u8>> = state
+        .get_tags()
+        .await
+        .iter()
+        .map(|tag| tag.into())
+        .collect();
 
-            from = Some(mark);
+    for branch in state.get_branches().await {
+        if tags.contains(&branch) {
+            continue;
+        }
+
+        let branch_str = std::str::from_utf8(&branch)?;
+        let git_branch = branch_renames.git_branch(branch_str);
+        if result.has_seen_branch(&branch) && branch_filter.contains(&branch) {
+            // Branches with new patchsets have already been updated.
+            if !skip_empty_branches
+                && result
+                    .branch_iter()
+                    .all(|(name, _patchsets)| name != &branch)
+            {
+                if let Some(mark) = state.get_last_patchset_mark_on_branch(&branch).await {
+                    output.branch(&git_branch, mark.into()).await?;
+                }
+            }
+        } else {
+            log::info!("deleting obsolete branch {}", git_branch);
+            output.delete_branch(&git_branch).await?;
         }
     }
 
-    // Set the HEAD of the branch in Git.
-    if let Some(head_mark) = from {
-        output.branch(branch_str, head_mark).await?;
+    Ok(())
+}
+
+/// Deletes the tags that were imported by earlier runs, but no longer have any
+/// file revisions, along with the branches of their fake commits.
+async fn delete_obsolete_tags(state: &Manager, output: &Output) -> anyhow::Result<()> {
+    for tag in state.get_tags_with_marks().await {
+        if state
+            .get_file_revisions_for_tag(&tag)
+            .await
+            .iter()
+            .is_some()
+        {
+            continue;
+        }
+
+        let tag_str = String::from_utf8_lossy(&tag);
+        log::info!("deleting obsolete tag {}", tag_str);
+        output.delete_tag(&tag_str).await?;
+        output.delete_branch(&format!("tags/{}", tag_str)).await?;
+        state.remove_tag(&tag).await;
     }
 
     Ok(())
 }
 
+/// Returns the ChangeLog entries added by the given file revisions, if the
+/// patchset's own message is trivial and any of them added an entry.
+async fn changelog_message(
+    state: &Manager,
+    options: &CommitOptions,
+    patchset: &PatchSet<FileRevisionID>,
+    file_revision_ids: &[FileRevisionID],
+) -> anyhow::Result<Option<String>> {
+    let changelogs = match &options.changelogs {
+        Some(changelogs) if is_trivial(&patchset.message) => changelogs,
+        _ => return Ok(None),
+    };
+
+    let mut entries = Vec::new();
+    for id in file_revision_ids.iter() {
+        let key = &state.get_file_revision_by_id(*id).await?.key;
+        if let Some(entry) = changelogs.get(&key.path, &key.revision) {
+            entries.push(entry);
+        }
+    }
+
+    Ok(if entries.is_empty() {
+        None
+    } else {
+        Some(entries.join("\n"))
+    })
+}
+
+/// Appends the given trailers to the message, such as a `CVS-Resurrected`
+/// trailer for each path that was re-added after previously being deleted in
+/// CVS.
+fn trailer_message(message: &str, trailers: &[(&str, String)]) -> String {
+    if trailers.is_empty() {
+        return message.to_string();
+    }
+
+    let mut message = message.trim_end().to_string();
+    message.push_str("\n\n");
+    for (key, value) in trailers {
+        message.push_str(&format!("{}: {}\n", key, value));
+    }
+
+    message
+}
+
 /// Send tags to git-fast-import.
-async fn send_tags(state: &Manager, output: &Output, identity: Identity) -> anyhow::Result<()> {
+///
+/// Returns each tag that needed a new fake commit, along with the commit's mark
+/// and parent.
+#[allow(clippy::too_many_arguments)]
+async fn send_tags(
+    state: &Manager,
+    output: &Output,
+    identity: Identity,
+    orphans: tag::OrphanTags,
+    head_branch: &str,
+    rewrite_root: Option<&RewriteRoot>,
+    import_trailer: Option<&str>,
+    update_refs: bool,
+) -> anyhow::Result<Vec<(Vec<u8>, Mark, tag::Parent)>> {
     // We have to operate on a clone of the tag names, as keeping the iterator
-    // alive would keep a read lock on the tag state.
-    let tags: Vec<Vec<u8>> = state
+    // alive would keep a read lock on the tag state. We also sort them, since
+    // the state doesn't store them in a stable order.
+    let mut tags: Vec<Vec<u8>> = state
         .get_tags()
         .await
         .iter()
         .map(|tag| tag.into())
         .collect();
+    tags.sort();
 
-    let processor = tag::Processor::new(state, output, identity);
-    for tag in tags.iter() {
-        processor.process(tag).await?;
+    let processor = tag::Processor::new(
+        state,
+        output,
+        identity,
+        orphans,
+        head_branch,
+        rewrite_root.cloned(),
+        import_trailer,
+        update_refs,
+    );
+    let mut sent = Vec::new();
+    for tag in tags.into_iter() {
+        if let Some((mark, parent)) = processor.process(&tag).await? {
+            tracing::info!(
+                tag = %String::from_utf8_lossy(&tag),
+                mark = mark.as_usize(),
+                "tag committed"
+            );
+            sent.push((tag, mark, parent));
+        }
     }
 
-    Ok(())
+    Ok(sent)
 }
 
 /// Save the created marks back into the database.
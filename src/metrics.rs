@@ -0,0 +1,237 @@
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
+
+use crate::report::unix_seconds;
+
+/// How long to wait before accepting another metrics connection after an error.
+#[cfg(feature = "metrics")]
+const ACCEPT_BACKOFF: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Import statistics, which can be served in the Prometheus text format when
+/// built with the `metrics` feature.
+///
+/// Like the other registries, this is cheap to clone, and all clones update
+/// the same statistics.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Metrics {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    files_parsed: AtomicU64,
+    file_errors: AtomicU64,
+    commits: AtomicU64,
+    last_commit_time: AtomicU64,
+    fast_import_bytes: AtomicU64,
+    run_start_time: AtomicU64,
+    run_duration: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let metrics = Self::default();
+        metrics
+            .inner
+            .run_start_time
+            .store(unix_seconds(&SystemTime::now()), Ordering::Relaxed);
+        metrics
+    }
+
+    pub fn file_parsed(&self) {
+        self.inner.files_parsed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn file_error(&self) {
+        self.inner.file_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records commits sent to Git, along with the time of the newest CVS
+    /// commit among them.
+    pub fn commits(&self, count: usize, newest: Option<&SystemTime>) {
+        self.inner
+            .commits
+            .fetch_add(count as u64, Ordering::Relaxed);
+        if let Some(newest) = newest {
+            self.inner
+                .last_commit_time
+                .fetch_max(unix_seconds(newest), Ordering::Relaxed);
+        }
+    }
+
+    /// Records the number of bytes written to `git fast-import` so far.
+    pub fn fast_import_bytes(&self, total: u64) {
+        self.inner
+            .fast_import_bytes
+            .fetch_max(total, Ordering::Relaxed);
+    }
+
+    /// Records that the run is complete.
+    pub fn finish(&self) {
+        let start = self.inner.run_start_time.load(Ordering::Relaxed);
+        self.inner.run_duration.store(
+            unix_seconds(&SystemTime::now()).saturating_sub(start),
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format.
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, kind, help, value) in [
+            (
+                "files_parsed_total",
+                "counter",
+                "RCS files parsed",
+                &self.inner.files_parsed,
+            ),
+            (
+                "file_errors_total",
+                "counter",
+                "RCS files that could not be imported",
+                &self.inner.file_errors,
+            ),
+            (
+                "commits_total",
+                "counter",
+                "commits sent to Git",
+                &self.inner.commits,
+            ),
+            (
+                "last_commit_timestamp_seconds",
+                "gauge",
+                "time of the newest CVS commit imported; subtract from time() for the lag behind CVS",
+                &self.inner.last_commit_time,
+            ),
+            (
+                "fast_import_bytes_total",
+                "counter",
+                "bytes written to git fast-import",
+                &self.inner.fast_import_bytes,
+            ),
+            (
+                "run_start_timestamp_seconds",
+                "gauge",
+                "time the current run started",
+                &self.inner.run_start_time,
+            ),
+            (
+                "last_run_duration_seconds",
+                "gauge",
+                "duration of the last completed run",
+                &self.inner.run_duration,
+            ),
+        ]
+        .iter()
+        {
+            // Writing to a String can't fail.
+            let _ = writeln!(out, "# HELP git_cvs_fast_import_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE git_cvs_fast_import_{} {}", name, kind);
+            let _ = writeln!(
+                out,
+                "git_cvs_fast_import_{} {}",
+                name,
+                value.load(Ordering::Relaxed)
+            );
+        }
+
+        out
+    }
+
+    /// Serves the metrics over HTTP on the given address until the process
+    /// exits. Every request receives the metrics, regardless of its path.
+    #[cfg(feature = "metrics")]
+    pub async fn serve(&self, address: &str) -> anyhow::Result<()> {
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::TcpListener,
+        };
+
+        let listener = TcpListener::bind(address).await?;
+        log::info!("serving metrics on {}", listener.local_addr()?);
+
+        let metrics = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, peer) = match listener.accept().await {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        // Errors such as running out of file descriptors
+                        // usually persist for a while, so we back off rather
+                        // than spinning and flooding the log.
+                        log::warn!("error accepting metrics connection: {}", e);
+                        tokio::time::sleep(ACCEPT_BACKOFF).await;
+                        continue;
+                    }
+                };
+
+                let body = metrics.render();
+                tokio::spawn(async move {
+                    // We don't care what was requested, but we need to read
+                    // the request before responding.
+                    let mut buf = [0; 1024];
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    );
+                    if let Err(e) = async {
+                        let _ = stream.read(&mut buf).await?;
+                        stream.write_all(response.as_bytes()).await?;
+                        stream.shutdown().await
+                    }
+                    .await
+                    {
+                        log::debug!("error serving metrics to {}: {}", peer, e);
+                    }
+                });
+            }
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.file_parsed();
+        metrics.file_parsed();
+        metrics.file_error();
+        metrics.commits(3, Some(&(UNIX_EPOCH + Duration::from_secs(2000))));
+        metrics.commits(1, Some(&(UNIX_EPOCH + Duration::from_secs(1000))));
+        metrics.fast_import_bytes(4096);
+
+        let rendered = metrics.render();
+        for line in [
+            "git_cvs_fast_import_files_parsed_total 2",
+            "git_cvs_fast_import_file_errors_total 1",
+            "git_cvs_fast_import_commits_total 4",
+            "git_cvs_fast_import_last_commit_timestamp_seconds 2000",
+            "git_cvs_fast_import_fast_import_bytes_total 4096",
+            "# TYPE git_cvs_fast_import_commits_total counter",
+        ]
+        .iter()
+        {
+            assert!(
+                rendered.lines().any(|have| have == *line),
+                "{:?} not found in {}",
+                line,
+                rendered
+            );
+        }
+    }
+}
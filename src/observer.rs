@@ -1,13 +1,16 @@
 use std::{
-    borrow::Borrow,
-    collections::HashMap,
+    borrow::{Borrow, Cow},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    mem,
+    os::unix::prelude::OsStrExt,
     path::{Path, PathBuf},
+    sync::{Arc, Mutex},
     time::{Duration, SystemTime},
 };
 
-use comma_v::{Delta, DeltaText, Num, Sym};
-use git_cvs_fast_import_state::{FileRevisionID, Manager};
-use git_fast_import::Mark;
+use comma_v::{Delta, Num, Sym};
+use git_cvs_fast_import_state::{FileRevisionID, FileRevisionKey, Manager};
+use git_fast_import::{Identity, Mark};
 use patchset::{Detector, PatchSet};
 use thiserror::Error;
 use tokio::{
@@ -18,28 +21,50 @@ use tokio::{
     task::{self, JoinHandle},
 };
 
+use crate::{
+    losses::{LossKind, LossRegistry},
+    squash::SquashRule,
+};
+
+/// The message of the revision that stands in for the history of a file before
+/// it was truncated by `--max-revisions-per-file`.
+const TRUNCATED_MESSAGE: &str =
+    "History truncated: earlier revisions were squashed into this commit\n";
+
 /// An `Observer` receives a stream of file revisions and hands them to both the
 /// patchset detector and the state manager.
 #[derive(Clone, Debug)]
 pub(crate) struct Observer {
     file_revision_tx: UnboundedSender<Message>,
     state: Manager,
+    seen_branches: Arc<Mutex<BTreeSet<Vec<u8>>>>,
+    seen_tags: Option<Arc<Mutex<SeenTags>>>,
+    branch_points: Arc<Mutex<BranchPoints>>,
+
+    /// The error that stopped the observer worker, if it has stopped.
+    failure: Arc<Mutex<Option<String>>>,
 }
 
+/// The file revisions on each tag, as defined in the files observed in this
+/// run.
+pub(crate) type SeenTags = HashMap<Vec<u8>, BTreeSet<FileRevisionID>>;
+
+/// The branch point of each branch that has no revisions of its own in at
+/// least one file, as the branch it was made from and the file revision it was
+/// made at in each such file.
+pub(crate) type BranchPoints = BTreeMap<Vec<u8>, BTreeSet<(Vec<u8>, FileRevisionID)>>;
+
 /// A message sent to the observer worker.
 ///
 /// This is public because it's exposed within the error type, but otherwise is
 /// an implementation detail.
 #[derive(Debug)]
 pub(crate) struct Message {
-    file_revision: FileRevision,
-    id_tx: oneshot::Sender<FileRevisionID>,
+    file_revisions: Vec<FileRevision>,
+    ids_tx: oneshot::Sender<Vec<FileRevisionID>>,
 }
 
-/// A file revision sent to an observer worker.
-///
-/// This is public because it's exposed within the error type, but otherwise is
-/// an implementation detail.
+/// A file revision to be sent to an observer worker.
 #[derive(Debug)]
 pub(crate) struct FileRevision {
     path: PathBuf,
@@ -49,129 +74,393 @@ pub(crate) struct FileRevision {
     author: String,
     message: String,
     time: SystemTime,
+    resurrected: bool,
+
+    /// The fields that weren't valid UTF-8, and were converted lossily.
+    lossy: Vec<&'static str>,
+}
+
+impl FileRevision {
+    /// Builds a file revision from its RCS delta and log message.
+    ///
+    /// `resurrected` should be true if the revision re-adds a file that was
+    /// deleted in the revision it was derived from.
+    pub(crate) fn new<I>(
+        path: &Path,
+        revision: &Num,
+        branches: I,
+        mark: Option<Mark>,
+        delta: &Delta,
+        log: &[u8],
+        resurrected: bool,
+    ) -> Self
+    where
+        I: Iterator,
+        I::Item: Borrow<Sym>,
+    {
+        let mut lossy = Vec::new();
+        let mut convert = |field, bytes| match String::from_utf8_lossy(bytes) {
+            Cow::Borrowed(s) => s.to_string(),
+            Cow::Owned(s) => {
+                lossy.push(field);
+                s
+            }
+        };
+        let author = convert("author", &delta.author);
+        let message = convert("log message", log);
+
+        Self {
+            path: path.to_path_buf(),
+            revision: revision.to_string(),
+            mark,
+            branches: branches.map(|branch| branch.borrow().to_vec()).collect(),
+            author,
+            message,
+            time: delta.date,
+            resurrected,
+            lossy,
+        }
+    }
+
+    /// Replaces the message of the revision with one noting that the history
+    /// before it was truncated. The message is the same for every file, so
+    /// that truncated files are squashed into as few commits as possible.
+    pub(crate) fn truncate_history(&mut self) {
+        self.message = TRUNCATED_MESSAGE.to_string();
+        self.lossy.retain(|field| *field != "log message");
+    }
 }
 
 impl Observer {
     /// Constructs a new file revision observer, along with a collector that can
     /// be awaited once all observers have been dropped to receive the final
     /// result of the observations.
-    pub(crate) fn new(delta: Duration, state: Manager) -> (Self, Collector) {
+    ///
+    /// Patchsets are detected using `delta`, unless the branch has its own
+    /// delta in `branch_deltas`. If `file_commits` is true, file revisions
+    /// aren't grouped at all, and each becomes a patchset of its own. Once
+    /// detection is complete, each of the `squash_rules` is applied in turn to
+    /// the patchsets on its branch.
+    ///
+    /// If `seen_tags` is true, the file revisions on each tag observed in this
+    /// run are also collected, so that tags that have moved or been deleted
+    /// can be found once the observations are complete.
+    ///
+    /// Any lossy conversions in the observed file revisions are recorded in
+    /// `losses`.
+    pub(crate) fn new(
+        delta: Duration,
+        branch_deltas: HashMap<Vec<u8>, Duration>,
+        file_commits: bool,
+        squash_rules: Vec<SquashRule>,
+        seen_tags: bool,
+        state: Manager,
+        losses: LossRegistry,
+    ) -> (Self, Collector) {
         let (file_revision_tx, mut file_revision_rx) = mpsc::unbounded_channel::<Message>();
 
+        let seen_branches = Arc::new(Mutex::new(BTreeSet::new()));
+        let seen_tags = seen_tags.then(|| Arc::new(Mutex::new(HashMap::new())));
+        let branch_points = Arc::new(Mutex::new(BTreeMap::new()));
+        let failure = Arc::new(Mutex::new(None));
         let task_state = state.clone();
+        let task_failure = failure.clone();
         let join_handle = task::spawn(async move {
             let mut detectors = HashMap::new();
+            let mut resurrections = HashSet::new();
+
+            // The number of revisions whose times were clamped, which is only
+            // summarised once every revision has been observed, since clock
+            // skew tends to affect many revisions at once.
+            let mut clamped = 0;
 
             while let Some(msg) = file_revision_rx.recv().await {
-                let id = task_state
-                    .add_file_revision(
-                        msg.file_revision.path.as_path(),
-                        &msg.file_revision.revision,
-                        msg.file_revision.mark,
-                        msg.file_revision.branches.iter(),
-                        &msg.file_revision.author,
-                        &msg.file_revision.message,
-                        &msg.file_revision.time,
-                    )
-                    .await?;
-
-                for branch in msg.file_revision.branches.iter() {
-                    let detector = detectors
-                        .entry(branch.clone())
-                        .or_insert_with(|| Detector::new(delta));
-
-                    detector.add_file_commit(
-                        msg.file_revision.path.clone(),
-                        id,
-                        msg.file_revision.author.clone(),
-                        msg.file_revision.message.clone(),
-                        msg.file_revision.time,
-                    );
+                let ids = task_state
+                    .add_file_revisions(msg.file_revisions.iter().map(|file_revision| {
+                        git_cvs_fast_import_state::FileRevision {
+                            key: FileRevisionKey {
+                                path: file_revision.path.clone(),
+                                revision: file_revision.revision.clone(),
+                            },
+                            mark: file_revision.mark.map(|mark| mark.into()),
+                            branches: file_revision.branches.clone(),
+                            author: file_revision.author.clone(),
+                            message: file_revision.message.clone(),
+                            time: file_revision.time,
+                        }
+                    }))
+                    .await
+                    .map_err(|(index, source)| {
+                        let file_revision = &msg.file_revisions[index];
+                        Error::FileRevision {
+                            path: file_revision.path.clone(),
+                            revision: file_revision.revision.clone(),
+                            source,
+                        }
+                    });
+
+                let ids = match ids {
+                    Ok(ids) => ids,
+                    Err(e) => {
+                        // The discovery workers only find out that the observer
+                        // has stopped when they next send it something, so the
+                        // cause is logged now, and kept for them to report.
+                        log::error!("{}", e);
+                        *task_failure.lock().unwrap() = Some(e.to_string());
+                        return Err(e);
+                    }
+                };
+
+                // Each file's revisions are sent together, in revision order,
+                // but a clock jump on the CVS server can leave a revision
+                // timestamped earlier than the revision it was derived from.
+                // If the detector saw the real times, a deletion could be
+                // committed before the revision it deletes, leaving the file in
+                // the branch head, so times are clamped to keep each branch in
+                // revision order.
+                let mut latest: HashMap<(Vec<u8>, PathBuf), SystemTime> = HashMap::new();
+
+                for (file_revision, id) in msg.file_revisions.into_iter().zip(ids.iter()) {
+                    if file_revision.resurrected {
+                        resurrections.insert(*id);
+                    }
+
+                    for field in file_revision.lossy.iter() {
+                        losses.record(
+                            &file_revision.path,
+                            LossKind::LossyUtf8,
+                            format!(
+                                "revision {} has a {} that isn't valid UTF-8",
+                                file_revision.revision, field
+                            ),
+                        );
+                    }
+
+                    if Identity::parse(&file_revision.author, file_revision.time).is_err() {
+                        losses.record(
+                            &file_revision.path,
+                            LossKind::SanitizedAuthor,
+                            format!(
+                                "revision {} has an author that can't appear in a Git identity: {:?}",
+                                file_revision.revision, file_revision.author
+                            ),
+                        );
+                    }
+
+                    // The path and revision are unique to each file revision,
+                    // so using them as the commit key stops the detector from
+                    // linking file revisions together.
+                    let key = if file_commits {
+                        let mut key = file_revision.path.as_os_str().as_bytes().to_vec();
+                        key.push(0);
+                        key.extend_from_slice(file_revision.revision.as_bytes());
+                        Some(key)
+                    } else {
+                        None
+                    };
+
+                    for branch in file_revision.branches.iter() {
+                        let key_time = (branch.clone(), file_revision.path.clone());
+                        let time = match latest.get(&key_time) {
+                            Some(previous) if *previous > file_revision.time => {
+                                log::debug!(
+                                    "{} revision {} is earlier than the revision before it on {}; committing it after that revision",
+                                    file_revision.path.display(),
+                                    file_revision.revision,
+                                    String::from_utf8_lossy(branch)
+                                );
+                                clamped += 1;
+                                *previous
+                            }
+                            _ => file_revision.time,
+                        };
+                        latest.insert(key_time, time);
+
+                        let detector = detectors.entry(branch.clone()).or_insert_with(|| {
+                            Detector::new(branch_deltas.get(branch).copied().unwrap_or(delta))
+                        });
+
+                        detector.add_file_commit(
+                            file_revision.path.clone(),
+                            *id,
+                            file_revision.author.clone(),
+                            file_revision.message.clone(),
+                            time,
+                            key.clone(),
+                        );
+                    }
                 }
 
-                msg.id_tx
-                    .send(id)
-                    .expect("cannot return file ID back to caller")
+                msg.ids_tx
+                    .send(ids)
+                    .expect("cannot return file IDs back to caller")
+            }
+
+            if clamped > 0 {
+                log::warn!(
+                    "{} revision(s) were earlier than the revision before them on their branch, and will be committed after it",
+                    clamped
+                );
             }
 
-            Ok::<HashMap<Vec<u8>, Detector<FileRevisionID>>, Error>(detectors)
+            Ok::<Observations, Error>((detectors, resurrections))
         });
 
         (
             Self {
                 file_revision_tx,
                 state,
+                seen_branches: seen_branches.clone(),
+                seen_tags: seen_tags.clone(),
+                branch_points: branch_points.clone(),
+                failure,
+            },
+            Collector {
+                join_handle,
+                seen_branches,
+                seen_tags,
+                branch_points,
+                squash_rules,
             },
-            Collector { join_handle },
         )
     }
 
-    /// Observe a single file revision, and return its ID as stored in the state
-    /// manager.
-    pub(crate) async fn file_revision<I>(
+    /// Observe a batch of file revisions, typically all of the new revisions
+    /// within a single file, and return their IDs as stored in the state
+    /// manager in the same order.
+    ///
+    /// Batching avoids a channel round trip for each revision.
+    pub(crate) async fn file_revisions(
         &self,
-        path: &Path,
-        revision: &Num,
-        branches: I,
-        mark: Option<Mark>,
-        delta: &Delta,
-        text: &DeltaText,
-    ) -> Result<FileRevisionID, Error>
+        file_revisions: Vec<FileRevision>,
+    ) -> Result<Vec<FileRevisionID>, Error> {
+        if file_revisions.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        tracing::debug!(count = file_revisions.len(), "file revisions observed");
+        let (tx, rx) = oneshot::channel();
+        if let Err(e) = self.file_revision_tx.send(Message {
+            file_revisions,
+            ids_tx: tx,
+        }) {
+            return Err(self.stopped().unwrap_or_else(|| e.into()));
+        }
+
+        match rx.await {
+            Ok(ids) => Ok(ids),
+            Err(e) => Err(self.stopped().unwrap_or_else(|| e.into())),
+        }
+    }
+
+    /// Returns the error that stopped the observer worker, if it stopped
+    /// because of an error.
+    fn stopped(&self) -> Option<Error> {
+        self.failure.lock().unwrap().clone().map(Error::Stopped)
+    }
+
+    /// Observe the branches defined in a file, whether or not they have any new
+    /// revisions.
+    pub(crate) fn branches<I>(&self, branches: I)
     where
         I: Iterator,
         I::Item: Borrow<Sym>,
     {
-        let (tx, rx) = oneshot::channel();
-
-        self.file_revision_tx.send(Message {
-            file_revision: FileRevision {
-                path: path.to_path_buf(),
-                revision: revision.to_string(),
-                mark,
-                branches: branches.map(|branch| branch.borrow().to_vec()).collect(),
-                author: String::from_utf8_lossy(&delta.author).into_owned(),
-                message: String::from_utf8_lossy(&text.log).into_owned(),
-                time: delta.date,
-            },
-            id_tx: tx,
-        })?;
+        self.seen_branches
+            .lock()
+            .unwrap()
+            .extend(branches.map(|branch| branch.borrow().to_vec()));
+    }
 
-        Ok(rx.await?)
+    /// Observe the branch point of a branch that has no revisions of its own
+    /// in a file: `parent` is the branch it was made from, and
+    /// `file_revision_id` is the revision of the file it was made at.
+    pub(crate) fn branch_point(
+        &self,
+        branch: &Sym,
+        parent: &Sym,
+        file_revision_id: FileRevisionID,
+    ) {
+        self.branch_points
+            .lock()
+            .unwrap()
+            .entry(branch.to_vec())
+            .or_default()
+            .insert((parent.to_vec(), file_revision_id));
     }
 
     /// Observe a single file revision tag.
     pub(crate) async fn tag(&self, tag: &Sym, file_revision_id: FileRevisionID) {
+        if let Some(seen_tags) = &self.seen_tags {
+            seen_tags
+                .lock()
+                .unwrap()
+                .entry(tag.to_vec())
+                .or_default()
+                .insert(file_revision_id);
+        }
         self.state.add_tag(tag, file_revision_id).await;
     }
 }
 
 type BranchDetectorHashMap = HashMap<Vec<u8>, Detector<FileRevisionID>>;
+type Observations = (BranchDetectorHashMap, HashSet<FileRevisionID>);
 
 /// The `Collector` is used to wait for all file revisions to be observed, and
 /// then can be used to access the observation result.
 #[derive(Debug)]
 pub(crate) struct Collector {
-    join_handle: JoinHandle<Result<BranchDetectorHashMap, Error>>,
+    join_handle: JoinHandle<Result<Observations, Error>>,
+    seen_branches: Arc<Mutex<BTreeSet<Vec<u8>>>>,
+    seen_tags: Option<Arc<Mutex<SeenTags>>>,
+    branch_points: Arc<Mutex<BranchPoints>>,
+    squash_rules: Vec<SquashRule>,
 }
 
 /// An object that can be joined to wait for the results of the [`Observer`].
 impl Collector {
     /// Waits for the observations to be complete, the results their results.
     pub(crate) async fn join(self) -> Result<ObservationResult, Error> {
+        let (detectors, resurrections) = self.join_handle.await??;
+
+        let mut branches: BTreeMap<Vec<u8>, Vec<PatchSet<FileRevisionID>>> = detectors
+            .into_iter()
+            .map(|(branch, detector)| (branch, detector.into_patchset_iter().collect()))
+            .collect();
+        for rule in self.squash_rules.iter() {
+            if let Some(patchsets) = branches.get_mut(&rule.branch) {
+                let before = patchsets.len();
+                *patchsets = rule.apply(mem::take(patchsets));
+                log::debug!(
+                    "squash rule for {} merged {} patchset(s) into {}",
+                    String::from_utf8_lossy(&rule.branch),
+                    before,
+                    patchsets.len()
+                );
+            }
+        }
+
         Ok(ObservationResult {
-            branches: self
-                .join_handle
-                .await??
-                .into_iter()
-                .map(|(branch, detector)| (branch, detector.into_patchset_iter().collect()))
-                .collect(),
+            branches,
+            resurrections,
+            seen_branches: mem::take(&mut *self.seen_branches.lock().unwrap()),
+            seen_tags: self
+                .seen_tags
+                .map(|seen_tags| mem::take(&mut *seen_tags.lock().unwrap())),
+            branch_points: mem::take(&mut *self.branch_points.lock().unwrap()),
         })
     }
 }
 
 /// The result of observing file revisions and tags with [`Observer`].
+///
+/// Branches are kept in name order so that the import is deterministic.
 pub(crate) struct ObservationResult {
-    branches: HashMap<Vec<u8>, Vec<PatchSet<FileRevisionID>>>,
+    branches: BTreeMap<Vec<u8>, Vec<PatchSet<FileRevisionID>>>,
+    resurrections: HashSet<FileRevisionID>,
+    seen_branches: BTreeSet<Vec<u8>>,
+    seen_tags: Option<SeenTags>,
+    branch_points: BranchPoints,
 }
 
 impl ObservationResult {
@@ -180,6 +469,37 @@ impl ObservationResult {
     ) -> impl Iterator<Item = (&Vec<u8>, &Vec<PatchSet<FileRevisionID>>)> {
         self.branches.iter()
     }
+
+    /// Iterates over the branches defined in any observed file, in name order.
+    pub(crate) fn seen_branch_iter(&self) -> impl Iterator<Item = &Vec<u8>> {
+        self.seen_branches.iter()
+    }
+
+    /// Returns true if the branch is defined in any observed file, even if it
+    /// has no new patchsets.
+    pub(crate) fn has_seen_branch(&self, branch: &[u8]) -> bool {
+        self.seen_branches.contains(branch)
+    }
+
+    /// Iterates over the branch points of branches that have no revisions of
+    /// their own in at least one observed file, in name order.
+    pub(crate) fn branch_point_iter(
+        &self,
+    ) -> impl Iterator<Item = (&Vec<u8>, &BTreeSet<(Vec<u8>, FileRevisionID)>)> {
+        self.branch_points.iter()
+    }
+
+    /// Returns the file revisions on each tag observed in this run, if they
+    /// were collected.
+    pub(crate) fn seen_tags(&self) -> Option<&SeenTags> {
+        self.seen_tags.as_ref()
+    }
+
+    /// Returns true if the file revision re-added a file that had previously
+    /// been deleted.
+    pub(crate) fn is_resurrection(&self, id: FileRevisionID) -> bool {
+        self.resurrections.contains(&id)
+    }
 }
 
 /// Errors that can be returned when observing.
@@ -196,4 +516,14 @@ pub(crate) enum Error {
 
     #[error(transparent)]
     State(#[from] git_cvs_fast_import_state::Error),
+
+    #[error("cannot record {} revision {revision}: {source}", .path.display())]
+    FileRevision {
+        path: PathBuf,
+        revision: String,
+        source: git_cvs_fast_import_state::Error,
+    },
+
+    #[error("file revisions can no longer be observed: {0}")]
+    Stopped(String),
 }
@@ -0,0 +1,61 @@
+//! Support for `--idle-priority`, which lowers the CPU and I/O priority of the
+//! import so that a mirror running on a shared server, such as the NFS server
+//! hosting the CVSROOT, only uses it when nothing else needs it.
+
+use std::io;
+
+/// The nice value of the lowest CPU priority.
+const IDLE_NICE: libc::c_int = 19;
+
+/// Lowers the CPU and I/O priority of every thread in the process to idle.
+///
+/// On Linux, both priorities belong to each thread rather than the process, so
+/// each existing thread is lowered in turn. Threads and processes started
+/// afterwards, such as the runtime's blocking threads and `git fast-import`,
+/// inherit the priority of the thread that starts them.
+#[cfg(target_os = "linux")]
+pub(crate) fn idle() -> io::Result<()> {
+    // These aren't exposed by libc, but are part of the kernel ABI: see
+    // ioprio_set(2).
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+    const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+    for entry in std::fs::read_dir("/proc/self/task")? {
+        let tid: libc::id_t = match entry?.file_name().to_str().map(str::parse) {
+            Some(Ok(tid)) => tid,
+            _ => continue,
+        };
+
+        // A thread may exit while we're iterating, which is fine.
+        let check = |result: libc::c_long| match result {
+            -1 => match io::Error::last_os_error() {
+                e if e.raw_os_error() == Some(libc::ESRCH) => Ok(()),
+                e => Err(e),
+            },
+            _ => Ok(()),
+        };
+        check(unsafe { libc::setpriority(libc::PRIO_PROCESS, tid, IDLE_NICE) }.into())?;
+        check(unsafe {
+            libc::syscall(
+                libc::SYS_ioprio_set,
+                IOPRIO_WHO_PROCESS,
+                tid,
+                IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+            )
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Lowers the CPU priority of the process to idle. Other platforms don't have
+/// a portable way to lower the I/O priority, so it's left alone.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn idle() -> io::Result<()> {
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, IDLE_NICE) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
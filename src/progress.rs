@@ -0,0 +1,103 @@
+//! Periodic logging of how much of the fast-import stream has been written,
+//! and how quickly, while the import is running.
+//!
+//! This helps to tell what an import is waiting on: a low rate while files are
+//! being parsed means that the import is limited by applying RCS deltas,
+//! whereas a low rate once patchsets are being sent means that it's limited by
+//! the pipe, or by Git writing packs.
+
+use std::time::{Duration, Instant};
+
+use git_cvs_fast_import_process::ByteCount;
+use tokio::{
+    task::JoinHandle,
+    time::{self, MissedTickBehavior},
+};
+
+use crate::metrics::Metrics;
+
+/// Logs the size of the fast-import stream at a regular interval until it's
+/// finished.
+#[derive(Debug)]
+pub(crate) struct Progress {
+    bytes: ByteCount,
+    metrics: Metrics,
+    started: Instant,
+    handle: JoinHandle<()>,
+}
+
+impl Progress {
+    /// Starts logging the given count every interval.
+    pub(crate) fn spawn(bytes: ByteCount, metrics: Metrics, every: Duration) -> Self {
+        let started = Instant::now();
+        let handle = tokio::spawn({
+            let bytes = bytes.clone();
+            let metrics = metrics.clone();
+            async move {
+                let mut interval = time::interval_at((started + every).into(), every);
+                interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                let mut last = (0, started);
+                loop {
+                    interval.tick().await;
+                    let now = Instant::now();
+                    let total = bytes.get();
+                    metrics.fast_import_bytes(total);
+                    log::info!(
+                        "wrote {} to git fast-import ({} over the last {:.0?})",
+                        megabytes(total),
+                        rate(total - last.0, now - last.1),
+                        now - last.1
+                    );
+                    last = (total, now);
+                }
+            }
+        });
+
+        Self {
+            bytes,
+            metrics,
+            started,
+            handle,
+        }
+    }
+
+    /// Stops logging, and logs the total size of the stream along with the
+    /// average rate it was written at.
+    pub(crate) fn finish(self) {
+        self.handle.abort();
+
+        let total = self.bytes.get();
+        self.metrics.fast_import_bytes(total);
+        log::info!(
+            "wrote {} to git fast-import ({} on average)",
+            megabytes(total),
+            rate(total, self.started.elapsed())
+        );
+    }
+}
+
+fn megabytes(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / 1e6)
+}
+
+fn rate(bytes: u64, elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs_f64();
+    if seconds > 0.0 {
+        format!("{:.1} MB/s", bytes as f64 / 1e6 / seconds)
+    } else {
+        "- MB/s".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate() {
+        assert_eq!(megabytes(0), "0.0 MB");
+        assert_eq!(megabytes(12_345_678), "12.3 MB");
+        assert_eq!(rate(25_000_000, Duration::from_secs(10)), "2.5 MB/s");
+        assert_eq!(rate(25_000_000, Duration::ZERO), "- MB/s");
+    }
+}
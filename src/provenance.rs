@@ -0,0 +1,132 @@
+//! Per-file RCS admin metadata, written with `--provenance` so that audits of
+//! a conversion can see how CVS would have treated each file.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::BufWriter,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use comma_v::Admin;
+use serde::Serialize;
+
+/// A shared registry of the RCS admin metadata of each file, keyed by the path
+/// of the file within the repository.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ProvenanceRegistry {
+    files: Arc<Mutex<BTreeMap<String, Provenance>>>,
+}
+
+/// The RCS admin metadata of a single file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct Provenance {
+    /// The comment leader, which was used by old versions of RCS to prefix
+    /// the lines of `$Log$` keyword expansions.
+    pub comment: Option<String>,
+
+    /// The integrity string, which is reserved by RCS and rarely set.
+    pub integrity: Option<String>,
+
+    /// The keyword substitution mode set in the file, such as `b` or `o`, if
+    /// any. CVS uses `kv` for files without one.
+    pub expand: Option<String>,
+
+    /// True if CVS treats the file as binary, which is the case when the
+    /// substitution mode is `b`.
+    pub binary: bool,
+
+    /// True if CVS expands keywords such as `$Id$` when the file is checked
+    /// out. The import always uses the unexpanded content stored in the RCS
+    /// file, regardless of the mode.
+    pub keywords: bool,
+}
+
+impl Provenance {
+    fn new(admin: &Admin) -> Self {
+        let lossy = |value: &[u8]| String::from_utf8_lossy(value).into_owned();
+        let expand = admin.expand.as_deref().map(|expand| lossy(expand));
+
+        Self {
+            comment: admin.comment.as_deref().map(|comment| lossy(comment)),
+            integrity: admin.integrity.as_deref().map(|integrity| lossy(integrity)),
+            binary: expand.as_deref() == Some("b"),
+            keywords: !matches!(expand.as_deref(), Some("b") | Some("o")),
+            expand,
+        }
+    }
+}
+
+impl ProvenanceRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the admin metadata of the file at the given path.
+    pub fn record(&self, path: &Path, admin: &Admin) {
+        self.files
+            .lock()
+            .unwrap()
+            .insert(path.to_string_lossy().into(), Provenance::new(admin));
+    }
+
+    /// Writes the recorded metadata as a JSON object keyed by path.
+    pub fn write_to(&self, path: &Path) -> anyhow::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(serde_json::to_writer_pretty(
+            file,
+            &*self.files.lock().unwrap(),
+        )?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn admin(comment: Option<&str>, expand: Option<&str>) -> Admin {
+        Admin {
+            head: None,
+            branch: None,
+            access: Vec::new(),
+            symbols: Vec::new(),
+            locks: Default::default(),
+            strict: false,
+            integrity: None,
+            comment: comment.map(|comment| comment.as_bytes().to_vec().into()),
+            expand: expand.map(|expand| expand.as_bytes().to_vec().into()),
+        }
+    }
+
+    #[test]
+    fn test_provenance() {
+        for (expand, binary, keywords) in [
+            (None, false, true),
+            (Some("kv"), false, true),
+            (Some("k"), false, true),
+            (Some("o"), false, false),
+            (Some("b"), true, false),
+        ] {
+            let provenance = Provenance::new(&admin(Some("# "), expand));
+            assert_eq!(provenance.comment.as_deref(), Some("# "), "{:?}", expand);
+            assert_eq!(provenance.expand.as_deref(), expand);
+            assert_eq!(provenance.binary, binary, "{:?}", expand);
+            assert_eq!(provenance.keywords, keywords, "{:?}", expand);
+        }
+
+        let registry = ProvenanceRegistry::new();
+        registry.record(Path::new("mod/b.bin"), &admin(None, Some("b")));
+        registry.record(Path::new("mod/a.txt"), &admin(Some("# "), None));
+        assert_eq!(
+            registry
+                .files
+                .lock()
+                .unwrap()
+                .keys()
+                .cloned()
+                .collect::<Vec<_>>(),
+            vec!["mod/a.txt", "mod/b.bin"]
+        );
+    }
+}
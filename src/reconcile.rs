@@ -0,0 +1,195 @@
+//! Post-import reconciliation of the tree of each branch head in Git with the
+//! files that the state records on the branch.
+//!
+//! Commits are sent as a series of changes on top of their parent, so a
+//! change that was missed, such as a deletion, stays missing on every later
+//! commit. Comparing the whole tree of the branch head catches that, at the
+//! cost of listing the tree of every branch.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::OsStr,
+    os::unix::prelude::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use git_cvs_fast_import_state::{FileRevisionID, Manager};
+use git_fast_import::Mark;
+use serde::Serialize;
+use tokio::process::Command;
+
+/// A branch head whose tree didn't match the state, and the commit that was
+/// created to correct it.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Reconciliation {
+    pub branch: String,
+    pub mark: usize,
+    pub sha: String,
+
+    /// The files that were missing from the tree, or had the wrong content.
+    pub modified: Vec<String>,
+
+    /// The files that should have been deleted from the tree.
+    pub deleted: Vec<String>,
+}
+
+/// The changes needed to make the tree of a branch head match the state.
+#[derive(Debug, Default)]
+pub(crate) struct Drift {
+    /// The files to write, along with the file revision and blob mark of each.
+    pub modified: Vec<(PathBuf, FileRevisionID, Mark)>,
+
+    /// The files to delete, along with the file revision that deleted each.
+    pub deleted: Vec<(PathBuf, FileRevisionID)>,
+}
+
+impl Drift {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.modified.is_empty() && self.deleted.is_empty()
+    }
+
+    /// Returns the file revisions that the changes bring the tree up to date
+    /// with.
+    pub(crate) fn file_revision_ids(&self) -> Vec<FileRevisionID> {
+        self.modified
+            .iter()
+            .map(|(_path, id, _mark)| *id)
+            .chain(self.deleted.iter().map(|(_path, id)| *id))
+            .collect()
+    }
+}
+
+/// Compares the tree of the commit `sha` with the latest revision of each file
+/// on the branch in the state. `shas` maps each mark to the object name that
+/// git gave it, and `git_path` maps each path in the state to its path in the
+/// Git repository.
+///
+/// Only files that the state knows about are compared: anything else in the
+/// tree, such as `.gitkeep` placeholders or the files of a grafted commit, is
+/// left alone.
+pub(crate) async fn drift<F>(
+    state: &Manager,
+    branch: &[u8],
+    sha: &str,
+    shas: &BTreeMap<Mark, String>,
+    git_command: &OsStr,
+    git_repo: &OsStr,
+    git_path: F,
+) -> anyhow::Result<Drift>
+where
+    F: Fn(&Path) -> PathBuf,
+{
+    let tree = ls_tree(git_command, git_repo, sha).await?;
+
+    let mut drift = Drift::default();
+    for (path, revision) in state.get_files_on_branch(branch).await?.into_iter() {
+        let id = match state
+            .get_file_revision_id(&path, &revision.key.revision)
+            .await
+        {
+            Some(id) => id,
+            None => continue,
+        };
+        let actual = tree.get(&git_path(&path));
+
+        match revision.mark {
+            Some(mark) => {
+                let mark = Mark::from(mark);
+                match shas.get(&mark) {
+                    Some(expected) if actual != Some(expected) => {
+                        drift.modified.push((path, id, mark))
+                    }
+                    Some(_) => {}
+                    None => log::warn!(
+                        "{} {}: no SHA is known for mark {}; not reconciling",
+                        path.display(),
+                        revision.key.revision,
+                        mark
+                    ),
+                }
+            }
+            None if actual.is_some() => drift.deleted.push((path, id)),
+            None => {}
+        }
+    }
+
+    Ok(drift)
+}
+
+/// Returns the object name of every blob in the tree of the given commit,
+/// keyed by path.
+///
+/// This always runs `git ls-tree`, whichever backend wrote the commits.
+async fn ls_tree(
+    git_command: &OsStr,
+    git_repo: &OsStr,
+    sha: &str,
+) -> anyhow::Result<HashMap<PathBuf, String>> {
+    let output = Command::new(git_command)
+        .arg("-C")
+        .arg(git_repo)
+        .args(["ls-tree", "-r", "-z", "--full-tree", sha])
+        .output()
+        .await
+        .context("cannot run git ls-tree")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "git ls-tree {} failed: {}",
+            sha,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    parse_ls_tree(&output.stdout)
+}
+
+/// Parses the output of `git ls-tree -r -z`, where each entry is of the form
+/// `MODE TYPE SHA\tPATH` followed by a NUL.
+fn parse_ls_tree(output: &[u8]) -> anyhow::Result<HashMap<PathBuf, String>> {
+    let mut blobs = HashMap::new();
+    for entry in output
+        .split(|byte| *byte == 0)
+        .filter(|entry| !entry.is_empty())
+    {
+        let (info, path) = match entry.iter().position(|byte| *byte == b'\t') {
+            Some(tab) => (&entry[..tab], &entry[tab + 1..]),
+            None => anyhow::bail!(
+                "unexpected git ls-tree entry: {}",
+                String::from_utf8_lossy(entry)
+            ),
+        };
+
+        let info = std::str::from_utf8(info)?;
+        if let [_mode, "blob", sha] = info.split(' ').collect::<Vec<_>>().as_slice() {
+            blobs.insert(PathBuf::from(OsStr::from_bytes(path)), sha.to_string());
+        }
+    }
+
+    Ok(blobs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ls_tree() -> anyhow::Result<()> {
+        let a = "a".repeat(40);
+        let b = "b".repeat(40);
+        let output = format!(
+            "100644 blob {}\tmod/a.txt\0100755 blob {}\tmod/dir/b c.txt\0160000 commit {}\tsub\0",
+            a, b, a
+        );
+
+        let blobs = parse_ls_tree(output.as_bytes())?;
+        assert_eq!(blobs.len(), 2);
+        assert_eq!(blobs.get(Path::new("mod/a.txt")), Some(&a));
+        assert_eq!(blobs.get(Path::new("mod/dir/b c.txt")), Some(&b));
+
+        assert!(parse_ls_tree(b"").unwrap().is_empty());
+        assert!(parse_ls_tree(b"100644 blob aaaa\0").is_err());
+
+        Ok(())
+    }
+}
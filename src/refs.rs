@@ -0,0 +1,31 @@
+//! The Git refs that commits are written to.
+
+/// The namespace that commits are written to with `--no-update-refs`, so that
+/// they stay reachable without moving any branches or tags.
+pub(crate) const SCRATCH_NAMESPACE: &str = "refs/git-cvs-fast-import";
+
+/// Returns the ref that commits on the given branch are written to, which is
+/// the branch itself unless `update_refs` is false. Tags are imported as
+/// commits on a branch named `tags/TAG`.
+pub(crate) fn commit_ref(branch: &str, update_refs: bool) -> String {
+    if update_refs {
+        format!("refs/heads/{}", branch)
+    } else {
+        format!("{}/heads/{}", SCRATCH_NAMESPACE, branch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commit_ref() {
+        assert_eq!(commit_ref("main", true), "refs/heads/main");
+        assert_eq!(commit_ref("tags/REL1", true), "refs/heads/tags/REL1");
+        assert_eq!(
+            commit_ref("main", false),
+            "refs/git-cvs-fast-import/heads/main"
+        );
+    }
+}
@@ -0,0 +1,207 @@
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use git_cvs_fast_import_state::Run;
+use serde::Serialize;
+
+use crate::{
+    diffstat::DiffStat,
+    errors::{ErrorGroup, ErrorRegistry},
+    losses::{Loss, LossRegistry},
+    reconcile::Reconciliation,
+    skip_list::SkipList,
+    spot_check::SpotCheck,
+};
+
+/// A machine readable summary of an import, written as JSON when `--report`
+/// is given.
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct Report {
+    /// The ID of the run, which is also recorded in the store.
+    pub run_id: String,
+
+    /// The number of files that could not be imported.
+    pub skipped_files: usize,
+
+    /// The errors that caused files to be skipped, grouped by category.
+    pub errors: Vec<ErrorGroup>,
+
+    /// The places where information was lost in the conversion.
+    pub losses: Vec<Loss>,
+
+    /// The commits created for new patchsets in this import.
+    pub commits: Vec<ReportCommit>,
+
+    /// The Git ref for each CVS branch and tag, along with the commit it
+    /// points to.
+    pub refs: Vec<ReportRef>,
+
+    /// The fake commits created for tags in this import, and the parent each
+    /// one was given.
+    pub tags: Vec<ReportTag>,
+
+    /// The fake tag commits created in this import that match `--sign-tags`,
+    /// and so should be signed once the import is complete.
+    pub sign_tags: Vec<ReportSignTag>,
+
+    /// The commits whose CVS time is earlier than their parent commit.
+    pub clock_skews: Vec<ReportClockSkew>,
+
+    /// The result of the spot check, if `--spot-check` was given.
+    pub spot_check: Option<SpotCheck>,
+
+    /// The branches whose tree was corrected by `--reconcile`.
+    pub reconciliations: Vec<Reconciliation>,
+
+    /// The entries on the skip list, if `--skip-list` was given.
+    pub skip_list: Vec<ReportSkipListEntry>,
+}
+
+/// A commit created for a patchset.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct ReportCommit {
+    pub branch: String,
+
+    /// The patchset mark, which can be used to look the patchset up in the
+    /// state.
+    pub mark: usize,
+
+    pub sha: String,
+
+    /// The files and lines changed by the patchset, including any changes
+    /// carried over from patchsets dropped by a hook.
+    pub diffstat: DiffStat,
+}
+
+/// The Git ref created for a CVS branch or tag.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct ReportRef {
+    /// The CVS symbol, or the name of the head branch.
+    pub symbol: String,
+    pub kind: ReportRefKind,
+
+    /// The full name of the ref, such as `refs/heads/main`.
+    #[serde(rename = "ref")]
+    pub ref_name: String,
+
+    pub mark: usize,
+    pub sha: String,
+
+    /// True if the ref points to a commit synthesized for a tag, rather than
+    /// to a commit created for a patchset.
+    pub synthesized: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ReportRefKind {
+    Branch,
+    Tag,
+}
+
+/// A fake commit created for a tag.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct ReportTag {
+    pub tag: String,
+    pub mark: usize,
+    pub sha: String,
+    pub parent: ReportTagParent,
+
+    /// The parent commit, unless the fake commit is a root commit.
+    pub parent_mark: Option<usize>,
+    pub parent_sha: Option<String>,
+}
+
+/// A fake tag commit that should be signed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct ReportSignTag {
+    /// The full name of the tag's ref, such as `refs/tags/REL1`.
+    #[serde(rename = "ref")]
+    pub ref_name: String,
+
+    pub mark: usize,
+    pub sha: String,
+
+    /// Why the tag should be signed, such as `new tag matching REL*`.
+    pub reason: String,
+}
+
+/// Why a fake tag commit was given its parent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ReportTagParent {
+    /// The tag moved, so it's parented on its previous fake commit.
+    PreviousTag,
+
+    /// The last patchset that includes any of the tag's file revisions.
+    Patchset,
+
+    /// The tag had no other parent, and `--orphan-tags head` was given.
+    BranchHead,
+
+    /// The tag had no other parent, and `--orphan-tags empty-root` was given.
+    EmptyRoot,
+
+    /// The fake commit is a root commit.
+    None,
+}
+
+/// A commit with a CVS time earlier than its parent, typically because the
+/// clock on the CVS server jumped. Times are in seconds since the Unix epoch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct ReportClockSkew {
+    pub branch: String,
+    pub mark: usize,
+    pub time: u64,
+    pub parent_time: u64,
+
+    /// True if the commit time was adjusted to the parent time by
+    /// `--normalize-times`.
+    pub adjusted: bool,
+}
+
+/// An RCS file on the skip list, which is never imported.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct ReportSkipListEntry {
+    /// The path of the RCS file relative to the CVSROOT.
+    pub path: String,
+    pub reason: String,
+
+    /// True if the file was found in the directories being imported.
+    pub found: bool,
+}
+
+/// Converts a time to seconds since the Unix epoch, as used in the report.
+/// Times before the epoch are clamped to zero.
+pub(crate) fn unix_seconds(time: &SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+impl Report {
+    pub fn new(
+        run: &Run,
+        errors: &ErrorRegistry,
+        losses: &LossRegistry,
+        skip_list: Option<&SkipList>,
+    ) -> Self {
+        Self {
+            run_id: run.id.clone(),
+            skipped_files: errors.len(),
+            errors: errors.groups(),
+            losses: losses.losses(),
+            skip_list: skip_list.map(SkipList::report).unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+
+    pub fn write_to(&self, path: &Path) -> anyhow::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(serde_json::to_writer_pretty(file, self)?)
+    }
+}
@@ -0,0 +1,483 @@
+//! The `log` subcommand, which prints the history of each RCS file in a
+//! CVSROOT in the same form as `rlog`, straight from the parser, so that it
+//! can be checked before anything is imported.
+
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    io::Write,
+    os::unix::prelude::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use comma_v::{DateQuirks, Num};
+use structopt::StructOpt;
+
+use crate::source;
+
+const REVISION_SEPARATOR: &[u8] = b"----------------------------";
+const FILE_SEPARATOR: &[u8] =
+    b"=============================================================================";
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "git-cvs-fast-import log",
+    about = "Prints the revisions, dates, authors, branches, and tags of each RCS file in a CVSROOT in the same form as rlog, as read by the parser used for imports, without importing anything."
+)]
+pub(crate) struct Opt {
+    #[structopt(
+        short,
+        long,
+        env = "CVSROOT",
+        parse(from_os_str),
+        help = "the CVSROOT, which must be a local directory, a tar archive of one (optionally gzipped), or a remote CVSROOT such as :pserver:user@host:/cvsroot; if omitted, the $CVSROOT environment variable will be used"
+    )]
+    cvsroot: PathBuf,
+
+    #[structopt(
+        long,
+        default_value = "cvs",
+        parse(from_os_str),
+        help = "the cvs binary to use when accessing a remote CVSROOT"
+    )]
+    cvs: OsString,
+
+    #[structopt(
+        long,
+        default_value = "none",
+        help = "deviations from the RCS date format to accept, as with an import: a comma separated list of padding, tm-years, month-names, and overflow, or all or none"
+    )]
+    date_quirks: DateQuirks,
+
+    #[structopt(
+        long,
+        help = "only print the header of each file, including its symbols, as rlog -h does; only the admin section of each file is parsed, so this is much quicker"
+    )]
+    header: bool,
+
+    #[structopt(
+        name = "DIRECTORY",
+        parse(from_os_str),
+        help = "the top level directories to print from the CVSROOT; if omitted, all directories will be printed"
+    )]
+    directories: Vec<PathBuf>,
+}
+
+/// Prints the log of each RCS file in the CVSROOT to `out`.
+///
+/// Files that can't be parsed are skipped with a warning on stderr, as with
+/// `list`.
+pub(crate) fn run(opt: &Opt, out: &mut dyn Write) -> anyhow::Result<()> {
+    let source = source::open(&opt.cvsroot, &opt.cvs)?;
+
+    let mut found = 0;
+    let walk = |sink: &mut dyn FnMut(source::Entry) -> anyhow::Result<()>| {
+        if opt.header {
+            source.walk_admin(&opt.directories, sink)
+        } else {
+            source.walk(&opt.directories, sink)
+        }
+    };
+    walk(&mut |entry| {
+        if entry.is_dir()? || !entry.is_comma_v() {
+            return Ok(());
+        }
+
+        let (path, contents) = entry.into_contents()?;
+        let written = if opt.header {
+            comma_v::parse_admin(&contents).map(|admin| write_header(out, &path, &admin, None))
+        } else {
+            comma_v::parse_with_quirks(&contents, opt.date_quirks)
+                .map(|file| write_log(out, &path, &file))
+        };
+        match written {
+            Ok(result) => {
+                result?;
+                found += 1;
+            }
+            Err(e) => eprintln!("skipping {}: {}", path.display(), e),
+        }
+        Ok(())
+    })?;
+
+    if found == 0 {
+        anyhow::bail!(
+            "no ,v files were found in {}",
+            source.locations(&opt.directories).join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Writes the full log of a file: its header, followed by each revision.
+fn write_log(out: &mut dyn Write, path: &Path, file: &comma_v::File) -> std::io::Result<()> {
+    write_header(out, path, &file.admin, Some(file))?;
+    for num in revisions(file) {
+        write_revision(out, file, num)?;
+    }
+    out.write_all(FILE_SEPARATOR)?;
+    out.write_all(b"\n")
+}
+
+/// Writes the header of a file. The revision count and description are only
+/// written if the full file was parsed.
+fn write_header(
+    out: &mut dyn Write,
+    path: &Path,
+    admin: &comma_v::Admin,
+    file: Option<&comma_v::File>,
+) -> std::io::Result<()> {
+    out.write_all(b"\nRCS file: ")?;
+    out.write_all(path.as_os_str().as_bytes())?;
+    out.write_all(b"\nhead:")?;
+    if let Some(head) = &admin.head {
+        write!(out, " {}", head)?;
+    }
+    out.write_all(b"\nbranch:")?;
+    if let Some(branch) = &admin.branch {
+        write!(out, " {}", branch)?;
+    }
+    out.write_all(b"\n")?;
+
+    writeln!(out, "locks:{}", if admin.strict { " strict" } else { "" })?;
+    let mut locks: Vec<_> = admin.locks.iter().collect();
+    locks.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+    for (user, num) in locks {
+        out.write_all(b"\t")?;
+        out.write_all(user)?;
+        writeln!(out, ": {}", num)?;
+    }
+
+    writeln!(out, "access list:")?;
+    for user in admin.access.iter() {
+        out.write_all(b"\t")?;
+        out.write_all(user)?;
+        out.write_all(b"\n")?;
+    }
+
+    writeln!(out, "symbolic names:")?;
+    for (symbol, num) in admin.symbols.iter() {
+        out.write_all(b"\t")?;
+        out.write_all(symbol)?;
+        writeln!(out, ": {}", symbol_num(num))?;
+    }
+
+    out.write_all(b"keyword substitution: ")?;
+    out.write_all(admin.expand.as_deref().map(Vec::as_slice).unwrap_or(b"kv"))?;
+    out.write_all(b"\n")?;
+
+    match file {
+        Some(file) => {
+            writeln!(
+                out,
+                "total revisions: {};\tselected revisions: {}",
+                file.delta.len(),
+                file.delta.len()
+            )?;
+            writeln!(out, "description:")?;
+            out.write_all(&file.desc)?;
+            if !file.desc.is_empty() && !file.desc.ends_with(b"\n") {
+                out.write_all(b"\n")?;
+            }
+        }
+        None => {
+            out.write_all(FILE_SEPARATOR)?;
+            out.write_all(b"\n")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a single revision, preceded by a separator.
+fn write_revision(out: &mut dyn Write, file: &comma_v::File, num: &Num) -> std::io::Result<()> {
+    let delta = &file.delta[num];
+
+    out.write_all(REVISION_SEPARATOR)?;
+    writeln!(out, "\nrevision {}", num)?;
+    write!(
+        out,
+        "date: {};  author: ",
+        DateTime::<Utc>::from(delta.date).format("%Y/%m/%d %H:%M:%S")
+    )?;
+    out.write_all(&delta.author)?;
+    out.write_all(b";  state: ")?;
+    out.write_all(
+        delta
+            .state
+            .as_deref()
+            .map(Vec::as_slice)
+            .unwrap_or_default(),
+    )?;
+    out.write_all(b";")?;
+    if let Some((added, deleted)) = lines(file, num) {
+        write!(out, "  lines: +{} -{};", added, deleted)?;
+    }
+    if let Some(commit_id) = &delta.commit_id {
+        out.write_all(b"  commitid: ")?;
+        out.write_all(commit_id)?;
+        out.write_all(b";")?;
+    }
+    out.write_all(b"\n")?;
+
+    if !delta.branches.is_empty() {
+        out.write_all(b"branches:")?;
+        for branch in delta.branches.iter() {
+            write!(out, "  {};", branch.to_branch())?;
+        }
+        out.write_all(b"\n")?;
+    }
+
+    let log = file
+        .delta_text
+        .get(num)
+        .map(|text| text.log.as_slice())
+        .unwrap_or_default();
+    let log = log.strip_suffix(b"\n").unwrap_or(log);
+    if log.is_empty() {
+        out.write_all(b"*** empty log message ***\n")
+    } else {
+        out.write_all(log)?;
+        out.write_all(b"\n")
+    }
+}
+
+/// Returns the revisions of the file in the order rlog prints them: the
+/// trunk from the head revision back, followed by each branch from its newest
+/// revision back to its first, in the order their branch points were printed.
+fn revisions(file: &comma_v::File) -> Vec<&Num> {
+    let mut seen = HashSet::new();
+
+    // Each line of development is followed through `next` until it ends, or
+    // it refers to a revision that doesn't exist or was already printed.
+    let mut follow = |start: Option<&Num>| {
+        let mut line = Vec::new();
+        let mut num = start;
+        while let Some((key, delta)) = num.and_then(|num| file.delta.get_key_value(num)) {
+            if !seen.insert(key) {
+                break;
+            }
+            line.push(key);
+            num = delta.next.as_ref();
+        }
+        line
+    };
+
+    let mut revisions = follow(file.admin.head.as_ref());
+    let mut i = 0;
+    while i < revisions.len() {
+        for branch in file.delta[revisions[i]].branches.iter() {
+            revisions.extend(follow(Some(branch)).into_iter().rev());
+        }
+        i += 1;
+    }
+
+    revisions
+}
+
+/// Returns the number of lines added and deleted by a revision, relative to
+/// the revision it was derived from, if it has one and its ed script can be
+/// read.
+///
+/// On a branch, the script of a revision turns its parent into it, whereas on
+/// the trunk, the script of the previous revision turns the revision back into
+/// the previous one.
+fn lines(file: &comma_v::File, num: &Num) -> Option<(usize, usize)> {
+    let (script, reversed) = if num.is_on_trunk() {
+        (file.delta.get(num)?.next.as_ref()?, true)
+    } else {
+        (num, false)
+    };
+    let commands = rcs_ed::Script::parse(file.delta_text.get(script)?.text.as_slice())
+        .into_command_list()
+        .ok()?;
+
+    let added = commands.iter().map(rcs_ed::Command::lines_added).sum();
+    let deleted = commands.iter().map(rcs_ed::Command::lines_deleted).sum();
+    Some(if reversed {
+        (deleted, added)
+    } else {
+        (added, deleted)
+    })
+}
+
+/// Formats a symbol's revision as RCS stores it: branches other than vendor
+/// branches are stored with a magic zero, such as `1.1.0.2` for `1.1.2`.
+fn symbol_num(num: &Num) -> String {
+    match num {
+        Num::Branch(parts) if parts.len() > 1 && parts[parts.len() - 1] % 2 == 0 => {
+            let (branch, point) = parts.split_last().unwrap();
+            format!(
+                "{}.0.{}",
+                point
+                    .iter()
+                    .map(|part| part.to_string())
+                    .collect::<Vec<_>>()
+                    .join("."),
+                branch
+            )
+        }
+        _ => num.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::source::rlog::{Log, Parser};
+
+    const A_TXT: &[u8] = b"head\t1.2;
+access;
+symbols
+\tBR:1.1.0.2
+\tVENDOR:1.1.1
+\tREL1:1.1;
+locks; strict;
+comment\t@# @;
+
+
+1.2
+date\t2021.08.20.17.34.26;\tauthor adam;\tstate Exp;
+branches;
+next\t1.1;
+commitid\tabc;
+
+1.1
+date\t2021.08.20.17.00.00;\tauthor adam;\tstate Exp;
+branches
+\t1.1.2.1;
+next\t;
+
+1.1.2.1
+date\t2021.08.21.10.00.00;\tauthor bob;\tstate dead;
+branches;
+next\t1.1.2.2;
+
+1.1.2.2
+date\t2021.08.22.10.00.00;\tauthor bob;\tstate Exp;
+branches;
+next\t;
+
+
+desc
+@@
+
+
+1.2
+log
+@second
+@
+text
+@line one
+line two
+@
+
+
+1.1
+log
+@first
+@
+text
+@d2 1
+@
+
+
+1.1.2.1
+log
+@@
+text
+@a1 1
+branch line
+@
+
+
+1.1.2.2
+log
+@on branch
+@
+text
+@d1 1
+@
+";
+
+    #[test]
+    fn test_write_log() -> anyhow::Result<()> {
+        let file = comma_v::parse(A_TXT)?;
+        let mut out = Vec::new();
+        write_log(&mut out, Path::new("/cvs/mod/a.txt,v"), &file)?;
+
+        assert_eq!(
+            String::from_utf8(out.clone())?,
+            "
+RCS file: /cvs/mod/a.txt,v
+head: 1.2
+branch:
+locks: strict
+access list:
+symbolic names:
+\tBR: 1.1.0.2
+\tVENDOR: 1.1.1
+\tREL1: 1.1
+keyword substitution: kv
+total revisions: 4;\tselected revisions: 4
+description:
+----------------------------
+revision 1.2
+date: 2021/08/20 17:34:26;  author: adam;  state: Exp;  lines: +1 -0;  commitid: abc;
+second
+----------------------------
+revision 1.1
+date: 2021/08/20 17:00:00;  author: adam;  state: Exp;
+branches:  1.1.2;
+first
+----------------------------
+revision 1.1.2.2
+date: 2021/08/22 10:00:00;  author: bob;  state: Exp;  lines: +0 -1;
+on branch
+----------------------------
+revision 1.1.2.1
+date: 2021/08/21 10:00:00;  author: bob;  state: dead;  lines: +1 -0;
+*** empty log message ***
+=============================================================================
+"
+        );
+
+        // The output can be read back by the parser for cvs rlog output.
+        let logs = Parser::new(out.as_slice()).collect::<Result<Vec<Log>, _>>()?;
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].head, Some(Num::from_str("1.2")?));
+        assert_eq!(logs[0].symbols.len(), 3);
+        assert_eq!(
+            logs[0]
+                .revisions
+                .iter()
+                .map(|revision| revision.num.to_string())
+                .collect::<Vec<_>>(),
+            ["1.2", "1.1", "1.1.2.2", "1.1.2.1"]
+        );
+        assert_eq!(logs[0].revisions[1].branches, [Num::from_str("1.1.2")?]);
+        assert_eq!(logs[0].revisions[3].message, b"");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_header() -> anyhow::Result<()> {
+        let admin = comma_v::parse_admin(A_TXT)?;
+        let mut out = Vec::new();
+        write_header(&mut out, Path::new("a.txt,v"), &admin, None)?;
+
+        let out = String::from_utf8(out)?;
+        assert!(out.contains("\tBR: 1.1.0.2\n"), "{}", out);
+        assert!(!out.contains("total revisions"), "{}", out);
+        assert!(out.ends_with(&format!(
+            "keyword substitution: kv\n{}\n",
+            String::from_utf8_lossy(FILE_SEPARATOR)
+        )));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,68 @@
+use std::{
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+use thiserror::Error;
+
+/// A directory within the Git repository that every imported file is nested
+/// under, such as `legacy/cvs`.
+///
+/// Only the paths sent to Git are rewritten: the state always records the
+/// paths of files within the CVS repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RewriteRoot(PathBuf);
+
+impl RewriteRoot {
+    /// Returns the path that the given file is imported at.
+    pub(crate) fn join(&self, path: &Path) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl FromStr for RewriteRoot {
+    type Err = InvalidRewriteRootError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Normalising the components also removes any trailing slash.
+        let mut root = PathBuf::new();
+        for component in Path::new(s).components() {
+            match component {
+                Component::Normal(part) => root.push(part),
+                _ => return Err(InvalidRewriteRootError(s.to_string())),
+            }
+        }
+
+        if root.as_os_str().is_empty() {
+            return Err(InvalidRewriteRootError(s.to_string()));
+        }
+        Ok(Self(root))
+    }
+}
+
+#[derive(Debug, Error)]
+#[error(
+    "invalid root {0:?}: expected a relative path without . or .. components, such as legacy/cvs"
+)]
+pub(crate) struct InvalidRewriteRootError(String);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite_root() {
+        for (s, expected) in [
+            ("legacy", "legacy/mod/a.txt"),
+            ("legacy/cvs", "legacy/cvs/mod/a.txt"),
+            ("legacy/cvs/", "legacy/cvs/mod/a.txt"),
+        ] {
+            let root: RewriteRoot = s.parse().unwrap();
+            assert_eq!(root.join(Path::new("mod/a.txt")), Path::new(expected));
+        }
+
+        for s in ["", "/", "/legacy", "../legacy", "legacy/../cvs", "./legacy"] {
+            assert!(s.parse::<RewriteRoot>().is_err(), "{:?}", s);
+        }
+    }
+}
@@ -0,0 +1,60 @@
+//! The ID of each run, which is recorded in the store and the report, and, with
+//! `--import-trailer`, in an `X-CVS-Import` trailer on every commit, so that
+//! converted commits can be traced back to the run that made them.
+
+use std::time::SystemTime;
+
+use git_cvs_fast_import_state::Run;
+use rand::Rng;
+
+/// The key of the trailer added with `--import-trailer`.
+pub(crate) const TRAILER: &str = "X-CVS-Import";
+
+/// Starts a new run with a random ID.
+pub(crate) fn start() -> Run {
+    Run {
+        id: uuid(rand::thread_rng().gen()),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        started: SystemTime::now(),
+    }
+}
+
+/// Returns the value of the trailer for the run, such as
+/// `git-cvs-fast-import 0.2.0 run 1b4e28ba-2fa1-4d2e-883f-0016d3cca427`.
+pub(crate) fn trailer(run: &Run) -> String {
+    format!("git-cvs-fast-import {} run {}", run.version, run.id)
+}
+
+/// Formats the given random bytes as a version 4 UUID.
+fn uuid(mut bytes: [u8; 16]) -> String {
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uuid() {
+        assert_eq!(uuid([0; 16]), "00000000-0000-4000-8000-000000000000");
+        assert_eq!(uuid([0xff; 16]), "ffffffff-ffff-4fff-bfff-ffffffffffff");
+
+        let run = start();
+        assert_ne!(run.id, start().id);
+        assert_eq!(
+            trailer(&run),
+            format!("git-cvs-fast-import {} run {}", run.version, run.id)
+        );
+    }
+}
@@ -0,0 +1,188 @@
+//! Lists of RCS files to skip, given with `--skip-list`, such as files that
+//! are known to be damaged beyond repair.
+//!
+//! Each line of the file is the path of an RCS file relative to the CVSROOT,
+//! including its `,v` suffix and any `Attic` directory, followed by the reason
+//! that it's skipped, such as:
+//!
+//! ```text
+//! # Truncated when the disk filled up in 2004.
+//! mod/Attic/huge.bin,v  truncated
+//! mod/doc/notes.txt,v   contains revisions with duplicate numbers
+//! ```
+//!
+//! Blank lines and lines starting with `#` are ignored.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use thiserror::Error;
+
+use crate::report::ReportSkipListEntry;
+
+#[derive(Debug, Error)]
+pub(crate) enum SkipListError {
+    #[error("cannot read skip list {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("invalid entry on line {line} of skip list {path}: {message}")]
+    Entry {
+        path: PathBuf,
+        line: usize,
+        message: &'static str,
+    },
+}
+
+/// The RCS files to skip, along with the reason for each one.
+///
+/// Like the registries, this is cheap to clone, and all clones record the
+/// files that were found in the same place.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SkipList {
+    entries: Arc<BTreeMap<PathBuf, String>>,
+
+    /// The paths on the list that were found in the CVSROOT.
+    found: Arc<Mutex<BTreeSet<PathBuf>>>,
+}
+
+impl SkipList {
+    pub(crate) fn read(path: &Path) -> Result<Self, SkipListError> {
+        let raw = fs::read_to_string(path).map_err(|source| SkipListError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Self::parse(path, &raw)
+    }
+
+    fn parse(path: &Path, raw: &str) -> Result<Self, SkipListError> {
+        let mut entries = BTreeMap::new();
+        for (index, line) in raw.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = |message| SkipListError::Entry {
+                path: path.to_path_buf(),
+                line: index + 1,
+                message,
+            };
+
+            // The path ends at the first ,v that's followed by whitespace, so
+            // paths may contain spaces.
+            let end = line
+                .match_indices(",v")
+                .map(|(start, _)| start + 2)
+                .find(|end| line[*end..].starts_with(char::is_whitespace) || *end == line.len())
+                .ok_or_else(|| error("expected the path of a ,v file"))?;
+            let reason = line[end..].trim();
+            if reason.is_empty() {
+                return Err(error("expected a reason after the path"));
+            }
+
+            if entries
+                .insert(PathBuf::from(&line[..end]), reason.to_string())
+                .is_some()
+            {
+                return Err(error("the path is already on the list"));
+            }
+        }
+
+        Ok(Self {
+            entries: Arc::new(entries),
+            found: Default::default(),
+        })
+    }
+
+    /// Returns the reason that the RCS file at the given path, relative to the
+    /// CVSROOT, is skipped, or `None` if it isn't on the list.
+    pub(crate) fn skip(&self, path: &Path) -> Option<&str> {
+        let reason = self.entries.get(path)?;
+        self.found.lock().unwrap().insert(path.to_path_buf());
+        Some(reason)
+    }
+
+    /// Returns every entry on the list, keyed by path.
+    pub(crate) fn entries(&self) -> &BTreeMap<PathBuf, String> {
+        &self.entries
+    }
+
+    /// Returns the entries for the report, noting which files were found.
+    pub(crate) fn report(&self) -> Vec<ReportSkipListEntry> {
+        let found = self.found.lock().unwrap();
+        self.entries
+            .iter()
+            .map(|(path, reason)| ReportSkipListEntry {
+                path: path.to_string_lossy().into(),
+                reason: reason.clone(),
+                found: found.contains(path),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() -> anyhow::Result<()> {
+        let skip_list = SkipList::parse(
+            Path::new("skip.txt"),
+            "# broken files\n\nmod/Attic/a b.c,v  truncated in 2004\n  mod/x,v.c,v\tbad delta\n",
+        )?;
+        assert_eq!(
+            skip_list.entries().iter().collect::<Vec<_>>(),
+            vec![
+                (
+                    &PathBuf::from("mod/Attic/a b.c,v"),
+                    &"truncated in 2004".to_string()
+                ),
+                (&PathBuf::from("mod/x,v.c,v"), &"bad delta".to_string()),
+            ]
+        );
+
+        for (raw, line) in [
+            ("mod/a.c,v\n", 1),
+            ("# none\nmod/a.c truncated\n", 2),
+            ("mod/a.c,v one\nmod/a.c,v two\n", 2),
+        ] {
+            match SkipList::parse(Path::new("skip.txt"), raw) {
+                Err(SkipListError::Entry { line: have, .. }) => assert_eq!(have, line, "{:?}", raw),
+                other => panic!("unexpected result for {:?}: {:?}", raw, other),
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_skip() -> anyhow::Result<()> {
+        let skip_list = SkipList::parse(
+            Path::new("skip.txt"),
+            "mod/a.c,v truncated\nmod/b.c,v missing deltas\n",
+        )?;
+        // Files found through any clone are included in the report.
+        let clone = skip_list.clone();
+        assert_eq!(clone.skip(Path::new("mod/a.c,v")), Some("truncated"));
+        assert_eq!(skip_list.skip(Path::new("mod/c.c,v")), None);
+
+        let report = skip_list.report();
+        assert_eq!(
+            report
+                .iter()
+                .map(|entry| (entry.path.as_str(), entry.found))
+                .collect::<Vec<_>>(),
+            vec![("mod/a.c,v", true), ("mod/b.c,v", false)]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,424 @@
+//! Sources of RCS files that can be handed to discovery.
+
+use std::{
+    ffi::OsStr,
+    fs::{self, File},
+    io::{self, BufReader, Read},
+    ops::Deref,
+    os::unix::{fs::MetadataExt, prelude::OsStrExt},
+    path::{Component, Path, PathBuf},
+};
+
+use flate2::read::GzDecoder;
+use memmap2::Mmap;
+use walkdir::WalkDir;
+
+use self::remote::Remote;
+
+mod remote;
+pub(crate) mod rlog;
+
+/// An entry that may be parsed by discovery.
+#[derive(Debug)]
+pub(crate) enum Entry {
+    /// A path on the local filesystem, which will be read when it's handled.
+    Path(PathBuf),
+
+    /// A file that has already been read from the source, such as a file
+    /// within an archive.
+    Buffer { path: PathBuf, contents: Vec<u8> },
+}
+
+impl Entry {
+    pub fn path(&self) -> &Path {
+        match self {
+            Entry::Path(path) => path,
+            Entry::Buffer { path, .. } => path,
+        }
+    }
+
+    pub fn is_dir(&self) -> io::Result<bool> {
+        match self {
+            Entry::Path(path) => Ok(fs::metadata(path)?.is_dir()),
+            Entry::Buffer { .. } => Ok(false),
+        }
+    }
+
+    /// Returns the size of the entry's contents, which is zero for
+    /// directories.
+    pub fn size(&self) -> io::Result<u64> {
+        match self {
+            Entry::Path(path) => {
+                let metadata = fs::metadata(path)?;
+                Ok(if metadata.is_dir() { 0 } else { metadata.len() })
+            }
+            Entry::Buffer { contents, .. } => Ok(contents.len() as u64),
+        }
+    }
+
+    /// Returns the device and inode of the entry if it's a file on the local
+    /// filesystem with more than one hard link, which can be used to find the
+    /// other paths that refer to the same file.
+    pub fn hard_link_id(&self) -> io::Result<Option<(u64, u64)>> {
+        match self {
+            Entry::Path(path) => {
+                let metadata = fs::metadata(path)?;
+                Ok(if metadata.is_file() && metadata.nlink() > 1 {
+                    Some((metadata.dev(), metadata.ino()))
+                } else {
+                    None
+                })
+            }
+            Entry::Buffer { .. } => Ok(None),
+        }
+    }
+
+    pub fn is_comma_v(&self) -> bool {
+        self.path().as_os_str().as_bytes().ends_with(b",v")
+    }
+
+    /// Returns the contents of the entry, reading it from disk if required.
+    pub fn into_contents(self) -> io::Result<(PathBuf, Vec<u8>)> {
+        match self {
+            Entry::Path(path) => {
+                let contents = fs::read(&path)?;
+                Ok((path, contents))
+            }
+            Entry::Buffer { path, contents } => Ok((path, contents)),
+        }
+    }
+
+    /// Returns the contents of the entry, as with [`Entry::into_contents`],
+    /// except that files on the local filesystem of at least `mmap_threshold`
+    /// bytes are mapped into memory rather than read.
+    ///
+    /// Mapped files are paged in from the page cache as they're parsed, and
+    /// discovery only copies each revision out of them as it's converted, so
+    /// they don't have to fit in memory.
+    pub fn into_mapped_contents(self, mmap_threshold: u64) -> io::Result<(PathBuf, Contents)> {
+        match self {
+            Entry::Path(path) => {
+                let file = File::open(&path)?;
+                if file.metadata()?.len() < mmap_threshold {
+                    let mut contents = Vec::new();
+                    BufReader::new(file).read_to_end(&mut contents)?;
+                    return Ok((path, Contents::Buffer(contents)));
+                }
+
+                // Mapping a file is only unsafe if it's modified while it's
+                // mapped, but RCS and CVS never modify ,v files in place: they
+                // write a new file and rename it over the old one.
+                let mmap = unsafe { Mmap::map(&file)? };
+                Ok((path, Contents::Mapped(mmap)))
+            }
+            Entry::Buffer { path, contents } => Ok((path, Contents::Buffer(contents))),
+        }
+    }
+}
+
+/// The contents of an entry, either read into a buffer or mapped from disk.
+#[derive(Debug)]
+pub(crate) enum Contents {
+    Buffer(Vec<u8>),
+    Mapped(Mmap),
+}
+
+impl Deref for Contents {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Contents::Buffer(contents) => contents,
+            Contents::Mapped(mmap) => mmap,
+        }
+    }
+}
+
+/// A `Source` provides the RCS files in a CVSROOT.
+pub(crate) trait Source {
+    /// The prefix that should be stripped from each entry path to calculate
+    /// the path of the file within the repository.
+    fn prefix(&self) -> &Path;
+
+    /// Walks the source, calling `sink` with each entry within the given
+    /// directories, or all entries if `directories` is empty. Directories are
+    /// relative to the CVSROOT.
+    fn walk(
+        &self,
+        directories: &[PathBuf],
+        sink: &mut dyn FnMut(Entry) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()>;
+
+    /// Walks the source as with [`walk()`][Self::walk], except that only the
+    /// admin section of each RCS file, which is all that
+    /// [`comma_v::parse_admin`] reads, has to be complete. Sources that would
+    /// otherwise have to fetch the content of every revision can skip it.
+    fn walk_admin(
+        &self,
+        directories: &[PathBuf],
+        sink: &mut dyn FnMut(Entry) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        self.walk(directories, sink)
+    }
+
+    /// Describes where [`walk()`][Self::walk] looks for entries within the
+    /// given directories, for use in error messages.
+    fn locations(&self, directories: &[PathBuf]) -> Vec<String>;
+}
+
+/// Opens the given CVSROOT: directories are walked on the local filesystem,
+/// files are treated as tar archives, optionally compressed with gzip, and
+/// CVSROOT strings with a method such as `:pserver:` or `:ext:` are accessed
+/// using the `cvs` client.
+pub(crate) fn open(cvsroot: &Path, cvs: &OsStr) -> anyhow::Result<Box<dyn Source>> {
+    if cvsroot.as_os_str().as_bytes().starts_with(b":") {
+        Ok(Box::new(Remote::new(cvs, cvsroot.as_os_str())))
+    } else if fs::metadata(cvsroot)?.is_file() {
+        Ok(Box::new(Tar::new(cvsroot)))
+    } else {
+        Ok(Box::new(Filesystem::new(cvsroot)))
+    }
+}
+
+/// A CVSROOT on the local filesystem.
+#[derive(Debug)]
+pub(crate) struct Filesystem {
+    cvsroot: PathBuf,
+}
+
+impl Filesystem {
+    pub fn new(cvsroot: &Path) -> Self {
+        Self {
+            cvsroot: cvsroot.to_path_buf(),
+        }
+    }
+
+    fn paths(&self, directories: &[PathBuf]) -> Vec<PathBuf> {
+        if directories.is_empty() {
+            vec![self.cvsroot.clone()]
+        } else {
+            directories
+                .iter()
+                .map(|dir| self.cvsroot.join(dir))
+                .collect()
+        }
+    }
+}
+
+impl Source for Filesystem {
+    fn prefix(&self) -> &Path {
+        &self.cvsroot
+    }
+
+    fn walk(
+        &self,
+        directories: &[PathBuf],
+        sink: &mut dyn FnMut(Entry) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        // Entries are walked in name order so that the first of a set of hard
+        // links is stable between runs.
+        for path in self.paths(directories) {
+            for entry in WalkDir::new(path).sort_by_file_name() {
+                log::trace!("sending {:?} to discovery", &entry);
+                sink(Entry::Path(entry?.into_path()))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn locations(&self, directories: &[PathBuf]) -> Vec<String> {
+        // Relative paths are resolved, since a relative CVSROOT may not be
+        // relative to the directory the user expects.
+        self.paths(directories)
+            .into_iter()
+            .map(|path| {
+                fs::canonicalize(&path)
+                    .unwrap_or(path)
+                    .display()
+                    .to_string()
+            })
+            .collect()
+    }
+}
+
+/// A CVSROOT within a tar archive, which is streamed without being extracted.
+///
+/// Paths within the archive are treated as being relative to the CVSROOT, so
+/// the archive should be created from within the CVSROOT: for example, `tar -C
+/// /cvs -czf cvsroot.tar.gz .`.
+#[derive(Debug)]
+pub(crate) struct Tar {
+    archive: PathBuf,
+}
+
+impl Tar {
+    pub fn new(archive: &Path) -> Self {
+        Self {
+            archive: archive.to_path_buf(),
+        }
+    }
+}
+
+impl Source for Tar {
+    fn prefix(&self) -> &Path {
+        Path::new("")
+    }
+
+    fn walk(
+        &self,
+        directories: &[PathBuf],
+        sink: &mut dyn FnMut(Entry) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let mut reader = BufReader::new(File::open(&self.archive)?);
+
+        // Sniff the gzip magic number to figure out if we need to decompress
+        // the archive.
+        let mut magic = [0u8; 2];
+        reader.read_exact(&mut magic)?;
+        let reader = io::Cursor::new(magic).chain(reader);
+
+        if magic == [0x1f, 0x8b] {
+            walk_tar(GzDecoder::new(reader), directories, sink)
+        } else {
+            walk_tar(reader, directories, sink)
+        }
+    }
+
+    fn locations(&self, directories: &[PathBuf]) -> Vec<String> {
+        let archive = fs::canonicalize(&self.archive).unwrap_or_else(|_| self.archive.clone());
+        if directories.is_empty() {
+            vec![archive.display().to_string()]
+        } else {
+            directories
+                .iter()
+                .map(|dir| format!("{} within {}", dir.display(), archive.display()))
+                .collect()
+        }
+    }
+}
+
+fn walk_tar<R: Read>(
+    reader: R,
+    directories: &[PathBuf],
+    sink: &mut dyn FnMut(Entry) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut archive = tar::Archive::new(reader);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        // Normalise away any leading ./ components, since archives are
+        // commonly created with them.
+        let path: PathBuf = entry
+            .path()?
+            .components()
+            .filter(|component| !matches!(component, Component::CurDir))
+            .collect();
+
+        if !directories.is_empty() && !directories.iter().any(|dir| path.starts_with(dir)) {
+            continue;
+        }
+
+        if !path.as_os_str().as_bytes().ends_with(b",v") {
+            log::trace!("ignoring {} due to non-,v suffix", path.display());
+            continue;
+        }
+
+        let mut contents = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut contents)?;
+
+        log::trace!("sending {} to discovery", path.display());
+        sink(Entry::Buffer { path, contents })?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_tar(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *contents).unwrap();
+        }
+
+        builder.into_inner().unwrap()
+    }
+
+    fn collect(archive: &[u8], directories: &[PathBuf]) -> Vec<(PathBuf, Vec<u8>)> {
+        let mut entries = Vec::new();
+        walk_tar(archive, directories, &mut |entry| {
+            entries.push(entry.into_contents()?);
+            Ok(())
+        })
+        .unwrap();
+
+        entries
+    }
+
+    #[test]
+    fn test_walk_tar() {
+        let archive = build_tar(&[
+            ("./foo/a,v", b"a"),
+            ("./foo/Attic/b,v", b"b"),
+            ("./foo/README", b"readme"),
+            ("bar/c,v", b"c"),
+        ]);
+
+        assert_eq!(
+            collect(&archive, &[]),
+            vec![
+                (PathBuf::from("foo/a,v"), b"a".to_vec()),
+                (PathBuf::from("foo/Attic/b,v"), b"b".to_vec()),
+                (PathBuf::from("bar/c,v"), b"c".to_vec()),
+            ]
+        );
+
+        assert_eq!(
+            collect(&archive, &[PathBuf::from("bar")]),
+            vec![(PathBuf::from("bar/c,v"), b"c".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_into_mapped_contents() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("a,v");
+        std::fs::write(&path, b"head 1.1;")?;
+
+        // Files below the threshold are read, and files at or above it are
+        // mapped, but their contents are the same either way.
+        for (threshold, mapped) in [(10, false), (9, true), (0, true)] {
+            let (_, contents) = Entry::Path(path.clone()).into_mapped_contents(threshold)?;
+            assert_eq!(
+                matches!(contents, Contents::Mapped(_)),
+                mapped,
+                "{}",
+                threshold
+            );
+            assert_eq!(&*contents, b"head 1.1;");
+        }
+
+        // Buffered entries from other sources are never mapped.
+        let entry = Entry::Buffer {
+            path: path.clone(),
+            contents: b"head 1.1;".to_vec(),
+        };
+        assert!(matches!(
+            entry.into_mapped_contents(0)?,
+            (_, Contents::Buffer(_))
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,469 @@
+//! A source that uses the CVS client to access a remote CVSROOT.
+//!
+//! Since the CVS protocol doesn't provide access to the underlying ,v files,
+//! we instead use `cvs rlog` to enumerate each file and its revisions, fetch
+//! the contents of each revision with `cvs co -p`, and then reconstitute an
+//! equivalent ,v file that can be handed to discovery as normal.
+
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    io::{BufReader, Write},
+    os::unix::prelude::OsStrExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use chrono::{TimeZone, Utc};
+use comma_v::Num;
+
+use super::{
+    rlog::{self, Log},
+    Entry, Source,
+};
+
+/// A CVSROOT accessed through the CVS client, such as
+/// `:pserver:anonymous@cvs.example.com:/cvsroot`.
+#[derive(Debug)]
+pub(crate) struct Remote {
+    cvs: OsString,
+    cvsroot: OsString,
+
+    /// The path of the CVSROOT on the server.
+    prefix: PathBuf,
+}
+
+impl Remote {
+    pub fn new(cvs: &OsStr, cvsroot: &OsStr) -> Self {
+        Self {
+            cvs: cvs.to_os_string(),
+            cvsroot: cvsroot.to_os_string(),
+            prefix: server_path(cvsroot),
+        }
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.cvs);
+        command.arg("-d").arg(&self.cvsroot).arg("-Q");
+        command
+    }
+
+    /// Fetches the contents of the given revision without keyword expansion,
+    /// which is how it would be stored in the ,v file.
+    fn checkout(&self, path: &Path, revision: &Num) -> anyhow::Result<Vec<u8>> {
+        let output = self
+            .command()
+            .args(["co", "-p", "-ko", "-r"])
+            .arg(revision.to_string())
+            .arg(path)
+            .stderr(Stdio::inherit())
+            .output()?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "cvs co of {} revision {} failed: {}",
+                path.display(),
+                revision,
+                output.status
+            );
+        }
+
+        Ok(output.stdout)
+    }
+
+    /// Walks the files in `cvs rlog` output. If `admin_only` is true, only the
+    /// header of each file is requested, with `rlog -h`, and nothing is checked
+    /// out, so each entry only has the admin section of its ,v file.
+    fn walk_logs(
+        &self,
+        directories: &[PathBuf],
+        admin_only: bool,
+        sink: &mut dyn FnMut(Entry) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let mut command = self.command();
+        command.arg("rlog");
+        if admin_only {
+            command.arg("-h");
+        }
+        let mut child = command
+            .args(if directories.is_empty() {
+                vec![PathBuf::from(".")]
+            } else {
+                directories.to_vec()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("cannot get stdout for cvs rlog"))?;
+
+        for log in rlog::Parser::new(BufReader::new(stdout)) {
+            let log = log?;
+            let rcs_file = PathBuf::from(OsStr::from_bytes(&log.rcs_file));
+            log::trace!("fetching revisions of {}", rcs_file.display());
+
+            let path = module_path(&rcs_file, &self.prefix);
+            let mut contents = HashMap::new();
+            for revision in log.revisions.iter() {
+                if revision.state != b"dead" {
+                    contents.insert(revision.num.clone(), self.checkout(&path, &revision.num)?);
+                }
+            }
+
+            sink(Entry::Buffer {
+                path: rcs_file,
+                contents: to_comma_v(&log, &contents)?,
+            })?;
+        }
+
+        let status = child.wait()?;
+        if !status.success() {
+            anyhow::bail!("cvs rlog failed: {}", status);
+        }
+
+        Ok(())
+    }
+}
+
+impl Source for Remote {
+    fn prefix(&self) -> &Path {
+        &self.prefix
+    }
+
+    fn walk(
+        &self,
+        directories: &[PathBuf],
+        sink: &mut dyn FnMut(Entry) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        self.walk_logs(directories, false, sink)
+    }
+
+    fn walk_admin(
+        &self,
+        directories: &[PathBuf],
+        sink: &mut dyn FnMut(Entry) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        self.walk_logs(directories, true, sink)
+    }
+
+    fn locations(&self, directories: &[PathBuf]) -> Vec<String> {
+        let cvsroot = self.cvsroot.to_string_lossy();
+        if directories.is_empty() {
+            vec![cvsroot.into_owned()]
+        } else {
+            directories
+                .iter()
+                .map(|dir| format!("{} within {}", dir.display(), cvsroot))
+                .collect()
+        }
+    }
+}
+
+/// Extracts the path of the repository on the server from a CVSROOT string,
+/// such as `/cvsroot` from `:pserver:user@host:2401/cvsroot`.
+fn server_path(cvsroot: &OsStr) -> PathBuf {
+    let bytes = cvsroot.as_bytes();
+    let path = match bytes.iter().rposition(|c| *c == b':') {
+        Some(i) => &bytes[i + 1..],
+        None => bytes,
+    };
+
+    // Strip any port number that precedes the path.
+    let start = path
+        .iter()
+        .position(|c| !c.is_ascii_digit())
+        .unwrap_or(path.len());
+
+    PathBuf::from(OsStr::from_bytes(&path[start..]))
+}
+
+/// Calculates the path that the CVS client will recognise for a file, given
+/// the path to the ,v file on the server.
+fn module_path(rcs_file: &Path, prefix: &Path) -> PathBuf {
+    let relative = rcs_file.strip_prefix(prefix).unwrap_or(rcs_file);
+    let file = relative.file_name().unwrap_or_default().as_bytes();
+    let file = OsStr::from_bytes(file.strip_suffix(b",v").unwrap_or(file));
+
+    match relative.parent() {
+        Some(parent) if parent.ends_with("Attic") => {
+            parent.parent().unwrap_or_else(|| Path::new("")).join(file)
+        }
+        Some(parent) => parent.join(file),
+        None => PathBuf::from(file),
+    }
+}
+
+/// Builds a ,v file from the given log and revision contents.
+///
+/// Rather than calculating real diffs, each delta text other than the head
+/// consists of an ed script that replaces the entire content of the previous
+/// revision, which is sufficient for our purposes.
+fn to_comma_v(log: &Log, contents: &HashMap<Num, Vec<u8>>) -> anyhow::Result<Vec<u8>> {
+    let empty = Vec::new();
+    let content = |num: &Num| contents.get(num).unwrap_or(&empty);
+
+    // rlog doesn't provide the next pointers, so we have to reconstruct them:
+    // trunk revisions point to the previous revision, whereas branch revisions
+    // point to the next revision on the same branch.
+    let mut trunk: Vec<&Num> = Vec::new();
+    let mut branches: HashMap<Num, Vec<&Num>> = HashMap::new();
+    for revision in log.revisions.iter() {
+        match &revision.num {
+            Num::Commit(_) if revision.num.is_on_trunk() => trunk.push(&revision.num),
+            Num::Commit(_) => branches
+                .entry(revision.num.to_branch())
+                .or_default()
+                .push(&revision.num),
+            Num::Branch(_) => anyhow::bail!("unexpected branch revision {}", revision.num),
+        }
+    }
+    trunk.sort_unstable_by(|a, b| b.cmp(a));
+    branches
+        .values_mut()
+        .for_each(|revisions| revisions.sort_unstable());
+
+    let mut next: HashMap<&Num, &Num> = HashMap::new();
+    let mut previous: HashMap<&Num, &Num> = HashMap::new();
+    for revisions in std::iter::once(&trunk).chain(branches.values()) {
+        for pair in revisions.windows(2) {
+            next.insert(pair[0], pair[1]);
+            previous.insert(pair[1], pair[0]);
+        }
+    }
+
+    let mut first_on_branch: HashMap<&Num, Vec<&Num>> = HashMap::new();
+    for revision in log.revisions.iter() {
+        for branch in revision.branches.iter() {
+            if let Some(first) = branches
+                .get(&branch.to_branch())
+                .and_then(|revisions| revisions.first())
+            {
+                first_on_branch
+                    .entry(&revision.num)
+                    .or_default()
+                    .push(first);
+                previous.insert(first, &revision.num);
+            }
+        }
+    }
+
+    let mut out = Vec::new();
+    match &log.head {
+        Some(head) => writeln!(out, "head\t{};", head)?,
+        None => writeln!(out, "head\t;")?,
+    }
+    writeln!(out, "access;")?;
+    write!(out, "symbols")?;
+    for (name, num) in log.symbols.iter() {
+        out.extend_from_slice(b"\n\t");
+        out.extend_from_slice(name);
+        write!(out, ":{}", num)?;
+    }
+    writeln!(out, ";")?;
+    writeln!(out, "locks; strict;")?;
+    writeln!(out)?;
+
+    for revision in log.revisions.iter() {
+        writeln!(out, "\n{}", revision.num)?;
+        let date = Utc
+            .timestamp_opt(revision.date, 0)
+            .single()
+            .ok_or_else(|| anyhow::anyhow!("invalid date on revision {}", revision.num))?;
+        writeln!(
+            out,
+            "date\t{};\tauthor {};\tstate {};",
+            date.format("%Y.%m.%d.%H.%M.%S"),
+            String::from_utf8_lossy(&revision.author),
+            String::from_utf8_lossy(&revision.state),
+        )?;
+        write!(out, "branches")?;
+        for first in first_on_branch.get(&revision.num).into_iter().flatten() {
+            write!(out, "\n\t{}", first)?;
+        }
+        writeln!(out, ";")?;
+        match next.get(&revision.num) {
+            Some(next) => writeln!(out, "next\t{};", next)?,
+            None => writeln!(out, "next\t;")?,
+        }
+        if let Some(commit_id) = &revision.commit_id {
+            writeln!(out, "commitid\t{};", String::from_utf8_lossy(commit_id))?;
+        }
+    }
+
+    writeln!(out, "\n\ndesc\n@@\n")?;
+
+    for revision in log.revisions.iter() {
+        writeln!(out, "\n{}\nlog", revision.num)?;
+        write_string(&mut out, &revision.message);
+        out.extend_from_slice(b"\ntext\n");
+
+        let text = content(&revision.num);
+        match previous.get(&revision.num) {
+            Some(previous) => {
+                let mut script = format!(
+                    "d1 {}\na0 {}\n",
+                    line_count(content(previous)),
+                    line_count(text)
+                )
+                .into_bytes();
+                script.extend_from_slice(text);
+                write_string(&mut out, &script);
+            }
+            None => write_string(&mut out, text),
+        }
+        out.push(b'\n');
+    }
+
+    Ok(out)
+}
+
+/// Counts lines in the same way as [`rcs_ed::File`], where a last line without
+/// a newline is still a line.
+fn line_count(content: &[u8]) -> usize {
+    content.iter().filter(|c| **c == b'\n').count()
+        + match content.last() {
+            Some(c) if *c != b'\n' => 1,
+            _ => 0,
+        }
+}
+
+fn write_string(out: &mut Vec<u8>, s: &[u8]) {
+    out.push(b'@');
+    for c in s {
+        if *c == b'@' {
+            out.push(b'@');
+        }
+        out.push(*c);
+    }
+    out.push(b'@');
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rcs_ed::{File, Script};
+
+    use super::*;
+    use crate::source::rlog::Revision;
+
+    fn num(s: &str) -> Num {
+        Num::from_str(s).unwrap()
+    }
+
+    fn revision(n: &str, state: &str, branches: &[&str]) -> Revision {
+        Revision {
+            num: num(n),
+            date: 1629478800,
+            author: b"adam".to_vec(),
+            state: state.as_bytes().to_vec(),
+            commit_id: None,
+            branches: branches.iter().map(|branch| num(branch)).collect(),
+            message: format!("revision {} @ HEAD", n).into_bytes(),
+        }
+    }
+
+    #[test]
+    fn test_server_path() {
+        assert_eq!(
+            server_path(OsStr::new(":pserver:anonymous@cvs.example.com:/cvsroot")),
+            PathBuf::from("/cvsroot")
+        );
+        assert_eq!(
+            server_path(OsStr::new(":pserver:cvs.example.com:2401/cvsroot")),
+            PathBuf::from("/cvsroot")
+        );
+        assert_eq!(
+            server_path(OsStr::new(":ext:user@host:/var/lib/cvs")),
+            PathBuf::from("/var/lib/cvs")
+        );
+    }
+
+    #[test]
+    fn test_module_path() {
+        let prefix = Path::new("/cvsroot");
+        assert_eq!(
+            module_path(Path::new("/cvsroot/mod/foo,v"), prefix),
+            PathBuf::from("mod/foo")
+        );
+        assert_eq!(
+            module_path(Path::new("/cvsroot/mod/Attic/foo,v"), prefix),
+            PathBuf::from("mod/foo")
+        );
+    }
+
+    #[test]
+    fn test_to_comma_v() -> anyhow::Result<()> {
+        let log = Log {
+            rcs_file: b"/cvsroot/mod/foo,v".to_vec(),
+            head: Some(num("1.2")),
+            symbols: vec![
+                (b"BRANCH".to_vec(), num("1.1.0.2")),
+                (b"TAG".to_vec(), num("1.1")),
+            ],
+            revisions: vec![
+                revision("1.2", "Exp", &[]),
+                revision("1.1", "Exp", &["1.1.2"]),
+                revision("1.1.2.2", "dead", &[]),
+                revision("1.1.2.1", "Exp", &[]),
+            ],
+        };
+
+        let contents: HashMap<Num, Vec<u8>> = vec![
+            (num("1.2"), b"a\nb\nc\n".to_vec()),
+            (num("1.1"), b"a @ b\n".to_vec()),
+            (num("1.1.2.1"), b"branch".to_vec()),
+        ]
+        .into_iter()
+        .collect();
+
+        let cv = comma_v::parse(&to_comma_v(&log, &contents)?)?;
+        assert_eq!(cv.head(), Some(&num("1.2")));
+        assert_eq!(cv.admin.symbols.len(), 2);
+
+        // Walk each revision in the same way as discovery, and ensure we get
+        // the expected contents.
+        let mut file = File::new(cv.revision(&num("1.2")).unwrap().1.text.as_cursor())?;
+        assert_eq!(file.as_bytes(), b"a\nb\nc\n");
+
+        let (delta, delta_text) = cv.revision(&num("1.1")).unwrap();
+        assert_eq!(*delta_text.log, b"revision 1.1 @ HEAD");
+        assert_eq!(delta.branches, vec![num("1.1.2.1")]);
+        assert_eq!(delta.next, None);
+        file.apply_in_place(&Script::parse(delta_text.text.as_cursor()).into_command_list()?)?;
+        assert_eq!(file.as_bytes(), b"a @ b\n");
+
+        let (delta, delta_text) = cv.revision(&num("1.1.2.1")).unwrap();
+        assert_eq!(delta.next, Some(num("1.1.2.2")));
+        file.apply_in_place(&Script::parse(delta_text.text.as_cursor()).into_command_list()?)?;
+        assert_eq!(file.as_bytes(), b"branch");
+
+        let (delta, delta_text) = cv.revision(&num("1.1.2.2")).unwrap();
+        assert_eq!(delta.state.as_ref().unwrap().0, b"dead".to_vec());
+        file.apply_in_place(&Script::parse(delta_text.text.as_cursor()).into_command_list()?)?;
+        assert_eq!(file.as_bytes(), b"");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_comma_v_admin_only() -> anyhow::Result<()> {
+        // rlog -h doesn't list any revisions, but the admin section is still
+        // complete.
+        let log = Log {
+            rcs_file: b"/cvsroot/mod/foo,v".to_vec(),
+            head: Some(num("1.2")),
+            symbols: vec![(b"TAG".to_vec(), num("1.1"))],
+            revisions: Vec::new(),
+        };
+
+        let admin = comma_v::parse_admin(&to_comma_v(&log, &HashMap::new())?)?;
+        assert_eq!(admin.head, Some(num("1.2")));
+        assert_eq!(admin.symbols.len(), 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,436 @@
+//! A parser for the output of `cvs rlog`.
+
+use std::{
+    io::{self, BufRead},
+    iter::Peekable,
+    str::FromStr,
+};
+
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use comma_v::Num;
+use thiserror::Error;
+
+const REVISION_SEPARATOR: &[u8] = b"----------------------------";
+const FILE_SEPARATOR: &[u8] =
+    b"=============================================================================";
+
+/// The log of a single RCS file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Log {
+    pub rcs_file: Vec<u8>,
+    pub head: Option<Num>,
+    pub symbols: Vec<(Vec<u8>, Num)>,
+    pub revisions: Vec<Revision>,
+}
+
+/// A single revision within a [`Log`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Revision {
+    pub num: Num,
+
+    /// The date of the revision, as seconds since the Unix epoch.
+    pub date: i64,
+    pub author: Vec<u8>,
+    pub state: Vec<u8>,
+    pub commit_id: Option<Vec<u8>>,
+
+    /// The branches that sprout from this revision. Note that these are the
+    /// branch numbers (such as `1.1.2`), not the first revision on each branch.
+    pub branches: Vec<Num>,
+    pub message: Vec<u8>,
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum Error {
+    #[error("invalid date: {0}")]
+    InvalidDate(String),
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error("invalid revision number: {0}")]
+    InvalidNum(String),
+
+    #[error("unexpected end of input in the log for {0}")]
+    UnexpectedEof(String),
+}
+
+/// `Parser` iterates over the file logs within `cvs rlog` output.
+pub(crate) struct Parser<R: BufRead> {
+    lines: Peekable<io::Split<R>>,
+}
+
+impl<R: BufRead> Parser<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: reader.split(b'\n').peekable(),
+        }
+    }
+
+    fn next_line(&mut self) -> Option<Result<Vec<u8>, Error>> {
+        self.lines.next().map(|line| {
+            line.map(|mut line| {
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                line
+            })
+            .map_err(Error::from)
+        })
+    }
+
+    fn parse_file(&mut self, rcs_file: Vec<u8>) -> Result<Log, Error> {
+        let mut log = Log {
+            rcs_file,
+            head: None,
+            symbols: Vec::new(),
+            revisions: Vec::new(),
+        };
+        let eof = |log: &Log| Error::UnexpectedEof(String::from_utf8_lossy(&log.rcs_file).into());
+
+        // Parse the header, up to and including the description.
+        let mut in_symbols = false;
+        loop {
+            let line = self.next_line().ok_or_else(|| eof(&log))??;
+
+            if in_symbols {
+                if let Some(symbol) = line.strip_prefix(b"\t") {
+                    if let Some((name, num)) = split_once(symbol, b": ") {
+                        log.symbols.push((name.to_vec(), parse_num(num)?));
+                    }
+                    continue;
+                }
+                in_symbols = false;
+            }
+
+            if let Some(head) = line.strip_prefix(b"head:") {
+                let head = trim(head);
+                if !head.is_empty() {
+                    log.head = Some(parse_num(head)?);
+                }
+            } else if line.starts_with(b"symbolic names:") {
+                in_symbols = true;
+            } else if line.starts_with(b"description:") {
+                break;
+            } else if line == FILE_SEPARATOR {
+                // rlog -h only prints the header, without a description.
+                return Ok(log);
+            }
+        }
+
+        // Skip the description, which is terminated by either the first
+        // revision or the end of the file.
+        loop {
+            let line = self.next_line().ok_or_else(|| eof(&log))??;
+            if line == FILE_SEPARATOR {
+                return Ok(log);
+            }
+            if line == REVISION_SEPARATOR && self.peek_revision() {
+                break;
+            }
+        }
+
+        // Parse each revision.
+        loop {
+            let line = self.next_line().ok_or_else(|| eof(&log))??;
+            let num = line
+                .strip_prefix(b"revision ")
+                .map(|rest| {
+                    rest.split(|c| *c == b'\t' || *c == b' ')
+                        .next()
+                        .unwrap_or(rest)
+                })
+                .ok_or_else(|| eof(&log))?;
+            let num = parse_num(num)?;
+
+            let line = self.next_line().ok_or_else(|| eof(&log))??;
+            let mut revision = parse_date_line(num, &line)?;
+
+            let mut message: Vec<Vec<u8>> = Vec::new();
+            let done = loop {
+                let line = self.next_line().ok_or_else(|| eof(&log))??;
+                if line == FILE_SEPARATOR {
+                    break true;
+                }
+                if line == REVISION_SEPARATOR && self.peek_revision() {
+                    break false;
+                }
+
+                match line.strip_prefix(b"branches:") {
+                    Some(branches) if message.is_empty() => {
+                        for branch in branches.split(|c| *c == b';') {
+                            let branch = trim(branch);
+                            if !branch.is_empty() {
+                                revision.branches.push(parse_num(branch)?);
+                            }
+                        }
+                    }
+                    _ => message.push(line),
+                }
+            };
+
+            revision.message = message.join(&b'\n');
+            if revision.message == b"*** empty log message ***" {
+                revision.message.clear();
+            }
+            log.revisions.push(revision);
+
+            if done {
+                return Ok(log);
+            }
+        }
+    }
+
+    fn peek_revision(&mut self) -> bool {
+        matches!(self.lines.peek(), Some(Ok(line)) if line.starts_with(b"revision "))
+    }
+}
+
+impl<R: BufRead> Iterator for Parser<R> {
+    type Item = Result<Log, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.next_line()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e)),
+            };
+
+            if let Some(rcs_file) = line.strip_prefix(b"RCS file:") {
+                return Some(self.parse_file(trim(rcs_file).to_vec()));
+            }
+        }
+    }
+}
+
+/// Parses the line following the revision number, which looks like:
+///
+/// ```text
+/// date: 2021/08/20 17:34:26;  author: adam;  state: Exp;  lines: +1 -0;
+/// ```
+fn parse_date_line(num: Num, line: &[u8]) -> Result<Revision, Error> {
+    let mut revision = Revision {
+        num,
+        date: 0,
+        author: Vec::new(),
+        state: Vec::new(),
+        commit_id: None,
+        branches: Vec::new(),
+        message: Vec::new(),
+    };
+
+    for field in line.split(|c| *c == b';') {
+        if let Some((key, value)) = split_once(trim(field), b": ") {
+            let value = trim(value);
+            match key {
+                b"date" => revision.date = parse_date(value)?,
+                b"author" => revision.author = value.to_vec(),
+                b"state" => revision.state = value.to_vec(),
+                b"commitid" => revision.commit_id = Some(value.to_vec()),
+                _ => {}
+            }
+        }
+    }
+
+    Ok(revision)
+}
+
+/// Parses a date in either of the formats used by CVS: older versions use
+/// `2021/08/20 17:34:26` in UTC, while newer versions use `2021-08-20 17:34:26
+/// +0000`.
+fn parse_date(date: &[u8]) -> Result<i64, Error> {
+    let date = String::from_utf8_lossy(date);
+
+    if let Ok(dt) = DateTime::parse_from_str(&date, "%Y-%m-%d %H:%M:%S %z") {
+        return Ok(dt.timestamp());
+    }
+    if let Ok(dt) = NaiveDateTime::parse_from_str(&date, "%Y/%m/%d %H:%M:%S") {
+        return Ok(Utc.from_utc_datetime(&dt).timestamp());
+    }
+
+    Err(Error::InvalidDate(date.into()))
+}
+
+fn parse_num(num: &[u8]) -> Result<Num, Error> {
+    let num = String::from_utf8_lossy(num);
+    Num::from_str(&num).map_err(|_| Error::InvalidNum(num.into()))
+}
+
+fn split_once<'a>(input: &'a [u8], delimiter: &[u8]) -> Option<(&'a [u8], &'a [u8])> {
+    input
+        .windows(delimiter.len())
+        .position(|window| window == delimiter)
+        .map(|i| (&input[..i], &input[i + delimiter.len()..]))
+}
+
+fn trim(input: &[u8]) -> &[u8] {
+    let start = input
+        .iter()
+        .position(|c| !c.is_ascii_whitespace())
+        .unwrap_or(input.len());
+    let end = input
+        .iter()
+        .rposition(|c| !c.is_ascii_whitespace())
+        .map(|i| i + 1)
+        .unwrap_or(start);
+
+    &input[start..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn num(s: &str) -> Num {
+        Num::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_parser() {
+        let input = b"
+RCS file: /cvs/mod/foo,v
+head: 1.2
+branch:
+locks: strict
+access list:
+symbolic names:
+\tBRANCH: 1.1.0.2
+\tTAG: 1.1
+keyword substitution: kv
+total revisions: 3;\tselected revisions: 3
+description:
+----------------------------
+revision 1.2
+date: 2021-08-20 17:34:26 +0100;  author: adam;  state: Exp;  lines: +1 -0;  commitid: abc;
+second
+
+----------------------------
+not a separator
+----------------------------
+revision 1.1
+date: 2021/08/20 17:00:00;  author: adam;  state: Exp;
+branches:  1.1.2;
+*** empty log message ***
+----------------------------
+revision 1.1.2.1
+date: 2021/08/21 17:00:00;  author: bob;  state: dead;
+on a branch
+=============================================================================
+
+RCS file: /cvs/mod/Attic/bar,v
+head:
+branch:
+locks: strict
+access list:
+symbolic names:
+keyword substitution: kv
+total revisions: 0;\tselected revisions: 0
+description:
+a description
+=============================================================================
+";
+
+        let logs = Parser::new(input.as_ref())
+            .collect::<Result<Vec<Log>, Error>>()
+            .unwrap();
+
+        assert_eq!(
+            logs,
+            vec![
+                Log {
+                    rcs_file: b"/cvs/mod/foo,v".to_vec(),
+                    head: Some(num("1.2")),
+                    symbols: vec![
+                        (b"BRANCH".to_vec(), num("1.1.0.2")),
+                        (b"TAG".to_vec(), num("1.1")),
+                    ],
+                    revisions: vec![
+                        Revision {
+                            num: num("1.2"),
+                            date: 1629477266,
+                            author: b"adam".to_vec(),
+                            state: b"Exp".to_vec(),
+                            commit_id: Some(b"abc".to_vec()),
+                            branches: vec![],
+                            message: b"second\n\n----------------------------\nnot a separator"
+                                .to_vec(),
+                        },
+                        Revision {
+                            num: num("1.1"),
+                            date: 1629478800,
+                            author: b"adam".to_vec(),
+                            state: b"Exp".to_vec(),
+                            commit_id: None,
+                            branches: vec![num("1.1.2")],
+                            message: Vec::new(),
+                        },
+                        Revision {
+                            num: num("1.1.2.1"),
+                            date: 1629565200,
+                            author: b"bob".to_vec(),
+                            state: b"dead".to_vec(),
+                            commit_id: None,
+                            branches: vec![],
+                            message: b"on a branch".to_vec(),
+                        },
+                    ],
+                },
+                Log {
+                    rcs_file: b"/cvs/mod/Attic/bar,v".to_vec(),
+                    head: None,
+                    symbols: vec![],
+                    revisions: vec![],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parser_header_only() {
+        // rlog -h stops after the header, without a description.
+        let input = b"
+RCS file: /cvs/mod/foo,v
+head: 1.2
+branch:
+locks: strict
+access list:
+symbolic names:
+\tTAG: 1.1
+keyword substitution: kv
+total revisions: 3
+=============================================================================
+
+RCS file: /cvs/mod/bar,v
+head: 1.1
+branch:
+locks: strict
+access list:
+symbolic names:
+keyword substitution: kv
+total revisions: 1
+=============================================================================
+";
+
+        let logs = Parser::new(input.as_ref())
+            .collect::<Result<Vec<Log>, Error>>()
+            .unwrap();
+
+        assert_eq!(
+            logs,
+            vec![
+                Log {
+                    rcs_file: b"/cvs/mod/foo,v".to_vec(),
+                    head: Some(num("1.2")),
+                    symbols: vec![(b"TAG".to_vec(), num("1.1"))],
+                    revisions: vec![],
+                },
+                Log {
+                    rcs_file: b"/cvs/mod/bar,v".to_vec(),
+                    head: Some(num("1.1")),
+                    symbols: vec![],
+                    revisions: vec![],
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,381 @@
+//! Post-import verification of file revision content.
+
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    path::PathBuf,
+    process::Stdio,
+};
+
+use anyhow::Context;
+use comma_v::{DateQuirks, Num};
+use git_cvs_fast_import_state::Manager;
+use rand::seq::IteratorRandom;
+use rcs_ed::{File, Script};
+use serde::Serialize;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    process::Command,
+    task,
+};
+
+use crate::{
+    discovery::{munge_raw_path, PathNormalization},
+    source::Source,
+};
+
+/// The result of a spot check.
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct SpotCheck {
+    /// The number of file revisions that were compared against Git.
+    pub checked: usize,
+
+    /// The file revisions whose blob in Git doesn't match the content in the
+    /// RCS file.
+    pub mismatches: Vec<Mismatch>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct Mismatch {
+    pub path: String,
+    pub revision: String,
+    pub sha: String,
+    pub reason: String,
+}
+
+/// A file revision selected for checking.
+#[derive(Debug)]
+struct Sample {
+    revision: Num,
+    sha: String,
+}
+
+/// Picks up to `count` random file revisions from the state, reconstructs
+/// their content from the RCS files in `source`, and compares that content to
+/// the blobs in the Git repository using `git cat-file --batch`.
+///
+/// Only file revisions within `directories` are picked, if any are given. This
+/// must be run after the marks have been saved to the state, since the marks
+/// are used to find the blob for each revision.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn spot_check(
+    state: &Manager,
+    source: &dyn Source,
+    date_quirks: DateQuirks,
+    path_normalization: PathNormalization,
+    directories: &[PathBuf],
+    git_command: &OsStr,
+    git_repo: &OsStr,
+    count: usize,
+) -> anyhow::Result<SpotCheck> {
+    let mut raw_marks = Vec::new();
+    state.get_raw_marks(&mut raw_marks).await?;
+    let shas = git_fast_import::read_marks(raw_marks.as_slice())?;
+
+    // Pick the revisions to check, grouped by path so that each RCS file only
+    // has to be parsed once.
+    let mut samples: HashMap<PathBuf, Vec<Sample>> = HashMap::new();
+    let mut candidates = Vec::new();
+    for id in state.get_file_revision_ids_with_marks().await {
+        let revision = state.get_file_revision_by_id(id).await?;
+        if directories.is_empty()
+            || directories
+                .iter()
+                .any(|dir| revision.key.path.starts_with(dir))
+        {
+            candidates.push(revision);
+        }
+    }
+    for revision in candidates
+        .into_iter()
+        .choose_multiple(&mut rand::thread_rng(), count)
+    {
+        let mark = match revision.mark {
+            Some(mark) => git_fast_import::Mark::from(mark),
+            None => continue,
+        };
+        let sha = match shas.get(&mark) {
+            Some(sha) => sha.clone(),
+            None => {
+                log::warn!(
+                    "{} {}: no SHA is known for mark {}; not checking",
+                    revision.key.path.display(),
+                    revision.key.revision,
+                    mark
+                );
+                continue;
+            }
+        };
+
+        samples
+            .entry(revision.key.path.clone())
+            .or_default()
+            .push(Sample {
+                revision: revision.key.revision.parse()?,
+                sha,
+            });
+    }
+
+    // Walk the source to find the RCS file for each path, and reconstruct the
+    // content of each sampled revision.
+    let mut expected: Vec<(PathBuf, Sample, Vec<u8>)> = Vec::new();
+    let prefix = source.prefix().to_path_buf();
+    source.walk(directories, &mut |entry| {
+        if entry.is_dir()? || !entry.is_comma_v() {
+            return Ok(());
+        }
+
+        let path = munge_raw_path(entry.path(), &prefix, path_normalization);
+        let path_samples = match samples.remove(&path) {
+            Some(path_samples) => path_samples,
+            None => return Ok(()),
+        };
+
+        let (raw_path, contents) = entry.into_contents()?;
+        let cv = comma_v::parse_with_quirks(&contents, date_quirks)
+            .with_context(|| format!("cannot parse {}", raw_path.display()))?;
+        let mut contents = revision_contents(
+            &cv,
+            &path_samples
+                .iter()
+                .map(|sample| sample.revision.clone())
+                .collect(),
+        )
+        .with_context(|| format!("cannot reconstruct revisions in {}", raw_path.display()))?;
+
+        for sample in path_samples {
+            match contents.remove(&sample.revision) {
+                Some(content) => expected.push((path.clone(), sample, content)),
+                None => log::warn!(
+                    "{} {}: revision not found in {}; not checking",
+                    path.display(),
+                    sample.revision,
+                    raw_path.display()
+                ),
+            }
+        }
+
+        Ok(())
+    })?;
+
+    for (path, path_samples) in samples.into_iter() {
+        log::warn!(
+            "{}: RCS file not found; not checking {} revision(s)",
+            path.display(),
+            path_samples.len()
+        );
+    }
+
+    // Finally, we can ask git for the blobs and compare them.
+    let blobs = cat_file(
+        git_command,
+        git_repo,
+        expected
+            .iter()
+            .map(|(_path, sample, _content)| sample.sha.clone()),
+    )
+    .await?;
+
+    let mut result = SpotCheck {
+        checked: expected.len(),
+        mismatches: Vec::new(),
+    };
+    for ((path, sample, content), blob) in expected.into_iter().zip(blobs) {
+        let reason = match blob {
+            None => "blob is missing from the Git repository".to_string(),
+            Some(blob) if blob != content => format!(
+                "blob does not match the RCS revision ({} byte(s) in Git, {} byte(s) in RCS)",
+                blob.len(),
+                content.len()
+            ),
+            Some(_) => continue,
+        };
+
+        log::error!("{} {}: {}", path.display(), sample.revision, reason);
+        result.mismatches.push(Mismatch {
+            path: path.to_string_lossy().into(),
+            revision: sample.revision.to_string(),
+            sha: sample.sha,
+            reason,
+        });
+    }
+
+    Ok(result)
+}
+
+/// Reconstructs the content of the given revisions by applying the deltas in
+/// the RCS file, in the same way as discovery.
+pub(crate) fn revision_contents(
+    cv: &comma_v::File,
+    wanted: &HashSet<Num>,
+) -> anyhow::Result<HashMap<Num, Vec<u8>>> {
+    let mut found = HashMap::new();
+    let head = match cv.head() {
+        Some(head) => head,
+        None => anyhow::bail!("cannot find HEAD revision"),
+    };
+
+    walk_tree(cv, None, head, wanted, &mut HashSet::new(), &mut found)?;
+    Ok(found)
+}
+
+fn walk_tree(
+    cv: &comma_v::File,
+    mut contents: Option<File>,
+    revision: &Num,
+    wanted: &HashSet<Num>,
+    visited: &mut HashSet<Num>,
+    found: &mut HashMap<Num, Vec<u8>>,
+) -> anyhow::Result<()> {
+    let mut revision = revision;
+
+    loop {
+        if found.len() == wanted.len() {
+            return Ok(());
+        }
+        if !visited.insert(revision.clone()) {
+            anyhow::bail!("revision {} is referenced more than once", revision);
+        }
+
+        let (delta, delta_text) = match cv.revision(revision) {
+            Some(revision) => revision,
+            None => anyhow::bail!("cannot find revision {}", revision),
+        };
+
+        let file = match contents.as_mut() {
+            Some(file) => {
+                let commands = Script::parse(delta_text.text.as_cursor()).into_command_list()?;
+                file.apply_in_place(&commands)
+                    .with_context(|| format!("cannot apply the delta for revision {}", revision))?;
+                file
+            }
+            None => contents.insert(File::new(delta_text.text.as_cursor())?),
+        };
+
+        if wanted.contains(revision) {
+            found.insert(revision.clone(), file.as_bytes());
+        }
+
+        for branch_revision in delta.branches.iter() {
+            walk_tree(
+                cv,
+                contents.clone(),
+                branch_revision,
+                wanted,
+                visited,
+                found,
+            )?;
+        }
+
+        match &delta.next {
+            Some(next) => revision = next,
+            None => return Ok(()),
+        }
+    }
+}
+
+/// Retrieves the given blobs from the Git repository using `git cat-file
+/// --batch`, returning `None` for each blob that doesn't exist.
+async fn cat_file<I>(
+    git_command: &OsStr,
+    git_repo: &OsStr,
+    shas: I,
+) -> anyhow::Result<Vec<Option<Vec<u8>>>>
+where
+    I: Iterator<Item = String>,
+{
+    let shas: Vec<String> = shas.collect();
+
+    let mut child = Command::new(git_command)
+        .arg("-C")
+        .arg(git_repo)
+        .arg("cat-file")
+        .arg("--batch")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow::anyhow!("cannot open git cat-file stdin"))?;
+    let mut stdout = BufReader::new(
+        child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("cannot open git cat-file stdout"))?,
+    );
+
+    // We write the requests in a separate task, since git may block writing
+    // its output before it has read all of its input.
+    let input = shas
+        .iter()
+        .map(|sha| format!("{}\n", sha))
+        .collect::<String>();
+    let writer = task::spawn(async move {
+        stdin.write_all(input.as_bytes()).await?;
+        stdin.shutdown().await
+    });
+
+    let mut blobs = Vec::with_capacity(shas.len());
+    for sha in shas.iter() {
+        let mut header = String::new();
+        stdout.read_line(&mut header).await?;
+
+        // Each response is either "<sha> missing" or "<sha> <type> <size>",
+        // followed by the content and a newline.
+        let fields: Vec<&str> = header.split_whitespace().collect();
+        match fields.as_slice() {
+            [_, "missing"] => blobs.push(None),
+            [_, "blob", size] => {
+                let mut content = vec![0; size.parse()?];
+                stdout.read_exact(&mut content).await?;
+                stdout.read_exact(&mut [0; 1]).await?;
+                blobs.push(Some(content));
+            }
+            _ => anyhow::bail!("unexpected git cat-file response for {}: {:?}", sha, header),
+        }
+    }
+
+    writer.await??;
+    let status = child.wait().await?;
+    if !status.success() {
+        anyhow::bail!("git cat-file exited with {}", status);
+    }
+
+    Ok(blobs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_revision_contents() {
+        let cv = comma_v::parse(include_bytes!(
+            "../tests/golden/resurrection/cvsroot/mod/c.txt,v"
+        ))
+        .unwrap();
+
+        let wanted: HashSet<Num> = ["1.3", "1.1", "1.2.2.1"]
+            .iter()
+            .map(|num| num.parse().unwrap())
+            .collect();
+        let mut have: Vec<(String, Vec<u8>)> = revision_contents(&cv, &wanted)
+            .unwrap()
+            .into_iter()
+            .map(|(num, content)| (num.to_string(), content))
+            .collect();
+        have.sort();
+
+        assert_eq!(
+            have,
+            vec![
+                ("1.1".to_string(), b"original\n".to_vec()),
+                ("1.2.2.1".to_string(), b"branch\n".to_vec()),
+                ("1.3".to_string(), b"restored\n".to_vec()),
+            ]
+        );
+    }
+}
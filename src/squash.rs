@@ -0,0 +1,227 @@
+//! Post-detection squashing of runs of small patchsets on a branch, such as
+//! those made by scripts that commit each file with its own message.
+
+use std::{fmt::Debug, str::FromStr, time::Duration};
+
+use patchset::PatchSet;
+use thiserror::Error;
+
+/// A rule that merges consecutive patchsets on a branch into a single patchset
+/// when they have the same author, their messages all match `pattern`, and
+/// each is no more than `window` after the one before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SquashRule {
+    pub branch: Vec<u8>,
+    pub window: Duration,
+    pub pattern: glob::Pattern,
+}
+
+impl SquashRule {
+    /// Applies the rule to the patchsets on its branch, which must be in time
+    /// order.
+    ///
+    /// A merged patchset has the time of the last patchset in the run, and
+    /// the messages of the run in order, separated by blank lines, with
+    /// repeated messages only included once.
+    pub(crate) fn apply<ID>(&self, patchsets: Vec<PatchSet<ID>>) -> Vec<PatchSet<ID>>
+    where
+        ID: Debug + Clone + Eq,
+    {
+        let mut squashed: Vec<PatchSet<ID>> = Vec::with_capacity(patchsets.len());
+        let mut last_message: Option<String> = None;
+
+        for patchset in patchsets.into_iter() {
+            let matches = self.pattern.matches(patchset.message.trim_end());
+            match squashed.last_mut() {
+                Some(previous)
+                    if matches
+                        && last_message.is_some()
+                        && previous.author == patchset.author
+                        && patchset
+                            .time
+                            .duration_since(previous.time)
+                            .unwrap_or_default()
+                            <= self.window =>
+                {
+                    if last_message.as_deref() != Some(patchset.message.as_str()) {
+                        previous.message =
+                            format!("{}\n\n{}", previous.message.trim_end(), patchset.message);
+                        last_message = Some(patchset.message.clone());
+                    }
+                    previous.merge(patchset);
+                }
+                _ => {
+                    last_message = matches.then(|| patchset.message.clone());
+                    squashed.push(patchset);
+                }
+            }
+        }
+
+        squashed
+    }
+}
+
+impl FromStr for SquashRule {
+    type Err = SquashRuleError;
+
+    /// Parses a `BRANCH=DURATION:PATTERN` rule, such as `BR=5m:Automated*`.
+    /// RCS doesn't allow colons in branch names, so the pattern starts after
+    /// the first colon.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (branch, window, pattern) = match s.split_once(':') {
+            Some((head, pattern)) => match head.rsplit_once('=') {
+                Some((branch, window)) if !branch.is_empty() => (branch, window, pattern),
+                _ => return Err(SquashRuleError::Syntax(s.to_string())),
+            },
+            None => return Err(SquashRuleError::Syntax(s.to_string())),
+        };
+
+        Ok(Self {
+            branch: branch.as_bytes().to_vec(),
+            window: crate::units::parse_duration(window).map_err(|e| SquashRuleError::Window {
+                branch: branch.to_string(),
+                message: e.to_string(),
+            })?,
+            pattern: glob::Pattern::new(pattern).map_err(|e| SquashRuleError::Pattern {
+                branch: branch.to_string(),
+                message: e.to_string(),
+            })?,
+        })
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum SquashRuleError {
+    #[error("invalid message pattern in squash rule for branch {branch}: {message}")]
+    Pattern { branch: String, message: String },
+
+    #[error("invalid squash rule {0:?}: expected BRANCH=DURATION:PATTERN")]
+    Syntax(String),
+
+    #[error("invalid window in squash rule for branch {branch}: {message}")]
+    Window { branch: String, message: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::SystemTime};
+
+    use patchset::Detector;
+
+    use super::*;
+
+    #[test]
+    fn test_squash_rule_parse() {
+        assert_eq!(
+            "BR=5m:Automated *".parse::<SquashRule>().unwrap(),
+            SquashRule {
+                branch: b"BR".to_vec(),
+                window: Duration::from_secs(300),
+                pattern: glob::Pattern::new("Automated *").unwrap(),
+            }
+        );
+        assert_eq!(
+            "a=b=10:x:y".parse::<SquashRule>().unwrap(),
+            SquashRule {
+                branch: b"a=b".to_vec(),
+                window: Duration::from_secs(10),
+                pattern: glob::Pattern::new("x:y").unwrap(),
+            }
+        );
+
+        for s in [
+            "BR",
+            "BR=5m",
+            "=5m:*",
+            "BR:*",
+            "BR=soon:*",
+            "BR=5m:[",
+            ":BR=5m",
+        ] {
+            assert!(s.parse::<SquashRule>().is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn test_squash_rule_apply() {
+        let rule: SquashRule = "main=60s:auto*".parse().unwrap();
+        let mut detector = Detector::new(Duration::from_secs(0));
+        for (id, (time, author, message, file)) in [
+            (100, "bot", "auto: one\n", "a"),
+            (130, "bot", "auto: two\n", "b"),
+            (150, "bot", "auto: two\n", "a"),
+            // Too late.
+            (300, "bot", "auto: three\n", "c"),
+            // A different author.
+            (310, "adam", "auto: four\n", "c"),
+            // A message that doesn't match, which also ends the run.
+            (320, "bot", "manual\n", "d"),
+            (330, "bot", "auto: five\n", "d"),
+        ]
+        .iter()
+        .enumerate()
+        {
+            detector.add_file_commit(
+                PathBuf::from(file),
+                id + 1,
+                author.to_string(),
+                message.to_string(),
+                timestamp(*time),
+                None,
+            );
+        }
+        let patchsets: Vec<PatchSet<usize>> = detector.into_patchset_iter().collect();
+
+        let have: Vec<_> = rule
+            .apply(patchsets)
+            .into_iter()
+            .map(|patchset| {
+                (
+                    patchset.time,
+                    patchset.message.clone(),
+                    patchset
+                        .file_revision_iter()
+                        .map(|(file, ids)| (file.display().to_string(), ids.clone()))
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            have,
+            vec![
+                (
+                    timestamp(150),
+                    String::from("auto: one\n\nauto: two\n"),
+                    vec![
+                        (String::from("a"), vec![1, 3]),
+                        (String::from("b"), vec![2])
+                    ]
+                ),
+                (
+                    timestamp(300),
+                    String::from("auto: three\n"),
+                    vec![(String::from("c"), vec![4])]
+                ),
+                (
+                    timestamp(310),
+                    String::from("auto: four\n"),
+                    vec![(String::from("c"), vec![5])]
+                ),
+                (
+                    timestamp(320),
+                    String::from("manual\n"),
+                    vec![(String::from("d"), vec![6])]
+                ),
+                (
+                    timestamp(330),
+                    String::from("auto: five\n"),
+                    vec![(String::from("d"), vec![7])]
+                ),
+            ]
+        );
+    }
+
+    fn timestamp(ts: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(ts)
+    }
+}
@@ -0,0 +1,174 @@
+//! The `state` subcommand, which converts the store to and from portable
+//! JSON, so that a conversion can be moved between machines or inspected, and
+//! lists what it has imported.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use git_cvs_fast_import_state::{Compression, Manager, Sections};
+use structopt::StructOpt;
+use thiserror::Error;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "git-cvs-fast-import state",
+    about = "Exports the store to a portable format, imports a store from it, or lists the branches and tags in it."
+)]
+pub(crate) enum Opt {
+    #[structopt(about = "Writes the store to standard output.")]
+    Export {
+        #[structopt(
+            long,
+            default_value = "json",
+            help = "the format to write the store in; only json is supported"
+        )]
+        format: Format,
+
+        #[structopt(
+            short,
+            long,
+            parse(from_os_str),
+            help = "the file storing the repository metadata"
+        )]
+        store: PathBuf,
+    },
+
+    #[structopt(
+        about = "Lists the branches and tags in the store, along with the mark of the commit each one was last imported as, without loading the file revisions or marks."
+    )]
+    Refs {
+        #[structopt(
+            short,
+            long,
+            parse(from_os_str),
+            help = "the file storing the repository metadata"
+        )]
+        store: PathBuf,
+    },
+
+    #[structopt(
+        about = "Creates a store from the JSON written by state export on standard input."
+    )]
+    Import {
+        #[structopt(long, help = "replace the store if it already exists")]
+        force: bool,
+
+        #[structopt(
+            short,
+            long,
+            parse(from_os_str),
+            help = "the file to write the repository metadata to"
+        )]
+        store: PathBuf,
+
+        #[structopt(
+            long,
+            default_value = "0",
+            help = "how to compress the store: none, or a zstd level, where 0 is zstd's default level"
+        )]
+        state_compression: Compression,
+    },
+}
+
+/// The formats that the store can be exported in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Format {
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = UnknownFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            _ => Err(UnknownFormatError(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("unknown format {0:?}: the only supported format is json")]
+pub(crate) struct UnknownFormatError(String);
+
+/// Runs the subcommand, reading from `input` and writing to `output`.
+pub(crate) async fn run(
+    opt: &Opt,
+    input: &mut dyn Read,
+    output: &mut dyn Write,
+) -> anyhow::Result<()> {
+    match opt {
+        Opt::Export {
+            format: Format::Json,
+            store,
+        } => {
+            let state = Manager::deserialize_from(BufReader::new(File::open(store)?)).await?;
+            state.export_json(BufWriter::new(output)).await?;
+        }
+        Opt::Refs { store } => {
+            let state = Manager::deserialize_sections_from(
+                BufReader::new(File::open(store)?),
+                Sections {
+                    patchsets: true,
+                    tags: true,
+                    ..Sections::NONE
+                },
+            )
+            .await?;
+            write_refs(&state, output).await?;
+        }
+        Opt::Import {
+            force,
+            store,
+            state_compression,
+        } => {
+            if store.exists() && !force {
+                anyhow::bail!(
+                    "{} already exists; use --force to replace it",
+                    store.display()
+                );
+            }
+
+            let state = Manager::import_json(input).await?;
+            state
+                .serialize_into(BufWriter::new(File::create(store)?), *state_compression)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes one line for each branch and tag in the state, with the kind of
+/// ref, its name, and the mark of its commit, separated by tabs, in the same
+/// form as the `list` subcommand. Branches are listed first.
+async fn write_refs(state: &Manager, output: &mut dyn Write) -> anyhow::Result<()> {
+    // The fake commits for tags are recorded on a branch named after the tag,
+    // so those branches are only listed as tags.
+    let tags = state.get_tags_with_marks().await;
+    for branch in state.get_branches().await {
+        if tags.contains(&branch) {
+            continue;
+        }
+        if let Some(mark) = state.get_last_patchset_mark_on_branch(&branch).await {
+            writeln!(
+                output,
+                "branch\t{}\t{}",
+                String::from_utf8_lossy(&branch),
+                mark
+            )?;
+        }
+    }
+
+    for tag in tags.iter() {
+        if let Some(mark) = state.get_mark_for_tag(tag).await {
+            writeln!(output, "tag\t{}\t{}", String::from_utf8_lossy(tag), mark)?;
+        }
+    }
+
+    Ok(())
+}
@@ -1,31 +1,125 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use git_cvs_fast_import_process::Output;
 use git_cvs_fast_import_state::Manager;
 use git_fast_import::{CommitBuilder, FileCommand, Identity, Mark};
+use thiserror::Error;
+
+use crate::{refs, root::RewriteRoot};
 
 pub(crate) struct Processor {
     state: Manager,
     output: Output,
     identity: Identity,
+    orphans: OrphanTags,
+    head_branch: Vec<u8>,
+    rewrite_root: Option<RewriteRoot>,
+    trailers: Vec<(&'static str, String)>,
+    update_refs: bool,
 }
 
-enum Parent {
+/// The parent given to the fake commit for a tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Parent {
+    /// The previous fake commit for the tag, since the tag has moved.
     PreviousTag(Mark),
-    FileContent { mark: Mark, time: SystemTime },
+
+    /// The last patchset that includes any of the tag's file revisions.
+    FileContent {
+        mark: Mark,
+        time: SystemTime,
+    },
+
+    /// The head branch, as the tag has no other parent.
+    BranchHead(Mark),
+
+    /// An empty root commit, as the tag has no other parent.
+    EmptyRoot(Mark),
+
     None,
 }
 
+impl Parent {
+    pub(crate) fn mark(&self) -> Option<Mark> {
+        match self {
+            Parent::PreviousTag(mark)
+            | Parent::FileContent { mark, .. }
+            | Parent::BranchHead(mark)
+            | Parent::EmptyRoot(mark) => Some(*mark),
+            Parent::None => None,
+        }
+    }
+}
+
+/// What to parent the fake commit for a tag on when none of its file revisions
+/// are in a patchset, such as when they're only on a vendor branch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OrphanTags {
+    /// The fake commit is a root commit.
+    Orphan,
+
+    /// The fake commit is parented on an empty root commit, which is shared
+    /// between all such tags.
+    EmptyRoot,
+
+    /// The fake commit is parented on the head of the head branch.
+    Head,
+}
+
+impl OrphanTags {
+    pub(crate) const VARIANTS: &'static [&'static str] = &["orphan", "empty-root", "head"];
+}
+
+impl FromStr for OrphanTags {
+    type Err = UnknownOrphanTagsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "orphan" => Ok(Self::Orphan),
+            "empty-root" => Ok(Self::EmptyRoot),
+            "head" => Ok(Self::Head),
+            _ => Err(UnknownOrphanTagsError(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("unknown orphan tag handling: {0}")]
+pub(crate) struct UnknownOrphanTagsError(String);
+
 impl Processor {
-    pub(crate) fn new(state: &Manager, output: &Output, identity: Identity) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        state: &Manager,
+        output: &Output,
+        identity: Identity,
+        orphans: OrphanTags,
+        head_branch: &str,
+        rewrite_root: Option<RewriteRoot>,
+        import_trailer: Option<&str>,
+        update_refs: bool,
+    ) -> Self {
         Self {
             state: state.clone(),
             output: output.clone(),
             identity,
+            orphans,
+            head_branch: head_branch.as_bytes().to_vec(),
+            rewrite_root,
+            trailers: import_trailer
+                .map(|value| (crate::run::TRAILER, value.to_string()))
+                .into_iter()
+                .collect(),
+            update_refs,
         }
     }
 
-    pub(crate) async fn process(&self, tag: &[u8]) -> anyhow::Result<()> {
+    /// Creates or moves the given tag, returning the mark of the fake commit
+    /// along with its parent if a new fake commit was needed.
+    pub(crate) async fn process(&self, tag: &[u8]) -> anyhow::Result<Option<(Mark, Parent)>> {
         // For each tag, we need to fake a Git commit with the correct content,
         // since CVS tags don't map onto Git tags especially gracefully, then
         // send a relevant tag.
@@ -41,25 +135,42 @@ impl Processor {
         let mut parent = Parent::None;
         log::trace!("processing tag {}", &tag_str);
 
+        // If this tag has already been seen previously, then there will be a
+        // previous fake commit, along with a digest of its file revisions that
+        // we can compare against without fetching anything else. Stores written
+        // before digests were added won't have one, so those tags are compared
+        // the slow way below.
+        let previous = self.state.get_mark_for_tag(tag).await;
+        let unchanged = match previous {
+            Some(_) => self.state.is_tag_unchanged(tag).await,
+            None => None,
+        };
+        if unchanged == Some(true) {
+            log::trace!("not changing tag {}, as its digest matches", &tag_str);
+            return Ok(None);
+        }
+
         let file_revision_iter = self.state.get_file_revisions_for_tag(tag).await;
         let file_revision_ids = match file_revision_iter.iter() {
             Some(ids) => ids,
             None => {
                 log::debug!("tag {} does not have any file revisions", &tag_str);
-                return Ok(());
+                return Ok(None);
             }
         };
 
-        // If this tag has already been seen previously, then there will be a
-        // previous fake commit. Let's see if there is, and then we can figure
-        // out if the content has changed.
-        if let Some(mark) = self.state.get_mark_for_tag(tag).await {
-            // Grab the patchset content and compare it to what we have now.
-            let patchset = self.state.get_patchset_from_mark(&mark).await?;
-            if &patchset.file_revisions == file_revision_ids {
-                // Nothing to do here; continue.
-                log::trace!("not changing tag {}, as content matches", &tag_str);
-                return Ok(());
+        if let Some(mark) = previous {
+            if unchanged.is_none() {
+                // Grab the patchset content and compare it to what we have now.
+                let patchset = self.state.get_patchset_from_mark(&mark).await?;
+                if &patchset.file_revisions == file_revision_ids {
+                    // Nothing to do here, other than recording the digest for
+                    // next time, which needs the read lock to be released.
+                    log::trace!("not changing tag {}, as content matches", &tag_str);
+                    drop(file_revision_iter);
+                    self.state.record_tag_digest(tag).await;
+                    return Ok(None);
+                }
             }
 
             // Since it doesn't match, we'll have to create a new fake commit,
@@ -68,36 +179,47 @@ impl Processor {
             parent = Parent::PreviousTag(mark);
         }
 
-        let mut builder = CommitBuilder::new(format!("refs/heads/tags/{}", &tag_str));
-        builder
-            .committer(self.identity.clone())
-            .message(format!("Fake commit for tag {}.", &tag_str));
+        let mut builder = CommitBuilder::new(refs::commit_ref(
+            &format!("tags/{}", &tag_str),
+            self.update_refs,
+        ));
+        builder.message(crate::trailer_message(
+            &format!("Fake commit for tag {}.", &tag_str),
+            &self.trailers,
+        ));
 
         // Unlike regular commits, we'll remove all the file content and
         // then attach the new content that is known to be on the tag. This
         // means that Git will have to figure out what the diffs look like.
         builder.add_file_command(FileCommand::DeleteAll);
 
+        // The author of the fake commit is the author of the most recent file
+        // revision in the tag, so that blame on the tag branch points at
+        // someone who contributed to it, rather than the tag identity.
         let mut time = UNIX_EPOCH;
+        let mut author = None;
         for file_revision_id in file_revision_ids.iter() {
             let file_revision = self
                 .state
                 .get_file_revision_by_id(*file_revision_id)
                 .await?;
 
+            let path = match &self.rewrite_root {
+                Some(root) => root.join(&file_revision.key.path),
+                None => file_revision.key.path.clone(),
+            };
             match file_revision.mark {
                 Some(mark) => builder.add_file_command(FileCommand::Modify {
                     mode: git_fast_import::Mode::Normal,
                     mark: mark.into(),
-                    path: file_revision.key.path.clone(),
-                }),
-                None => builder.add_file_command(FileCommand::Delete {
-                    path: file_revision.key.path.clone(),
+                    path,
                 }),
+                None => builder.add_file_command(FileCommand::Delete { path }),
             };
 
-            if file_revision.time > time {
-                time = file_revision.time;
+            if file_revision.time > time || author.is_none() {
+                time = time.max(file_revision.time);
+                author = Some(file_revision.author.clone());
             }
 
             if let Parent::PreviousTag(_) = parent {
@@ -117,6 +239,10 @@ impl Processor {
                         // Nothing to do, since we have a previous tag to parent
                         // on.
                     }
+                    Parent::BranchHead(_) | Parent::EmptyRoot(_) => {
+                        // These are only chosen once every file revision has
+                        // been checked.
+                    }
                     Parent::FileContent {
                         mark: _mark,
                         time: parent_time,
@@ -138,6 +264,22 @@ impl Processor {
             }
         }
 
+        // If the tag is an orphan, then we may need to find it a parent.
+        if parent == Parent::None {
+            parent = match self.orphans {
+                OrphanTags::Orphan => Parent::None,
+                OrphanTags::EmptyRoot => Parent::EmptyRoot(self.empty_root(tag).await?),
+                OrphanTags::Head => match self
+                    .state
+                    .get_last_patchset_mark_on_branch(&self.head_branch)
+                    .await
+                {
+                    Some(mark) => Parent::BranchHead(mark.into()),
+                    None => Parent::None,
+                },
+            };
+        }
+
         // Set the parent commit, if any.
         match parent {
             Parent::PreviousTag(mark) => {
@@ -156,7 +298,33 @@ impl Processor {
                 );
                 builder.from(mark);
             }
-            Parent::None => {}
+            Parent::BranchHead(mark) => {
+                log::debug!(
+                    "tag {} has no parent, so is parented on the head branch at commit {}",
+                    &tag_str,
+                    mark
+                );
+                builder.from(mark);
+            }
+            Parent::EmptyRoot(mark) => {
+                log::debug!(
+                    "tag {} has no parent, so is parented on the empty root commit {}",
+                    &tag_str,
+                    mark
+                );
+                builder.from(mark);
+            }
+            Parent::None => {
+                log::debug!("tag {} has no parent, so is a root commit", &tag_str);
+            }
+        }
+
+        // The commit is committed at the time of the most recent file
+        // revision, rather than the current time, so that importing the same
+        // CVS repository again produces the same commit.
+        builder.committer(self.committer(time)?);
+        if let Some(author) = author {
+            builder.author(crate::author_identity(&author, time)?);
         }
 
         // Now we can send the commit.
@@ -171,9 +339,54 @@ impl Processor {
 
         self.state.add_tag_mark(tag, mark).await;
 
-        // And we can tag the commit.
-        self.output.lightweight_tag(&tag_str, mark).await?;
+        // And we can tag the commit, unless refs are being left alone.
+        if self.update_refs {
+            self.output.lightweight_tag(&tag_str, mark).await?;
+        }
+
+        Ok(Some((mark, parent)))
+    }
+
+    /// Returns the mark of the empty root commit that orphan tags are parented
+    /// on, creating it on the tag's fake branch if it doesn't exist yet.
+    ///
+    /// The empty root is recorded as a patchset at the Unix epoch without any
+    /// file revisions, which no real patchset can have, so that later runs can
+    /// find it again.
+    async fn empty_root(&self, tag: &[u8]) -> anyhow::Result<Mark> {
+        if let Some(mark) = self
+            .state
+            .get_mark_from_patchset_content(&UNIX_EPOCH, std::iter::empty())
+            .await
+        {
+            return Ok(mark);
+        }
+
+        let mut builder = CommitBuilder::new(refs::commit_ref(
+            &format!("tags/{}", String::from_utf8_lossy(tag)),
+            self.update_refs,
+        ));
+        builder
+            .committer(self.committer(UNIX_EPOCH)?)
+            .message(crate::trailer_message(
+                "Empty root commit for tags without a parent.",
+                &self.trailers,
+            ));
+
+        let mark = self.output.commit(builder.build()?).await?;
+        self.state
+            .add_patchset(mark, tag, &UNIX_EPOCH, std::iter::empty())
+            .await;
+
+        Ok(mark)
+    }
 
-        Ok(())
+    /// Returns the tag identity at the given time.
+    fn committer(&self, when: SystemTime) -> anyhow::Result<Identity> {
+        Ok(Identity::new(
+            self.identity.name().map(String::from),
+            self.identity.email().to_string(),
+            when,
+        )?)
     }
 }
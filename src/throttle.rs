@@ -0,0 +1,115 @@
+//! A limit on how quickly RCS files are read from the CVSROOT, set with
+//! `--max-read-bytes-per-sec`, so that an import running as a mirror doesn't
+//! saturate a shared server, such as an NFS server hosting the CVSROOT.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// A read rate limit shared between the discovery workers.
+///
+/// Each read is given a slot after the slots of the reads before it, as long
+/// as the read would take at the limit, so a file larger than a second's worth
+/// of reading is still read in one go, but delays the reads that follow it.
+/// Time spent idle isn't saved up, so the limit isn't an average over the whole
+/// run.
+#[derive(Debug, Clone)]
+pub(crate) struct ReadLimiter {
+    bytes_per_sec: u64,
+    schedule: Arc<Mutex<Schedule>>,
+}
+
+#[derive(Debug)]
+struct Schedule {
+    /// The time from which the next read may start.
+    next: Instant,
+
+    /// The time that reads have been delayed for. Workers usually wait at the
+    /// same time, so this is the wall clock time during which any worker was
+    /// waiting, rather than the sum of every wait.
+    waited: Duration,
+
+    /// The end of the latest wait included in `waited`.
+    waited_until: Instant,
+}
+
+impl ReadLimiter {
+    /// Creates a limiter, returning `None` if the limit is zero, which means
+    /// that reads aren't limited.
+    pub(crate) fn new(bytes_per_sec: usize) -> Option<Self> {
+        let now = Instant::now();
+        (bytes_per_sec > 0).then(|| Self {
+            bytes_per_sec: bytes_per_sec as u64,
+            schedule: Arc::new(Mutex::new(Schedule {
+                next: now,
+                waited: Duration::ZERO,
+                waited_until: now,
+            })),
+        })
+    }
+
+    /// Waits until the given number of bytes can be read without exceeding
+    /// the limit.
+    pub(crate) async fn acquire(&self, bytes: u64) {
+        let now = Instant::now();
+        let start = self.reserve(bytes, now);
+        if start > now {
+            tokio::time::sleep_until(start.into()).await;
+        }
+    }
+
+    /// Returns the wall clock time during which reads were delayed.
+    pub(crate) fn waited(&self) -> Duration {
+        self.schedule.lock().unwrap().waited
+    }
+
+    /// Reserves the next slot for a read of the given size, returning the
+    /// time at which it may start.
+    fn reserve(&self, bytes: u64, now: Instant) -> Instant {
+        let mut schedule = self.schedule.lock().unwrap();
+        let start = schedule.next.max(now);
+        schedule.next = start + Duration::from_secs_f64(bytes as f64 / self.bytes_per_sec as f64);
+
+        // Slots are handed out in order, so each wait ends no earlier than the
+        // waits before it, and only the part after them is new.
+        let from = now.max(schedule.waited_until);
+        if start > from {
+            schedule.waited += start - from;
+            schedule.waited_until = start;
+        }
+
+        start
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserve() {
+        assert!(ReadLimiter::new(0).is_none());
+
+        let limiter = ReadLimiter::new(100).unwrap();
+        let now = Instant::now();
+
+        // The first read starts straight away, and the reads after it wait for
+        // the time it would take at the limit.
+        assert_eq!(limiter.reserve(200, now), now);
+        assert_eq!(limiter.reserve(50, now), now + Duration::from_secs(2));
+        assert_eq!(
+            limiter.reserve(0, now + Duration::from_secs(1)),
+            now + Duration::from_millis(2500)
+        );
+
+        // Waits that overlap are only counted once.
+        assert_eq!(limiter.waited(), Duration::from_millis(2500));
+
+        // Time spent idle isn't saved up for later reads.
+        let later = now + Duration::from_secs(10);
+        assert_eq!(limiter.reserve(100, later), later);
+        assert_eq!(limiter.reserve(100, later), later + Duration::from_secs(1));
+        assert_eq!(limiter.waited(), Duration::from_millis(3500));
+    }
+}
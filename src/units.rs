@@ -0,0 +1,185 @@
+//! Parsing for command line values with human friendly units, such as `90s`
+//! or `10MB`.
+
+use std::{convert::TryFrom, time::Duration};
+
+use thiserror::Error;
+
+/// Parses a duration made up of one or more numbers with units, such as `90s`,
+/// `2m`, `1h30m`, or `2 minutes`. A number without a unit is in seconds.
+pub(crate) fn parse_duration(s: &str) -> Result<Duration, UnitError> {
+    let mut rest = s.trim();
+    if rest.is_empty() {
+        return Err(UnitError::Empty);
+    }
+
+    let mut total = Duration::ZERO;
+    while !rest.is_empty() {
+        let (number, unit, tail) = split_component(rest);
+        let number: u64 = number
+            .parse()
+            .map_err(|_| UnitError::Number(s.to_string()))?;
+        let per = match unit.to_ascii_lowercase().as_str() {
+            "ms" | "msec" | "msecs" | "millisecond" | "milliseconds" => Duration::from_millis(1),
+            "" | "s" | "sec" | "secs" | "second" | "seconds" => Duration::from_secs(1),
+            "m" | "min" | "mins" | "minute" | "minutes" => Duration::from_secs(60),
+            "h" | "hr" | "hrs" | "hour" | "hours" => Duration::from_secs(60 * 60),
+            "d" | "day" | "days" => Duration::from_secs(24 * 60 * 60),
+            _ => {
+                return Err(UnitError::Unit {
+                    value: s.to_string(),
+                    unit: unit.to_string(),
+                    expected: "ms, s, m, h, or d",
+                })
+            }
+        };
+
+        total = u32::try_from(number)
+            .ok()
+            .and_then(|number| per.checked_mul(number))
+            .and_then(|component| total.checked_add(component))
+            .ok_or_else(|| UnitError::Overflow(s.to_string()))?;
+        rest = tail;
+    }
+
+    Ok(total)
+}
+
+/// Parses a size in bytes, such as `512`, `64KiB`, or `10MB`. As with `dd`,
+/// `KB`, `MB`, and `GB` are powers of 1000, whereas `K`, `M`, and `G` are the
+/// same as `KiB`, `MiB`, and `GiB`, which are powers of 1024.
+pub(crate) fn parse_size(s: &str) -> Result<usize, UnitError> {
+    let trimmed = s.trim();
+    if trimmed.is_empty() {
+        return Err(UnitError::Empty);
+    }
+
+    let (number, unit, tail) = split_component(trimmed);
+    if !tail.is_empty() {
+        return Err(UnitError::Number(s.to_string()));
+    }
+    let number: usize = number
+        .parse()
+        .map_err(|_| UnitError::Number(s.to_string()))?;
+    let per: usize = match unit.to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1000,
+        "k" | "kib" => 1 << 10,
+        "mb" => 1000 * 1000,
+        "m" | "mib" => 1 << 20,
+        "gb" => 1000 * 1000 * 1000,
+        "g" | "gib" => 1 << 30,
+        _ => {
+            return Err(UnitError::Unit {
+                value: s.to_string(),
+                unit: unit.to_string(),
+                expected: "B, KB, KiB, MB, MiB, GB, or GiB",
+            })
+        }
+    };
+
+    number
+        .checked_mul(per)
+        .ok_or_else(|| UnitError::Overflow(s.to_string()))
+}
+
+/// Splits the leading number and unit from a value, returning the number, the
+/// unit, and the rest of the value with any leading whitespace removed.
+fn split_component(s: &str) -> (&str, &str, &str) {
+    let number_end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, rest) = s.split_at(number_end);
+
+    let rest = rest.trim_start();
+    let unit_end = rest
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(rest.len());
+    let (unit, tail) = rest.split_at(unit_end);
+
+    (number, unit, tail.trim_start())
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub(crate) enum UnitError {
+    #[error("a value must be provided")]
+    Empty,
+
+    #[error("invalid number in {0:?}")]
+    Number(String),
+
+    #[error("{0:?} is too large")]
+    Overflow(String),
+
+    #[error("unknown unit {unit:?} in {value:?}: expected {expected}")]
+    Unit {
+        value: String,
+        unit: String,
+        expected: &'static str,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        for (s, secs) in [
+            ("120", 120),
+            ("90s", 90),
+            ("2m", 120),
+            ("1h", 3600),
+            ("1h30m", 5400),
+            ("1h 30m 5s", 5405),
+            ("2 minutes", 120),
+            ("10 MIN", 600),
+            ("1d", 86400),
+        ] {
+            assert_eq!(parse_duration(s), Ok(Duration::from_secs(secs)), "{}", s);
+        }
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+
+        assert_eq!(parse_duration(" "), Err(UnitError::Empty));
+        assert_eq!(
+            parse_duration("soon"),
+            Err(UnitError::Number("soon".into()))
+        );
+        assert_eq!(
+            parse_duration("1.5h"),
+            Err(UnitError::Number("1.5h".into()))
+        );
+        assert_eq!(parse_duration("-1s"), Err(UnitError::Number("-1s".into())));
+        assert_eq!(
+            parse_duration("2w"),
+            Err(UnitError::Unit {
+                value: "2w".into(),
+                unit: "w".into(),
+                expected: "ms, s, m, h, or d",
+            })
+        );
+        assert_eq!(
+            parse_duration("99999999999d"),
+            Err(UnitError::Overflow("99999999999d".into()))
+        );
+    }
+
+    #[test]
+    fn test_parse_size() {
+        for (s, size) in [
+            ("512", 512),
+            ("512B", 512),
+            ("10MB", 10_000_000),
+            ("10 MiB", 10 << 20),
+            ("10M", 10 << 20),
+            ("64kib", 64 << 10),
+            ("1GB", 1_000_000_000),
+            ("0", 0),
+        ] {
+            assert_eq!(parse_size(s), Ok(size), "{}", s);
+        }
+
+        assert_eq!(parse_size(""), Err(UnitError::Empty));
+        assert_eq!(parse_size("MB"), Err(UnitError::Number("MB".into())));
+        assert_eq!(parse_size("1MB2"), Err(UnitError::Number("1MB2".into())));
+        assert!(matches!(parse_size("10TB"), Err(UnitError::Unit { .. })));
+    }
+}
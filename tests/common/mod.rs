@@ -0,0 +1,493 @@
+//! Helpers shared by the integration tests.
+//!
+//! [`Harness`] runs the full `git-cvs-fast-import` pipeline against a
+//! temporary CVSROOT, store, and Git repository, and [`RcsFile`] builds the
+//! `,v` files within the CVSROOT without needing CVS or RCS to be installed.
+
+// Each integration test only uses some of the helpers.
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+use tempfile::TempDir;
+
+/// Runs `git` with the given arguments, panicking if it fails, and returns its
+/// standard output.
+pub fn git(args: &[&str]) -> String {
+    let output = Command::new("git").args(args).output().unwrap();
+    assert!(
+        output.status.success(),
+        "git {:?} failed: {:?}",
+        args,
+        output
+    );
+
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Initialises a bare Git repository at the given path.
+pub fn init_repo(path: &Path) {
+    git(&["init", "--quiet", "--bare", path.to_str().unwrap()]);
+}
+
+/// A temporary CVSROOT, store, and Git repository to import between.
+///
+/// The store and repository persist between calls to [`Harness::import`], so
+/// calling it again after changing the CVSROOT performs an incremental import.
+#[derive(Debug)]
+pub struct Harness {
+    dir: TempDir,
+    backend: String,
+}
+
+impl Harness {
+    /// Creates an empty CVSROOT and Git repository, which will be imported
+    /// with the given backend.
+    pub fn new(backend: &str) -> Self {
+        let harness = Self {
+            dir: TempDir::new().unwrap(),
+            backend: backend.to_string(),
+        };
+
+        fs::create_dir(harness.cvsroot()).unwrap();
+        init_repo(&harness.repo());
+        harness
+    }
+
+    pub fn cvsroot(&self) -> PathBuf {
+        self.dir.path().join("cvsroot")
+    }
+
+    pub fn repo(&self) -> PathBuf {
+        self.dir.path().join("repo.git")
+    }
+
+    pub fn store(&self) -> PathBuf {
+        self.dir.path().join("store")
+    }
+
+    /// Returns the path of a scratch file outside the CVSROOT and repository,
+    /// such as for a report.
+    pub fn scratch(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    /// Writes an RCS file to the given path within the CVSROOT, replacing any
+    /// existing file.
+    pub fn write(&self, path: &str, file: &RcsFile) {
+        let path = self.cvsroot().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, file.to_string()).unwrap();
+    }
+
+    /// Imports the CVSROOT with any additional arguments, panicking if the
+    /// import fails.
+    pub fn import(&self, args: &[&str]) {
+        let output = self.try_import(args);
+        assert!(
+            output.status.success(),
+            "import failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    /// Imports the CVSROOT with any additional arguments, returning the output
+    /// of the import whether or not it succeeded.
+    pub fn try_import(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_git-cvs-fast-import"))
+            .arg("--backend")
+            .arg(&self.backend)
+            .arg("--cvsroot")
+            .arg(self.cvsroot())
+            .arg("--git-repo")
+            .arg(self.repo())
+            .arg("--store")
+            .arg(self.store())
+            .args(args)
+            .output()
+            .unwrap()
+    }
+
+    /// Runs `git-cvs-fast-import cat` against the CVSROOT with the given
+    /// arguments, returning its output.
+    pub fn cat(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_git-cvs-fast-import"))
+            .arg("cat")
+            .arg("--cvsroot")
+            .arg(self.cvsroot())
+            .args(args)
+            .output()
+            .unwrap()
+    }
+
+    /// Runs `git-cvs-fast-import list` on the CVSROOT with any additional
+    /// arguments, returning its output.
+    pub fn list(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_git-cvs-fast-import"))
+            .arg("list")
+            .arg("--cvsroot")
+            .arg(self.cvsroot())
+            .args(args)
+            .output()
+            .unwrap()
+    }
+
+    /// Runs `git-cvs-fast-import log` against the CVSROOT with the given
+    /// arguments, returning its output.
+    pub fn rlog(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_git-cvs-fast-import"))
+            .arg("log")
+            .arg("--cvsroot")
+            .arg(self.cvsroot())
+            .args(args)
+            .output()
+            .unwrap()
+    }
+
+    /// Runs `git-cvs-fast-import state` with the given arguments and standard
+    /// input, returning its output.
+    pub fn state(&self, args: &[&str], stdin: &[u8]) -> Output {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_git-cvs-fast-import"))
+            .arg("state")
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(stdin).unwrap();
+        child.wait_with_output().unwrap()
+    }
+
+    /// Runs `git` within the repository.
+    pub fn git(&self, args: &[&str]) -> String {
+        let repo = self.repo();
+        let mut full = vec!["-C", repo.to_str().unwrap()];
+        full.extend_from_slice(args);
+        git(&full)
+    }
+
+    /// Returns the refs in the repository.
+    pub fn refs(&self) -> Vec<String> {
+        lines(&self.git(&["for-each-ref", "--format=%(refname)"]))
+    }
+
+    /// Returns the subject and author e-mail, which is the CVS username, of each
+    /// commit reachable from the given revision, newest first.
+    pub fn log(&self, rev: &str) -> Vec<String> {
+        lines(&self.git(&["log", "--format=%s (%ae)", rev]))
+    }
+
+    /// Returns the commit ID of the given revision.
+    pub fn rev_parse(&self, rev: &str) -> String {
+        self.git(&["rev-parse", rev]).trim().to_string()
+    }
+
+    /// Returns the paths of the files in the tree of the given revision.
+    pub fn ls_tree(&self, rev: &str) -> Vec<String> {
+        lines(&self.git(&["ls-tree", "-r", "--name-only", rev]))
+    }
+
+    /// Returns the content of the given file at the given revision.
+    pub fn show(&self, rev: &str, path: &str) -> String {
+        self.git(&["show", &format!("{}:{}", rev, path)])
+    }
+}
+
+fn lines(s: &str) -> Vec<String> {
+    s.lines().map(String::from).collect()
+}
+
+/// A builder for RCS files, as written by CVS.
+///
+/// Revisions are given with their full content, and converted into the deltas
+/// RCS stores: the head revision of the trunk has its full text, earlier trunk
+/// revisions are reverse deltas, and branch revisions are forward deltas from
+/// the revision they branch from. Each delta simply replaces every line, which
+/// is valid, if not what RCS would choose.
+#[derive(Debug, Default)]
+pub struct RcsFile {
+    revisions: Vec<Revision>,
+    symbols: Vec<(String, String)>,
+    integrity: Option<String>,
+    expand: Option<String>,
+}
+
+#[derive(Debug)]
+struct Revision {
+    num: String,
+    date: String,
+    author: String,
+    log: String,
+    dead: bool,
+    content: String,
+}
+
+impl RcsFile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a revision with the given content, which must be empty or end with
+    /// a newline. `num` is the revision number, such as `1.2` or `1.1.2.1`,
+    /// and `date` is in the RCS format, such as `2021.08.20.17.00.00`.
+    ///
+    /// Revisions must be added in order on each branch, and branch revisions
+    /// after the revision they branch from.
+    pub fn revision(
+        mut self,
+        num: &str,
+        date: &str,
+        author: &str,
+        log: &str,
+        content: &str,
+    ) -> Self {
+        assert!(content.is_empty() || content.ends_with('\n'));
+        self.revisions.push(Revision {
+            num: num.to_string(),
+            date: date.to_string(),
+            author: author.to_string(),
+            log: log.to_string(),
+            dead: false,
+            content: content.to_string(),
+        });
+        self
+    }
+
+    /// Adds a revision that deletes the file, as `cvs remove` does.
+    pub fn delete(mut self, num: &str, date: &str, author: &str, log: &str) -> Self {
+        let content = self
+            .parent(num)
+            .map(|parent| parent.content.clone())
+            .unwrap_or_default();
+        self.revisions.push(Revision {
+            num: num.to_string(),
+            date: date.to_string(),
+            author: author.to_string(),
+            log: log.to_string(),
+            dead: true,
+            content,
+        });
+        self
+    }
+
+    /// Adds a symbol, which is a tag if `num` is a revision, or a branch if it's
+    /// a magic branch number, such as `1.1.0.2`.
+    pub fn symbol(mut self, name: &str, num: &str) -> Self {
+        self.symbols.push((name.to_string(), num.to_string()));
+        self
+    }
+
+    /// Sets the integrity string, which must not contain `@`.
+    pub fn integrity(mut self, integrity: &str) -> Self {
+        self.integrity = Some(integrity.to_string());
+        self
+    }
+
+    /// Sets the keyword substitution mode, such as `b` for a binary file.
+    pub fn expand(mut self, mode: &str) -> Self {
+        self.expand = Some(mode.to_string());
+        self
+    }
+
+    fn get(&self, num: &str) -> Option<&Revision> {
+        self.revisions.iter().find(|revision| revision.num == num)
+    }
+
+    /// Returns the revision that the given revision is derived from.
+    fn parent(&self, num: &str) -> Option<&Revision> {
+        let parts: Vec<u32> = num.split('.').map(|part| part.parse().unwrap()).collect();
+        let (last, prefix) = parts.split_last().unwrap();
+
+        let parent = if *last > 1 {
+            let mut parent = prefix.to_vec();
+            parent.push(last - 1);
+            parent
+        } else if prefix.len() > 1 {
+            prefix[..prefix.len() - 1].to_vec()
+        } else {
+            return None;
+        };
+
+        self.get(&join(&parent))
+    }
+
+    fn is_trunk(num: &str) -> bool {
+        num.split('.').count() == 2
+    }
+
+    /// Returns the trunk revisions, newest first, followed by the branch
+    /// revisions in the order they were added.
+    fn ordered(&self) -> Vec<&Revision> {
+        let mut trunk: Vec<&Revision> = self
+            .revisions
+            .iter()
+            .filter(|revision| Self::is_trunk(&revision.num))
+            .collect();
+        trunk.reverse();
+
+        trunk
+            .into_iter()
+            .chain(
+                self.revisions
+                    .iter()
+                    .filter(|revision| !Self::is_trunk(&revision.num)),
+            )
+            .collect()
+    }
+
+    /// Returns the revision that follows the given revision in the `next`
+    /// field: the previous trunk revision, or the next revision on a branch.
+    fn next(&self, revision: &Revision) -> Option<&Revision> {
+        if Self::is_trunk(&revision.num) {
+            self.parent(&revision.num)
+        } else {
+            self.revisions.iter().find(|other| {
+                !Self::is_trunk(&other.num)
+                    && self.parent(&other.num).map(|parent| parent.num.as_str())
+                        == Some(revision.num.as_str())
+                    && branch_of(&other.num) == branch_of(&revision.num)
+            })
+        }
+    }
+
+    /// Returns the first revision of each branch that starts at the given
+    /// revision.
+    fn branches(&self, revision: &Revision) -> Vec<&Revision> {
+        self.revisions
+            .iter()
+            .filter(|other| {
+                !Self::is_trunk(&other.num)
+                    && other.num.ends_with(".1")
+                    && branch_of(&other.num).rsplit_once('.').unwrap().0 == revision.num
+            })
+            .collect()
+    }
+
+    /// Returns the text of the delta for the given revision.
+    fn text(&self, revision: &Revision) -> String {
+        if Self::is_trunk(&revision.num) {
+            // Trunk revisions are stored as reverse deltas from the revision
+            // after them, except for the head.
+            match self.revisions.iter().rev().find(|other| {
+                Self::is_trunk(&other.num)
+                    && self.parent(&other.num).map(|parent| parent.num.as_str())
+                        == Some(revision.num.as_str())
+            }) {
+                Some(child) => replace(&child.content, &revision.content),
+                None => revision.content.clone(),
+            }
+        } else {
+            let parent = self.parent(&revision.num).unwrap();
+            replace(&parent.content, &revision.content)
+        }
+    }
+}
+
+impl std::fmt::Display for RcsFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ordered = self.ordered();
+        let head = ordered
+            .first()
+            .filter(|revision| Self::is_trunk(&revision.num))
+            .map(|revision| revision.num.as_str())
+            .unwrap_or("");
+
+        writeln!(f, "head\t{};", head)?;
+        writeln!(f, "access;")?;
+        write!(f, "symbols")?;
+        for (name, num) in self.symbols.iter() {
+            write!(f, "\n\t{}:{}", name, num)?;
+        }
+        writeln!(f, ";")?;
+        writeln!(f, "locks; strict;")?;
+        if let Some(integrity) = &self.integrity {
+            writeln!(f, "integrity\t@{}@;", integrity)?;
+        }
+        writeln!(f, "comment\t@# @;")?;
+        if let Some(mode) = &self.expand {
+            writeln!(f, "expand\t@{}@;", mode)?;
+        }
+        writeln!(f)?;
+
+        for revision in ordered.iter() {
+            writeln!(f)?;
+            writeln!(f, "{}", revision.num)?;
+            writeln!(
+                f,
+                "date\t{};\tauthor {};\tstate {};",
+                revision.date,
+                revision.author,
+                if revision.dead { "dead" } else { "Exp" }
+            )?;
+            write!(f, "branches")?;
+            for branch in self.branches(revision) {
+                write!(f, "\n\t{}", branch.num)?;
+            }
+            writeln!(f, ";")?;
+            writeln!(
+                f,
+                "next\t{};",
+                self.next(revision)
+                    .map(|next| next.num.as_str())
+                    .unwrap_or("")
+            )?;
+        }
+
+        writeln!(f)?;
+        writeln!(f)?;
+        writeln!(f, "desc")?;
+        writeln!(f, "@@")?;
+
+        for revision in ordered.iter() {
+            writeln!(f)?;
+            writeln!(f)?;
+            writeln!(f, "{}", revision.num)?;
+            writeln!(f, "log")?;
+            writeln!(f, "{}", quote(&format!("{}\n", revision.log)))?;
+            writeln!(f, "text")?;
+            writeln!(f, "{}", quote(&self.text(revision)))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn join(parts: &[u32]) -> String {
+    parts
+        .iter()
+        .map(|part| part.to_string())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Returns the branch number of a branch revision, such as `1.1.2` for
+/// `1.1.2.3`.
+fn branch_of(num: &str) -> &str {
+    num.rsplit_once('.').unwrap().0
+}
+
+/// Returns an ed script that replaces every line of `from` with `to`.
+fn replace(from: &str, to: &str) -> String {
+    let (from_lines, to_lines) = (from.lines().count(), to.lines().count());
+
+    let mut script = String::new();
+    if from_lines > 0 {
+        script.push_str(&format!("d1 {}\n", from_lines));
+    }
+    if to_lines > 0 {
+        script.push_str(&format!("a{} {}\n{}", from_lines, to_lines, to));
+    }
+    script
+}
+
+/// Quotes an RCS string.
+fn quote(s: &str) -> String {
+    format!("@{}@", s.replace('@', "@@"))
+}
@@ -0,0 +1,113 @@
+//! Golden file tests for conversions.
+//!
+//! Each directory within `tests/golden` contains a `cvsroot` to convert and a
+//! `stream` file containing the expected fast-import stream, as recorded by
+//! `--record`. An optional `args` file contains additional arguments to pass
+//! to the conversion, one per line. Set `UPDATE_GOLDEN=1` to rewrite the streams after an
+//! intentional change in the conversion output.
+//!
+//! These tests require `git` to be installed.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use tempfile::TempDir;
+
+mod common;
+use common::{git, init_repo};
+
+/// Returns each ref in the repository along with the tree it points to, which
+/// is independent of any identity timestamps that were normalised in the
+/// recording.
+fn ref_trees(repo: &Path) -> String {
+    git(&[
+        "-C",
+        repo.to_str().unwrap(),
+        "for-each-ref",
+        "--format=%(refname) %(tree)",
+    ])
+}
+
+/// Converts the given CVSROOT, returning the path to the Git repository and the
+/// recorded stream.
+fn convert(dir: &TempDir, cvsroot: &Path, args: &[String]) -> (PathBuf, String) {
+    let repo = dir.path().join("converted.git");
+    let record = dir.path().join("stream");
+    init_repo(&repo);
+
+    let status = Command::new(env!("CARGO_BIN_EXE_git-cvs-fast-import"))
+        .arg("--backend")
+        .arg("gitoxide")
+        .arg("--cvsroot")
+        .arg(cvsroot)
+        .arg("--git-repo")
+        .arg(&repo)
+        .arg("--store")
+        .arg(dir.path().join("store"))
+        .arg("--record")
+        .arg(&record)
+        // Files are discovered and parsed concurrently, so we need to limit
+        // the workers to one to get a deterministic stream.
+        .arg("--jobs")
+        .arg("1")
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success(), "conversion of {:?} failed", cvsroot);
+
+    (repo, fs::read_to_string(record).unwrap())
+}
+
+/// Replays a recorded stream into a new repository using `git fast-import`.
+fn replay(dir: &TempDir, stream: &Path) -> PathBuf {
+    let repo = dir.path().join("replayed.git");
+    init_repo(&repo);
+
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(&repo)
+        .arg("fast-import")
+        .arg("--quiet")
+        .stdin(fs::File::open(stream).unwrap())
+        .status()
+        .unwrap();
+    assert!(status.success(), "replay of {:?} failed", stream);
+
+    repo
+}
+
+#[test]
+fn test_golden() {
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+
+    for entry in fs::read_dir(&root).unwrap() {
+        let case = entry.unwrap().path();
+        let golden = case.join("stream");
+        let dir = TempDir::new().unwrap();
+
+        let args: Vec<String> = match fs::read_to_string(case.join("args")) {
+            Ok(args) => args.lines().map(String::from).collect(),
+            Err(_) => Vec::new(),
+        };
+
+        let (converted, stream) = convert(&dir, &case.join("cvsroot"), &args);
+        if update {
+            fs::write(&golden, &stream).unwrap();
+        }
+        assert_eq!(
+            stream,
+            fs::read_to_string(&golden).unwrap(),
+            "recorded stream for {:?} does not match the golden file",
+            case
+        );
+
+        let replayed = replay(&dir, &golden);
+        assert_eq!(ref_trees(&converted), ref_trees(&replayed));
+    }
+}
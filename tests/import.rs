@@ -0,0 +1,2103 @@
+//! End to end tests that import synthetic CVS repositories with each backend,
+//! and check the resulting Git repository.
+//!
+//! These tests require `git` to be installed.
+
+mod common;
+
+use common::{Harness, RcsFile};
+
+const BACKENDS: &[&str] = &["fast-import", "gitoxide"];
+
+/// The backend used to test features that don't depend on how objects are
+/// written to Git, such as parsing and patchset detection: only features that
+/// write or read Git objects and refs themselves are tested with every backend.
+const DEFAULT_BACKEND: &str = "fast-import";
+
+/// A file with two trunk revisions, a branch, and a tag.
+fn a_txt() -> RcsFile {
+    RcsFile::new()
+        .symbol("BR", "1.1.0.2")
+        .symbol("REL1", "1.1")
+        .revision("1.1", "2021.01.01.00.00.00", "adam", "add files", "one\n")
+        .revision(
+            "1.2",
+            "2021.01.02.00.00.00",
+            "adam",
+            "change a",
+            "one\ntwo\n",
+        )
+        .revision(
+            "1.1.2.1",
+            "2021.01.03.00.00.00",
+            "bob",
+            "change a on BR",
+            "one\nbranch\n",
+        )
+}
+
+/// A file that's added alongside `a.txt`, and then deleted.
+fn b_txt() -> RcsFile {
+    RcsFile::new()
+        .symbol("REL1", "1.1")
+        .revision("1.1", "2021.01.01.00.00.00", "adam", "add files", "b\n")
+        .delete("1.2", "2021.01.04.00.00.00", "adam", "remove b")
+}
+
+fn setup(backend: &str) -> Harness {
+    let harness = Harness::new(backend);
+    harness.write("mod/a.txt,v", &a_txt());
+    harness.write("mod/Attic/b.txt,v", &b_txt());
+    harness
+}
+
+#[test]
+fn test_import() {
+    for backend in BACKENDS {
+        let harness = setup(backend);
+        harness.import(&[]);
+
+        let refs = harness.refs();
+        for expected in ["refs/heads/main", "refs/heads/BR", "refs/tags/REL1"] {
+            assert!(
+                refs.iter().any(|r| r == expected),
+                "{}: {} missing from {:?}",
+                backend,
+                expected,
+                refs
+            );
+        }
+
+        assert_eq!(
+            harness.log("main"),
+            vec!["remove b (adam)", "change a (adam)", "add files (adam)"],
+            "{}",
+            backend
+        );
+        assert_eq!(harness.ls_tree("main"), vec!["mod/a.txt"], "{}", backend);
+        assert_eq!(
+            harness.show("main", "mod/a.txt"),
+            "one\ntwo\n",
+            "{}",
+            backend
+        );
+
+        assert_eq!(
+            harness.ls_tree("REL1"),
+            vec!["mod/a.txt", "mod/b.txt"],
+            "{}",
+            backend
+        );
+        assert_eq!(harness.show("REL1", "mod/a.txt"), "one\n", "{}", backend);
+        assert_eq!(harness.show("REL1", "mod/b.txt"), "b\n", "{}", backend);
+
+        // The fake commit for the tag is authored by the author of its most
+        // recent file revision, but committed by the tag identity, both at
+        // the time of that revision.
+        assert_eq!(
+            harness.git(&["log", "-1", "--format=%ae %at %ce %ct", "REL1"]),
+            "adam 1609459200 git-cvs-fast-import 1609459200\n",
+            "{}",
+            backend
+        );
+
+        assert_eq!(
+            harness.log("BR").first().map(String::as_str),
+            Some("change a on BR (bob)"),
+            "{}",
+            backend
+        );
+        assert_eq!(
+            harness.show("BR", "mod/a.txt"),
+            "one\nbranch\n",
+            "{}",
+            backend
+        );
+    }
+}
+
+#[test]
+fn test_incremental_import() {
+    for backend in BACKENDS {
+        let harness = setup(backend);
+        harness.import(&[]);
+        let main = harness.rev_parse("main");
+        let br = harness.rev_parse("BR");
+        let rel1 = harness.rev_parse("REL1");
+
+        harness.write(
+            "mod/a.txt,v",
+            &a_txt().revision(
+                "1.3",
+                "2021.01.05.00.00.00",
+                "carol",
+                "add c",
+                "one\ntwo\nthree\n",
+            ),
+        );
+        harness.write(
+            "mod/c.txt,v",
+            &RcsFile::new().revision("1.1", "2021.01.05.00.00.00", "carol", "add c", "c\n"),
+        );
+        harness.import(&[]);
+
+        // The new commit builds on the existing history, which is unchanged.
+        assert_eq!(harness.rev_parse("main~1"), main, "{}", backend);
+        assert_eq!(harness.rev_parse("BR"), br, "{}", backend);
+
+        // Tags with the same file revisions aren't recreated.
+        assert_eq!(harness.rev_parse("REL1"), rel1, "{}", backend);
+        assert_eq!(
+            harness.log("main").first().map(String::as_str),
+            Some("add c (carol)"),
+            "{}",
+            backend
+        );
+        assert_eq!(
+            harness.ls_tree("main"),
+            vec!["mod/a.txt", "mod/c.txt"],
+            "{}",
+            backend
+        );
+        assert_eq!(
+            harness.show("main", "mod/a.txt"),
+            "one\ntwo\nthree\n",
+            "{}",
+            backend
+        );
+
+        // Importing again without any changes doesn't create any commits.
+        harness.import(&[]);
+        assert_eq!(harness.log("main").len(), 4, "{}", backend);
+    }
+}
+
+#[test]
+fn test_excluded_symbols() {
+    let harness = setup(DEFAULT_BACKEND);
+    harness.write(
+        "mod/a.txt,v",
+        &a_txt()
+            .symbol("nightly-1", "1.2")
+            .symbol("tmp-BR", "1.2.0.4")
+            .revision(
+                "1.2.4.1",
+                "2021.01.06.00.00.00",
+                "bob",
+                "change a on tmp-BR",
+                "tmp\n",
+            ),
+    );
+
+    // The revision on the excluded branch isn't reported as a loss, so
+    // --strict still succeeds.
+    harness.import(&[
+        "--strict",
+        "--exclude-branches",
+        "tmp-*",
+        "--exclude-tags",
+        "nightly-*",
+    ]);
+
+    let refs = harness.refs();
+    assert!(
+        refs.iter()
+            .all(|r| !r.contains("tmp-") && !r.contains("nightly-")),
+        "{:?}",
+        refs
+    );
+    assert!(refs.iter().any(|r| r == "refs/heads/BR"));
+    assert!(refs.iter().any(|r| r == "refs/tags/REL1"));
+}
+
+#[test]
+fn test_no_patchsets() {
+    let harness = setup(DEFAULT_BACKEND);
+    harness.import(&["--no-patchsets"]);
+
+    // a.txt and b.txt were added together, but are now separate commits.
+    assert_eq!(
+        harness.log("main"),
+        vec![
+            "remove b (adam)",
+            "change a (adam)",
+            "add files (adam)",
+            "add files (adam)"
+        ]
+    );
+    // The files were added at the same time, so either may be first.
+    assert_eq!(harness.ls_tree("main~3").len(), 1);
+    assert_eq!(harness.ls_tree("main~2"), vec!["mod/a.txt", "mod/b.txt"]);
+}
+
+#[test]
+fn test_revision_trailer() {
+    let harness = setup(DEFAULT_BACKEND);
+    harness.import(&["--revision-trailer"]);
+
+    assert_eq!(
+        harness.git(&["log", "-1", "--format=%B", "main~2"]),
+        "add files\n\nCVS-Revision: mod/a.txt@1.1\nCVS-Revision: mod/b.txt@1.1\n\n"
+    );
+    assert_eq!(
+        harness.git(&[
+            "log",
+            "--format=%(trailers:key=CVS-Revision,valueonly)",
+            "main~1..main"
+        ]),
+        "mod/b.txt@1.2\n\n"
+    );
+}
+
+#[test]
+fn test_import_trailer() {
+    let harness = setup(DEFAULT_BACKEND);
+    let report = harness.scratch("report.json");
+    harness.import(&["--import-trailer", "--report", report.to_str().unwrap()]);
+
+    let report: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&report).unwrap()).unwrap();
+    let run_id = report["run_id"].as_str().unwrap();
+    let trailer = format!(
+        "git-cvs-fast-import {} run {}",
+        env!("CARGO_PKG_VERSION"),
+        run_id
+    );
+
+    // Every commit has the trailer, including the fake commit for the tag.
+    let values = harness.git(&[
+        "log",
+        "--format=%(trailers:key=X-CVS-Import,valueonly)",
+        "main",
+        "BR",
+        "REL1",
+    ]);
+    let values: Vec<&str> = values.lines().filter(|line| !line.is_empty()).collect();
+    let count = harness.git(&["rev-list", "--count", "main", "BR", "REL1"]);
+    assert_eq!(values.len().to_string(), count.trim());
+    assert!(values.iter().all(|value| *value == trailer));
+
+    // The run is recorded in the store, and later runs are added to it.
+    harness.import(&[]);
+    let store = harness.store().to_str().unwrap().to_string();
+    let output = harness.state(&["export", "--format", "json", "--store", &store], b"");
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let runs = json["runs"].as_array().unwrap();
+    assert_eq!(runs.len(), 2);
+    assert_eq!(runs[0]["id"], run_id);
+    assert_eq!(runs[0]["version"], env!("CARGO_PKG_VERSION"));
+    assert_ne!(runs[1]["id"], run_id);
+}
+
+#[test]
+fn test_attic_only_directory() {
+    let harness = setup(DEFAULT_BACKEND);
+    let x_txt = RcsFile::new()
+        .revision("1.1", "2021.01.02.12.00.00", "carol", "add x", "x\n")
+        .delete("1.2", "2021.01.05.00.00.00", "carol", "remove x");
+    harness.write("old/Attic/x.txt,v", &x_txt);
+    harness.import(&["--gitkeep"]);
+
+    // Every file in old is in the Attic, but its history is still imported,
+    // and the directory is kept once the file is deleted.
+    assert_eq!(
+        harness.log("main"),
+        vec![
+            "remove x (carol)",
+            "remove b (adam)",
+            "add x (carol)",
+            "change a (adam)",
+            "add files (adam)"
+        ]
+    );
+    assert_eq!(
+        harness.ls_tree("main~2"),
+        vec!["mod/a.txt", "mod/b.txt", "old/x.txt"]
+    );
+    assert_eq!(harness.ls_tree("main"), vec!["mod/a.txt", "old/.gitkeep"]);
+    assert_eq!(harness.show("main", "old/.gitkeep"), "");
+
+    // Re-adding the file in a later run removes the placeholder.
+    harness.write(
+        "old/Attic/x.txt,v",
+        &x_txt.revision("1.3", "2021.01.06.00.00.00", "carol", "restore x", "x\n"),
+    );
+    harness.import(&["--gitkeep"]);
+    assert_eq!(harness.ls_tree("main"), vec!["mod/a.txt", "old/x.txt"]);
+}
+
+#[test]
+fn test_report_refs() {
+    for backend in BACKENDS {
+        let harness = setup(backend);
+        let report = harness.scratch("report.json");
+        harness.import(&["--report", report.to_str().unwrap()]);
+
+        let report: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&report).unwrap()).unwrap();
+        let refs: Vec<(&str, &str, &str, &str, bool)> = report["refs"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| {
+                (
+                    r["symbol"].as_str().unwrap(),
+                    r["kind"].as_str().unwrap(),
+                    r["ref"].as_str().unwrap(),
+                    r["sha"].as_str().unwrap(),
+                    r["synthesized"].as_bool().unwrap(),
+                )
+            })
+            .collect();
+
+        let main = harness.rev_parse("main");
+        let br = harness.rev_parse("BR");
+        let rel1 = harness.rev_parse("REL1");
+        assert_eq!(
+            refs,
+            vec![
+                ("BR", "branch", "refs/heads/BR", br.as_str(), false),
+                ("main", "branch", "refs/heads/main", main.as_str(), false),
+                ("REL1", "tag", "refs/tags/REL1", rel1.as_str(), true),
+            ],
+            "{}",
+            backend
+        );
+    }
+}
+
+#[test]
+fn test_orphan_tags() {
+    for backend in BACKENDS {
+        for (policy, expected) in [
+            ("orphan", "none"),
+            ("head", "branch_head"),
+            ("empty-root", "empty_root"),
+        ] {
+            // The tagged revision isn't on a named branch, so isn't in any
+            // patchset that the tag could be parented on.
+            let harness = setup(backend);
+            harness.write(
+                "mod/a.txt,v",
+                &a_txt().symbol("ORPHAN", "1.2.4.1").revision(
+                    "1.2.4.1",
+                    "2021.01.06.00.00.00",
+                    "bob",
+                    "change a on an unnamed branch",
+                    "unnamed\n",
+                ),
+            );
+            let report = harness.scratch("report.json");
+            harness.import(&[
+                "--orphan-tags",
+                policy,
+                "--report",
+                report.to_str().unwrap(),
+            ]);
+
+            let report: serde_json::Value =
+                serde_json::from_slice(&std::fs::read(&report).unwrap()).unwrap();
+            let tags = report["tags"].as_array().unwrap();
+            let orphan = tags.iter().find(|t| t["tag"] == "ORPHAN").unwrap();
+            let rel1 = tags.iter().find(|t| t["tag"] == "REL1").unwrap();
+            assert_eq!(orphan["parent"], expected, "{} {}", backend, policy);
+            assert_eq!(rel1["parent"], "patchset", "{} {}", backend, policy);
+            assert_eq!(
+                harness.show("ORPHAN", "mod/a.txt"),
+                "unnamed\n",
+                "{} {}",
+                backend,
+                policy
+            );
+
+            let parents = harness.git(&["log", "-1", "--format=%P", "ORPHAN"]);
+            match policy {
+                "orphan" => {
+                    assert_eq!(parents.trim(), "", "{}", backend);
+                    assert!(orphan["parent_sha"].is_null(), "{}", backend);
+                }
+                "head" => {
+                    assert_eq!(parents.trim(), harness.rev_parse("main"), "{}", backend);
+                    assert_eq!(orphan["parent_sha"], parents.trim(), "{}", backend);
+                }
+                _ => {
+                    assert_eq!(
+                        harness.ls_tree("ORPHAN^"),
+                        Vec::<String>::new(),
+                        "{}",
+                        backend
+                    );
+                    assert_eq!(orphan["parent_sha"], parents.trim(), "{}", backend);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_dead_at_tip() {
+    for backend in BACKENDS {
+        let harness = Harness::new(backend);
+
+        // Deleted at the tip of a branch, but not on the trunk.
+        harness.write(
+            "mod/Attic/branch.txt,v",
+            &RcsFile::new()
+                .symbol("BR", "1.1.0.2")
+                .revision("1.1", "2021.01.01.00.00.00", "adam", "add", "one\n")
+                .revision("1.1.2.1", "2021.01.02.00.00.00", "bob", "change", "two\n")
+                .delete("1.1.2.2", "2021.01.03.00.00.00", "bob", "remove"),
+        );
+
+        // Deleted on the trunk, with the deletion timestamped before the
+        // revision it deletes, such as after a clock jump on the server.
+        harness.write(
+            "mod/Attic/skew.txt,v",
+            &RcsFile::new()
+                .revision("1.1", "2021.01.01.00.00.00", "adam", "add", "one\n")
+                .revision("1.2", "2021.01.05.00.00.00", "adam", "change skew", "two\n")
+                .delete("1.3", "2021.01.04.00.00.00", "adam", "remove skew"),
+        );
+
+        // Added and deleted within the same patchset.
+        harness.write(
+            "mod/Attic/quick.txt,v",
+            &RcsFile::new()
+                .revision("1.1", "2021.01.06.00.00.00", "carol", "oops", "one\n")
+                .delete("1.2", "2021.01.06.00.00.30", "carol", "oops"),
+        );
+
+        harness.write(
+            "mod/live.txt,v",
+            &RcsFile::new().symbol("BR", "1.1.0.2").revision(
+                "1.1",
+                "2021.01.01.00.00.00",
+                "adam",
+                "add",
+                "live\n",
+            ),
+        );
+        harness.import(&[]);
+
+        // Each head matches what cvs co would check out: the deleted files are
+        // gone, but their history is intact.
+        assert_eq!(
+            harness.ls_tree("main"),
+            vec!["mod/branch.txt", "mod/live.txt"],
+            "{}",
+            backend
+        );
+        assert_eq!(harness.ls_tree("BR"), vec!["mod/live.txt"], "{}", backend);
+        assert_eq!(
+            harness.log("main"),
+            vec![
+                "oops (carol)",
+                "remove skew (adam)",
+                "change skew (adam)",
+                "add (adam)"
+            ],
+            "{}",
+            backend
+        );
+        assert_eq!(
+            harness.show("main~2", "mod/skew.txt"),
+            "two\n",
+            "{}",
+            backend
+        );
+        assert_eq!(
+            harness.show("BR~1", "mod/branch.txt"),
+            "two\n",
+            "{}",
+            backend
+        );
+    }
+}
+
+#[test]
+fn test_rewrite_root() {
+    let harness = setup(DEFAULT_BACKEND);
+    harness.import(&["--rewrite-root", "legacy/cvs/"]);
+
+    assert_eq!(
+        harness.ls_tree("main~1"),
+        vec!["legacy/cvs/mod/a.txt", "legacy/cvs/mod/b.txt"]
+    );
+    assert_eq!(harness.ls_tree("main"), vec!["legacy/cvs/mod/a.txt"]);
+    assert_eq!(
+        harness.ls_tree("REL1"),
+        vec!["legacy/cvs/mod/a.txt", "legacy/cvs/mod/b.txt"]
+    );
+    assert_eq!(harness.show("BR", "legacy/cvs/mod/a.txt"), "one\nbranch\n");
+}
+
+#[test]
+fn test_graft_onto() {
+    for backend in BACKENDS {
+        // An existing commit with an empty tree, such as the history of a
+        // repository that the CVS history is being added to.
+        let harness = setup(backend);
+        let existing = harness.git(&[
+            "-c",
+            "user.name=Git",
+            "-c",
+            "user.email=git",
+            "commit-tree",
+            "4b825dc642cb6eb9a060e54bf8d69288fbee4904",
+            "-m",
+            "existing",
+        ]);
+        harness.git(&["update-ref", "refs/heads/existing", existing.trim()]);
+
+        let output = harness.try_import(&["--graft-onto", "missing"]);
+        assert!(!output.status.success(), "{}", backend);
+
+        harness.import(&["--graft-onto", "existing"]);
+        assert_eq!(
+            harness.log("main"),
+            vec![
+                "remove b (adam)",
+                "change a (adam)",
+                "add files (adam)",
+                "existing (git)"
+            ],
+            "{}",
+            backend
+        );
+        assert_eq!(harness.rev_parse("main~3"), existing.trim(), "{}", backend);
+    }
+}
+
+#[test]
+fn test_max_commit_files() {
+    for backend in BACKENDS {
+        // The first patchset adds both files, so is split into two commits.
+        let harness = setup(backend);
+        harness.import(&["--max-commit-files", "1"]);
+        assert_eq!(
+            harness.log("main"),
+            vec![
+                "remove b (adam)",
+                "change a (adam)",
+                "add files (adam)",
+                "add files (adam)"
+            ],
+            "{}",
+            backend
+        );
+        assert_eq!(harness.ls_tree("main~3"), vec!["mod/a.txt"], "{}", backend);
+        assert_eq!(
+            harness.ls_tree("REL1"),
+            vec!["mod/a.txt", "mod/b.txt"],
+            "{}",
+            backend
+        );
+
+        // The split patchset is recorded against its last commit, so isn't
+        // sent again.
+        let head = harness.rev_parse("main");
+        harness.import(&["--max-commit-files", "1"]);
+        assert_eq!(harness.rev_parse("main"), head, "{}", backend);
+    }
+}
+
+#[test]
+fn test_max_revisions_per_file() {
+    let harness = Harness::new(DEFAULT_BACKEND);
+    let mut file = RcsFile::new();
+    for i in 1..=4 {
+        file = file.revision(
+            &format!("1.{}", i),
+            &format!("2021.01.0{}.00.00.00", i),
+            "adam",
+            &format!("change {}", i),
+            &format!("{}\n", i),
+        );
+    }
+    harness.write("mod/c.txt,v", &file);
+    harness.import(&["--max-revisions-per-file", "2"]);
+
+    // Revision 1.2 stands in for the history before it, and 1.1 is
+    // dropped.
+    assert_eq!(
+        harness.log("main"),
+        vec![
+            "change 4 (adam)",
+            "change 3 (adam)",
+            "History truncated: earlier revisions were squashed into this commit (adam)"
+        ]
+    );
+    assert_eq!(harness.show("main~2", "mod/c.txt"), "2\n");
+
+    let head = harness.rev_parse("main");
+    harness.import(&["--max-revisions-per-file", "2"]);
+    assert_eq!(harness.rev_parse("main"), head);
+}
+
+#[test]
+fn test_squash_rule() {
+    let harness = Harness::new(DEFAULT_BACKEND);
+    let mut file = RcsFile::new();
+    for (i, (time, author, message)) in [
+        ("2021.01.01.00.00.00", "adam", "add c"),
+        ("2021.01.02.00.00.00", "bot", "auto: one"),
+        ("2021.01.02.00.01.00", "bot", "auto: two"),
+        ("2021.01.02.00.02.00", "bot", "auto: three"),
+        ("2021.01.02.00.10.00", "bot", "auto: four"),
+    ]
+    .iter()
+    .enumerate()
+    {
+        file = file.revision(
+            &format!("1.{}", i + 1),
+            time,
+            author,
+            message,
+            &format!("{}\n", i + 1),
+        );
+    }
+    harness.write("mod/c.txt,v", &file);
+    harness.import(&["--squash-rule", "main=2m:auto:*"]);
+
+    assert_eq!(
+        harness.log("main"),
+        vec!["auto: four (bot)", "auto: one (bot)", "add c (adam)"]
+    );
+    assert_eq!(harness.show("main~1", "mod/c.txt"), "4\n");
+
+    let head = harness.rev_parse("main");
+    harness.import(&["--squash-rule", "main=2m:auto:*"]);
+    assert_eq!(harness.rev_parse("main"), head);
+}
+
+#[test]
+fn test_prune_tags() {
+    // a.txt, with the given tags.
+    let a_txt = |tags: &[(&str, &str)]| {
+        tags.iter().fold(
+            RcsFile::new()
+                .symbol("BR", "1.1.0.2")
+                .revision("1.1", "2021.01.01.00.00.00", "adam", "add files", "one\n")
+                .revision(
+                    "1.2",
+                    "2021.01.02.00.00.00",
+                    "adam",
+                    "change a",
+                    "one\ntwo\n",
+                ),
+            |file, (tag, num)| file.symbol(tag, num),
+        )
+    };
+
+    for backend in BACKENDS {
+        let harness = setup(backend);
+        harness.import(&["--prune-tags"]);
+        let rel1 = harness.rev_parse("REL1");
+
+        // Move REL1 to the later revision of a.txt, remove it from b.txt, and
+        // add REL2 to a revision that has already been imported.
+        harness.write("mod/a.txt,v", &a_txt(&[("REL1", "1.2"), ("REL2", "1.1")]));
+        harness.write(
+            "mod/Attic/b.txt,v",
+            &RcsFile::new()
+                .revision("1.1", "2021.01.01.00.00.00", "adam", "add files", "b\n")
+                .delete("1.2", "2021.01.04.00.00.00", "adam", "remove b"),
+        );
+        harness.import(&["--prune-tags"]);
+
+        assert_eq!(harness.ls_tree("REL1"), vec!["mod/a.txt"], "{}", backend);
+        assert_eq!(
+            harness.show("REL1", "mod/a.txt"),
+            "one\ntwo\n",
+            "{}",
+            backend
+        );
+        assert_eq!(harness.rev_parse("REL1~1"), rel1, "{}", backend);
+        assert_eq!(harness.show("REL2", "mod/a.txt"), "one\n", "{}", backend);
+
+        // Removing REL1 altogether deletes both the tag and its branch.
+        harness.write("mod/a.txt,v", &a_txt(&[("REL2", "1.1")]));
+        harness.import(&["--prune-tags"]);
+        let refs = harness.refs();
+        for deleted in ["refs/tags/REL1", "refs/heads/tags/REL1"] {
+            assert!(
+                !refs.iter().any(|r| r == deleted),
+                "{}: {:?}",
+                backend,
+                refs
+            );
+        }
+        assert!(refs.iter().any(|r| r == "refs/tags/REL2"), "{}", backend);
+
+        // Nothing changes on a later run.
+        let rel2 = harness.rev_parse("REL2");
+        harness.import(&["--prune-tags"]);
+        assert_eq!(harness.rev_parse("REL2"), rel2, "{}", backend);
+        assert_eq!(harness.refs(), refs, "{}", backend);
+    }
+}
+
+#[test]
+fn test_no_update_refs() {
+    for backend in BACKENDS {
+        let harness = setup(backend);
+        harness.import(&[]);
+        let main = harness.rev_parse("main");
+        let refs = harness.refs();
+
+        harness.write(
+            "mod/a.txt,v",
+            &a_txt().revision(
+                "1.3",
+                "2021.01.05.00.00.00",
+                "adam",
+                "change a again",
+                "one\ntwo\nthree\n",
+            ),
+        );
+        harness.import(&["--no-update-refs"]);
+
+        // The new commit is only on the scratch ref.
+        assert_eq!(harness.rev_parse("main"), main, "{}", backend);
+        let scratch = harness.rev_parse("refs/git-cvs-fast-import/heads/main");
+        assert_eq!(
+            harness.rev_parse("refs/git-cvs-fast-import/heads/main~1"),
+            main,
+            "{}",
+            backend
+        );
+        assert_eq!(
+            harness
+                .refs()
+                .into_iter()
+                .filter(|r| !r.starts_with("refs/git-cvs-fast-import/"))
+                .collect::<Vec<_>>(),
+            refs,
+            "{}",
+            backend
+        );
+
+        // The next commit imported without the option extends the scratch
+        // commit.
+        harness.write(
+            "mod/a.txt,v",
+            &a_txt()
+                .revision(
+                    "1.3",
+                    "2021.01.05.00.00.00",
+                    "adam",
+                    "change a again",
+                    "one\ntwo\nthree\n",
+                )
+                .revision(
+                    "1.4",
+                    "2021.01.06.00.00.00",
+                    "adam",
+                    "change a once more",
+                    "one\ntwo\nthree\nfour\n",
+                ),
+        );
+        harness.import(&[]);
+        assert_eq!(harness.rev_parse("main~1"), scratch, "{}", backend);
+    }
+}
+
+#[test]
+fn test_provenance() {
+    let harness = setup(DEFAULT_BACKEND);
+    harness.write(
+        "mod/c.bin,v",
+        &RcsFile::new().expand("b").revision(
+            "1.1",
+            "2021.01.04.00.00.00",
+            "adam",
+            "add c",
+            "$Id$\n",
+        ),
+    );
+    let provenance = harness.scratch("provenance.json");
+    harness.import(&["--provenance", provenance.to_str().unwrap()]);
+
+    let provenance: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&provenance).unwrap()).unwrap();
+    assert_eq!(
+        provenance["mod/a.txt"],
+        serde_json::json!({
+            "comment": "# ",
+            "integrity": null,
+            "expand": null,
+            "binary": false,
+            "keywords": true,
+        })
+    );
+    assert_eq!(provenance["mod/b.txt"]["binary"], false);
+    assert_eq!(provenance["mod/c.bin"]["expand"], "b");
+    assert_eq!(provenance["mod/c.bin"]["binary"], true);
+    assert_eq!(provenance["mod/c.bin"]["keywords"], false);
+
+    // Keywords are never expanded in the imported content.
+    assert_eq!(harness.show("main", "mod/c.bin"), "$Id$\n");
+}
+
+#[test]
+fn test_empty_cvsroot() {
+    // A CVSROOT without any ,v files is almost certainly a mistake.
+    let harness = Harness::new(DEFAULT_BACKEND);
+    harness.write("mod/README", &RcsFile::new());
+
+    let output = harness.try_import(&["mod"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let searched = std::fs::canonicalize(harness.cvsroot().join("mod")).unwrap();
+    assert!(
+        stderr.contains(&format!("no ,v files were found in {}", searched.display())),
+        "{}",
+        stderr
+    );
+
+    harness.import(&["--allow-empty", "mod"]);
+    assert_eq!(harness.refs(), Vec::<String>::new());
+}
+
+#[test]
+fn test_list() {
+    // Listing doesn't depend on the backend, or touch the repository.
+    let harness = setup(BACKENDS[0]);
+    harness.write(
+        "other/c.txt,v",
+        &RcsFile::new().symbol("REL1", "1.1").revision(
+            "1.1",
+            "2021.01.04.00.00.00",
+            "adam",
+            "add c",
+            "c\n",
+        ),
+    );
+
+    let output = harness.list(&[]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "branch\tBR\t1\ntag\tREL1\t3\n"
+    );
+
+    let output = harness.list(&["other"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "tag\tREL1\t1\n");
+    assert_eq!(harness.refs(), Vec::<String>::new());
+}
+
+#[test]
+fn test_subcommand_directory() {
+    // A directory named after a subcommand is imported, rather than being
+    // treated as the subcommand, as long as it exists in the CVSROOT.
+    let harness = setup(DEFAULT_BACKEND);
+    harness.write(
+        "list/c.txt,v",
+        &RcsFile::new().revision("1.1", "2021.01.04.00.00.00", "adam", "add c", "c\n"),
+    );
+
+    let output = harness.list(&[
+        "--backend",
+        DEFAULT_BACKEND,
+        "--git-repo",
+        harness.repo().to_str().unwrap(),
+        "--store",
+        harness.store().to_str().unwrap(),
+    ]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(harness.ls_tree("main"), vec!["list/c.txt"]);
+
+    // Without the import options, it's still the subcommand.
+    let output = harness.list(&[]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "branch\tBR\t1\ntag\tREL1\t2\n"
+    );
+}
+
+#[test]
+fn test_log() {
+    // Like listing, logging doesn't depend on the backend.
+    let harness = setup(BACKENDS[0]);
+
+    let output = harness.rlog(&[]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("mod/a.txt,v\nhead: 1.2\n"), "{}", stdout);
+    assert!(stdout.contains("\tBR: 1.1.0.2\n"), "{}", stdout);
+    assert!(stdout.contains("\nrevision 1.1.2.1\n"), "{}", stdout);
+    assert!(stdout.contains("state: dead;"), "{}", stdout);
+    assert!(!stdout.contains("total revisions: 0;"), "{}", stdout);
+
+    let output = harness.rlog(&["--header"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("\tREL1: 1.1\n"), "{}", stdout);
+    assert!(!stdout.contains("revision 1.1"), "{}", stdout);
+    assert_eq!(harness.refs(), Vec::<String>::new());
+}
+
+#[test]
+fn test_cat() {
+    // Like listing, printing a revision doesn't depend on the backend.
+    let harness = setup(BACKENDS[0]);
+
+    for (args, expected) in [
+        (["mod/a.txt", "1.2"], "one\ntwo\n"),
+        (["mod/a.txt", "1.1"], "one\n"),
+        (["mod/a.txt,v", "1.1.2.1"], "one\nbranch\n"),
+        (["mod/b.txt", "1.1"], "b\n"),
+        (["mod/Attic/b.txt,v", "1.1"], "b\n"),
+    ] {
+        let output = harness.cat(&args);
+        assert!(
+            output.status.success(),
+            "{:?}: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+    }
+
+    for (args, expected) in [
+        (["mod/a.txt", "1.3"], "revision 1.3 was not found"),
+        (["mod/a.txt", "1.1.0.2"], "1.1.2 is a branch"),
+        (["mod/c.txt", "1.1"], "mod/c.txt was not found"),
+    ] {
+        let output = harness.cat(&args);
+        assert!(!output.status.success(), "{:?}", args);
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains(expected), "{:?}: {}", args, stderr);
+    }
+    assert_eq!(harness.refs(), Vec::<String>::new());
+}
+
+#[test]
+fn test_state_export_import() {
+    let harness = setup(DEFAULT_BACKEND);
+    harness.import(&[]);
+    let head = harness.rev_parse("main");
+
+    let store = harness.store().to_str().unwrap().to_string();
+    let output = harness.state(&["export", "--format", "json", "--store", &store], b"");
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["version"], 1);
+    assert_eq!(json["file_revisions"][0]["path"], "mod/a.txt");
+    assert_eq!(json["tags"][0]["name"], "REL1");
+
+    // Importing over an existing store requires --force.
+    let imported = harness.state(&["import", "--store", &store], &output.stdout);
+    assert!(!imported.status.success());
+    std::fs::remove_file(harness.store()).unwrap();
+    let imported = harness.state(&["import", "--store", &store], &output.stdout);
+    assert!(imported.status.success());
+    let reexported = harness.state(&["export", "--store", &store], b"");
+    assert_eq!(reexported.stdout, output.stdout);
+
+    // The imported store knows everything has been sent, so only the new
+    // run is added to it.
+    harness.import(&[]);
+    assert_eq!(harness.rev_parse("main"), head);
+    let reexported = harness.state(&["export", "--store", &store], b"");
+    let mut rejson: serde_json::Value = serde_json::from_slice(&reexported.stdout).unwrap();
+    let runs = rejson["runs"].as_array_mut().unwrap();
+    assert_eq!(runs.len(), 2);
+    runs.pop();
+    assert_eq!(rejson, json);
+}
+
+#[test]
+fn test_events() {
+    let harness = setup(DEFAULT_BACKEND);
+    let events = harness.scratch("events.jsonl");
+    harness.import(&["--events", events.to_str().unwrap()]);
+
+    let events: Vec<serde_json::Value> = std::fs::read_to_string(&events)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let messages = |message: &str| {
+        events
+            .iter()
+            .filter(|event| event["message"] == message)
+            .collect::<Vec<_>>()
+    };
+
+    let mut files: Vec<&str> = messages("file parsed")
+        .into_iter()
+        .map(|event| event["spans"][0]["path"].as_str().unwrap())
+        .collect();
+    files.sort_unstable();
+    assert_eq!(files.len(), 2);
+    assert!(files[0].ends_with("mod/Attic/b.txt,v"));
+    assert!(files[1].ends_with("mod/a.txt,v"));
+
+    let mut branches: Vec<&str> = messages("patchset committed")
+        .into_iter()
+        .map(|event| event["fields"]["branch"].as_str().unwrap())
+        .collect();
+    branches.dedup();
+    assert_eq!(branches, ["BR", "main"]);
+
+    let tags: Vec<&str> = messages("tag committed")
+        .into_iter()
+        .map(|event| event["fields"]["tag"].as_str().unwrap())
+        .collect();
+    assert_eq!(tags, ["REL1"]);
+}
+
+#[test]
+fn test_reproducible() {
+    for backend in BACKENDS {
+        let first = setup(backend);
+        first.import(&[]);
+        let second = setup(backend);
+        second.import(&[]);
+
+        // Importing the same CVS repository again produces the same commits,
+        // including the fake commits for tags.
+        assert_eq!(second.refs(), first.refs(), "{}", backend);
+        for r in first.refs() {
+            assert_eq!(
+                second.rev_parse(&r),
+                first.rev_parse(&r),
+                "{} {}",
+                backend,
+                r
+            );
+        }
+    }
+}
+
+#[test]
+fn test_diverged_marks() {
+    for backend in BACKENDS {
+        let harness = setup(backend);
+        harness.import(&[]);
+
+        // Drop the last mark from the store, as if the import had been
+        // interrupted before Git's marks were saved.
+        let store = harness.store().to_str().unwrap().to_string();
+        let output = harness.state(&["export", "--store", &store], b"");
+        let mut json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        let marks = json["marks"].as_str().unwrap().to_string();
+        let mut lines: Vec<&str> = marks.lines().collect();
+        let last = lines.pop().unwrap().split(' ').next().unwrap().to_string();
+        json["marks"] = format!("{}\n", lines.join("\n")).into();
+        let imported = harness.state(
+            &["import", "--force", "--store", &store],
+            &serde_json::to_vec(&json).unwrap(),
+        );
+        assert!(imported.status.success(), "{}", backend);
+
+        let output = harness.try_import(&[]);
+        assert!(!output.status.success(), "{}", backend);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains(&format!("starting with {}", last)),
+            "{}: {}",
+            backend,
+            stderr
+        );
+        assert!(
+            stderr.contains("the store no longer matches the Git repository"),
+            "{}: {}",
+            backend,
+            stderr
+        );
+    }
+}
+
+#[test]
+fn test_missing_objects() {
+    for backend in BACKENDS {
+        let harness = setup(backend);
+        harness.import(&[]);
+
+        // Replace the repository with an empty one, as if it had been restored
+        // from a backup taken before the import.
+        std::fs::remove_dir_all(harness.repo()).unwrap();
+        common::init_repo(&harness.repo());
+
+        let output = harness.try_import(&[]);
+        assert!(!output.status.success(), "{}", backend);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("object(s) in the store are not in the Git repository"),
+            "{}: {}",
+            backend,
+            stderr
+        );
+        assert!(
+            stderr.contains("the store no longer matches the Git repository"),
+            "{}: {}",
+            backend,
+            stderr
+        );
+    }
+}
+
+#[test]
+fn test_state_compression() {
+    let harness = setup(DEFAULT_BACKEND);
+    harness.import(&["--state-compression", "none"]);
+    let head = harness.rev_parse("main");
+    let uncompressed = std::fs::metadata(harness.store()).unwrap().len();
+
+    // The uncompressed store can be read, and written compressed again.
+    harness.import(&["--state-compression", "19"]);
+    assert_eq!(harness.rev_parse("main"), head);
+    let compressed = std::fs::metadata(harness.store()).unwrap().len();
+    assert!(compressed < uncompressed);
+
+    harness.import(&[]);
+    assert_eq!(harness.rev_parse("main"), head);
+
+    let output = harness.try_import(&["--state-compression", "fast"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_state_refs() {
+    for backend in BACKENDS {
+        let harness = setup(backend);
+        harness.import(&[]);
+
+        let store = harness.store().to_str().unwrap().to_string();
+        let output = harness.state(&["refs", "--store", &store], b"");
+        assert!(output.status.success(), "{}", backend);
+        let refs: Vec<(String, String)> = String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let fields: Vec<&str> = line.split('\t').collect();
+                assert_eq!(fields.len(), 3, "{}: {:?}", backend, line);
+                assert!(fields[2].starts_with(':'), "{}: {:?}", backend, line);
+                (fields[0].to_string(), fields[1].to_string())
+            })
+            .collect();
+        assert_eq!(
+            refs,
+            [
+                ("branch".to_string(), "BR".to_string()),
+                ("branch".to_string(), "main".to_string()),
+                ("tag".to_string(), "REL1".to_string()),
+            ],
+            "{}",
+            backend
+        );
+    }
+}
+
+#[test]
+fn test_branch_rename() {
+    let harness = setup(DEFAULT_BACKEND);
+    let args = ["--branch-rename", "B(R)=release/$1"];
+    harness.import(&args);
+
+    let refs = harness.refs();
+    assert!(refs.contains(&"refs/heads/release/R".to_string()));
+    assert!(!refs.contains(&"refs/heads/BR".to_string()));
+    let head = harness.rev_parse("release/R");
+
+    // Later runs keep updating the renamed branch.
+    harness.write(
+        "mod/a.txt,v",
+        &a_txt().revision(
+            "1.1.2.2",
+            "2021.01.05.00.00.00",
+            "bob",
+            "change a on BR again",
+            "one\nbranch\nagain\n",
+        ),
+    );
+    harness.import(&args);
+    assert_eq!(harness.rev_parse("release/R~1"), head);
+    assert!(!harness.refs().contains(&"refs/heads/BR".to_string()));
+
+    // Renames can't make two branches share a name.
+    let output = harness.try_import(&["--branch-rename", "BR=main"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("branches BR and main would both be imported as main"));
+}
+
+#[test]
+fn test_changelog_messages() {
+    let harness = setup(DEFAULT_BACKEND);
+    let changelog = RcsFile::new()
+        .revision(
+            "1.1",
+            "2021.01.06.00.00.00",
+            "carol",
+            "add changelog",
+            "2021-01-06  carol\n\n\t* c.c: Add c.\n",
+        )
+        .revision(
+            "1.2",
+            "2021.01.07.00.00.00",
+            "carol",
+            "*** empty log message ***",
+            "2021-01-07  carol\n\n\t* c.c (main): Return 1.\n\n2021-01-06  carol\n\n\t* c.c: Add c.\n",
+        );
+    let c_c = RcsFile::new()
+        .revision(
+            "1.1",
+            "2021.01.06.00.00.00",
+            "carol",
+            "add changelog",
+            "0\n",
+        )
+        .revision(
+            "1.2",
+            "2021.01.07.00.00.00",
+            "carol",
+            "*** empty log message ***",
+            "1\n",
+        );
+    harness.write("gnu/ChangeLog,v", &changelog);
+    harness.write("gnu/c.c,v", &c_c);
+    harness.import(&["--changelog-messages"]);
+
+    // The entry added to the ChangeLog replaces the empty message, but
+    // messages that say something are left alone.
+    assert_eq!(
+        harness.git(&["log", "-1", "--format=%B", "main"]),
+        "2021-01-07  carol\n\n* c.c (main): Return 1.\n\n"
+    );
+    assert_eq!(harness.log("main~1")[0], "add changelog (carol)");
+}
+
+#[test]
+fn test_missing_head() {
+    let harness = setup(DEFAULT_BACKEND);
+    std::fs::write(
+        harness.cvsroot().join("mod/empty.txt,v"),
+        "head\t;\naccess;\nsymbols;\nlocks; strict;\n\n\ndesc\n@@\n",
+    )
+    .unwrap();
+
+    // Strict mode doesn't allow any file to be skipped.
+    let output = harness.try_import(&["--strict"]);
+    assert!(!output.status.success());
+    assert!(harness.refs().is_empty());
+
+    // Otherwise, the file is skipped and everything else is imported, but
+    // the import exits with the same status as with --ignore-file-errors.
+    let output = harness.try_import(&[]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("missing HEAD revision (1 file(s))"),
+        "{}",
+        stderr
+    );
+    assert_eq!(
+        harness.log("main"),
+        vec!["remove b (adam)", "change a (adam)", "add files (adam)"]
+    );
+}
+
+#[test]
+fn test_every_file_fatal() {
+    // Parsing happens before anything reaches the backend.
+    let harness = Harness::new(DEFAULT_BACKEND);
+    std::fs::create_dir_all(harness.cvsroot().join("mod")).unwrap();
+    for file in ["a.txt,v", "b.txt,v", "c.txt,v"] {
+        std::fs::write(harness.cvsroot().join("mod").join(file), "not RCS\n").unwrap();
+    }
+
+    // A single worker hits a fatal error on the first file, but still parses
+    // the rest, so that the summary covers every file.
+    let output = harness.try_import(&["--jobs", "1"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("3 file(s) could not be imported"),
+        "{}",
+        stderr
+    );
+    for file in ["mod/a.txt,v", "mod/b.txt,v", "mod/c.txt,v"] {
+        assert!(stderr.contains(file), "{}: {}", file, stderr);
+    }
+    assert!(!stderr.contains("closed channel"), "{}", stderr);
+}
+
+#[test]
+fn test_emit_empty_commits() {
+    // A file that was only ever deleted, as happens when a file is added on a
+    // branch, so its trunk revision doesn't change anything.
+    let c_txt = RcsFile::new().delete("1.1", "2021.01.05.00.00.00", "carol", "remove c");
+    let history = vec!["remove b (adam)", "change a (adam)", "add files (adam)"];
+
+    for backend in BACKENDS {
+        let harness = setup(backend);
+        harness.write("mod/Attic/c.txt,v", &c_txt);
+        harness.import(&[]);
+        assert_eq!(harness.log("main"), history, "{}", backend);
+
+        // The skipped patchset is recorded as sent.
+        let head = harness.rev_parse("main");
+        harness.import(&["--emit-empty-commits"]);
+        assert_eq!(harness.rev_parse("main"), head, "{}", backend);
+
+        let harness = setup(backend);
+        harness.write("mod/Attic/c.txt,v", &c_txt);
+        harness.import(&["--emit-empty-commits"]);
+        let mut expected = vec!["remove c (carol)"];
+        expected.extend(history.iter());
+        assert_eq!(harness.log("main"), expected, "{}", backend);
+        assert_eq!(
+            harness.rev_parse("main^{tree}"),
+            harness.rev_parse("main~1^{tree}"),
+            "{}",
+            backend
+        );
+        assert_eq!(
+            harness.git(&["show", "--format=", "--name-only", "main"]),
+            "",
+            "{}",
+            backend
+        );
+    }
+}
+
+#[test]
+fn test_skip_list() {
+    let harness = setup(DEFAULT_BACKEND);
+    std::fs::write(harness.cvsroot().join("mod/broken.txt,v"), "not RCS\n").unwrap();
+    let output = harness.try_import(&[]);
+    assert!(!output.status.success());
+
+    let skip_list = harness.scratch("skip.txt");
+    std::fs::write(
+        &skip_list,
+        "# known to be broken\nmod/broken.txt,v  truncated\nmod/gone.txt,v  removed\n",
+    )
+    .unwrap();
+    let report = harness.scratch("report.json");
+    harness.import(&[
+        "--skip-list",
+        skip_list.to_str().unwrap(),
+        "--report",
+        report.to_str().unwrap(),
+    ]);
+    assert_eq!(
+        harness.log("main"),
+        vec!["remove b (adam)", "change a (adam)", "add files (adam)"]
+    );
+
+    // The skipped file isn't an error, but the list is in the report and
+    // the store.
+    let report: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&report).unwrap()).unwrap();
+    assert_eq!(report["skipped_files"], 0);
+    assert_eq!(
+        report["skip_list"],
+        serde_json::json!([
+            {"path": "mod/broken.txt,v", "reason": "truncated", "found": true},
+            {"path": "mod/gone.txt,v", "reason": "removed", "found": false},
+        ])
+    );
+
+    let store = harness.store().to_str().unwrap().to_string();
+    let output = harness.state(&["export", "--format", "json", "--store", &store], b"");
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        json["skipped_files"],
+        serde_json::json!([
+            {"path": "mod/broken.txt,v", "reason": "truncated"},
+            {"path": "mod/gone.txt,v", "reason": "removed"},
+        ])
+    );
+}
+
+#[test]
+fn test_date_quirks() {
+    let harness = setup(DEFAULT_BACKEND);
+    let c_txt = RcsFile::new().revision("1.1", "2021.Jan.06.00.00.00", "carol", "add c", "c\n");
+    harness.write("mod/c.txt,v", &c_txt);
+
+    // The date is rejected by default, and the error includes it as it
+    // appears in the file.
+    let output = harness.try_import(&[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("invalid date \"2021.Jan.06.00.00.00\""),
+        "{}",
+        stderr
+    );
+
+    harness.import(&["--date-quirks", "month-names"]);
+    assert_eq!(
+        harness.git(&["log", "-1", "--format=%s %at", "main"]),
+        "add c 1609891200\n"
+    );
+}
+
+#[test]
+fn test_integrity() {
+    let harness = setup(DEFAULT_BACKEND);
+
+    // The checksum for 1.1 is correct, but the one for 1.2 is for
+    // different content.
+    let c_txt = RcsFile::new()
+        .revision("1.1", "2021.01.06.00.00.00", "carol", "add c", "hello\n")
+        .revision("1.2", "2021.01.07.00.00.00", "carol", "change c", "bye\n")
+        .integrity(
+            "1.1:sha1:f572d396fae9206628714fb2ce00f72e94f2258f 1.2:sha1:da39a3ee5e6b4b0d3255bfef95601890afd80709",
+        );
+    harness.write("mod/c.txt,v", &c_txt);
+
+    let output = harness.try_import(&["--strict"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("failed integrity check") && stderr.contains("revision 1.2 doesn't"),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("revision 1.1 doesn't"));
+
+    // Without --strict, the mismatch is only a warning.
+    harness.import(&[]);
+    assert_eq!(harness.show("main", "mod/c.txt"), "bye\n");
+}
+
+#[test]
+fn test_skip_empty_branches() {
+    const DROP_ALL: &str = "while read -r patchset; do echo null; done";
+
+    for backend in BACKENDS {
+        let harness = setup(backend);
+        harness.import(&[]);
+        let main = harness.rev_parse("main");
+
+        // Every patchset in the next run is dropped, so main has nothing new.
+        harness.write(
+            "mod/a.txt,v",
+            &a_txt().revision(
+                "1.3",
+                "2021.01.05.00.00.00",
+                "adam",
+                "change a again",
+                "one\ntwo\nthree\n",
+            ),
+        );
+        harness.git(&["update-ref", "-d", "refs/heads/main"]);
+        harness.import(&["--patchset-hook", DROP_ALL, "--skip-empty-branches"]);
+        assert!(
+            !harness.refs().contains(&"refs/heads/main".to_string()),
+            "{}",
+            backend
+        );
+
+        // Without the option, main is reset to its last commit.
+        harness.write(
+            "mod/a.txt,v",
+            &a_txt()
+                .revision(
+                    "1.3",
+                    "2021.01.05.00.00.00",
+                    "adam",
+                    "change a again",
+                    "one\ntwo\nthree\n",
+                )
+                .revision(
+                    "1.4",
+                    "2021.01.06.00.00.00",
+                    "adam",
+                    "change a once more",
+                    "one\ntwo\nthree\nfour\n",
+                ),
+        );
+        harness.import(&["--patchset-hook", DROP_ALL]);
+        assert_eq!(harness.rev_parse("main"), main, "{}", backend);
+    }
+}
+
+#[test]
+fn test_set_head() {
+    for backend in BACKENDS {
+        let harness = setup(backend);
+        harness.git(&["symbolic-ref", "HEAD", "refs/heads/master"]);
+
+        // HEAD is left alone by default.
+        harness.import(&[]);
+        assert_eq!(
+            harness.git(&["symbolic-ref", "HEAD"]).trim(),
+            "refs/heads/master",
+            "{}",
+            backend
+        );
+
+        // The head branch is found by its name in Git.
+        let harness = setup(backend);
+        harness.import(&["--set-head", "--branch-rename", "ma(in)=trunk-$1"]);
+        assert_eq!(
+            harness.git(&["symbolic-ref", "HEAD"]).trim(),
+            "refs/heads/trunk-in",
+            "{}",
+            backend
+        );
+        assert_eq!(
+            harness.git(&["rev-parse", "HEAD"]),
+            harness.git(&["rev-parse", "trunk-in"]),
+            "{}",
+            backend
+        );
+    }
+}
+
+#[test]
+fn test_moved_branch() {
+    for backend in BACKENDS {
+        let harness = setup(backend);
+        harness.import(&[]);
+        let main = harness.rev_parse("main");
+
+        // Commit directly to main in Git, as if someone pushed to the mirror.
+        let pushed = harness
+            .git(&[
+                "-c",
+                "user.name=eve",
+                "-c",
+                "user.email=eve",
+                "commit-tree",
+                "-p",
+                &main,
+                "-m",
+                "pushed",
+                "main^{tree}",
+            ])
+            .trim()
+            .to_string();
+        harness.git(&["update-ref", "refs/heads/main", &pushed]);
+
+        let output = harness.try_import(&[]);
+        assert!(!output.status.success(), "{}", backend);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains(&format!(
+                "main is at {} in the Git repository, but was last imported as {}",
+                pushed, main
+            )),
+            "{}: {}",
+            backend,
+            stderr
+        );
+        assert_eq!(harness.rev_parse("main"), pushed, "{}", backend);
+
+        // --force resets the branch, even without any new patchsets.
+        harness.import(&["--force"]);
+        assert_eq!(harness.rev_parse("main"), main, "{}", backend);
+    }
+}
+
+#[test]
+fn test_report_diffstat() {
+    let harness = setup(DEFAULT_BACKEND);
+    let report = harness.scratch("report.json");
+    harness.import(&["--report", report.to_str().unwrap()]);
+
+    let report: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&report).unwrap()).unwrap();
+    let mut diffstats: Vec<(String, &str, u64, u64, u64, u64)> = report["commits"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|commit| {
+            let diffstat = &commit["diffstat"];
+            (
+                harness.git(&["log", "-1", "--format=%s", commit["sha"].as_str().unwrap()]),
+                commit["branch"].as_str().unwrap(),
+                diffstat["files"].as_u64().unwrap(),
+                diffstat["added"].as_u64().unwrap(),
+                diffstat["deleted"].as_u64().unwrap(),
+                diffstat["binary"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    diffstats.sort();
+
+    // The line counts come from the RCS deltas, and the test files are
+    // written with each delta replacing every line of the file.
+    assert_eq!(
+        diffstats,
+        vec![
+            ("add files\n".to_string(), "BR", 1, 1, 0, 0),
+            ("add files\n".to_string(), "main", 2, 2, 0, 0),
+            ("change a\n".to_string(), "main", 1, 2, 1, 0),
+            ("change a on BR\n".to_string(), "BR", 1, 2, 1, 0),
+            ("remove b\n".to_string(), "main", 1, 0, 1, 0),
+        ]
+    );
+}
+
+#[test]
+fn test_sign_tags() {
+    for backend in BACKENDS {
+        let harness = setup(backend);
+        harness.write("mod/a.txt,v", &a_txt().symbol("NIGHTLY", "1.2"));
+        let report = harness.scratch("report.json");
+        let signed = harness.scratch("signed.json");
+        let hook = format!("cat > '{}'", signed.display());
+        harness.import(&[
+            "--report",
+            report.to_str().unwrap(),
+            "--sign-tags",
+            "REL*",
+            "--post-import-hook",
+            &hook,
+        ]);
+
+        let report: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&report).unwrap()).unwrap();
+        let mark = report["tags"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|tag| tag["tag"] == "REL1")
+            .unwrap()["mark"]
+            .clone();
+
+        // Only the matching tag is listed, both in the report and on the
+        // hook's stdin.
+        let want = serde_json::json!([{
+            "ref": "refs/tags/REL1",
+            "mark": mark,
+            "sha": harness.rev_parse("REL1"),
+            "reason": "new tag matching REL*",
+        }]);
+        assert_eq!(report["sign_tags"], want, "{}", backend);
+        let lines: Vec<serde_json::Value> = std::fs::read_to_string(&signed)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(serde_json::Value::from(lines), want, "{}", backend);
+
+        // A failing hook fails the import.
+        let output = harness.try_import(&["--post-import-hook", "exit 3"]);
+        assert!(!output.status.success(), "{}", backend);
+    }
+}
+
+#[test]
+fn test_repack() {
+    for backend in BACKENDS {
+        let harness = setup(backend);
+        let counts = |harness: &Harness| {
+            let counts = harness.git(&["count-objects", "-v"]);
+            let count = |name: &str| {
+                counts
+                    .lines()
+                    .find_map(|line| line.strip_prefix(name))
+                    .unwrap()
+                    .trim()
+                    .parse::<usize>()
+                    .unwrap()
+            };
+            (count("count:"), count("packs:"))
+        };
+
+        // Each run leaves its own objects behind.
+        harness.import(&[]);
+        harness.write(
+            "mod/a.txt,v",
+            &a_txt().revision(
+                "1.3",
+                "2021.01.05.00.00.00",
+                "adam",
+                "change a again",
+                "3\n",
+            ),
+        );
+        harness.import(&[]);
+        assert_ne!(counts(&harness), (0, 1), "{}", backend);
+
+        // Repacking leaves a single pack, without changing the history.
+        let main = harness.rev_parse("main");
+        harness.import(&["--repack", "--repack-depth", "10", "--repack-window", "10"]);
+        assert_eq!(counts(&harness), (0, 1), "{}", backend);
+        assert_eq!(harness.rev_parse("main"), main, "{}", backend);
+        harness.git(&["fsck", "--no-dangling"]);
+    }
+}
+
+#[test]
+fn test_preflight_work_tree() {
+    // The gitoxide backend doesn't run git, so only checks that it can open
+    // the repository.
+    let harness = setup(DEFAULT_BACKEND);
+    let work_tree = harness.scratch("work");
+    std::fs::create_dir(&work_tree).unwrap();
+    harness.git(&["config", "core.bare", "false"]);
+    harness.git(&["config", "core.worktree", work_tree.to_str().unwrap()]);
+
+    // A work tree without changes to tracked files is fine.
+    std::fs::write(work_tree.join("untracked.txt"), "untracked\n").unwrap();
+    harness.import(&[]);
+    let main = harness.rev_parse("main");
+
+    // Uncommitted changes are refused before anything is imported.
+    std::fs::write(work_tree.join("staged.txt"), "staged\n").unwrap();
+    harness.git(&["add", "staged.txt"]);
+    harness.write(
+        "mod/a.txt,v",
+        &a_txt().revision(
+            "1.3",
+            "2021.01.05.00.00.00",
+            "adam",
+            "change a again",
+            "3\n",
+        ),
+    );
+    let output = harness.try_import(&[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("has uncommitted changes"), "{}", stderr);
+    assert!(stderr.contains("A  staged.txt"), "{}", stderr);
+    assert_eq!(harness.rev_parse("main"), main);
+}
+
+#[test]
+fn test_init() {
+    for backend in BACKENDS {
+        for bare in [true, false] {
+            // gitoxide treats a path ending in .git as the Git directory
+            // itself, so can't open a work tree at the harness's repository
+            // path.
+            if *backend == "gitoxide" && !bare {
+                continue;
+            }
+
+            let harness = setup(backend);
+            std::fs::remove_dir_all(harness.repo()).unwrap();
+
+            // Without --init, a missing repository is an error.
+            assert!(!harness.try_import(&[]).status.success(), "{}", backend);
+            assert!(!harness.repo().exists(), "{}", backend);
+
+            let mut args = vec!["--init", "--branch-rename", "main=trunk"];
+            if bare {
+                args.push("--bare");
+            }
+            harness.import(&args);
+            assert_eq!(
+                harness.git(&["rev-parse", "--is-bare-repository"]).trim(),
+                bare.to_string(),
+                "{}",
+                backend
+            );
+            assert_eq!(
+                harness.git(&["symbolic-ref", "HEAD"]).trim(),
+                "refs/heads/trunk",
+                "{}",
+                backend
+            );
+            assert_eq!(
+                harness.log("HEAD"),
+                harness.log("trunk"),
+                "{} {}",
+                backend,
+                bare
+            );
+        }
+    }
+}
+
+#[test]
+fn test_max_read_bytes_per_sec() {
+    let harness = setup(DEFAULT_BACKEND);
+    let size: u64 = ["mod/a.txt,v", "mod/Attic/b.txt,v"]
+        .iter()
+        .map(|path| {
+            std::fs::metadata(harness.cvsroot().join(path))
+                .unwrap()
+                .len()
+        })
+        .sum();
+
+    // At half the combined size of the files per second, the larger file,
+    // which is read first, delays the other for more than a second.
+    let started = std::time::Instant::now();
+    let output = harness.try_import(&[
+        "--jobs",
+        "1",
+        "--max-read-bytes-per-sec",
+        &(size / 2).to_string(),
+    ]);
+    assert!(output.status.success());
+    assert!(started.elapsed() >= std::time::Duration::from_secs(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("reading files was delayed"), "{}", stderr);
+
+    let limited = harness.rev_parse("main");
+    let harness = setup(DEFAULT_BACKEND);
+    harness.import(&[]);
+    assert_eq!(harness.rev_parse("main"), limited);
+}
+
+#[test]
+fn test_idle_priority() {
+    // The hook inherits the priority of the import.
+    let harness = setup(DEFAULT_BACKEND);
+    let nice = harness.scratch("nice.txt");
+    harness.import(&[
+        "--idle-priority",
+        "--post-import-hook",
+        &format!("nice > {}", nice.display()),
+    ]);
+    assert_eq!(std::fs::read_to_string(&nice).unwrap(), "19\n");
+}
+
+#[test]
+fn test_path_normalization() {
+    // A directory named "café" by a Mac, with the accent as a combining
+    // character.
+    let harness = setup(DEFAULT_BACKEND);
+    harness.write("mod/cafe\u{301}/menu.txt,v", &b_txt());
+    harness.import(&["--path-normalization", "nfc"]);
+
+    let files = harness.git(&[
+        "-c",
+        "core.quotePath=false",
+        "ls-tree",
+        "-r",
+        "--name-only",
+        "REL1",
+    ]);
+    assert_eq!(
+        files.lines().collect::<Vec<_>>(),
+        ["mod/a.txt", "mod/b.txt", "mod/caf\u{e9}/menu.txt"]
+    );
+
+    // The form is recorded, so a later run can't change it.
+    let output = harness.try_import(&["--path-normalization", "nfd"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--path-normalization nfc"));
+    harness.import(&["--path-normalization", "nfc"]);
+
+    // Likewise, a store imported without normalization can't start
+    // normalizing paths later.
+    let harness = setup(DEFAULT_BACKEND);
+    harness.import(&[]);
+    let output = harness.try_import(&["--path-normalization", "nfc"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_skip_branch_add_placeholders() {
+    // A file added on BR, which CVS records as a dead 1.1 on the trunk that
+    // the branch grows from.
+    let c_txt = RcsFile::new()
+        .symbol("BR", "1.1.0.2")
+        .delete(
+            "1.1",
+            "2021.01.05.00.00.00",
+            "carol",
+            "file c.txt was initially added on branch BR.",
+        )
+        .revision(
+            "1.1.2.1",
+            "2021.01.05.00.00.00",
+            "carol",
+            "add c on BR",
+            "c\n",
+        );
+    let placeholder = "file c.txt was initially added on branch BR. (carol)".to_string();
+
+    let harness = setup(DEFAULT_BACKEND);
+    harness.write("mod/Attic/c.txt,v", &c_txt);
+    harness.import(&["--emit-empty-commits", "--resurrection-trailer"]);
+    assert!(harness.log("BR").contains(&placeholder));
+    assert!(harness
+        .git(&["log", "-1", "--format=%B", "BR"])
+        .contains("CVS-Resurrected: mod/c.txt"));
+
+    let harness = setup(DEFAULT_BACKEND);
+    harness.write("mod/Attic/c.txt,v", &c_txt);
+    harness.import(&[
+        "--emit-empty-commits",
+        "--resurrection-trailer",
+        "--skip-branch-add-placeholders",
+    ]);
+    assert_eq!(
+        harness.log("BR"),
+        [
+            "add c on BR (carol)",
+            "change a on BR (bob)",
+            "add files (adam)"
+        ]
+    );
+    assert_eq!(
+        harness.git(&["log", "-1", "--format=%B", "BR"]).trim(),
+        "add c on BR"
+    );
+    assert_eq!(harness.ls_tree("BR"), ["mod/a.txt", "mod/c.txt"]);
+    assert_eq!(harness.ls_tree("main"), ["mod/a.txt"]);
+}
+
+#[test]
+fn test_reconcile() {
+    for backend in BACKENDS {
+        let harness = setup(backend);
+        harness.import(&["--reconcile"]);
+        let head = harness.rev_parse("main");
+
+        // Nothing has drifted, so nothing is committed.
+        harness.import(&["--reconcile"]);
+        assert_eq!(harness.rev_parse("main"), head, "{}", backend);
+
+        // Make the state disagree with Git by marking the latest revision of
+        // a.txt as deleted.
+        let store = harness.store().to_str().unwrap().to_string();
+        let output = harness.state(&["export", "--store", &store], b"");
+        let mut json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        for revision in json["file_revisions"].as_array_mut().unwrap() {
+            if revision["path"] == "mod/a.txt" && revision["revision"] == "1.2" {
+                revision["mark"] = serde_json::Value::Null;
+            }
+        }
+        std::fs::remove_file(harness.store()).unwrap();
+        let imported = harness.state(
+            &["import", "--store", &store],
+            &serde_json::to_vec(&json).unwrap(),
+        );
+        assert!(imported.status.success(), "{}", backend);
+
+        let report = harness.scratch("report.json");
+        harness.import(&["--reconcile", "--report", report.to_str().unwrap()]);
+        assert_eq!(
+            harness.git(&["rev-parse", "main^"]).trim(),
+            head,
+            "{}",
+            backend
+        );
+        assert_eq!(
+            harness.log("main")[0],
+            "Reconcile main with CVS (git-cvs-fast-import)",
+            "{}",
+            backend
+        );
+        assert!(harness.ls_tree("main").is_empty(), "{}", backend);
+
+        let report: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&report).unwrap()).unwrap();
+        assert_eq!(
+            report["reconciliations"][0]["branch"], "main",
+            "{}",
+            backend
+        );
+        assert_eq!(
+            report["reconciliations"][0]["deleted"][0], "mod/a.txt",
+            "{}",
+            backend
+        );
+
+        // The corrective commit is recorded as the head of the branch, so the
+        // next run neither moves it back nor commits it again.
+        let reconciled = harness.rev_parse("main");
+        harness.import(&["--reconcile"]);
+        assert_eq!(harness.rev_parse("main"), reconciled, "{}", backend);
+    }
+}
+
+#[test]
+fn test_missing_symbol_revisions() {
+    // A file whose REL2 tag points to a revision that was removed with
+    // `rcs -o`.
+    let c_txt = RcsFile::new()
+        .symbol("REL2", "1.3")
+        .revision("1.1", "2021.01.05.00.00.00", "carol", "add c", "c\n")
+        .revision("1.2", "2021.01.06.00.00.00", "carol", "change c", "c\nc\n");
+
+    let harness = setup(DEFAULT_BACKEND);
+    harness.write("mod/c.txt,v", &c_txt);
+    let report = harness.scratch("report.json");
+    harness.import(&["--report", report.to_str().unwrap()]);
+    assert_eq!(harness.show("REL2", "mod/c.txt"), "c\nc\n");
+
+    let report: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&report).unwrap()).unwrap();
+    let losses = report["losses"].as_array().unwrap();
+    assert!(
+        losses
+            .iter()
+            .any(|loss| loss["path"] == "mod/c.txt" && loss["kind"] == "missing_symbol_revision"),
+        "{:?}",
+        losses
+    );
+
+    let harness = setup(DEFAULT_BACKEND);
+    harness.write("mod/c.txt,v", &c_txt);
+    harness.import(&["--missing-symbol-revisions", "drop"]);
+    assert!(!harness.refs().iter().any(|r| r == "refs/tags/REL2"));
+}
+
+#[test]
+fn test_create_empty_branches() {
+    // EMPTY is created after a.txt has already been imported, and nothing is
+    // committed to it until later.
+    let empty = a_txt().symbol("EMPTY", "1.2.0.4");
+
+    for backend in BACKENDS {
+        let harness = setup(backend);
+        harness.import(&[]);
+        harness.write("mod/a.txt,v", &empty);
+        harness.import(&[]);
+        assert!(
+            !harness.refs().iter().any(|r| r == "refs/heads/EMPTY"),
+            "{}",
+            backend
+        );
+
+        // a.txt was branched at 1.2, before b.txt was removed.
+        harness.import(&["--create-empty-branches"]);
+        assert_eq!(
+            harness.rev_parse("EMPTY"),
+            harness.rev_parse("main~1"),
+            "{}",
+            backend
+        );
+
+        // The first commit on the branch follows its branch point.
+        harness.write(
+            "mod/a.txt,v",
+            &a_txt().symbol("EMPTY", "1.2.0.4").revision(
+                "1.2.4.1",
+                "2021.01.07.00.00.00",
+                "carol",
+                "change a on EMPTY",
+                "one\ntwo\nempty\n",
+            ),
+        );
+        harness.import(&["--create-empty-branches"]);
+        assert_eq!(
+            harness.log("EMPTY"),
+            [
+                "change a on EMPTY (carol)",
+                "change a (adam)",
+                "add files (adam)"
+            ],
+            "{}",
+            backend
+        );
+        assert_eq!(
+            harness.ls_tree("EMPTY"),
+            ["mod/a.txt", "mod/b.txt"],
+            "{}",
+            backend
+        );
+    }
+}